@@ -6,45 +6,9 @@ use log::{self};
 use serde::Deserialize;
 use serde_with::*;
 use std::collections::{HashMap, HashSet};
-use std::fmt;
 
-/// The ENV enum is required, however the fields can be changed.
-/// An environment variable is queried to determine the evironment
-/// which the server is running on, that is used to pull in config
-/// files matching the environment.
-#[derive(Clone, Debug, SmartDefault, Deserialize)]
-pub enum ENV {
-    Development,
-    Testing,
-    Stage,
-    #[default]
-    Production,
-}
-
-impl fmt::Display for ENV {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ENV::Development => write!(f, "Development"),
-            ENV::Testing => write!(f, "Testing"),
-            ENV::Stage => write!(f, "Stage"),
-            ENV::Production => write!(f, "Production"),
-            #[allow(unreachable_patterns)]
-            _ => panic!("Need to update Display for ENV"),
-        }
-    }
-}
-
-impl From<&str> for ENV {
-    fn from(env: &str) -> Self {
-        match env {
-            "Development" => ENV::Development,
-            "Testing" => ENV::Testing,
-            "Stage" => ENV::Stage,
-            "Production" => ENV::Production,
-            _ => panic!("Need to update From for ENV"),
-        }
-    }
-}
+/// The environment which the server is running in, which is open to any name.
+pub use config_foundation::Environment as ENV;
 
 /// This is the log filter. Notice that we're able to use the Display and FromStr impl.
 /// Normally, we'd have to use From<&str>, but serde_as has our back and provides a means
@@ -0,0 +1,222 @@
+// Back-to-back benchmark of the MachineSender designs, run over the same daisy chain of forwarders:
+//  * boxed: the receiver queues a boxed SendContext, per send, which is awaited once it returns, as in
+//    async_framework and server-core;
+//  * drain: the receiver queues (sender, instruction) pairs, which are drained once it returns, as in
+//    async_machine;
+//  * direct: the receiver is async, and awaits each send itself.
+// Each design is run for each combination of thread count and queue size, and the results are written to
+// stdout as a JSON array, for tracking.
+//
+// Usage: sender_bench [forwarders] [messages]
+use smol::{channel, future, Executor};
+
+use std::{future::Future, pin::Pin, sync::Arc, thread, time};
+
+const THREAD_COUNTS: [usize; 2] = [1, 4];
+const QUEUE_SIZES: [usize; 3] = [10, 100, 1000];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Design {
+    Boxed,
+    Drain,
+    Direct,
+}
+
+impl Design {
+    const ALL: [Design; 3] = [Design::Boxed, Design::Drain, Design::Direct];
+
+    fn name(self) -> &'static str {
+        match self {
+            Design::Boxed => "boxed",
+            Design::Drain => "drain",
+            Design::Direct => "direct",
+        }
+    }
+}
+
+// The boxed design erases the instruction type behind a trait object, and boxes the send future.
+trait AsyncSender: Send {
+    fn do_send(self: Box<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+struct SendContext<T>(channel::Sender<T>, T);
+
+impl<T: 'static + Send> AsyncSender for SendContext<T> {
+    fn do_send(self: Box<Self>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            self.0.send(self.1).await.ok();
+        })
+    }
+}
+
+#[derive(Default)]
+struct BoxedSender {
+    queue: Vec<Box<dyn AsyncSender>>,
+}
+
+impl BoxedSender {
+    fn send<T: 'static + Send>(&mut self, sender: &channel::Sender<T>, cmd: T) {
+        self.queue.push(Box::new(SendContext(sender.clone(), cmd)));
+    }
+}
+
+#[derive(Default)]
+struct DrainSender {
+    queue: Vec<(channel::Sender<usize>, usize)>,
+}
+
+impl DrainSender {
+    fn send(&mut self, sender: &channel::Sender<usize>, cmd: usize) { self.queue.push((sender.clone(), cmd)); }
+}
+
+// The result of a single run.
+#[derive(Debug)]
+struct BenchResult {
+    design: Design,
+    threads: usize,
+    queue_size: usize,
+    forwarders: usize,
+    messages: usize,
+    elapsed: time::Duration,
+}
+
+impl BenchResult {
+    fn to_json(&self) -> String {
+        let secs = self.elapsed.as_secs_f64();
+        let hops = (self.forwarders * self.messages) as f64;
+        format!(
+            "{{\"design\":\"{}\",\"threads\":{},\"queue_size\":{},\"forwarders\":{},\"messages\":{},\"elapsed_ms\":{:.3},\"hops_per_sec\":{:.0}}}",
+            self.design.name(),
+            self.threads,
+            self.queue_size,
+            self.forwarders,
+            self.messages,
+            secs * 1000.0,
+            if secs > 0.0 { hops / secs } else { 0.0 }
+        )
+    }
+}
+
+// Spawn a forwarder, which forwards to next, or, if last, closes done once it has received every message.
+fn spawn_forwarder(
+    design: Design, executor: &Executor<'static>, receiver: channel::Receiver<usize>, next: Option<channel::Sender<usize>>,
+    messages: usize, done: channel::Sender<()>,
+) {
+    executor
+        .spawn(async move {
+            let mut boxed = BoxedSender::default();
+            let mut drain = DrainSender::default();
+            let mut count = 0;
+            while let Ok(val) = receiver.recv().await {
+                count += 1;
+                let next = match next {
+                    Some(ref next) => next,
+                    None => {
+                        if count == messages {
+                            done.close();
+                        }
+                        continue;
+                    },
+                };
+                match design {
+                    Design::Boxed => {
+                        boxed.send(next, val);
+                        for sender in boxed.queue.drain(..) {
+                            sender.do_send().await;
+                        }
+                    },
+                    Design::Drain => {
+                        drain.send(next, val);
+                        for (sender, cmd) in drain.queue.drain(..) {
+                            sender.send(cmd).await.ok();
+                        }
+                    },
+                    Design::Direct => {
+                        next.send(val).await.ok();
+                    },
+                }
+            }
+        })
+        .detach();
+}
+
+// Run a design, returning the time taken for every message to pass through every forwarder.
+fn run(design: Design, threads: usize, queue_size: usize, forwarders: usize, messages: usize) -> BenchResult {
+    let (stop, signal) = channel::unbounded::<()>();
+    let executors: Vec<Arc<Executor<'static>>> = (0 .. threads).map(|_| Arc::new(Executor::new())).collect();
+    let handles: Vec<thread::JoinHandle<()>> = executors
+        .iter()
+        .enumerate()
+        .map(|(id, executor)| {
+            let executor = executor.clone();
+            let signal = signal.clone();
+            thread::Builder::new()
+                .name(format!("bench-{}", id))
+                .spawn(move || future::block_on(executor.run(signal.recv())).unwrap_or(()))
+                .expect("cannot spawn executor thread")
+        })
+        .collect();
+
+    let (done, finished) = channel::unbounded::<()>();
+    let mut next: Option<channel::Sender<usize>> = None;
+    for id in 0 .. forwarders {
+        let (sender, receiver) = channel::bounded::<usize>(queue_size);
+        spawn_forwarder(design, &executors[id % threads], receiver, next, messages, done.clone());
+        next = Some(sender);
+    }
+    drop(done);
+    let first = next.expect("at least one forwarder is required");
+
+    let start = time::Instant::now();
+    future::block_on(async {
+        for val in 1 ..= messages {
+            first.send(val).await.ok();
+        }
+        finished.recv().await.ok();
+    });
+    let elapsed = start.elapsed();
+
+    stop.close();
+    first.close();
+    for handle in handles {
+        handle.join().ok();
+    }
+    BenchResult {
+        design,
+        threads,
+        queue_size,
+        forwarders,
+        messages,
+        elapsed,
+    }
+}
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse::<usize>().expect("arguments must be numbers"));
+    let forwarders = args.next().unwrap_or(1_000).max(1);
+    let messages = args.next().unwrap_or(10_000).max(1);
+
+    let mut results = Vec::new();
+    for &threads in &THREAD_COUNTS {
+        for &queue_size in &QUEUE_SIZES {
+            // run the designs back-to-back, so each sees the same conditions
+            for &design in &Design::ALL {
+                let result = run(design, threads, queue_size, forwarders, messages);
+                eprintln!("{:?}", result);
+                results.push(result.to_json());
+            }
+        }
+    }
+    println!("[\n  {}\n]", results.join(",\n  "));
+}
+
+#[test]
+fn test_each_design() {
+    for &design in &Design::ALL {
+        let result = run(design, 2, 10, 10, 100);
+        assert_eq!(design, result.design);
+        assert!(result.to_json().starts_with(&format!("{{\"design\":\"{}\"", design.name())));
+    }
+}
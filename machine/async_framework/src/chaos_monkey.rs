@@ -62,8 +62,7 @@ impl TestDriver for ChaosMonkeyDriver {
         let senders = self.senders.clone();
         let message_count = self.message_count;
         let inflection_value = self.inflection_value;
-        EXECUTOR.0[0]
-            .clone()
+        get_executor()
             .spawn(async move {
                 for _ in 0 .. message_count {
                     let idx = range.sample(&mut rng);
@@ -153,7 +152,7 @@ mod tests {
     #[test]
     #[ignore]
     fn large_chaos_monkey() {
-        default_channel_max.store(1000);
+        set_default_channel_max(1000);
 
         // This time we're going to ramp it up and push 40_020_000 messages through.
         let mut config = ChaosMonkeyDriver::default();
@@ -14,7 +14,7 @@ pub trait TestDriver {
 /// The wait_for_notification function provides a common way to wait for a TestDriver::run() to complete.
 pub fn wait_for_notification(receiver: &TestMessageReceiver, _messages: usize, _duration: Duration) -> Result<(), ()> {
     let start = std::time::SystemTime::now();
-    let executor = EXECUTOR.0[0].clone();
+    let executor = get_executor();
     let r = receiver.clone();
     smol::future::block_on(executor.run(async move {
         if let Ok(_cmd) = r.recv().await {
@@ -82,8 +82,7 @@ impl TestDriver for DaisyChainDriver {
     fn run(&self) {
         let first_sender = self.first_sender.clone();
         let message_count = self.message_count;
-        EXECUTOR.0[0]
-            .clone()
+        get_executor()
             .spawn(async move {
                 if let Some(sender) = first_sender.as_ref() {
                     for msg_id in 0 .. message_count {
@@ -164,7 +163,7 @@ mod tests {
     fn large_daisy_chain() {
         CombinedLogger::init(vec![TermLogger::new(LevelFilter::Info, Config::default(), TerminalMode::Mixed)]).unwrap();
 
-        default_channel_max.store(1000);
+        set_default_channel_max(1000);
         let mut config = DaisyChainDriver::default();
         config.machine_count = 10_000;
         config.message_count = 20_000;
@@ -174,7 +173,7 @@ mod tests {
         assert_eq!(config.machine_count, config.forwarders.len());
         config.run();
 
-        EXECUTOR.2.close();
+        stop_executors();
         for f in &config.forwarders {
             let count = f.get_and_clear_received_count();
             if count != config.message_count {
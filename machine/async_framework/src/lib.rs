@@ -1,4 +1,3 @@
-use async_trait::async_trait;
 use smart_default::*;
 
 mod test_message;
@@ -10,14 +9,8 @@ pub use state_table::*;
 mod forwarder;
 pub use forwarder::*;
 
-mod executor;
-pub use executor::*;
-
-mod machine;
-pub use machine::*;
-
-mod machine_adapter;
-pub use machine_adapter::*;
+pub use machine_foundation::{machine::*, *};
+pub use server_core::stop_executors;
 
 mod daisy_chain;
 pub use daisy_chain::*;
@@ -1,4 +1,3 @@
-use super::*;
 use machine_impl::*;
 
 /// TestMessage is an instuction set used in testing. The [`Forwarder`] and other machines implement it.
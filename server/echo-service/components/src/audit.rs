@@ -0,0 +1,174 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Shorthand for a sender, that can be sent AuditCmd instructions.
+pub type AuditSender = channel::Sender<AuditCmd>;
+
+/// The kind of security-relevant event being recorded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    AdminCommand,
+    AuthFailure,
+    ConfigReload,
+    ConnectionClosed,
+}
+
+/// An AuditEvent is a single audit record. Beyond the kind and the actor, which is whoever
+/// caused the event, it carries a set of structured fields.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct AuditEvent {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub kind: AuditKind,
+    pub actor: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl AuditEvent {
+    /// Create an event, timestamped now.
+    pub fn new(kind: AuditKind, actor: &str) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Self {
+            timestamp,
+            kind,
+            actor: actor.to_string(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Add a field to the event.
+    pub fn with_field(mut self, name: &str, value: &str) -> Self {
+        self.fields.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Encode the event as a single line of JSON.
+    pub fn to_json(&self) -> String { serde_json::to_string(self).unwrap_or_default() }
+}
+
+/// AuditCmd is the instruction set of the audit log.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum AuditCmd {
+    /// Record an event.
+    Record(AuditEvent),
+}
+
+/// An AuditSink is where audit records end up. Sinks are append-only, each record is a line of JSON.
+pub trait AuditSink: Send + Sync {
+    /// Append a record to the sink.
+    fn append(&mut self, record: &str) -> io::Result<()>;
+}
+
+/// The FileSink appends records to a file, the file is never truncated.
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    /// Open, creating if needed, the file for appending.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl AuditSink for FileSink {
+    fn append(&mut self, record: &str) -> io::Result<()> {
+        self.file.write_all(record.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.file.flush()
+    }
+}
+
+/// The AuditMachine implements the AuditCmd instruction set, appending each event to all of its
+/// sinks. The audit log is kept apart from the application log, additional sinks can be used
+/// to forward records elsewhere.
+pub struct AuditMachine {
+    sinks: AtomicRefCell<Vec<Box<dyn AuditSink>>>,
+}
+
+impl fmt::Debug for AuditMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#AuditMachine {{ sinks: {} }}", self.sinks.borrow().len()) }
+}
+
+impl AuditMachine {
+    /// Create an audit log writing to a sink.
+    pub fn new(sink: Box<dyn AuditSink>) -> Self {
+        Self {
+            sinks: AtomicRefCell::new(vec![sink]),
+        }
+    }
+
+    /// Add a sink, which receives every record.
+    pub fn with_sink(self, sink: Box<dyn AuditSink>) -> Self {
+        self.sinks.borrow_mut().push(sink);
+        self
+    }
+}
+
+impl Machine<AuditCmd> for AuditMachine {
+    fn receive(&self, cmd: AuditCmd, _sender: &mut MachineSender) {
+        match cmd {
+            AuditCmd::Record(event) => {
+                let record = event.to_json();
+                for sink in self.sinks.borrow_mut().iter_mut() {
+                    if let Err(err) = sink.append(&record) {
+                        log::error!("failed to append audit record error={}", err);
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default, Clone)]
+    struct MemorySink {
+        records: Arc<Mutex<Vec<String>>>,
+    }
+    impl AuditSink for MemorySink {
+        fn append(&mut self, record: &str) -> io::Result<()> {
+            self.records.lock().unwrap().push(record.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn event_to_json() {
+        let mut event = AuditEvent::new(AuditKind::AuthFailure, "alice").with_field("backend", "static");
+        event.timestamp = 1;
+        assert_eq!(
+            r#"{"timestamp":1,"kind":"auth_failure","actor":"alice","fields":{"backend":"static"}}"#,
+            event.to_json()
+        );
+    }
+
+    #[test]
+    fn record_to_sinks() {
+        let first = MemorySink::default();
+        let second = MemorySink::default();
+        let audit = AuditMachine::new(Box::new(first.clone())).with_sink(Box::new(second.clone()));
+        let (_audit, sender) = machine_foundation::machine::create(audit);
+        sender
+            .try_send(AuditCmd::Record(AuditEvent::new(AuditKind::ConfigReload, "admin")))
+            .ok();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(1, first.records.lock().unwrap().len());
+        assert_eq!(*first.records.lock().unwrap(), *second.records.lock().unwrap());
+    }
+}
@@ -0,0 +1,422 @@
+use super::*;
+use machine_foundation::{get_executor, spawn_blocking, Machine, MachineSender};
+use once_cell::sync::Lazy;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use subtle::ConstantTimeEq;
+
+/// A token handed out on successful authentication.
+pub type AuthToken = String;
+/// Shorthand for a sender, that can be sent AuthCmd instructions.
+pub type AuthSender = channel::Sender<AuthCmd>;
+
+/// The credentials presented for authentication.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Credentials {
+    pub user: String,
+    pub secret: String,
+}
+
+impl Credentials {
+    /// Create credentials from a user and secret.
+    pub fn new(user: &str, secret: &str) -> Self {
+        Self {
+            user: user.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+}
+
+/// Represents all of the possible errors that can occur when authenticating.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AuthError {
+    /// The user is unknown or the secret doesn't match.
+    InvalidCredentials,
+    /// The token is unknown or has been revoked.
+    InvalidToken,
+    /// The backend couldn't service the request.
+    Unavailable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidCredentials => write!(f, "invalid credentials"),
+            Self::InvalidToken => write!(f, "invalid token"),
+            Self::Unavailable(s) => write!(f, "auth backend unavailable: {}", s),
+        }
+    }
+}
+
+/// AuthCmd is the instruction set of the auth component. Requests carry the sender which is
+/// sent the reply.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum AuthCmd {
+    /// Authenticate the credentials, replying with Authenticated.
+    Authenticate(Credentials, AuthSender),
+    /// Validate a token, replying with Validated.
+    Validate(AuthToken, AuthSender),
+    /// Revoke a token, after which it no longer validates.
+    Revoke(AuthToken),
+    /// Reply to Authenticate, providing the user and the result.
+    Authenticated(String, Result<AuthToken, AuthError>),
+    /// Reply to Validate, providing the token and the user it was issued to.
+    Validated(AuthToken, Result<String, AuthError>),
+}
+
+/// The AuthBackend is the pluggable part of the auth component, it verifies credentials.
+pub trait AuthBackend: Send + Sync {
+    /// Get the name of the backend, used for logging.
+    fn get_name(&self) -> &str;
+    /// Verify the credentials, returning the authenticated user.
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, AuthError>;
+}
+
+/// The number of PBKDF2 rounds a secret is hashed with, unless its hash says otherwise.
+pub const PBKDF2_ROUNDS: u32 = 100_000;
+
+// The prefix of a secret which is hashed, followed by the rounds, salt and hash, separated by $.
+const HASH_PREFIX: &str = "pbkdf2-sha256$";
+const SALT_LEN: usize = 16;
+
+// The secret verified against for a user who is unknown, so the time taken doesn't reveal which
+// users are known.
+static UNKNOWN_USER: Lazy<HashedSecret> = Lazy::new(|| HashedSecret::new("", PBKDF2_ROUNDS));
+
+// A secret, kept as its salted PBKDF2-HMAC-SHA256 hash, rather than the plaintext.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct HashedSecret {
+    rounds: u32,
+    salt: Vec<u8>,
+    hash: [u8; 32],
+}
+
+impl HashedSecret {
+    fn new(secret: &str, rounds: u32) -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let hash = Self::derive(secret, &salt, rounds);
+        Self { rounds, salt, hash }
+    }
+
+    fn derive(secret: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(secret.as_bytes(), salt, rounds, &mut hash);
+        hash
+    }
+
+    // Parse a hashed secret, from pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>.
+    fn parse(hashed: &str) -> Option<Self> {
+        let mut parts = hashed.strip_prefix(HASH_PREFIX)?.split('$');
+        let rounds = parts.next()?.parse().ok()?;
+        let salt = from_hex(parts.next()?)?;
+        let mut hash = [0u8; 32];
+        let bytes = from_hex(parts.next()?)?;
+        if bytes.len() != hash.len() || parts.next().is_some() {
+            return None;
+        }
+        hash.copy_from_slice(&bytes);
+        Some(Self { rounds, salt, hash })
+    }
+
+    // Check the secret against the hash, taking the same time wherever they differ.
+    fn verify(&self, secret: &str) -> bool { bool::from(Self::derive(secret, &self.salt, self.rounds).ct_eq(&self.hash)) }
+}
+
+impl fmt::Display for HashedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}${}${}", HASH_PREFIX, self.rounds, to_hex(&self.salt), to_hex(&self.hash))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() }
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?, 16).ok())
+        .collect()
+}
+
+/// The StaticBackend verifies credentials against a fixed set of users, keeping a salted hash of
+/// each secret, rather than the secret. The file format is one `user:secret` per line, blank
+/// lines and lines starting with `#` are ignored. A secret is better written to the file as its
+/// hash, from StaticBackend::hash_secret(), so the file doesn't hold it either.
+#[derive(Debug, Default)]
+pub struct StaticBackend {
+    users: HashMap<String, HashedSecret>,
+}
+
+impl StaticBackend {
+    /// Create a backend from (user, secret) pairs.
+    pub fn new(users: &[(&str, &str)]) -> Self {
+        let users = users
+            .iter()
+            .map(|(user, secret)| (user.to_string(), HashedSecret::new(secret, PBKDF2_ROUNDS)))
+            .collect();
+        Self { users }
+    }
+
+    /// Hash a secret, with a random salt, for the users file. The rounds should be PBKDF2_ROUNDS,
+    /// unless there's reason for them not to be.
+    pub fn hash_secret(secret: &str, rounds: u32) -> String { HashedSecret::new(secret, rounds).to_string() }
+
+    /// Load the users from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    fn parse(contents: &str) -> io::Result<Self> {
+        let mut users = HashMap::new();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((user, secret)) if !user.is_empty() => {
+                    let secret = secret.trim();
+                    let hashed = if secret.starts_with(HASH_PREFIX) {
+                        HashedSecret::parse(secret)
+                            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("line {} has a malformed hash", idx + 1)))?
+                    } else {
+                        HashedSecret::new(secret, PBKDF2_ROUNDS)
+                    };
+                    users.insert(user.trim().to_string(), hashed);
+                },
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {} is not user:secret", idx + 1),
+                    ))
+                },
+            }
+        }
+        Ok(Self { users })
+    }
+}
+
+impl AuthBackend for StaticBackend {
+    fn get_name(&self) -> &str { "static" }
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, AuthError> {
+        match self.users.get(&credentials.user) {
+            Some(hashed) if hashed.verify(&credentials.secret) => Ok(credentials.user.clone()),
+            Some(_) => Err(AuthError::InvalidCredentials),
+            None => {
+                // as long as verifying a secret of a known user
+                UNKNOWN_USER.verify(&credentials.secret);
+                Err(AuthError::InvalidCredentials)
+            },
+        }
+    }
+}
+
+/// The LdapBackend is a stub, holding the place of a directory lookup. It reports itself as
+/// unavailable until a directory client is wired in.
+#[derive(Debug, Default)]
+pub struct LdapBackend {
+    url: String,
+}
+
+impl LdapBackend {
+    /// Create a backend for the directory at url.
+    pub fn new(url: &str) -> Self { Self { url: url.to_string() } }
+}
+
+impl AuthBackend for LdapBackend {
+    fn get_name(&self) -> &str { "ldap" }
+    fn authenticate(&self, _credentials: &Credentials) -> Result<String, AuthError> {
+        Err(AuthError::Unavailable(format!("no ldap client for {}", self.url)))
+    }
+}
+
+/// The AuthMachine implements the AuthCmd instruction set. It authenticates via its backend and
+/// keeps the sessions, mapping each token to the user it was issued to. As verifying a secret is
+/// slow, by design, the backend is called on the blocking pool, rather than stalling the executor
+/// running the machine, so the reply to Authenticate is sent from there.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::machine;
+///
+/// let backend = StaticBackend::new(&[("alice", "secret")]);
+/// let (_auth, auth_sender) = machine::create(AuthMachine::new(Box::new(backend)));
+/// let (sender, receiver) = smol::channel::unbounded::<AuthCmd>();
+/// smol::block_on(async {
+///     let credentials = Credentials::new("alice", "secret");
+///     auth_sender.send(AuthCmd::Authenticate(credentials, sender)).await.ok();
+///     match receiver.recv().await {
+///         Ok(AuthCmd::Authenticated(user, Ok(_token))) => assert_eq!("alice", user),
+///         cmd => panic!("unexpected reply {:?}", cmd),
+///     }
+/// });
+/// ```
+pub struct AuthMachine {
+    backend: Arc<dyn AuthBackend>,
+    sessions: Arc<Mutex<HashMap<AuthToken, String>>>,
+    audit: Option<AuditSender>,
+}
+
+impl fmt::Debug for AuthMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#AuthMachine {{ backend: {} }}", self.backend.get_name()) }
+}
+
+impl AuthMachine {
+    /// Create an auth machine using the backend for verifying credentials.
+    pub fn new(backend: Box<dyn AuthBackend>) -> Self {
+        Self {
+            backend: Arc::from(backend),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            audit: None,
+        }
+    }
+
+    /// Record authentication failures in the audit log.
+    pub fn with_audit(mut self, audit: AuditSender) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Get the number of active sessions.
+    pub fn get_session_count(&self) -> usize { self.lock_sessions().len() }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, HashMap<AuthToken, String>> {
+        self.sessions.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn validate(&self, token: &str) -> Result<String, AuthError> { self.lock_sessions().get(token).cloned().ok_or(AuthError::InvalidToken) }
+}
+
+// Authenticate the credentials, on the blocking pool, then record the failure, or the session, and
+// reply.
+async fn authenticate(
+    backend: Arc<dyn AuthBackend>, sessions: Arc<Mutex<HashMap<AuthToken, String>>>, audit: Option<AuditSender>, credentials: Credentials,
+    reply: AuthSender,
+) {
+    let verifier = backend.clone();
+    let (credentials, res) = spawn_blocking(move || {
+        let res = verifier.authenticate(&credentials);
+        (credentials, res)
+    })
+    .await;
+    let res = res.map(|user| {
+        let token = uuid::Uuid::new_v4().to_string();
+        sessions.lock().unwrap_or_else(|err| err.into_inner()).insert(token.clone(), user);
+        token
+    });
+    if let Err(err) = &res {
+        log::info!(
+            "authentication failed user={} backend={} error={}",
+            credentials.user,
+            backend.get_name(),
+            err
+        );
+        if let Some(audit) = &audit {
+            let event = AuditEvent::new(AuditKind::AuthFailure, &credentials.user)
+                .with_field("backend", backend.get_name())
+                .with_field("error", &err.to_string());
+            audit.send(AuditCmd::Record(event)).await.ok();
+        }
+    }
+    reply.send(AuthCmd::Authenticated(credentials.user, res)).await.ok();
+}
+
+impl Machine<AuthCmd> for AuthMachine {
+    fn receive(&self, cmd: AuthCmd, sender: &mut MachineSender) {
+        match cmd {
+            AuthCmd::Authenticate(credentials, reply) => {
+                let task = authenticate(self.backend.clone(), self.sessions.clone(), self.audit.clone(), credentials, reply);
+                get_executor().spawn(task).detach();
+            },
+            AuthCmd::Validate(token, reply) => {
+                let res = self.validate(&token);
+                sender.send(reply, AuthCmd::Validated(token, res));
+            },
+            AuthCmd::Revoke(token) => {
+                self.lock_sessions().remove(&token);
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+
+    #[test]
+    fn static_backend_parse() {
+        let hashed = StaticBackend::hash_secret("hunter2", 1000);
+        assert!(!hashed.contains("hunter2"));
+        let backend = StaticBackend::parse(&format!("# users\nalice:secret\n\nbob: {}\n", hashed)).unwrap();
+        assert!(backend.users.values().all(|hashed| !hashed.to_string().contains("secret")));
+        assert_eq!(Ok("alice".to_string()), backend.authenticate(&Credentials::new("alice", "secret")));
+        assert_eq!(Ok("bob".to_string()), backend.authenticate(&Credentials::new("bob", "hunter2")));
+        assert_eq!(
+            Err(AuthError::InvalidCredentials),
+            backend.authenticate(&Credentials::new("bob", "secret"))
+        );
+        // an unknown user is verified against a secret as well, and fails the same way
+        assert_eq!(
+            Err(AuthError::InvalidCredentials),
+            backend.authenticate(&Credentials::new("carol", ""))
+        );
+        assert!(StaticBackend::parse("alice").is_err());
+        assert!(StaticBackend::parse("alice:pbkdf2-sha256$1000$00").is_err());
+    }
+
+    #[test]
+    fn ldap_backend_unavailable() {
+        let backend = LdapBackend::new("ldap://localhost");
+        assert!(matches!(
+            backend.authenticate(&Credentials::new("alice", "secret")),
+            Err(AuthError::Unavailable(_))
+        ));
+    }
+
+    #[test]
+    fn authenticate_validate_revoke() {
+        let backend = StaticBackend::new(&[("alice", "secret")]);
+        let (auth, auth_sender) = machine::create(AuthMachine::new(Box::new(backend)));
+        let (sender, receiver) = smol::channel::unbounded::<AuthCmd>();
+        smol::block_on(async {
+            let credentials = Credentials::new("alice", "wrong");
+            auth_sender.send(AuthCmd::Authenticate(credentials, sender.clone())).await.ok();
+            match receiver.recv().await {
+                Ok(AuthCmd::Authenticated(_, res)) => assert_eq!(Err(AuthError::InvalidCredentials), res),
+                cmd => panic!("unexpected reply {:#?}", cmd),
+            }
+
+            let credentials = Credentials::new("alice", "secret");
+            auth_sender.send(AuthCmd::Authenticate(credentials, sender.clone())).await.ok();
+            let token = match receiver.recv().await {
+                Ok(AuthCmd::Authenticated(_, Ok(token))) => token,
+                cmd => panic!("unexpected reply {:#?}", cmd),
+            };
+            assert_eq!(1, auth.get_session_count());
+
+            auth_sender.send(AuthCmd::Validate(token.clone(), sender.clone())).await.ok();
+            match receiver.recv().await {
+                Ok(AuthCmd::Validated(_, res)) => assert_eq!(Ok("alice".to_string()), res),
+                cmd => panic!("unexpected reply {:#?}", cmd),
+            }
+
+            auth_sender.send(AuthCmd::Revoke(token.clone())).await.ok();
+            auth_sender.send(AuthCmd::Validate(token, sender.clone())).await.ok();
+            match receiver.recv().await {
+                Ok(AuthCmd::Validated(_, res)) => assert_eq!(Err(AuthError::InvalidToken), res),
+                cmd => panic!("unexpected reply {:#?}", cmd),
+            }
+        });
+    }
+}
@@ -0,0 +1,268 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex as SyncMutex,
+    time::{Duration, Instant},
+};
+
+/// Shorthand for a sender, that can be sent AutoscaleCmd instructions.
+pub type AutoscaleSender = channel::Sender<AutoscaleCmd>;
+
+/// The metric observe_services() reports for each service, its count of things to drain, which
+/// for a connection oriented service is its connection count.
+pub const CONNECTIONS_METRIC: &str = "connections";
+
+/// AutoscaleCmd is the instruction set for feeding service metrics to the Autoscaler.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum AutoscaleCmd {
+    /// Observe the value of a service's metric (service, metric, value).
+    Observe(String, String, f64),
+    /// Add a rule for a service.
+    AddRule(String, ScalingRule),
+}
+
+/// The direction in which a service should be scaled.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScalingDirection {
+    Up,
+    Down,
+}
+
+/// A ScalingRule sets the thresholds of a service's metric, beyond which the service is scaled.
+/// Rules are deserialized from the config of the service, such as:
+///
+/// ```json
+/// "autoscale": [ { "metric": "connections", "scale_up_above": 800, "scale_down_below": 100, "cooldown_secs": 60 } ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ScalingRule {
+    /// The name of the metric.
+    pub metric: String,
+    /// Scale up when the metric is above this, if present.
+    #[serde(default)]
+    pub scale_up_above: Option<f64>,
+    /// Scale down when the metric is below this, if present.
+    #[serde(default)]
+    pub scale_down_below: Option<f64>,
+    /// The number of seconds after scaling before the rule scales again.
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+impl ScalingRule {
+    // Get the direction in which a value says to scale, if any.
+    fn evaluate(&self, value: f64) -> Option<ScalingDirection> {
+        match (self.scale_up_above, self.scale_down_below) {
+            (Some(above), _) if value > above => Some(ScalingDirection::Up),
+            (_, Some(below)) if value < below => Some(ScalingDirection::Down),
+            _ => None,
+        }
+    }
+}
+
+/// A ScalingEvent is passed to the scaling actions when a rule's threshold is crossed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalingEvent {
+    pub service: String,
+    pub metric: String,
+    pub value: f64,
+    pub direction: ScalingDirection,
+}
+
+/// A ScalingAction does the scaling, such as growing a worker pool or calling out to an
+/// orchestrator.
+pub type ScalingAction = Box<dyn Fn(&ScalingEvent) + Send + Sync>;
+
+struct Rule {
+    rule: ScalingRule,
+    scaled_at: Option<Instant>,
+}
+
+/// The Autoscaler implements the AutoscaleCmd instruction set. It is a policy engine, which holds
+/// the scaling rules of each service, and invokes the registered actions when an observed metric
+/// crosses a rule's threshold. After scaling, a rule waits out its cooldown before scaling again,
+/// giving the action time to take effect.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+///
+/// let rule = ScalingRule {
+///     metric: "connections".to_string(),
+///     scale_up_above: Some(100.0),
+///     scale_down_below: None,
+///     cooldown_secs: 60,
+/// };
+/// let autoscaler = Autoscaler::new()
+///     .with_rule("echo-service", rule)
+///     .with_action(|event| println!("scale {} {:?}", event.service, event.direction));
+/// autoscaler.observe("echo-service", "connections", 120.0);
+/// ```
+#[derive(Default)]
+pub struct Autoscaler {
+    rules: SyncMutex<HashMap<String, Vec<Rule>>>,
+    // the actions, and the service they are limited to, if any
+    actions: Vec<(Option<String>, ScalingAction)>,
+}
+
+impl fmt::Debug for Autoscaler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#Autoscaler {{ actions: {} }}", self.actions.len()) }
+}
+
+impl Autoscaler {
+    /// Create an Autoscaler, without rules or actions.
+    pub fn new() -> Self { Self::default() }
+
+    /// Add a rule for a service.
+    pub fn with_rule(self, service: &str, rule: ScalingRule) -> Self {
+        self.add_rule(service, rule);
+        self
+    }
+
+    /// Add the rules for a service, typically from its config.
+    pub fn with_rules(self, service: &str, rules: &[ScalingRule]) -> Self {
+        for rule in rules {
+            self.add_rule(service, rule.clone());
+        }
+        self
+    }
+
+    /// Add an action, which is invoked whenever any service is to be scaled.
+    pub fn with_action<F>(mut self, action: F) -> Self
+    where
+        F: Fn(&ScalingEvent) + Send + Sync + 'static,
+    {
+        self.actions.push((None, Box::new(action)));
+        self
+    }
+
+    /// Add an action, which is invoked whenever the service is to be scaled.
+    pub fn with_service_action<F>(mut self, service: &str, action: F) -> Self
+    where
+        F: Fn(&ScalingEvent) + Send + Sync + 'static,
+    {
+        self.actions.push((Some(service.to_string()), Box::new(action)));
+        self
+    }
+
+    /// Add a rule for a service.
+    pub fn add_rule(&self, service: &str, rule: ScalingRule) {
+        let rule = Rule { rule, scaled_at: None };
+        self.lock_rules().entry(service.to_string()).or_default().push(rule);
+    }
+
+    /// Observe the value of a service's metric, invoking the actions for each rule whose
+    /// threshold it crosses.
+    pub fn observe(&self, service: &str, metric: &str, value: f64) {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        if let Some(rules) = self.lock_rules().get_mut(service) {
+            for rule in rules.iter_mut().filter(|r| r.rule.metric == metric) {
+                let cooldown = Duration::from_secs(rule.rule.cooldown_secs);
+                if matches!(rule.scaled_at, Some(at) if now.duration_since(at) < cooldown) {
+                    continue;
+                }
+                if let Some(direction) = rule.rule.evaluate(value) {
+                    rule.scaled_at = Some(now);
+                    events.push(ScalingEvent {
+                        service: service.to_string(),
+                        metric: metric.to_string(),
+                        value,
+                        direction,
+                    });
+                }
+            }
+        }
+        // the actions are invoked without the lock, so they may add rules
+        for event in &events {
+            log::info!(
+                "scaling service={} {:?}, metric={} value={}",
+                event.service,
+                event.direction,
+                event.metric,
+                event.value
+            );
+            for (only, action) in &self.actions {
+                match only {
+                    Some(only) if only != service => (),
+                    _ => action(event),
+                }
+            }
+        }
+    }
+
+    /// Observe the connections metric of each of the manager's services.
+    pub fn observe_services(&self, manager: &ServiceManager) {
+        for service in manager.get_services() {
+            self.observe(service.get_name(), CONNECTIONS_METRIC, service.get_drain_count() as f64);
+        }
+    }
+
+    fn lock_rules(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<Rule>>> {
+        self.rules.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Machine<AutoscaleCmd> for Autoscaler {
+    fn receive(&self, cmd: AutoscaleCmd, _sender: &mut MachineSender) {
+        match cmd {
+            AutoscaleCmd::Observe(service, metric, value) => self.observe(&service, &metric, value),
+            AutoscaleCmd::AddRule(service, rule) => self.add_rule(&service, rule),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn rule(cooldown_secs: u64) -> ScalingRule {
+        ScalingRule {
+            metric: CONNECTIONS_METRIC.to_string(),
+            scale_up_above: Some(10.0),
+            scale_down_below: Some(2.0),
+            cooldown_secs,
+        }
+    }
+
+    #[test]
+    fn scale_on_thresholds() {
+        let events = Arc::new(SyncMutex::new(Vec::new()));
+        let recorded = events.clone();
+        let autoscaler = Autoscaler::new()
+            .with_rule("echo", rule(0))
+            .with_rule("chat", rule(60))
+            .with_service_action("echo", move |event| {
+                recorded.lock().unwrap().push((event.service.clone(), event.direction))
+            });
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 5.0);
+        autoscaler.observe("echo", "latency", 50.0);
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 11.0);
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 1.0);
+        // chat scales, but the action is only for echo
+        autoscaler.observe("chat", CONNECTIONS_METRIC, 11.0);
+        let expected = vec![
+            ("echo".to_string(), ScalingDirection::Up),
+            ("echo".to_string(), ScalingDirection::Down),
+        ];
+        assert_eq!(expected, *events.lock().unwrap());
+    }
+
+    #[test]
+    fn cooldown() {
+        let count = Arc::new(SyncMutex::new(0));
+        let counted = count.clone();
+        let autoscaler = Autoscaler::new()
+            .with_rules("echo", &[rule(60)])
+            .with_action(move |_| *counted.lock().unwrap() += 1);
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 11.0);
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 20.0);
+        autoscaler.observe("echo", CONNECTIONS_METRIC, 0.0);
+        assert_eq!(1, *count.lock().unwrap());
+    }
+}
@@ -0,0 +1,293 @@
+use super::*;
+use crate::{
+    compression::{Codec, Compression},
+    network::NetCore,
+};
+use machine_foundation::{Machine, MachineSender};
+use std::{collections::HashMap, convert::TryInto, fmt, io, time::Duration};
+
+/// Shorthand for a sender, that can be sent BatchCmd instructions.
+pub type BatchSender = channel::Sender<BatchCmd>;
+
+// The size of the length which prefixes each frame of a batch.
+const LEN_SIZE: usize = 4;
+
+/// BatchCmd is the instruction set for batching the serialized instructions sent to other nodes.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum BatchCmd {
+    /// Add a serialized instruction to the batch for a node.
+    Send(String, Vec<u8>),
+    /// Deliver the batch of every node, which isn't empty.
+    Flush,
+    /// Get the stats, which are sent to the sender.
+    GetStats(channel::Sender<BatchStats>),
+}
+
+/// BatchStats counts the instructions batched, and the bytes delivered, before and after
+/// compression.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BatchStats {
+    /// The number of batches delivered.
+    pub batches: u64,
+    /// The number of instructions delivered.
+    pub frames: u64,
+    /// The number of bytes of the batches, before they were compressed.
+    pub bytes: u64,
+    /// The number of bytes delivered.
+    pub wire_bytes: u64,
+}
+
+// The pending batch for a node, and the codec compressing its stream of batches.
+#[derive(Default)]
+struct Batch {
+    bytes: Vec<u8>,
+    frames: u64,
+    codec: Option<Codec>,
+}
+
+type BatchSink = Box<dyn Fn(&str, Vec<u8>) + Send + Sync>;
+
+/// The BatchMachine implements the BatchCmd instruction set. It batches the serialized instructions
+/// sent to each node, so a chatty cross-node topology pays a write per batch, rather than per
+/// instruction. A batch is delivered to the sink once it reaches the maximum size, or on Flush,
+/// which start_flush_timer() sends every max delay. Each instruction is prefixed by its length. If
+/// compressed, the batches of a node are a single stream, which an Unbatcher at the node reverses.
+///
+/// Nothing sends through it yet. It is the layer for a bridge sending instructions to machines on
+/// other nodes, which doesn't exist, so the sink is whatever writes the batches to the node.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+///
+/// let (delivered, batches) = smol::channel::unbounded();
+/// let batcher = BatchMachine::new(move |node, batch| {
+///     delivered.try_send((node.to_string(), batch)).ok();
+/// })
+/// .with_compression(Compression::Zstd);
+/// let (_batcher, sender) = machine_foundation::machine::create(batcher);
+/// let _timer = BatchMachine::start_flush_timer(sender.clone(), std::time::Duration::from_millis(5));
+/// sender.try_send(BatchCmd::Send("node-2".to_string(), b"ping".to_vec())).ok();
+/// sender.try_send(BatchCmd::Send("node-2".to_string(), b"pong".to_vec())).ok();
+///
+/// let (node, batch) = smol::block_on(batches.recv()).unwrap();
+/// let frames = Unbatcher::new(Some(Compression::Zstd)).unwrap().unbatch(&batch).unwrap();
+/// assert_eq!(("node-2".to_string(), vec![b"ping".to_vec(), b"pong".to_vec()]), (node, frames));
+/// ```
+pub struct BatchMachine {
+    sink: BatchSink,
+    compression: Option<Compression>,
+    max_batch_size: usize,
+    batches: AtomicRefCell<HashMap<String, Batch>>,
+    stats: AtomicRefCell<BatchStats>,
+}
+
+impl fmt::Debug for BatchMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#BatchMachine {{ compression: {:?}, max_batch_size: {} }}",
+            self.compression, self.max_batch_size
+        )
+    }
+}
+
+impl BatchMachine {
+    /// Create a BatchMachine, which delivers the batch for a node to the sink, such as by sending it
+    /// on the node's connection.
+    pub fn new<F>(sink: F) -> Self
+    where
+        F: Fn(&str, Vec<u8>) + Send + Sync + 'static,
+    {
+        Self {
+            sink: Box::new(sink),
+            compression: None,
+            max_batch_size: 64 * 1024,
+            batches: AtomicRefCell::new(HashMap::new()),
+            stats: AtomicRefCell::new(BatchStats::default()),
+        }
+    }
+
+    /// Compress the batches, the default is not to.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Set the size a batch is delivered at, without waiting for a flush, the default is 64KiB.
+    pub fn with_max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = size;
+        self
+    }
+
+    /// Start a task which sends Flush every max delay, which is the longest an instruction waits in
+    /// a batch. The task runs until the returned BackgroundTask is cancelled or dropped.
+    pub fn start_flush_timer(sender: BatchSender, max_delay: Duration) -> BackgroundTask {
+        let task = get_executor().spawn(async move {
+            loop {
+                smol::Timer::after(max_delay).await;
+                if sender.send(BatchCmd::Flush).await.is_err() {
+                    break;
+                }
+            }
+        });
+        BackgroundTask::detach(task, "batch flush")
+    }
+
+    fn send(&self, node: String, frame: Vec<u8>) {
+        let mut batches = self.batches.borrow_mut();
+        let batch = batches.entry(node.clone()).or_default();
+        batch.bytes.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        batch.bytes.extend(frame);
+        batch.frames += 1;
+        if batch.bytes.len() >= self.max_batch_size {
+            self.deliver(&node, batch);
+        }
+    }
+
+    fn flush(&self) {
+        for (node, batch) in self.batches.borrow_mut().iter_mut() {
+            if batch.frames > 0 {
+                self.deliver(node, batch);
+            }
+        }
+    }
+
+    // Deliver the batch for a node to the sink, compressing it if need be.
+    fn deliver(&self, node: &str, batch: &mut Batch) {
+        let bytes = std::mem::take(&mut batch.bytes);
+        let len = bytes.len() as u64;
+        let frames = std::mem::take(&mut batch.frames);
+        if batch.codec.is_none() {
+            if let Some(compression) = self.compression {
+                match Codec::new(compression) {
+                    Ok(codec) => batch.codec = Some(codec),
+                    Err(err) => log::warn!("batch compression failed node={} error={}", node, err),
+                }
+            }
+        }
+        let wire = match batch.codec.as_mut().map(|codec| codec.compress(&bytes)) {
+            Some(Ok(compressed)) => compressed,
+            Some(Err(err)) => {
+                log::warn!("batch dropped, compression failed node={} frames={} error={}", node, frames, err);
+                return;
+            },
+            None => bytes,
+        };
+        let mut stats = self.stats.borrow_mut();
+        stats.batches += 1;
+        stats.frames += frames;
+        stats.bytes += len;
+        stats.wire_bytes += wire.len() as u64;
+        (self.sink)(node, wire);
+    }
+}
+
+impl Machine<BatchCmd> for BatchMachine {
+    fn receive(&self, cmd: BatchCmd, _sender: &mut MachineSender) {
+        match cmd {
+            BatchCmd::Send(node, frame) => self.send(node, frame),
+            BatchCmd::Flush => self.flush(),
+            BatchCmd::GetStats(reply) => {
+                reply.try_send(*self.stats.borrow()).ok();
+            },
+        }
+    }
+    fn disconnected(&self) { self.flush(); }
+}
+
+/// The Unbatcher splits the batches received from a node back into the serialized instructions,
+/// decompressing them if they were compressed. A decompressed batch may not exceed the maximum
+/// frame size of NetCore.
+pub struct Unbatcher {
+    codec: Option<Codec>,
+    pending: Vec<u8>,
+}
+
+impl fmt::Debug for Unbatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#Unbatcher {{ pending: {} }}", self.pending.len()) }
+}
+
+impl Unbatcher {
+    /// Create an Unbatcher, for batches compressed with the compression, if any.
+    pub fn new(compression: Option<Compression>) -> io::Result<Self> {
+        Ok(Self {
+            codec: compression.map(Codec::new).transpose()?,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Split a batch into its instructions. An instruction split across batches is returned once
+    /// the rest of it has been received.
+    pub fn unbatch(&mut self, batch: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        match self.codec.as_mut() {
+            Some(codec) => {
+                let bytes = codec.decompress(batch, NetCore::get_max_frame_size())?;
+                self.pending.extend(bytes);
+            },
+            None => self.pending.extend_from_slice(batch),
+        }
+        let mut frames = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= LEN_SIZE {
+            let len_bytes: [u8; LEN_SIZE] = self.pending[start .. start + LEN_SIZE].try_into().unwrap_or_default();
+            let end = start + LEN_SIZE + u32::from_be_bytes(len_bytes) as usize;
+            if end > self.pending.len() {
+                break;
+            }
+            frames.push(self.pending[start + LEN_SIZE .. end].to_vec());
+            start = end;
+        }
+        self.pending.drain(.. start);
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as SyncMutex;
+
+    #[test]
+    fn batch_per_node() {
+        let delivered = Arc::new(SyncMutex::new(Vec::new()));
+        let sink = delivered.clone();
+        let batcher = BatchMachine::new(move |node, batch| sink.lock().unwrap().push((node.to_string(), batch)))
+            .with_compression(Compression::Deflate)
+            .with_max_batch_size(64);
+        let mut sender = MachineSender::default();
+        for n in 0 .. 3 {
+            batcher.receive(BatchCmd::Send("a".to_string(), vec![n; 16]), &mut sender);
+        }
+        batcher.receive(BatchCmd::Send("b".to_string(), b"hello".to_vec()), &mut sender);
+        // nothing is delivered until a flush, or a batch is full
+        assert!(delivered.lock().unwrap().is_empty());
+        batcher.receive(BatchCmd::Send("a".to_string(), vec![3; 16]), &mut sender);
+        assert_eq!(1, delivered.lock().unwrap().len());
+        batcher.receive(BatchCmd::Flush, &mut sender);
+        batcher.receive(BatchCmd::Flush, &mut sender);
+
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(2, delivered.len());
+        let unbatch = |batch: &[u8]| Unbatcher::new(Some(Compression::Deflate)).unwrap().unbatch(batch).unwrap();
+        assert_eq!("a", delivered[0].0);
+        assert_eq!((0 .. 4).map(|n| vec![n; 16]).collect::<Vec<_>>(), unbatch(&delivered[0].1));
+        assert_eq!("b", delivered[1].0);
+        assert_eq!(vec![b"hello".to_vec()], unbatch(&delivered[1].1));
+
+        let (reply, replies) = channel::unbounded();
+        batcher.receive(BatchCmd::GetStats(reply), &mut sender);
+        let stats = replies.try_recv().unwrap();
+        assert_eq!((2, 5, 4 * 20 + 9), (stats.batches, stats.frames, stats.bytes));
+    }
+
+    #[test]
+    fn unbatch_split_frame() {
+        let mut unbatcher = Unbatcher::new(None).unwrap();
+        let batch = [&5u32.to_be_bytes()[..], b"hello", &3u32.to_be_bytes()[..], b"bye"].concat();
+        assert_eq!(vec![b"hello".to_vec()], unbatcher.unbatch(&batch[.. 11]).unwrap());
+        assert_eq!(vec![b"bye".to_vec()], unbatcher.unbatch(&batch[11 ..]).unwrap());
+        assert!(unbatcher.unbatch(&[]).unwrap().is_empty());
+    }
+}
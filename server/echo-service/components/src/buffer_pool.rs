@@ -0,0 +1,112 @@
+use crossbeam::{atomic::AtomicCell, queue::SegQueue};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The buffers which have been recycled, and are waiting to be taken.
+static POOL: Pool = Pool::new();
+
+#[allow(non_upper_case_globals)]
+// The number of buffers the pool keeps, beyond which recycled buffers are freed.
+static max_pooled: AtomicCell<usize> = AtomicCell::new(1024);
+
+#[allow(non_upper_case_globals)]
+// The capacity of the largest buffer the pool keeps.
+static max_buffer_capacity: AtomicCell<usize> = AtomicCell::new(64 * 1024);
+
+/// The BufferPool recycles the byte buffers carried by high-rate instructions, such as
+/// ConnCmd::RecvBytes and NetCmd::SendBytes. NetCore reads into a buffer taken from the pool,
+/// and recycles the buffer of a SendBytes once written, so a machine which echoes, or transforms
+/// in place, the bytes it receives completes the round trip without an allocation. A machine which
+/// consumes the bytes can recycle them itself.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::BufferPool;
+///
+/// let mut buffer = BufferPool::take();
+/// buffer.extend_from_slice(b"hello");
+/// BufferPool::recycle(buffer);
+/// assert!(BufferPool::take().is_empty());
+/// ```
+pub struct BufferPool;
+
+impl BufferPool {
+    /// Take an empty buffer from the pool, or allocate one if the pool is empty.
+    pub fn take() -> Vec<u8> { POOL.take() }
+
+    /// Recycle a buffer, clearing it, and returning it to the pool. It is freed instead if the pool
+    /// is full, or its capacity is too large, or too small, to be worth keeping.
+    pub fn recycle(buffer: Vec<u8>) { POOL.recycle(buffer, max_pooled.load(), max_buffer_capacity.load()) }
+
+    /// Set the number of buffers the pool keeps, returning the previous value. The default is 1024.
+    pub fn set_max_pooled(count: usize) -> usize { max_pooled.swap(count) }
+    /// Get the number of buffers the pool keeps.
+    pub fn get_max_pooled() -> usize { max_pooled.load() }
+
+    /// Set the capacity of the largest buffer the pool keeps, returning the previous value. The
+    /// default is 64KiB.
+    pub fn set_max_buffer_capacity(capacity: usize) -> usize { max_buffer_capacity.swap(capacity) }
+    /// Get the capacity of the largest buffer the pool keeps.
+    pub fn get_max_buffer_capacity() -> usize { max_buffer_capacity.load() }
+
+    /// Get the number of buffers in the pool.
+    pub fn get_pooled() -> usize { POOL.len.load(Ordering::Relaxed) }
+}
+
+// A queue of buffers, and its length.
+struct Pool {
+    buffers: SegQueue<Vec<u8>>,
+    len: AtomicUsize,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            buffers: SegQueue::new(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn take(&self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buffer) => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                buffer
+            },
+            None => Vec::new(),
+        }
+    }
+
+    fn recycle(&self, mut buffer: Vec<u8>, max_len: usize, max_capacity: usize) {
+        let capacity = buffer.capacity();
+        if capacity == 0 || capacity > max_capacity || self.len.load(Ordering::Relaxed) >= max_len {
+            return;
+        }
+        buffer.clear();
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.buffers.push(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycle_buffers() {
+        let pool = Pool::new();
+        let mut buffer = Vec::with_capacity(4096);
+        buffer.extend_from_slice(b"hello");
+        let ptr = buffer.as_ptr();
+        pool.recycle(buffer, 2, 4096);
+        let buffer = pool.take();
+        assert_eq!(ptr, buffer.as_ptr());
+        assert!(buffer.is_empty());
+        assert!(pool.take().capacity() == 0);
+        // too small, too large, or too many, aren't kept
+        pool.recycle(Vec::new(), 2, 4096);
+        pool.recycle(Vec::with_capacity(4097), 2, 4096);
+        (0 .. 3).for_each(|_| pool.recycle(Vec::with_capacity(16), 2, 4096));
+        assert_eq!(2, pool.len.load(Ordering::Relaxed));
+    }
+}
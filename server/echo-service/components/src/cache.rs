@@ -0,0 +1,331 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use server_core::{MachineBuilder, MachineImpl};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// CacheCmd is the instruction set of a Cache. Unlike most instruction sets, it is generic over
+/// the key and value, so MachineImpl is implemented by hand, rather than derived.
+#[derive(Debug, Clone)]
+pub enum CacheCmd<K, V> {
+    /// Get the value of a key, which is sent to the sender.
+    Get(K, channel::Sender<Option<V>>),
+    /// Put a value, which expires after the cache's TTL.
+    Put(K, V),
+    /// Put a value, which expires after a specific TTL.
+    PutWithTtl(K, V, Duration),
+    /// Remove a key.
+    Remove(K),
+    /// Purge expired values, and write dirty values behind. The sender, if any, is sent the number
+    /// of values written.
+    Flush(Option<channel::Sender<usize>>),
+}
+
+impl<K, V> MachineImpl for CacheCmd<K, V>
+where
+    K: 'static + Send + Sync + Clone,
+    V: 'static + Send + Sync + Clone,
+{
+    type Adapter = Self;
+    type InstructionSet = Self;
+
+    fn variant_name(&self) -> &'static str {
+        match self {
+            Self::Get(..) => "Get",
+            Self::Put(..) => "Put",
+            Self::PutWithTtl(..) => "PutWithTtl",
+            Self::Remove(..) => "Remove",
+            Self::Flush(..) => "Flush",
+        }
+    }
+}
+
+impl<K, V> MachineBuilder for CacheCmd<K, V>
+where
+    K: 'static + Send + Sync + Clone,
+    V: 'static + Send + Sync + Clone,
+{
+    type InstructionSet = Self;
+}
+
+/// Shorthand for a sender, that can be sent CacheCmd instructions.
+pub type CacheSender<K, V> = channel::Sender<CacheCmd<K, V>>;
+
+/// KvEncode converts a cache key or value into the bytes written to the KvStore.
+pub trait KvEncode {
+    fn to_kv_bytes(&self) -> KvBytes;
+}
+
+impl KvEncode for String {
+    fn to_kv_bytes(&self) -> KvBytes { self.as_bytes().to_vec() }
+}
+
+impl KvEncode for Vec<u8> {
+    fn to_kv_bytes(&self) -> KvBytes { self.clone() }
+}
+
+// Builds the KvCmd which writes a value behind, or deletes it if it's been removed.
+type WriteBehind<K, V> = Box<dyn Fn(&K, Option<&V>) -> KvCmd + Send + Sync>;
+
+struct CacheEntry<V> {
+    value: V,
+    expires: Option<Instant>,
+    tick: u64,
+}
+
+impl<V> CacheEntry<V> {
+    fn is_expired(&self, now: Instant) -> bool { matches!(self.expires, Some(expires) if expires <= now) }
+}
+
+struct CacheState<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    // least recently used first
+    lru: BTreeMap<u64, K>,
+    dirty: HashSet<K>,
+    tick: u64,
+}
+
+/// The Cache is a machine implementing the CacheCmd instruction set. Values expire after a TTL,
+/// and once the cache holds max_entries values, the least recently used is evicted. Optionally,
+/// values are written behind to a KvStore, when flushed, evicted or expired.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::machine;
+/// use std::time::Duration;
+///
+/// let cache: Cache<String, String> = Cache::new(1000).with_ttl(Duration::from_secs(60));
+/// let (_cache, sender) = machine::create::<CacheCmd<String, String>, _>(cache);
+/// sender.try_send(CacheCmd::Put("session:1".to_string(), "alice".to_string())).ok();
+/// let (reply, receiver) = smol::channel::bounded(1);
+/// sender.try_send(CacheCmd::Get("session:1".to_string(), reply)).ok();
+/// assert_eq!(Ok(Some("alice".to_string())), smol::block_on(receiver.recv()));
+/// ```
+pub struct Cache<K, V> {
+    state: AtomicRefCell<CacheState<K, V>>,
+    max_entries: usize,
+    ttl: Option<Duration>,
+    write_behind: Option<(KvSender, WriteBehind<K, V>)>,
+}
+
+impl<K, V> fmt::Debug for Cache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#Cache {{ max_entries: {}, ttl: {:?} }}", self.max_entries, self.ttl)
+    }
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: 'static + Send + Sync + Clone + Eq + Hash,
+    V: 'static + Send + Sync + Clone,
+{
+    /// Create a cache holding at most max_entries values, which don't expire.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            state: AtomicRefCell::new(CacheState {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                dirty: HashSet::new(),
+                tick: 0,
+            }),
+            max_entries: max_entries.max(1),
+            ttl: None,
+            write_behind: None,
+        }
+    }
+
+    /// Expire values after ttl.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Write values behind to a KvStore.
+    pub fn with_write_behind(mut self, kv_sender: KvSender) -> Self
+    where
+        K: KvEncode,
+        V: KvEncode,
+    {
+        let write_behind = |key: &K, value: Option<&V>| match value {
+            Some(value) => KvCmd::Put(key.to_kv_bytes(), value.to_kv_bytes(), None),
+            None => KvCmd::Delete(key.to_kv_bytes(), None),
+        };
+        self.write_behind = Some((kv_sender, Box::new(write_behind)));
+        self
+    }
+
+    /// Start a task which sends Flush every interval. The task runs until the returned BackgroundTask
+    /// is cancelled or dropped.
+    pub fn start_flush_timer(sender: CacheSender<K, V>, interval: Duration) -> BackgroundTask {
+        let task = get_executor().spawn(async move {
+            loop {
+                smol::Timer::after(interval).await;
+                if sender.send(CacheCmd::Flush(None)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        BackgroundTask::detach(task, "cache flush")
+    }
+
+    fn get(&self, key: &K, now: Instant) -> Option<V> {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let entry = state.entries.get_mut(key)?;
+        if entry.is_expired(now) {
+            return None;
+        }
+        state.tick += 1;
+        state.lru.remove(&entry.tick);
+        entry.tick = state.tick;
+        state.lru.insert(entry.tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    fn put(&self, key: K, value: V, ttl: Option<Duration>, now: Instant, sender: &mut MachineSender) {
+        let mut state = self.state.borrow_mut();
+        state.tick += 1;
+        let entry = CacheEntry {
+            value,
+            expires: ttl.map(|ttl| now + ttl),
+            tick: state.tick,
+        };
+        state.lru.insert(entry.tick, key.clone());
+        if self.write_behind.is_some() {
+            state.dirty.insert(key.clone());
+        }
+        if let Some(previous) = state.entries.insert(key, entry) {
+            state.lru.remove(&previous.tick);
+        }
+        while state.entries.len() > self.max_entries {
+            let (_, oldest) = state.lru.pop_first().expect("lru tracks every entry");
+            let entry = state.entries.remove(&oldest);
+            if state.dirty.remove(&oldest) {
+                self.write(&oldest, entry.as_ref().map(|entry| &entry.value), sender);
+            }
+        }
+    }
+
+    fn remove(&self, key: &K, sender: &mut MachineSender) {
+        let mut state = self.state.borrow_mut();
+        if let Some(entry) = state.entries.remove(key) {
+            state.lru.remove(&entry.tick);
+        }
+        state.dirty.remove(key);
+        self.write(key, None, sender);
+    }
+
+    // Purge the expired values, and write the dirty ones behind, returning the number written.
+    fn flush(&self, now: Instant, sender: &mut MachineSender) -> usize {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let mut written = 0;
+        for key in state.dirty.drain() {
+            if let Some(entry) = state.entries.get(&key) {
+                self.write(&key, Some(&entry.value), sender);
+                written += 1;
+            }
+        }
+        let expired: Vec<K> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(entry) = state.entries.remove(&key) {
+                state.lru.remove(&entry.tick);
+            }
+        }
+        written
+    }
+
+    fn write(&self, key: &K, value: Option<&V>, sender: &mut MachineSender) {
+        if let Some((kv_sender, write_behind)) = &self.write_behind {
+            sender.send(kv_sender.clone(), write_behind(key, value));
+        }
+    }
+}
+
+impl<K, V> Machine<CacheCmd<K, V>> for Cache<K, V>
+where
+    K: 'static + Send + Sync + Clone + Eq + Hash,
+    V: 'static + Send + Sync + Clone,
+{
+    fn receive(&self, cmd: CacheCmd<K, V>, sender: &mut MachineSender) {
+        let now = Instant::now();
+        match cmd {
+            CacheCmd::Get(key, reply) => {
+                reply.try_send(self.get(&key, now)).ok();
+            },
+            CacheCmd::Put(key, value) => self.put(key, value, self.ttl, now, sender),
+            CacheCmd::PutWithTtl(key, value, ttl) => self.put(key, value, Some(ttl), now, sender),
+            CacheCmd::Remove(key) => self.remove(&key, sender),
+            CacheCmd::Flush(reply) => {
+                let written = self.flush(now, sender);
+                if let Some(reply) = reply {
+                    reply.try_send(written).ok();
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+
+    fn get(sender: &CacheSender<String, String>, key: &str) -> Option<String> {
+        let (reply, receiver) = channel::bounded(1);
+        sender.try_send(CacheCmd::Get(key.to_string(), reply)).ok();
+        smol::block_on(receiver.recv()).unwrap()
+    }
+
+    #[test]
+    fn lru_and_ttl() {
+        let cache: Cache<String, String> = Cache::new(2);
+        let (_cache, sender) = machine::create_unbounded::<CacheCmd<String, String>, _>(cache);
+        sender.try_send(CacheCmd::Put("a".to_string(), "1".to_string())).ok();
+        sender.try_send(CacheCmd::Put("b".to_string(), "2".to_string())).ok();
+        // using a makes b the least recently used
+        assert_eq!(Some("1".to_string()), get(&sender, "a"));
+        sender.try_send(CacheCmd::Put("c".to_string(), "3".to_string())).ok();
+        assert_eq!(None, get(&sender, "b"));
+        assert_eq!(Some("3".to_string()), get(&sender, "c"));
+
+        sender
+            .try_send(CacheCmd::PutWithTtl("d".to_string(), "4".to_string(), Duration::from_millis(10)))
+            .ok();
+        assert_eq!(Some("4".to_string()), get(&sender, "d"));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(None, get(&sender, "d"));
+    }
+
+    #[test]
+    fn write_behind() {
+        let path = std::env::temp_dir().join(format!("cache-{}.log", uuid::Uuid::new_v4()));
+        let (_store, kv_sender) = machine::create_unbounded(KvStore::open(&path).unwrap());
+        let cache: Cache<String, String> = Cache::new(1).with_write_behind(kv_sender.clone());
+        let (_cache, sender) = machine::create_unbounded::<CacheCmd<String, String>, _>(cache);
+        sender.try_send(CacheCmd::Put("a".to_string(), "1".to_string())).ok();
+        // evicting a writes it behind
+        sender.try_send(CacheCmd::Put("b".to_string(), "2".to_string())).ok();
+        let (reply, receiver) = channel::bounded(1);
+        sender.try_send(CacheCmd::Flush(Some(reply))).ok();
+        assert_eq!(Ok(1), smol::block_on(receiver.recv()));
+
+        std::thread::sleep(Duration::from_millis(20));
+        let (reply, receiver) = channel::bounded(1);
+        kv_sender.try_send(KvCmd::Scan(Vec::new(), reply)).ok();
+        let expected = vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())];
+        assert_eq!(Ok(expected), smol::block_on(receiver.recv()));
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,216 @@
+use crate::net_instructionset::FrameError;
+use serde::Deserialize;
+use std::io::{self, Write};
+
+// The most bytes output by the decoder at a time, which is also bounded by the bytes left below the
+// limit, so the decompressed bytes never exceed the limit by more than a byte.
+const DECOMPRESS_CHUNK: usize = 16 * 1024;
+
+/// Compression selects the algorithm which compresses the bytes of a connection on the wire. It is
+/// enabled for a connection via NetCmd::SetCompression, once both ends have agreed upon it, such
+/// as by negotiation in the service's protocol. From then, SendBytes is compressed before being
+/// written, and RecvBytes decompressed after being read, so the machine bound to the connection
+/// sees only the uncompressed bytes. Each SendBytes is flushed, so the peer can decompress it
+/// without waiting for more.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Raw deflate, without a zlib or gzip header.
+    Deflate,
+    /// A zstd stream.
+    Zstd,
+}
+
+/// CompressionStats counts the bytes of a compressed connection, before and after compression.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct CompressionStats {
+    /// The number of bytes sent, before they were compressed.
+    pub sent_bytes: u64,
+    /// The number of bytes written to the wire.
+    pub sent_wire_bytes: u64,
+    /// The number of bytes read from the wire.
+    pub recv_wire_bytes: u64,
+    /// The number of bytes received, once decompressed.
+    pub recv_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Get the ratio of the bytes sent to those written to the wire, 1.0 if none have been sent.
+    pub fn get_send_ratio(&self) -> f64 { ratio(self.sent_bytes, self.sent_wire_bytes) }
+    /// Get the ratio of the bytes received to those read from the wire, 1.0 if none have been read.
+    pub fn get_recv_ratio(&self) -> f64 { ratio(self.recv_bytes, self.recv_wire_bytes) }
+}
+
+fn ratio(bytes: u64, wire_bytes: u64) -> f64 {
+    match wire_bytes {
+        0 => 1.0,
+        _ => bytes as f64 / wire_bytes as f64,
+    }
+}
+
+enum Encoder {
+    Deflate(flate2::write::DeflateEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+enum Decoder {
+    Deflate(flate2::Decompress),
+    Zstd(zstd::stream::raw::Decoder<'static>),
+}
+
+impl Decoder {
+    // Decompress from input into output, returning the number of bytes read, and written. The
+    // output is bounded by the size of output, the rest being kept by the decoder until it is
+    // called again.
+    fn run(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)> {
+        match self {
+            Self::Deflate(decoder) => {
+                let (total_in, total_out) = (decoder.total_in(), decoder.total_out());
+                decoder
+                    .decompress(input, output, flate2::FlushDecompress::None)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Ok(((decoder.total_in() - total_in) as usize, (decoder.total_out() - total_out) as usize))
+            },
+            Self::Zstd(decoder) => {
+                use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+                let mut input = InBuffer::around(input);
+                let mut output = OutBuffer::around(output);
+                decoder.run(&mut input, &mut output)?;
+                Ok((input.pos(), output.pos()))
+            },
+        }
+    }
+}
+
+// Write the bytes, flush, and take what has been output.
+fn pump<W: Write>(writer: &mut W, bytes: &[u8], output: impl Fn(&mut W) -> &mut Vec<u8>) -> io::Result<Vec<u8>> {
+    writer.write_all(bytes)?;
+    writer.flush()?;
+    Ok(std::mem::take(output(writer)))
+}
+
+// The Codec of a compressed connection, which compresses the bytes sent, and decompresses those
+// received, as streams, along with the counts of bytes.
+pub(crate) struct Codec {
+    compression: Compression,
+    encoder: Encoder,
+    decoder: Decoder,
+    stats: CompressionStats,
+}
+
+impl std::fmt::Debug for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#Codec {{ compression: {:?}, stats: {:?} }}", self.compression, self.stats)
+    }
+}
+
+impl Codec {
+    pub(crate) fn new(compression: Compression) -> io::Result<Self> {
+        let (encoder, decoder) = match compression {
+            Compression::Deflate => (
+                Encoder::Deflate(flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default())),
+                Decoder::Deflate(flate2::Decompress::new(false)),
+            ),
+            Compression::Zstd => (
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 0)?),
+                Decoder::Zstd(zstd::stream::raw::Decoder::new()?),
+            ),
+        };
+        Ok(Self {
+            compression,
+            encoder,
+            decoder,
+            stats: CompressionStats::default(),
+        })
+    }
+
+    // Compress bytes to be sent.
+    pub(crate) fn compress(&mut self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let compressed = match &mut self.encoder {
+            Encoder::Deflate(encoder) => pump(encoder, bytes, |e| e.get_mut())?,
+            Encoder::Zstd(encoder) => pump(encoder, bytes, |e| e.get_mut())?,
+        };
+        self.stats.sent_bytes += bytes.len() as u64;
+        self.stats.sent_wire_bytes += compressed.len() as u64;
+        Ok(compressed)
+    }
+
+    // Decompress bytes received, which may be empty until more have been received. Should the
+    // output exceed max, unless it is 0, the error is a FrameError, once a byte more than max has
+    // been decompressed, rather than decompressing it all.
+    pub(crate) fn decompress(&mut self, bytes: &[u8], max: usize) -> io::Result<Vec<u8>> {
+        let limit = if max > 0 { max + 1 } else { usize::MAX };
+        let mut decompressed = Vec::new();
+        let mut output = vec![0u8; DECOMPRESS_CHUNK.min(limit)];
+        let mut input = bytes;
+        loop {
+            let room = output.len().min(limit - decompressed.len());
+            let (read, written) = self.decoder.run(input, &mut output[.. room])?;
+            decompressed.extend_from_slice(&output[.. written]);
+            input = &input[read ..];
+            if decompressed.len() == limit {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, FrameError::RecvTooLarge { max }));
+            }
+            // done once the input is consumed, and the decoder has no more output, or is stuck
+            if (input.is_empty() && written < room) || (read == 0 && written == 0) {
+                break;
+            }
+        }
+        self.stats.recv_wire_bytes += bytes.len() as u64;
+        self.stats.recv_bytes += decompressed.len() as u64;
+        Ok(decompressed)
+    }
+
+    pub(crate) fn get_stats(&self) -> CompressionStats { self.stats }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_streams() {
+        let text = b"hello world, hello world, hello world, hello world, hello world".to_vec();
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            let mut local = Codec::new(*compression).unwrap();
+            let mut remote = Codec::new(*compression).unwrap();
+            for _ in 0 .. 3 {
+                // each send is decompressed without waiting for more
+                let wire = local.compress(&text).unwrap();
+                assert_eq!(text, remote.decompress(&wire, 0).unwrap());
+            }
+            let stats = local.get_stats();
+            assert_eq!(3 * text.len() as u64, stats.sent_bytes);
+            assert!(stats.get_send_ratio() > 1.0, "{:?} ratio={}", compression, stats.get_send_ratio());
+            assert_eq!(stats.sent_wire_bytes, remote.get_stats().recv_wire_bytes);
+            assert_eq!(1.0, local.get_stats().get_recv_ratio());
+        }
+    }
+
+    #[test]
+    fn limit_decompression() {
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            let mut local = Codec::new(*compression).unwrap();
+            let mut remote = Codec::new(*compression).unwrap();
+            // a few compressed bytes may decompress to many
+            let wire = local.compress(&vec![0u8; 1 << 20]).unwrap();
+            assert!(wire.len() < 4096, "{:?} len={}", compression, wire.len());
+            let err = remote.decompress(&wire, 4096).unwrap_err();
+            let err = err.get_ref().and_then(|err| err.downcast_ref::<FrameError>());
+            assert_eq!(Some(&FrameError::RecvTooLarge { max: 4096 }), err);
+        }
+    }
+
+    #[test]
+    fn decompress_to_limit() {
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            let mut local = Codec::new(*compression).unwrap();
+            let mut remote = Codec::new(*compression).unwrap();
+            // exactly max bytes is within the limit
+            let wire = local.compress(&vec![7u8; 4096]).unwrap();
+            assert_eq!(vec![7u8; 4096], remote.decompress(&wire, 4096).unwrap());
+            let wire = local.compress(&vec![7u8; 4097]).unwrap();
+            assert!(remote.decompress(&wire, 4096).is_err());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use machine_foundation::{Machine, MachineSender};
+use std::{fmt, sync::Mutex as SyncMutex};
+
+/// A Controller owns the state of a service, or part of it. Rather than sharing the state behind
+/// an Arc<Mutex<..>>, the controller is driven by a ControllerMachine, and all access to the
+/// state is via instructions. A controller can handle several instruction sets, queries carry
+/// the sender which receives the answer.
+pub trait Controller<I>: Send {
+    /// Handle an instruction, with exclusive access to the controller.
+    fn handle(&mut self, cmd: I, sender: &mut MachineSender);
+    /// Called when the machine is no longer receiving the instruction set.
+    fn disconnected(&mut self) {}
+}
+
+/// The ControllerMachine is a machine which exclusively owns a controller, passing each instruction
+/// it receives to the controller. Instructions of a single instruction set are received one at a
+/// time. When extended with additional instruction sets, those are serialized with an internal lock,
+/// which is never visible to the controller, and is uncontended for a single instruction set.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::{machine, MachineSender};
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     count: usize,
+/// }
+/// impl Controller<ListenerCmd> for Counter {
+///     fn handle(&mut self, cmd: ListenerCmd, _sender: &mut MachineSender) {
+///         if let ListenerCmd::NewConn(..) = cmd {
+///             self.count += 1;
+///         }
+///     }
+/// }
+///
+/// let (_counter, sender) = machine::create::<ListenerCmd, _>(ControllerMachine::new(Counter::default()));
+/// sender.try_send(ListenerCmd::NewConn(1, "127.0.0.1:8080".to_string(), "127.0.0.1:5000".to_string())).ok();
+/// ```
+pub struct ControllerMachine<C> {
+    controller: SyncMutex<C>,
+}
+
+impl<C> fmt::Debug for ControllerMachine<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#ControllerMachine {{ .. }}") }
+}
+
+impl<C> ControllerMachine<C> {
+    /// Create a machine which takes ownership of the controller.
+    pub fn new(controller: C) -> Self {
+        Self {
+            controller: SyncMutex::new(controller),
+        }
+    }
+}
+
+impl<C, I> Machine<I> for ControllerMachine<C>
+where
+    C: 'static + Controller<I>,
+    I: 'static + Send + Sync,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) {
+        let mut controller = self.controller.lock().unwrap_or_else(|err| err.into_inner());
+        controller.handle(cmd, sender);
+    }
+    fn disconnected(&self) {
+        let mut controller = self.controller.lock().unwrap_or_else(|err| err.into_inner());
+        controller.disconnected();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListenerCmd;
+    use machine_foundation::machine;
+    use machine_impl::MachineImpl;
+    use smol::channel;
+
+    // A query, carrying the sender which is sent the answer.
+    #[derive(Debug, Clone, MachineImpl)]
+    enum CountQuery {
+        Get(channel::Sender<usize>),
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        count: usize,
+    }
+    impl Controller<ListenerCmd> for Counter {
+        fn handle(&mut self, cmd: ListenerCmd, _sender: &mut MachineSender) {
+            match cmd {
+                ListenerCmd::NewConn(..) => self.count += 1,
+                ListenerCmd::CloseConn(..) => self.count -= 1,
+                _ => (),
+            }
+        }
+    }
+    impl Controller<CountQuery> for Counter {
+        fn handle(&mut self, cmd: CountQuery, _sender: &mut MachineSender) {
+            match cmd {
+                CountQuery::Get(reply) => {
+                    reply.try_send(self.count).ok();
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn controller_instruction_sets() {
+        let (counter, listener_sender) = machine::create_unbounded::<ListenerCmd, _>(ControllerMachine::new(Counter::default()));
+        let query_sender = machine::extend::<CountQuery, _>(&counter);
+        for conn_id in 0 .. 3 {
+            listener_sender
+                .try_send(ListenerCmd::NewConn(conn_id, String::new(), String::new()))
+                .ok();
+        }
+        listener_sender.try_send(ListenerCmd::CloseConn(0, None)).ok();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let (sender, receiver) = channel::unbounded();
+        query_sender.try_send(CountQuery::Get(sender)).ok();
+        assert_eq!(Ok(2), smol::block_on(receiver.recv()));
+    }
+}
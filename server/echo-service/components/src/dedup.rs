@@ -0,0 +1,198 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    hash::Hash,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// The default number of ids remembered.
+const DEFAULT_WINDOW: usize = 10_000;
+
+// The ids seen, oldest first.
+struct Window<K> {
+    ids: HashSet<K>,
+    order: VecDeque<(K, Instant)>,
+}
+
+impl<K: Hash + Eq> Window<K> {
+    fn forget_oldest(&mut self) {
+        if let Some((oldest, _)) = self.order.pop_front() {
+            self.ids.remove(&oldest);
+        }
+    }
+}
+
+/// The Deduplicator wraps a machine, dropping instructions whose id has been seen within a sliding
+/// window, so that at-least-once delivery doesn't cause duplicate side effects. The id of an
+/// instruction is provided by the caller, an instruction without an id is always received. The
+/// window holds the most recent ids, up to a count and, optionally, for a time-to-live.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::{machine, Machine, MachineSender};
+///
+/// #[derive(Debug, Clone, machine_impl::MachineImpl)]
+/// pub enum Delivery {
+///     Message(u64, String),
+/// }
+///
+/// struct Mailbox;
+/// impl Machine<Delivery> for Mailbox {
+///     fn receive(&self, _cmd: Delivery, _sender: &mut MachineSender) {}
+/// }
+///
+/// let mailbox = Deduplicator::new(Mailbox, |cmd: &Delivery| match cmd {
+///     Delivery::Message(id, _) => Some(*id),
+/// })
+/// .with_window(1000);
+/// let (_mailbox, sender) = machine::create(mailbox);
+/// sender.try_send(Delivery::Message(1, "hello".to_string())).ok();
+/// // a redelivery is dropped
+/// sender.try_send(Delivery::Message(1, "hello".to_string())).ok();
+/// ```
+pub struct Deduplicator<M, F, K> {
+    machine: M,
+    id_of: F,
+    window: AtomicRefCell<Window<K>>,
+    capacity: usize,
+    ttl: Option<Duration>,
+    duplicates: AtomicUsize,
+}
+
+impl<M, F, K> fmt::Debug for Deduplicator<M, F, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#Deduplicator {{ capacity: {}, ttl: {:?} }}", self.capacity, self.ttl)
+    }
+}
+
+impl<M, F, K: Hash + Eq + Clone> Deduplicator<M, F, K> {
+    /// Wrap a machine, id_of provides the id of an instruction.
+    pub fn new(machine: M, id_of: F) -> Self {
+        Self {
+            machine,
+            id_of,
+            window: AtomicRefCell::new(Window {
+                ids: HashSet::new(),
+                order: VecDeque::new(),
+            }),
+            capacity: DEFAULT_WINDOW,
+            ttl: None,
+            duplicates: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set the number of ids remembered, the default is 10,000.
+    pub fn with_window(mut self, count: usize) -> Self {
+        self.capacity = count.max(1);
+        self
+    }
+
+    /// Forget ids once they've been remembered for ttl.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Get the wrapped machine.
+    pub fn get_machine(&self) -> &M { &self.machine }
+
+    /// Get the number of duplicate instructions dropped.
+    pub fn get_duplicate_count(&self) -> usize { self.duplicates.load(Ordering::SeqCst) }
+
+    // Remember an id, returning false if it is a duplicate.
+    fn remember(&self, id: K, now: Instant) -> bool {
+        let mut window = self.window.borrow_mut();
+        if let Some(ttl) = self.ttl {
+            while matches!(window.order.front(), Some((_, at)) if now.duration_since(*at) >= ttl) {
+                window.forget_oldest();
+            }
+        }
+        if window.ids.contains(&id) {
+            return false;
+        }
+        if window.order.len() >= self.capacity {
+            window.forget_oldest();
+        }
+        window.ids.insert(id.clone());
+        window.order.push_back((id, now));
+        true
+    }
+}
+
+impl<M, F, K, I> Machine<I> for Deduplicator<M, F, K>
+where
+    M: Machine<I>,
+    F: Fn(&I) -> Option<K> + Send + Sync,
+    K: Hash + Eq + Clone + Send + Sync,
+    I: 'static + Send + Sync,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) {
+        if let Some(id) = (self.id_of)(&cmd) {
+            if !self.remember(id, Instant::now()) {
+                self.duplicates.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+        self.machine.receive(cmd, sender);
+    }
+    fn disconnected(&self) { self.machine.disconnected(); }
+    fn weight(&self) -> usize { self.machine.weight() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+
+    #[derive(Debug, Clone, MachineImpl)]
+    enum Delivery {
+        Message(u64),
+        Ping,
+    }
+
+    #[derive(Default)]
+    struct Mailbox {
+        received: AtomicUsize,
+    }
+    impl Machine<Delivery> for Mailbox {
+        fn receive(&self, _cmd: Delivery, _sender: &mut MachineSender) { self.received.fetch_add(1, Ordering::SeqCst); }
+    }
+
+    fn id_of(cmd: &Delivery) -> Option<u64> {
+        match cmd {
+            Delivery::Message(id) => Some(*id),
+            Delivery::Ping => None,
+        }
+    }
+
+    #[test]
+    fn drop_duplicates() {
+        let mailbox = Deduplicator::new(Mailbox::default(), id_of).with_window(2);
+        let (mailbox, sender) = machine::create_unbounded(mailbox);
+        // 1 falls out of the window once 2 and 3 are seen
+        for cmd in vec![1, 1, 2, 1, 3, 3, 1]
+            .into_iter()
+            .map(Delivery::Message)
+            .chain(vec![Delivery::Ping, Delivery::Ping])
+        {
+            sender.try_send(cmd).ok();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(6, mailbox.get_machine().received.load(Ordering::SeqCst));
+        assert_eq!(3, mailbox.get_duplicate_count());
+    }
+
+    #[test]
+    fn window_ttl() {
+        let mailbox = Deduplicator::new(Mailbox::default(), id_of).with_ttl(Duration::from_millis(10));
+        let now = Instant::now();
+        assert!(mailbox.remember(1, now));
+        assert!(!mailbox.remember(1, now + Duration::from_millis(5)));
+        assert!(mailbox.remember(1, now + Duration::from_millis(20)));
+    }
+}
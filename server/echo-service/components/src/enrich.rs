@@ -0,0 +1,70 @@
+use super::*;
+use async_trait::async_trait;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+/// A ConnEnricher is invoked when a TCP listener accepts a connection, before the listener is told
+/// of it. The data it returns, if any, is attached to the connection, as if by SetConnData, so
+/// policy data, such as a GeoIP lookup, is kept out of every service's controller. The listener
+/// goes on accepting while a connection is enriched. It is set for a listener via
+/// NetCmd::SetEnricher.
+#[async_trait]
+pub trait ConnEnricher: fmt::Debug + Send + Sync {
+    /// Enrich the connection accepted from remote, by the listener bound to local.
+    async fn enrich(&self, local: &str, remote: SocketAddr) -> Option<ConnData>;
+}
+
+/// AddressClass classifies the client of a connection as internal, or external.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AddressClass {
+    /// A loopback, private, or link local address.
+    Internal,
+    /// Any other address.
+    External,
+}
+
+impl From<IpAddr> for AddressClass {
+    fn from(ip: IpAddr) -> Self {
+        let internal = match ip {
+            IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+            // unique local, fc00::/7, and link local, fe80::/10
+            IpAddr::V6(ip) => ip.is_loopback() || ip.segments()[0] & 0xfe00 == 0xfc00 || ip.segments()[0] & 0xffc0 == 0xfe80,
+        };
+        if internal {
+            Self::Internal
+        } else {
+            Self::External
+        }
+    }
+}
+
+/// The AddressClassifier enriches each connection with the AddressClass of its client.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AddressClassifier;
+
+#[async_trait]
+impl ConnEnricher for AddressClassifier {
+    async fn enrich(&self, _local: &str, remote: SocketAddr) -> Option<ConnData> { Some(Arc::new(AddressClass::from(remote.ip()))) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_addresses() {
+        let class = |ip: &str| AddressClass::from(ip.parse::<IpAddr>().unwrap());
+        assert_eq!(AddressClass::Internal, class("127.0.0.1"));
+        assert_eq!(AddressClass::Internal, class("10.1.2.3"));
+        assert_eq!(AddressClass::Internal, class("192.168.0.1"));
+        assert_eq!(AddressClass::Internal, class("::1"));
+        assert_eq!(AddressClass::Internal, class("fd12::1"));
+        assert_eq!(AddressClass::External, class("8.8.8.8"));
+        assert_eq!(AddressClass::External, class("2001:4860::8888"));
+
+        let data = smol::block_on(AddressClassifier.enrich("127.0.0.1:80", "1.1.1.1:1000".parse().unwrap())).unwrap();
+        assert_eq!(Some(&AddressClass::External), data.downcast_ref::<AddressClass>());
+    }
+}
@@ -0,0 +1,326 @@
+use super::*;
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{Shutdown, TcpStream},
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// How long to wait before peeking again, when the key isn't yet complete.
+const PEEK_INTERVAL: Duration = Duration::from_millis(5);
+
+// The bytes a front door sends ahead of a client it proxies, so that the front door of the node
+// it is proxied to handles the client, rather than proxying it again.
+pub(crate) const HOP_MARKER: &[u8] = b"\0front-door-hop\0";
+
+// What was peeked from a client, the key it was extracted, or the marker of a client proxied.
+enum Peeked {
+    Key(Option<String>),
+    Proxied,
+}
+
+/// A NodeRouter maps the key of a client to the node which owns it, for a partitioned service,
+/// such as sharded chat rooms. It is consulted by a FrontDoor for each connection accepted.
+pub trait NodeRouter: fmt::Debug + Send + Sync {
+    /// Get the address of the node which owns the key, None if it is owned by this node.
+    fn route(&self, key: &str) -> Option<String>;
+}
+
+// The FNV-1a hash, finished by the mix of splitmix64, so that similar keys are spread around the
+// ring. It is the same on every node, unlike the std hasher, which isn't promised to be across
+// builds.
+fn hash(bytes: &[u8]) -> u64 {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// The HashRing is a NodeRouter, which consistently hashes keys onto the nodes of the cluster, each
+/// node being the address its front door proxies to. Each node is placed on the ring a number of
+/// times, so that the keys are spread evenly, and when a node is added, or removed, only the keys
+/// it owns move.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+///
+/// let mut ring = HashRing::new("10.0.0.1:4000");
+/// ring.add_node("10.0.0.2:4000");
+/// let owner = ring.get_owner("room-42").to_string();
+/// ring.add_node("10.0.0.3:4000");
+/// assert!(ring.get_owner("room-42") == owner || ring.get_owner("room-42") == "10.0.0.3:4000");
+/// ```
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    local: String,
+    replicas: usize,
+    ring: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    /// Create a ring, with the address of this node upon it.
+    pub fn new(local: &str) -> Self {
+        let mut ring = Self {
+            local: local.to_string(),
+            replicas: 64,
+            ring: BTreeMap::new(),
+        };
+        ring.add_node(local);
+        ring
+    }
+
+    /// Set the number of times each node is placed on the ring, the default is 64.
+    pub fn with_replicas(mut self, replicas: usize) -> Self {
+        let nodes = self.get_nodes();
+        self.replicas = replicas.max(1);
+        self.ring.clear();
+        for node in &nodes {
+            self.add_node(node);
+        }
+        self
+    }
+
+    /// Add a node to the ring.
+    pub fn add_node(&mut self, node: &str) {
+        for replica in 0 .. self.replicas {
+            self.ring.insert(hash(format!("{}#{}", node, replica).as_bytes()), node.to_string());
+        }
+    }
+
+    /// Remove a node from the ring, such as when it has died.
+    pub fn remove_node(&mut self, node: &str) { self.ring.retain(|_, owner| owner != node); }
+
+    /// Get the nodes on the ring, ordered by address.
+    pub fn get_nodes(&self) -> Vec<String> {
+        let mut nodes: Vec<String> = self.ring.values().cloned().collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+
+    /// Get the node which owns the key, this node if the ring is otherwise empty.
+    pub fn get_owner(&self, key: &str) -> &str {
+        let point = hash(key.as_bytes());
+        self.ring
+            .range(point ..)
+            .chain(self.ring.iter())
+            .next()
+            .map_or(&self.local, |(_, node)| node)
+    }
+}
+
+impl NodeRouter for HashRing {
+    fn route(&self, key: &str) -> Option<String> {
+        let owner = self.get_owner(key);
+        if owner == self.local {
+            None
+        } else {
+            Some(owner.to_string())
+        }
+    }
+}
+
+/// FrontDoorStats counts the connections accepted by a front door, by where they were handled.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct FrontDoorStats {
+    /// The number of connections handled by this node.
+    pub local: u64,
+    /// The number of connections proxied to the node owning them.
+    pub proxied: u64,
+    /// The number of connections closed, as the node owning them couldn't be connected to.
+    pub failed: u64,
+}
+
+type ClientKey = Box<dyn Fn(SocketAddr, &[u8]) -> Option<String> + Send + Sync>;
+
+/// The FrontDoor puts a TCP listener in cluster front door mode, set via NetCmd::SetFrontDoor. For
+/// each connection accepted, the key of the client is extracted by a hook, from its address, and
+/// the first bytes it sent, which are peeked, rather than read. The bytes are peeked again, as more
+/// arrive, until the hook extracts a key, the peek size is reached, or the peek timeout passes. The
+/// router is consulted with the key, and the connection is either handled by this node, its
+/// listener being told of it, or is proxied to the node which owns the key, the listener not being
+/// told of it. A client without a key, such as one which sent nothing within the peek timeout, is
+/// handled by this node. The proxies run in the network's task group, so are stopped along with it.
+/// A client proxied is marked, so that the node it is proxied to handles it, even when the routers
+/// of the nodes disagree, rather than proxying it back and forth.
+pub struct FrontDoor {
+    router: Arc<dyn NodeRouter>,
+    key: ClientKey,
+    peek_size: usize,
+    peek_timeout: Duration,
+    local: AtomicU64,
+    proxied: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl fmt::Debug for FrontDoor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#FrontDoor {{ router: {:?}, peek_size: {}, peek_timeout: {:#?} }}",
+            self.router, self.peek_size, self.peek_timeout
+        )
+    }
+}
+
+impl FrontDoor {
+    /// Create a front door, routing by the key the hook extracts from the client's address, and the
+    /// first bytes it sent.
+    pub fn new<F>(router: Arc<dyn NodeRouter>, key: F) -> Self
+    where
+        F: Fn(SocketAddr, &[u8]) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            router,
+            key: Box::new(key),
+            peek_size: 256,
+            peek_timeout: Duration::from_secs(1),
+            local: AtomicU64::new(0),
+            proxied: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the most bytes peeked, and how long to wait for the client to send them, the default
+    /// is 256 bytes, for a second. A size of 0 keys the client by its address alone, without
+    /// waiting on it, so that a client proxied by another node isn't recognized as such.
+    pub fn with_peek(mut self, size: usize, timeout: Duration) -> Self {
+        self.peek_size = size;
+        self.peek_timeout = timeout;
+        self
+    }
+
+    /// Get the counts of the connections accepted.
+    pub fn get_stats(&self) -> FrontDoorStats {
+        FrontDoorStats {
+            local: self.local.load(Ordering::Relaxed),
+            proxied: self.proxied.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    // Admit a connection accepted, returning it if it is handled by this node, otherwise it is
+    // proxied to the node which owns it, the proxy being spawned in the tasks.
+    pub(crate) async fn admit(&self, stream: TcpStream, remote: SocketAddr, tasks: &BackgroundTaskGroup) -> Option<TcpStream> {
+        let owner = match self.peek_key(&stream, remote).await {
+            Peeked::Key(key) => key.and_then(|key| self.router.route(&key)),
+            Peeked::Proxied => match stream.clone().read_exact(&mut [0; HOP_MARKER.len()]).await {
+                Ok(()) => None,
+                Err(_) => return None,
+            },
+        };
+        let owner = match owner {
+            Some(owner) => owner,
+            None => {
+                self.local.fetch_add(1, Ordering::Relaxed);
+                return Some(stream);
+            },
+        };
+        let upstream = match TcpStream::connect(owner.as_str()).await {
+            Ok(mut upstream) => upstream.write_all(HOP_MARKER).await.map(|_| upstream),
+            Err(err) => Err(err),
+        };
+        match upstream {
+            Ok(upstream) => {
+                log::debug!("front door proxying remote_addr={} to node={}", remote, owner);
+                self.proxied.fetch_add(1, Ordering::Relaxed);
+                tasks.spawn(get_executor().spawn(proxy(stream, upstream)), "front door proxy");
+            },
+            Err(err) => {
+                log::warn!("front door closed remote_addr={}, node={} unreachable error={}", remote, owner, err);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+        None
+    }
+
+    // Get the key of the client, peeking at the bytes it sent until the hook extracts one, the
+    // peek size is reached, the client stops sending, or the peek timeout passes.
+    async fn peek_key(&self, stream: &TcpStream, remote: SocketAddr) -> Peeked {
+        if self.peek_size == 0 {
+            return Peeked::Key((self.key)(remote, &[]));
+        }
+        let mut bytes = vec![0; self.peek_size.max(HOP_MARKER.len())];
+        let deadline = Instant::now() + self.peek_timeout;
+        let mut len = 0;
+        while len < bytes.len() {
+            let peeked = async { Some(stream.peek(&mut bytes).await.unwrap_or(0)) };
+            let expired = async {
+                smol::Timer::at(deadline).await;
+                None
+            };
+            match smol::future::or(peeked, expired).await {
+                // the client closed, or sent nothing more, before the deadline
+                Some(0) | None => break,
+                Some(peeked) => len = peeked,
+            }
+            if bytes[.. len].starts_with(HOP_MARKER) {
+                return Peeked::Proxied;
+            }
+            // until more than the start of the marker has been peeked, the client may be proxied
+            let marked = HOP_MARKER.starts_with(&bytes[.. len]);
+            if !marked && len >= self.peek_size {
+                break;
+            }
+            if let Some(key) = (self.key)(remote, &bytes[.. len.min(self.peek_size)]).filter(|_| !marked) {
+                return Peeked::Key(Some(key));
+            }
+            if len < bytes.len() {
+                // a peek returns at once while there are bytes, so wait for more to arrive
+                smol::future::or(smol::Timer::after(PEEK_INTERVAL), smol::Timer::at(deadline)).await;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        Peeked::Key((self.key)(remote, &bytes[.. len.min(self.peek_size)]))
+    }
+}
+
+// Copy the bytes between a client and the node owning it, each way, until both have shut down.
+async fn proxy(client: TcpStream, upstream: TcpStream) {
+    let copy = |from: TcpStream, mut to: TcpStream| async move {
+        smol::io::copy(from, &mut to).await.ok();
+        to.shutdown(Shutdown::Write).ok();
+    };
+    smol::future::zip(copy(client.clone(), upstream.clone()), copy(upstream, client)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consistent_owners() {
+        let mut ring = HashRing::new("a:1");
+        ring.add_node("b:1");
+        ring.add_node("c:1");
+        assert_eq!(vec!["a:1", "b:1", "c:1"], ring.get_nodes());
+        let keys: Vec<String> = (0 .. 300).map(|n| format!("room-{}", n)).collect();
+        let owners: Vec<String> = keys.iter().map(|key| ring.get_owner(key).to_string()).collect();
+        for node in &["a:1", "b:1", "c:1"] {
+            assert!(owners.iter().filter(|owner| owner == node).count() > 50);
+        }
+        // only the keys of a node removed move
+        ring.remove_node("c:1");
+        for (key, owner) in keys.iter().zip(&owners) {
+            if owner != "c:1" {
+                assert_eq!(owner, ring.get_owner(key));
+            }
+        }
+        assert_eq!(None, ring.route(keys.iter().find(|key| ring.get_owner(key) == "a:1").unwrap()));
+        assert_eq!(
+            Some("b:1".to_string()),
+            ring.route(keys.iter().find(|key| ring.get_owner(key) == "b:1").unwrap())
+        );
+    }
+}
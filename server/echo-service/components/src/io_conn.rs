@@ -0,0 +1,190 @@
+use super::*;
+use machine_foundation::MachineImpl;
+use smol::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::sync::Mutex as SyncMutex;
+
+// The size of the buffer bytes are read into.
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// Pump an AsyncRead into a connection machine, sending it ConnCmd::RecvBytes for the bytes read,
+/// and ConnCmd::CloseConn once the reader is exhausted or fails. The pump stops when the returned
+/// task is cancelled.
+pub fn pump_reader<R>(conn_id: NetConnId, reader: R, sender: ConnSender) -> BackgroundTask
+where
+    R: 'static + AsyncRead + Send + Unpin,
+{
+    let task = get_executor().spawn(async move {
+        let mut reader = reader;
+        loop {
+            let mut buf = vec![0u8; READ_BUFFER_SIZE];
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => {
+                    sender.send(ConnCmd::CloseConn(conn_id)).await.ok();
+                    break;
+                },
+                Ok(bytes_read) => {
+                    buf.truncate(bytes_read);
+                    if sender.send(ConnCmd::RecvBytes(conn_id, buf)).await.is_err() {
+                        break;
+                    }
+                },
+            }
+        }
+    });
+    BackgroundTask::detach(task, &format!("io reader id={}", conn_id))
+}
+
+// Drain the instructions for a connection into an AsyncWrite, until the connection is closed.
+async fn drain_writer<W>(conn_id: NetConnId, mut writer: W, receiver: NetReceiver)
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Ok(cmd) = receiver.recv().await {
+        match cmd {
+            NetCmd::SendBytes(id, bytes) if id == conn_id => {
+                if let Err(err) = writer.write_all(&bytes).await {
+                    log::warn!("io conn id={} write failed, err={}", conn_id, err);
+                    break;
+                }
+                writer.flush().await.ok();
+            },
+            NetCmd::CloseConn(id) if id == conn_id => break,
+            NetCmd::Stop => break,
+            cmd => log::debug!("io conn id={} ignoring {}", conn_id, cmd.variant_name()),
+        }
+    }
+    writer.close().await.ok();
+}
+
+/// An IoConn binds an arbitrary transport, such as a serial port, child process stdio, or an
+/// in-memory pipe, to a connection machine, exactly as NetCore binds a TcpStream. The machine
+/// sends NetCmd::SendBytes and NetCmd::CloseConn to the IoConn's sender, rather than to NetCore,
+/// and receives ConnCmd instructions for the bytes read.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::{machine, Machine, MachineSender};
+///
+/// struct Echo {
+///     net_sender: NetSender,
+/// }
+/// impl Machine<ConnCmd> for Echo {
+///     fn receive(&self, cmd: ConnCmd, sender: &mut MachineSender) {
+///         if let ConnCmd::RecvBytes(conn_id, bytes) = cmd {
+///             sender.send(self.net_sender.clone(), NetCmd::SendBytes(conn_id, bytes));
+///         }
+///     }
+/// }
+///
+/// let conn = IoConn::new(1, smol::Unblock::new(std::io::sink()));
+/// let (_echo, echo_sender) = machine::create(Echo { net_sender: conn.get_sender() });
+/// conn.bind(smol::io::Cursor::new(b"hello".to_vec()), echo_sender);
+/// ```
+pub struct IoConn {
+    conn_id: NetConnId,
+    net_sender: NetSender,
+    recv_task: Arc<SyncMutex<Option<BackgroundTask>>>,
+}
+
+impl IoConn {
+    /// Create the connection, draining the bytes sent to it into writer.
+    pub fn new<W>(conn_id: NetConnId, writer: W) -> Self
+    where
+        W: 'static + AsyncWrite + Send + Unpin,
+    {
+        let (net_sender, receiver) = channel::unbounded::<NetCmd>();
+        let recv_task: Arc<SyncMutex<Option<BackgroundTask>>> = Arc::default();
+        let reader_task = recv_task.clone();
+        get_executor()
+            .spawn(async move {
+                drain_writer(conn_id, writer, receiver).await;
+                if let Some(task) = reader_task.lock().unwrap_or_else(|err| err.into_inner()).take() {
+                    task.cancel();
+                }
+                log::debug!("io conn id={} closed", conn_id);
+            })
+            .detach();
+        Self {
+            conn_id,
+            net_sender,
+            recv_task,
+        }
+    }
+
+    /// Get the sender which the connection machine sends NetCmd::SendBytes and NetCmd::CloseConn.
+    pub fn get_sender(&self) -> NetSender { self.net_sender.clone() }
+
+    /// Bind the connection machine, pumping reader into it. Binding again replaces the reader.
+    pub fn bind<R>(&self, reader: R, sender: ConnSender)
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+    {
+        let task = pump_reader(self.conn_id, reader, sender);
+        if let Some(task) = self.recv_task.lock().unwrap_or_else(|err| err.into_inner()).replace(task) {
+            task.cancel();
+        }
+    }
+
+    /// Close the connection, the writer is closed once the bytes already sent to it are written.
+    pub fn close(&self) { self.net_sender.try_send(NetCmd::CloseConn(self.conn_id)).ok(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::{machine, Machine, MachineSender};
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    // An AsyncWrite which sends what's written to a channel.
+    struct ChannelWriter(channel::Sender<Vec<u8>>);
+    impl AsyncWrite for ChannelWriter {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.0.try_send(buf.to_vec()).ok();
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> { Poll::Ready(Ok(())) }
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.0.close();
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct Upper {
+        net_sender: NetSender,
+    }
+    impl Machine<ConnCmd> for Upper {
+        fn receive(&self, cmd: ConnCmd, sender: &mut MachineSender) {
+            match cmd {
+                ConnCmd::RecvBytes(conn_id, bytes) => {
+                    sender.send(self.net_sender.clone(), NetCmd::SendBytes(conn_id, bytes.to_ascii_uppercase()))
+                },
+                ConnCmd::CloseConn(conn_id) => sender.send(self.net_sender.clone(), NetCmd::CloseConn(conn_id)),
+                ConnCmd::SendReady(..) | ConnCmd::Rejected(..) => (),
+            }
+        }
+    }
+
+    #[test]
+    fn io_conn_echo() {
+        let (sender, receiver) = channel::unbounded::<Vec<u8>>();
+        let conn = IoConn::new(7, ChannelWriter(sender));
+        let (_upper, upper_sender) = machine::create(Upper {
+            net_sender: conn.get_sender(),
+        });
+        conn.bind(smol::io::Cursor::new(b"hello world".to_vec()), upper_sender);
+        // the reader's close is echoed back, closing the writer, which ends the stream
+        let written: Vec<u8> = smol::block_on(async {
+            let mut written = Vec::new();
+            while let Ok(bytes) = receiver.recv().await {
+                written.extend(bytes);
+            }
+            written
+        });
+        assert_eq!(b"HELLO WORLD".to_vec(), written);
+    }
+}
@@ -0,0 +1,366 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+// The files, within a journal's directory. Each snapshot starts a new generation of the journal,
+// in a file of its own, journal-<generation>.log.
+const JOURNAL_PREFIX: &str = "journal-";
+const JOURNAL_EXTENSION: &str = "log";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+// A snapshot, and the generation of the journal which follows it. Taking a snapshot is committed
+// by renaming it into place, so a crash either side of that leaves a snapshot, and the journal
+// of the instructions received since it was taken, and none of those it covers.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    generation: u64,
+    state: serde_json::Value,
+}
+
+// The journal being appended to.
+struct Journal {
+    file: File,
+    // the number of instructions appended since the last snapshot
+    count: usize,
+    generation: u64,
+}
+
+/// Snapshot is implemented by a machine which is journaled, and by every service. A journaled
+/// machine which provides a snapshot has its journal truncated each time one is taken, and is
+/// restored from it before the journal is replayed. A service which provides a snapshot has it
+/// persisted by the ServiceManager once drained, and offered back when it next starts. The
+/// default is no snapshot.
+pub trait Snapshot {
+    /// Take a snapshot of the state.
+    fn snapshot(&self) -> Option<serde_json::Value> { None }
+    /// Restore the state from a snapshot.
+    fn restore(&self, _snapshot: serde_json::Value) {}
+}
+
+/// Journaled is an opt-in wrapper, which appends every instruction applied to a machine to a
+/// journal, as a line of JSON, before the machine receives it. When opened, the machine is
+/// rebuilt from its last snapshot and by replaying the journal which follows it. Each snapshot
+/// starts a new journal, so an instruction covered by a snapshot is never replayed on top of it.
+/// During replay, the instructions the machine sends are discarded, as they were sent when the
+/// instructions were first received. The instructions must be serializable, so can't carry
+/// senders.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::{machine, Machine, MachineSender};
+/// use serde::{Deserialize, Serialize};
+/// use std::sync::atomic::{AtomicI64, Ordering};
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize, machine_impl::MachineImpl)]
+/// pub enum Ledger {
+///     Credit(i64),
+/// }
+///
+/// #[derive(Default)]
+/// struct Account {
+///     balance: AtomicI64,
+/// }
+/// impl Machine<Ledger> for Account {
+///     fn receive(&self, cmd: Ledger, _sender: &mut MachineSender) {
+///         let Ledger::Credit(amount) = cmd;
+///         self.balance.fetch_add(amount, Ordering::SeqCst);
+///     }
+/// }
+/// impl Snapshot for Account {}
+///
+/// let dir = std::env::temp_dir().join(format!("account-{}", std::process::id()));
+/// let account = Journaled::open::<Ledger>(Account::default(), &dir).unwrap();
+/// let (_account, sender) = machine::create(account);
+/// sender.try_send(Ledger::Credit(100)).ok();
+/// # std::fs::remove_dir_all(&dir).ok();
+/// ```
+pub struct Journaled<M> {
+    machine: M,
+    dir: PathBuf,
+    journal: AtomicRefCell<Journal>,
+    snapshot_every: usize,
+}
+
+impl<M> fmt::Debug for Journaled<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#Journaled {{ {:?} }}", self.dir) }
+}
+
+impl<M: Snapshot> Journaled<M> {
+    /// Open the journal in dir, creating it if it doesn't exist, and rebuild the machine from it.
+    pub fn open<I>(machine: M, dir: impl AsRef<Path>) -> io::Result<Self>
+    where
+        I: 'static + Send + Sync + DeserializeOwned,
+        M: Machine<I>,
+    {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let generation = match read_snapshot(&dir)? {
+            Some(snapshot) => {
+                machine.restore(snapshot.state);
+                snapshot.generation
+            },
+            None => 0,
+        };
+        let (journaled, size) = replay_journal::<I>(&journal_path(&dir, generation))?;
+        let mut sender = MachineSender::default();
+        for cmd in journaled {
+            machine.receive(cmd, &mut sender);
+        }
+        remove_stale_journals(&dir, generation);
+        let file = OpenOptions::new().append(true).create(true).open(journal_path(&dir, generation))?;
+        // cut off a line torn by a crash while appending, else the next is appended onto it
+        if file.metadata()?.len() > size {
+            log::warn!("journal {:?} truncating torn instruction at {}", dir, size);
+            file.set_len(size)?;
+        }
+        Ok(Self {
+            machine,
+            dir,
+            journal: AtomicRefCell::new(Journal {
+                file,
+                count: 0,
+                generation,
+            }),
+            snapshot_every: 0,
+        })
+    }
+
+    /// Take a snapshot after every count instructions, the default, 0, is never.
+    pub fn with_snapshot_every(mut self, count: usize) -> Self {
+        self.snapshot_every = count;
+        self
+    }
+
+    /// Get the journaled machine.
+    pub fn get_machine(&self) -> &M { &self.machine }
+
+    /// Take a snapshot, starting a new journal. If the machine doesn't provide a snapshot, the
+    /// journal is kept.
+    pub fn snapshot(&self) -> io::Result<()> {
+        let mut journal = self.journal.borrow_mut();
+        self.write_snapshot(&mut journal)
+    }
+
+    fn write_snapshot(&self, journal: &mut Journal) -> io::Result<()> {
+        let state = match self.machine.snapshot() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        // the next journal exists before the snapshot naming it
+        let generation = journal.generation + 1;
+        let file = File::create(journal_path(&self.dir, generation))?;
+        let snapshot =
+            serde_json::to_string(&SnapshotFile { generation, state }).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // the snapshot replaces the previous one only once it is complete
+        let path = self.dir.join(SNAPSHOT_FILE);
+        let partial = path.with_extension("partial");
+        let mut written = File::create(&partial)?;
+        written.write_all(snapshot.as_bytes())?;
+        written.sync_data()?;
+        fs::rename(&partial, &path)?;
+        let previous = journal_path(&self.dir, journal.generation);
+        *journal = Journal {
+            file,
+            count: 0,
+            generation,
+        };
+        fs::remove_file(previous).ok();
+        Ok(())
+    }
+
+    fn append<I: Serialize>(&self, cmd: &I) -> io::Result<()> {
+        let mut journal = self.journal.borrow_mut();
+        let mut line = serde_json::to_string(cmd).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        line.push('\n');
+        journal.file.write_all(line.as_bytes())?;
+        journal.count += 1;
+        Ok(())
+    }
+}
+
+impl<M, I> Machine<I> for Journaled<M>
+where
+    M: Machine<I> + Snapshot,
+    I: 'static + Send + Sync + Serialize,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) {
+        if let Err(err) = self.append(&cmd) {
+            log::error!("journal {:?} append failed, err={}", self.dir, err);
+        }
+        self.machine.receive(cmd, sender);
+        if self.snapshot_every > 0 {
+            let mut journal = self.journal.borrow_mut();
+            if journal.count >= self.snapshot_every {
+                if let Err(err) = self.write_snapshot(&mut journal) {
+                    log::error!("journal {:?} snapshot failed, err={}", self.dir, err);
+                }
+            }
+        }
+    }
+    fn disconnected(&self) { self.machine.disconnected(); }
+    fn weight(&self) -> usize { self.machine.weight() }
+}
+
+/// Read the instructions journaled in dir, since the last snapshot, which is useful for debugging.
+/// A partially written instruction, at the end of the journal, is ignored, and is cut off when
+/// the journal is next opened.
+pub fn replay<I: DeserializeOwned>(dir: impl AsRef<Path>) -> io::Result<Vec<I>> {
+    let generation = read_snapshot(dir.as_ref())?.map_or(0, |snapshot| snapshot.generation);
+    replay_journal(&journal_path(dir.as_ref(), generation)).map(|(journaled, _)| journaled)
+}
+
+fn journal_path(dir: &Path, generation: u64) -> PathBuf { dir.join(format!("{}{}.{}", JOURNAL_PREFIX, generation, JOURNAL_EXTENSION)) }
+
+fn read_snapshot(dir: &Path) -> io::Result<Option<SnapshotFile>> {
+    match fs::read_to_string(dir.join(SNAPSHOT_FILE)) {
+        Ok(snapshot) => serde_json::from_str(&snapshot)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// Remove the journals of other generations, left by a crash while a snapshot was taken.
+fn remove_stale_journals(dir: &Path, generation: u64) {
+    let current = journal_path(dir, generation);
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let is_journal = name.to_string_lossy().starts_with(JOURNAL_PREFIX) && path.extension() == Some(JOURNAL_EXTENSION.as_ref());
+        if is_journal && path != current {
+            log::info!("journal {:?} removing stale journal {:?}", dir, name);
+            fs::remove_file(&path).ok();
+        }
+    }
+}
+
+// Read the instructions in a journal, along with the size of the journal up to the end of the
+// last complete instruction. A line which isn't complete, or can't be parsed, ends the replay.
+fn replay_journal<I: DeserializeOwned>(path: &Path) -> io::Result<(Vec<I>, u64)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(err) => return Err(err),
+    };
+    let mut reader = BufReader::new(file);
+    let mut journaled = Vec::new();
+    let mut size = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.last() != Some(&b'\n') {
+            log::warn!("journal {:?} replay stopped, partial instruction", path);
+            break;
+        }
+        match serde_json::from_slice(&line) {
+            Ok(cmd) => journaled.push(cmd),
+            Err(err) => {
+                log::warn!("journal {:?} replay stopped, err={}", path, err);
+                break;
+            },
+        }
+        size += line.len() as u64;
+    }
+    Ok((journaled, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    #[derive(Debug, Clone, Serialize, Deserialize, MachineImpl)]
+    enum CounterCmd {
+        Add(i64),
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        count: AtomicI64,
+    }
+    impl Machine<CounterCmd> for Counter {
+        fn receive(&self, cmd: CounterCmd, _sender: &mut MachineSender) {
+            let CounterCmd::Add(n) = cmd;
+            self.count.fetch_add(n, Ordering::SeqCst);
+        }
+    }
+    impl Snapshot for Counter {
+        fn snapshot(&self) -> Option<serde_json::Value> { Some(self.count.load(Ordering::SeqCst).into()) }
+        fn restore(&self, snapshot: serde_json::Value) { self.count.store(snapshot.as_i64().unwrap_or(0), Ordering::SeqCst); }
+    }
+
+    fn run(dir: &Path, snapshot_every: usize, adds: &[i64]) -> i64 {
+        let counter = Journaled::open::<CounterCmd>(Counter::default(), dir)
+            .unwrap()
+            .with_snapshot_every(snapshot_every);
+        let (counter, sender) = machine::create_unbounded(counter);
+        for n in adds {
+            sender.try_send(CounterCmd::Add(*n)).ok();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        counter.get_machine().count.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn rebuild_by_replay() {
+        let dir = std::env::temp_dir().join(format!("journal-{}", uuid::Uuid::new_v4()));
+        assert_eq!(6, run(&dir, 0, &[1, 2, 3]));
+        assert_eq!(3, replay::<CounterCmd>(&dir).unwrap().len());
+        assert_eq!(10, run(&dir, 0, &[4]));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rebuild_from_snapshot() {
+        let dir = std::env::temp_dir().join(format!("journal-{}", uuid::Uuid::new_v4()));
+        assert_eq!(15, run(&dir, 2, &[1, 2, 3, 4, 5]));
+        // the snapshot, taken after 4, holds 10, the journal holds the 5
+        let snapshot = read_snapshot(&dir).unwrap().unwrap();
+        assert_eq!((2, Some(10)), (snapshot.generation, snapshot.state.as_i64()));
+        assert_eq!(1, replay::<CounterCmd>(&dir).unwrap().len());
+        assert_eq!(15, run(&dir, 2, &[]));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn crash_during_snapshot() {
+        let dir = std::env::temp_dir().join(format!("journal-{}", uuid::Uuid::new_v4()));
+        assert_eq!(15, run(&dir, 2, &[1, 2, 3, 4, 5]));
+        // a crash once the snapshot was renamed, before the previous journal was removed
+        fs::write(journal_path(&dir, 1), "{\"Add\":3}\n{\"Add\":4}\n").unwrap();
+        // a crash before the snapshot was renamed, once the next journal was created
+        File::create(journal_path(&dir, 3)).unwrap();
+        assert_eq!(15, run(&dir, 0, &[]));
+        assert!(!journal_path(&dir, 1).exists());
+        assert!(!journal_path(&dir, 3).exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn truncate_torn_instruction() {
+        let dir = std::env::temp_dir().join(format!("journal-{}", uuid::Uuid::new_v4()));
+        assert_eq!(3, run(&dir, 0, &[1, 2]));
+        // a crash while appending
+        let mut file = OpenOptions::new().append(true).open(journal_path(&dir, 0)).unwrap();
+        file.write_all(b"{\"Add\":10").unwrap();
+        drop(file);
+        assert_eq!(7, run(&dir, 0, &[4]));
+        assert_eq!(3, replay::<CounterCmd>(&dir).unwrap().len());
+        assert_eq!(12, run(&dir, 0, &[5]));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,348 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Shorthand for a sender, that can be sent KvCmd instructions.
+pub type KvSender = channel::Sender<KvCmd>;
+
+/// A key, or a value, in the store.
+pub type KvBytes = Vec<u8>;
+
+/// The result of a KvCmd.
+pub type KvResult<T> = Result<T, KvError>;
+
+// The operations which are appended to the log.
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+
+// The size of a record's header: op, key length, value length, checksum.
+const HEADER_SIZE: usize = 13;
+
+// The log isn't compacted until it holds at least this many stale bytes.
+const MIN_COMPACTION_BYTES: u64 = 1024 * 1024;
+
+/// Represents the errors the store replies with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum KvError {
+    /// The log couldn't be written, or compacted.
+    Io(String),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "kv store io error {}", err),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<io::Error> for KvError {
+    fn from(err: io::Error) -> Self { Self::Io(err.to_string()) }
+}
+
+/// KvCmd is the instruction set of the key-value store. Queries carry the sender which is sent
+/// the answer, writes carry an optional sender, which is sent the result once the write is
+/// appended to the log. The write is only durable across power loss if the store syncs, see
+/// KvStore::with_sync().
+#[derive(Debug, Clone, MachineImpl)]
+pub enum KvCmd {
+    /// Get the value of a key.
+    Get(KvBytes, channel::Sender<Option<KvBytes>>),
+    /// Put a value for a key.
+    Put(KvBytes, KvBytes, Option<channel::Sender<KvResult<()>>>),
+    /// Delete a key.
+    Delete(KvBytes, Option<channel::Sender<KvResult<()>>>),
+    /// Get the keys and values, in key order, of the keys starting with a prefix.
+    Scan(KvBytes, channel::Sender<Vec<(KvBytes, KvBytes)>>),
+    /// Rewrite the log, dropping overwritten and deleted values.
+    Compact(Option<channel::Sender<KvResult<()>>>),
+}
+
+// The log and its index.
+struct KvLog {
+    path: PathBuf,
+    file: File,
+    index: BTreeMap<KvBytes, KvBytes>,
+    size: u64,
+    live: u64,
+}
+
+/// The KvStore is a simple log-structured store, which implements the KvCmd instruction set. Every
+/// write is appended to a log, which is replayed into an in-memory index when the store is opened,
+/// a torn write at the end of the log is discarded. The log is compacted once more than half of
+/// it is overwritten or deleted values.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::machine;
+///
+/// let path = std::env::temp_dir().join(format!("sessions-{}.log", std::process::id()));
+/// let (_store, sender) = machine::create(KvStore::open(&path).unwrap());
+/// sender.try_send(KvCmd::Put(b"session:1".to_vec(), b"alice".to_vec(), None)).ok();
+/// let (reply, receiver) = smol::channel::bounded(1);
+/// sender.try_send(KvCmd::Get(b"session:1".to_vec(), reply)).ok();
+/// assert_eq!(Ok(Some(b"alice".to_vec())), smol::block_on(receiver.recv()));
+/// # std::fs::remove_file(&path).ok();
+/// ```
+pub struct KvStore {
+    log: AtomicRefCell<KvLog>,
+    sync: bool,
+}
+
+impl fmt::Debug for KvStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#KvStore {{ {:?} }}", self.log.borrow().path) }
+}
+
+impl KvStore {
+    /// Open the store at path, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let (index, size, live) = replay(&mut file)?;
+        if size < file.metadata()?.len() {
+            log::warn!("kv store {:?} discarding torn write at offset {}", path, size);
+            file.set_len(size)?;
+        }
+        Ok(Self {
+            log: AtomicRefCell::new(KvLog {
+                path,
+                file,
+                index,
+                size,
+                live,
+            }),
+            sync: false,
+        })
+    }
+
+    /// Sync the log to disk after every write, trading throughput for durability across power loss.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    fn append(&self, op: u8, key: KvBytes, value: KvBytes) -> KvResult<()> {
+        let mut log = self.log.borrow_mut();
+        let record = encode(op, &key, &value);
+        let mut written = log.file.write_all(&record);
+        if written.is_ok() && self.sync {
+            written = log.file.sync_data();
+        }
+        if let Err(err) = written {
+            // drop any partial record, else the writes appended after it are lost on replay
+            let size = log.size;
+            log.file.set_len(size).ok();
+            return Err(err.into());
+        }
+        log.size += record.len() as u64;
+        let key_len = key.len();
+        let previous = if op == OP_PUT {
+            log.live += record.len() as u64;
+            log.index.insert(key, value)
+        } else {
+            log.index.remove(&key)
+        };
+        if let Some(previous) = previous {
+            log.live -= record_size(key_len, previous.len());
+        }
+        let stale = log.size - log.live;
+        if stale >= MIN_COMPACTION_BYTES && stale > log.live {
+            compact(&mut log)?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine<KvCmd> for KvStore {
+    fn receive(&self, cmd: KvCmd, _sender: &mut MachineSender) {
+        match cmd {
+            KvCmd::Get(key, reply) => {
+                reply.try_send(self.log.borrow().index.get(&key).cloned()).ok();
+            },
+            KvCmd::Put(key, value, reply) => reply_with(reply, self.append(OP_PUT, key, value)),
+            KvCmd::Delete(key, reply) => reply_with(reply, self.append(OP_DELETE, key, Vec::new())),
+            KvCmd::Scan(prefix, reply) => {
+                let entries = self
+                    .log
+                    .borrow()
+                    .index
+                    .range(prefix.clone() ..)
+                    .take_while(|(key, _)| key.starts_with(&prefix))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                reply.try_send(entries).ok();
+            },
+            KvCmd::Compact(reply) => reply_with(reply, compact(&mut self.log.borrow_mut())),
+        }
+    }
+}
+
+fn reply_with(reply: Option<channel::Sender<KvResult<()>>>, res: KvResult<()>) {
+    if let Err(err) = &res {
+        log::error!("{}", err);
+    }
+    if let Some(reply) = reply {
+        reply.try_send(res).ok();
+    }
+}
+
+// Rewrite the log with only the live values, replacing the log once the rewrite is complete.
+fn compact(log: &mut KvLog) -> KvResult<()> {
+    let compacted = log.path.with_extension("compact");
+    let mut file = File::create(&compacted)?;
+    for (key, value) in &log.index {
+        file.write_all(&encode(OP_PUT, key, value))?;
+    }
+    file.sync_all()?;
+    fs::rename(&compacted, &log.path)?;
+    log.file = OpenOptions::new().read(true).append(true).open(&log.path)?;
+    log.size = log.live;
+    Ok(())
+}
+
+fn record_size(key_len: usize, value_len: usize) -> u64 { (HEADER_SIZE + key_len + value_len) as u64 }
+
+// FNV-1a, which is enough to detect a torn write.
+fn checksum(key: &[u8], value: &[u8]) -> u32 {
+    key.iter()
+        .chain(value)
+        .fold(0x811c_9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+}
+
+fn encode(op: u8, key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_SIZE + key.len() + value.len());
+    record.push(op);
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(key, value).to_le_bytes());
+    record.extend_from_slice(key);
+    record.extend_from_slice(value);
+    record
+}
+
+// Replay the log into an index, returning it, the size of the valid log, and the size of the live records.
+fn replay(file: &mut File) -> io::Result<(BTreeMap<KvBytes, KvBytes>, u64, u64)> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let (mut index, mut size, mut live) = (BTreeMap::new(), 0, 0);
+    let mut header = [0u8; HEADER_SIZE];
+    while reader.read_exact(&mut header).is_ok() {
+        let u32_at = |at: usize| u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        let (key_len, value_len) = (u32_at(1) as usize, u32_at(5) as usize);
+        // a corrupt header mustn't allocate more than the log holds
+        if size + record_size(key_len, value_len) > len {
+            break;
+        }
+        let mut key = vec![0u8; key_len];
+        let mut value = vec![0u8; value_len];
+        if reader.read_exact(&mut key).is_err() || reader.read_exact(&mut value).is_err() || checksum(&key, &value) != u32_at(9) {
+            break;
+        }
+        let previous = match header[0] {
+            OP_PUT => {
+                live += record_size(key_len, value_len);
+                index.insert(key, value)
+            },
+            OP_DELETE => index.remove(&key),
+            _ => break,
+        };
+        if let Some(previous) = previous {
+            live -= record_size(key_len, previous.len());
+        }
+        size += record_size(key_len, value_len);
+    }
+    Ok((index, size, live))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+
+    fn temp_log() -> PathBuf { std::env::temp_dir().join(format!("kv-{}.log", uuid::Uuid::new_v4())) }
+
+    fn get(sender: &KvSender, key: &[u8]) -> Option<KvBytes> {
+        let (reply, receiver) = channel::bounded(1);
+        sender.try_send(KvCmd::Get(key.to_vec(), reply)).ok();
+        smol::block_on(receiver.recv()).unwrap()
+    }
+
+    fn put(sender: &KvSender, key: &[u8], value: &[u8]) {
+        let (reply, receiver) = channel::bounded(1);
+        sender.try_send(KvCmd::Put(key.to_vec(), value.to_vec(), Some(reply))).ok();
+        assert_eq!(Ok(Ok(())), smol::block_on(receiver.recv()));
+    }
+
+    #[test]
+    fn put_get_delete_scan() {
+        let path = temp_log();
+        let (_store, sender) = machine::create_unbounded(KvStore::open(&path).unwrap());
+        put(&sender, b"user:2", b"bob");
+        put(&sender, b"user:1", b"alice");
+        put(&sender, b"room:1", b"lobby");
+        put(&sender, b"user:1", b"alicia");
+        sender.try_send(KvCmd::Delete(b"user:2".to_vec(), None)).ok();
+        assert_eq!(Some(b"alicia".to_vec()), get(&sender, b"user:1"));
+        assert_eq!(None, get(&sender, b"user:2"));
+
+        let (reply, receiver) = channel::bounded(1);
+        sender.try_send(KvCmd::Scan(b"user:".to_vec(), reply)).ok();
+        assert_eq!(Ok(vec![(b"user:1".to_vec(), b"alicia".to_vec())]), smol::block_on(receiver.recv()));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn replay_and_compact() {
+        let path = temp_log();
+        {
+            let store = KvStore::open(&path).unwrap();
+            for n in 0 .. 10u8 {
+                store.append(OP_PUT, vec![n % 3], vec![n]).unwrap();
+            }
+            store.append(OP_DELETE, vec![0], Vec::new()).unwrap();
+        }
+        // a torn write is discarded
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&encode(OP_PUT, b"torn", b"write")[.. 15])
+            .unwrap();
+        let store = KvStore::open(&path).unwrap();
+        let expected: BTreeMap<KvBytes, KvBytes> = vec![(vec![1], vec![7]), (vec![2], vec![8])].into_iter().collect();
+        assert_eq!(expected, store.log.borrow().index);
+        assert_eq!(2 * record_size(1, 1), store.log.borrow().live);
+
+        compact(&mut store.log.borrow_mut()).unwrap();
+        assert_eq!(2 * record_size(1, 1), fs::metadata(&path).unwrap().len());
+        store.append(OP_PUT, vec![3], vec![9]).unwrap();
+        drop(store);
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(3, store.log.borrow().index.len());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn discard_corrupt_header() {
+        let path = temp_log();
+        KvStore::open(&path).unwrap().append(OP_PUT, b"k".to_vec(), b"v".to_vec()).unwrap();
+        let mut record = encode(OP_PUT, b"key", b"value");
+        record[5 .. 9].copy_from_slice(&u32::MAX.to_le_bytes());
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&record).unwrap();
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(1, store.log.borrow().index.len());
+        assert_eq!(record_size(1, 1), fs::metadata(&path).unwrap().len());
+        fs::remove_file(&path).ok();
+    }
+}
@@ -1,20 +1,77 @@
 use machine_impl::*;
 use smart_default::*;
 
-use machine_foundation::{get_executor, BackgroundTask};
+use machine_foundation::{get_executor, BackgroundHandle, BackgroundTask, BackgroundTaskGroup};
 
 use std::sync::Arc;
 
 use atomic_refcell::AtomicRefCell;
 use smol::{channel, lock::Mutex};
 
+mod audit;
+mod auth;
+mod autoscale;
+mod batch;
+mod buffer_pool;
+mod cache;
+mod compression;
+mod controller;
+mod dedup;
+mod enrich;
+mod front_door;
+mod io_conn;
+pub mod journal;
+mod kv_store;
+mod log_level;
+mod log_sink;
 mod net_instructionset;
 mod network;
+mod probe;
+mod process;
+mod seal;
 mod service;
+mod service_manager;
+mod sharded_state;
+mod sse;
+mod statsd;
+mod tarpit;
+mod watcher;
+mod worker_pool;
 
-pub use net_instructionset::{NetCmd, NetConnId, NetReceiver, NetSender};
+pub use audit::{AuditCmd, AuditEvent, AuditKind, AuditMachine, AuditSender, AuditSink, FileSink};
+pub use auth::{AuthBackend, AuthCmd, AuthError, AuthMachine, AuthSender, AuthToken, Credentials, LdapBackend, StaticBackend};
+pub use autoscale::{
+    AutoscaleCmd, AutoscaleSender, Autoscaler, ScalingAction, ScalingDirection, ScalingEvent, ScalingRule, CONNECTIONS_METRIC,
+};
+pub use batch::{BatchCmd, BatchMachine, BatchSender, BatchStats, Unbatcher};
+pub use buffer_pool::BufferPool;
+pub use cache::{Cache, CacheCmd, CacheSender, KvEncode};
+pub use compression::{Compression, CompressionStats};
+pub use controller::{Controller, ControllerMachine};
+pub use dedup::Deduplicator;
+pub use enrich::{AddressClass, AddressClassifier, ConnEnricher};
+pub use front_door::{FrontDoor, FrontDoorStats, HashRing, NodeRouter};
+pub use io_conn::{pump_reader, IoConn};
+pub use journal::{Journaled, Snapshot};
+pub use kv_store::{KvBytes, KvCmd, KvError, KvResult, KvSender, KvStore};
+pub use log_level::{LevelFilteredLogger, LogCmd, LogLevels, LogSender};
+#[cfg(unix)] pub use log_sink::JournaldLogger;
+pub use log_sink::{SyslogFacility, SyslogLogger, SyslogTransport, SyslogWriter};
+pub use net_instructionset::{
+    ConnCmd, ConnData, ConnSender, FrameError, ListenerCmd, ListenerSender, NetCmd, NetConnId, NetReceiver, NetSender, SwitchPolicy,
+};
 pub use network::NetCore;
-pub use service::{ServerService, ServiceError, ServiceResult, ServiceState, ServiceStateTransition};
+pub use probe::Probe;
+pub use process::{ProcessCmd, ProcessEvent, ProcessEventSender, ProcessId, ProcessManager, ProcessSender, ProcessSpec, RestartPolicy};
+pub use seal::{Opener, SealError, SealKey, Sealer};
+pub use service::{AsyncServiceStateTransition, ServerService, ServiceError, ServiceResult, ServiceState, ServiceStateTransition};
+pub use service_manager::{ServiceFactory, ServiceManager};
+pub use sharded_state::ShardedState;
+pub use sse::{SseConnection, SseEvent};
+pub use statsd::{MetricsCmd, MetricsSender, StatsdMachine};
+pub use tarpit::{TarpitPolicy, TarpitStats};
+pub use watcher::{FileChange, FileWatcher, WatchEvent, WatchEventSender};
+pub use worker_pool::{WorkerPool, WorkerPoolStats, WorkerStats, WORKER_INDEX_ENV};
 
 #[cfg(test)]
 mod tests {}
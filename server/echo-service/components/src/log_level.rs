@@ -0,0 +1,182 @@
+use super::*;
+use log::LevelFilter;
+use machine_foundation::{Machine, MachineSender};
+use std::{collections::BTreeMap, sync::RwLock};
+
+/// Shorthand for a sender, that can be sent LogCmd instructions.
+pub type LogSender = channel::Sender<LogCmd>;
+
+/// LogCmd is the instruction set for changing log levels at runtime, such as from an admin
+/// command. A change lasts until the levels are next reset from config.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum LogCmd {
+    /// Set the global level.
+    SetLevel(LevelFilter),
+    /// Set the level of a target, overriding the global level for it and the modules within it,
+    /// or, if None, remove the override.
+    SetTargetLevel(String, Option<LevelFilter>),
+    /// Reset the global and target levels, typically when config is reloaded.
+    Reset(LevelFilter, Vec<(String, LevelFilter)>),
+}
+
+#[derive(Debug)]
+struct Levels {
+    level: LevelFilter,
+    targets: BTreeMap<String, LevelFilter>,
+}
+
+/// The LogLevels implements the LogCmd instruction set. It holds the global level and the target
+/// levels, which a LevelFilteredLogger filters records by. A target's level applies to the modules
+/// within it, the most specific target applying.
+#[derive(Debug)]
+pub struct LogLevels {
+    levels: RwLock<Levels>,
+}
+
+impl LogLevels {
+    /// Create the levels, with the global level.
+    pub fn new(level: LevelFilter) -> Self {
+        Self {
+            levels: RwLock::new(Levels {
+                level,
+                targets: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Set the levels of targets, such as those from config.
+    pub fn with_targets<'a, I>(self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a String, &'a LevelFilter)>,
+    {
+        for (target, level) in targets {
+            self.set_target_level(target, Some(*level));
+        }
+        self
+    }
+
+    /// Set the global level.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.write().level = level;
+        self.update_max_level();
+    }
+
+    /// Set the level of a target, or, if None, remove it.
+    pub fn set_target_level(&self, target: &str, level: Option<LevelFilter>) {
+        match level {
+            Some(level) => self.write().targets.insert(target.to_string(), level),
+            None => self.write().targets.remove(target),
+        };
+        self.update_max_level();
+    }
+
+    /// Get the level of a target, which is the level of its most specific target, or the global
+    /// level if there's none.
+    pub fn get_level(&self, target: &str) -> LevelFilter {
+        let levels = self.read();
+        levels
+            .targets
+            .iter()
+            .filter(|(t, _)| target == t.as_str() || (target.starts_with(t.as_str()) && target[t.len() ..].starts_with("::")))
+            .max_by_key(|(t, _)| t.len())
+            .map_or(levels.level, |(_, level)| *level)
+    }
+
+    // The log crate skips records above the max level, so it is kept at the most verbose level.
+    fn update_max_level(&self) {
+        let levels = self.read();
+        let max_level = levels.targets.values().copied().fold(levels.level, std::cmp::max);
+        log::set_max_level(max_level);
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Levels> { self.levels.read().unwrap_or_else(|err| err.into_inner()) }
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Levels> { self.levels.write().unwrap_or_else(|err| err.into_inner()) }
+}
+
+impl Machine<LogCmd> for LogLevels {
+    fn receive(&self, cmd: LogCmd, _sender: &mut MachineSender) {
+        match cmd {
+            LogCmd::SetLevel(level) => self.set_level(level),
+            LogCmd::SetTargetLevel(target, level) => self.set_target_level(&target, level),
+            LogCmd::Reset(level, targets) => {
+                let mut levels = self.write();
+                levels.level = level;
+                levels.targets = targets.into_iter().collect();
+                drop(levels);
+                self.update_max_level();
+            },
+        }
+    }
+}
+
+/// The LevelFilteredLogger is a log::Log which passes the records allowed by the LogLevels to
+/// another logger, which should log every record it is passed.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::machine;
+///
+/// # struct Stdout;
+/// # impl log::Log for Stdout {
+/// #     fn enabled(&self, _metadata: &log::Metadata) -> bool { true }
+/// #     fn log(&self, record: &log::Record) { println!("{}", record.args()) }
+/// #     fn flush(&self) {}
+/// # }
+/// let (levels, sender) = machine::create(LogLevels::new(log::LevelFilter::Warn));
+/// LevelFilteredLogger::new(levels, Box::new(Stdout)).init().ok();
+/// // turn up the logging of the network
+/// sender.try_send(LogCmd::SetTargetLevel("components::network".to_string(), Some(log::LevelFilter::Debug))).ok();
+/// ```
+pub struct LevelFilteredLogger {
+    levels: Arc<LogLevels>,
+    logger: Box<dyn log::Log>,
+}
+
+impl LevelFilteredLogger {
+    /// Create a logger, filtering the records passed to logger by the levels.
+    pub fn new(levels: Arc<LogLevels>, logger: Box<dyn log::Log>) -> Self { Self { levels, logger } }
+
+    /// Install the logger as the global logger.
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        self.levels.update_max_level();
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl log::Log for LevelFilteredLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool { metadata.level() <= self.levels.get_level(metadata.target()) }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.logger.log(record);
+        }
+    }
+    fn flush(&self) { self.logger.flush() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn target_levels() {
+        let targets: HashMap<String, LevelFilter> = vec![("components".to_string(), LevelFilter::Info)].into_iter().collect();
+        let levels = LogLevels::new(LevelFilter::Warn).with_targets(&targets);
+        levels.set_target_level("components::network", Some(LevelFilter::Trace));
+        assert_eq!(LevelFilter::Warn, levels.get_level("server_core"));
+        assert_eq!(LevelFilter::Info, levels.get_level("components::service"));
+        assert_eq!(LevelFilter::Trace, levels.get_level("components::network"));
+        // a target only applies to the modules within it
+        assert_eq!(LevelFilter::Warn, levels.get_level("components_extra"));
+
+        let mut sender = MachineSender::default();
+        levels.receive(LogCmd::SetTargetLevel("components::network".to_string(), None), &mut sender);
+        levels.receive(LogCmd::SetLevel(LevelFilter::Error), &mut sender);
+        assert_eq!(LevelFilter::Info, levels.get_level("components::network"));
+        assert_eq!(LevelFilter::Error, levels.get_level("server_core"));
+        levels.receive(LogCmd::Reset(LevelFilter::Debug, Vec::new()), &mut sender);
+        assert_eq!(LevelFilter::Debug, levels.get_level("components::network"));
+    }
+}
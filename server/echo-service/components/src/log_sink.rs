@@ -0,0 +1,470 @@
+use super::*;
+use std::{
+    io::{self, Write},
+    net::{TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)] use std::os::unix::net::UnixDatagram;
+
+/// The path of the journald native protocol socket.
+#[cfg(unix)]
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+/// The path of the local syslog socket.
+#[cfg(unix)]
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+// The longest a write to a stream may block, before the connection is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// The interval between attempts to reconnect, once a connection has been dropped.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// The number of records a SyslogLogger queues for its writer, beyond which records are dropped.
+const LOG_QUEUE_CAPACITY: usize = 1024;
+
+/// Where syslog messages are sent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SyslogTransport {
+    /// Send datagrams to a remote collector, such as "127.0.0.1:514".
+    Udp(String),
+    /// Stream to a remote collector, using octet-counting framing.
+    Tcp(String),
+    /// Send datagrams to a local socket, such as "/dev/log".
+    #[cfg(unix)]
+    Unix(String),
+}
+
+#[cfg(unix)]
+impl Default for SyslogTransport {
+    fn default() -> Self { Self::Unix(SYSLOG_SOCKET_PATH.to_string()) }
+}
+
+/// The syslog facility, which identifies the type of program logging the message.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+pub enum SyslogFacility {
+    #[default]
+    User,
+    Daemon,
+    Auth,
+    AuthPriv,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Get the numerical code of the facility.
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::User => 1,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::AuthPriv => 10,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// The syslog severity of a log level.
+const fn syslog_severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+#[derive(Debug)]
+enum SyslogConnection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl SyslogConnection {
+    fn connect(transport: &SyslogTransport) -> io::Result<Self> {
+        Ok(match transport {
+            SyslogTransport::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                Self::Udp(socket)
+            },
+            SyslogTransport::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Self::Tcp(stream)
+            },
+            #[cfg(unix)]
+            SyslogTransport::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Self::Unix(socket)
+            },
+        })
+    }
+}
+
+/// The SyslogWriter formats RFC 5424 messages and sends them over its transport. Should sending
+/// fail, or a stream's write time out, the connection is dropped, and reconnected by a later
+/// write, at most once every second, the messages in between failing.
+#[derive(Debug)]
+pub struct SyslogWriter {
+    transport: SyslogTransport,
+    connection: Option<SyslogConnection>,
+    // when the connection was dropped, or last failed to reconnect
+    disconnected: Option<Instant>,
+    facility: SyslogFacility,
+    hostname: String,
+    app_name: String,
+    proc_id: u32,
+}
+
+impl SyslogWriter {
+    /// Connect to a syslog collector, logging as app_name.
+    pub fn connect(transport: &SyslogTransport, app_name: &str) -> io::Result<Self> {
+        let connection = SyslogConnection::connect(transport)?;
+        Ok(Self {
+            transport: transport.clone(),
+            connection: Some(connection),
+            disconnected: None,
+            facility: SyslogFacility::default(),
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+            app_name: app_name.to_string(),
+            proc_id: std::process::id(),
+        })
+    }
+
+    /// Override the default facility of User.
+    pub fn with_facility(mut self, facility: SyslogFacility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Format a message as RFC 5424. The hostname, app name and message id are header fields, so
+    /// are stripped of spaces and anything other than printable ASCII, and truncated to the
+    /// lengths the RFC allows.
+    pub fn format(&self, severity: u8, msg_id: &str, msg: &str) -> String {
+        format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility.code() * 8 + severity,
+            rfc3339_now(),
+            header_field(&self.hostname, 255),
+            header_field(&self.app_name, 48),
+            self.proc_id,
+            header_field(msg_id, 32),
+            msg
+        )
+    }
+
+    /// Send a message with the given severity.
+    pub fn write(&mut self, severity: u8, msg_id: &str, msg: &str) -> io::Result<()> {
+        let message = self.format(severity, msg_id, msg);
+        let connection = self.reconnect()?;
+        let res = match connection {
+            SyslogConnection::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            SyslogConnection::Tcp(stream) => stream.write_all(format!("{} {}", message.len(), message).as_bytes()),
+            #[cfg(unix)]
+            SyslogConnection::Unix(socket) => socket.send(message.as_bytes()).map(|_| ()),
+        };
+        if res.is_err() {
+            // a stream may be part way through a message, so can't be written to again
+            self.connection = None;
+            self.disconnected = Some(Instant::now());
+        }
+        res
+    }
+
+    // Get the connection, reconnecting if it was dropped, and the interval has passed.
+    fn reconnect(&mut self) -> io::Result<&mut SyslogConnection> {
+        if self.connection.is_none() {
+            if matches!(self.disconnected, Some(at) if at.elapsed() < RECONNECT_INTERVAL) {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "syslog disconnected"));
+            }
+            match SyslogConnection::connect(&self.transport) {
+                Ok(connection) => {
+                    self.connection = Some(connection);
+                    self.disconnected = None;
+                },
+                Err(err) => {
+                    self.disconnected = Some(Instant::now());
+                    return Err(err);
+                },
+            }
+        }
+        self.connection
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "syslog disconnected"))
+    }
+}
+
+// Strip a header field of anything other than printable ASCII, which excludes spaces, truncating
+// it to max, and using the nil value, -, if it is then empty.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value.chars().filter(|c| c.is_ascii_graphic()).take(max).collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Audit records are forwarded with a severity of Notice.
+impl AuditSink for SyslogWriter {
+    fn append(&mut self, record: &str) -> io::Result<()> { self.write(5, "audit", record) }
+}
+
+/// The SyslogLogger is a log::Log which sends records to syslog. Records are queued for a thread
+/// of its own, which writes them, so a thread logging, such as an executor thread, is never blocked
+/// by a slow, or unreachable, collector. Once the queue is full, records are dropped, and counted.
+/// The writer uses blocking sockets, rather than NetCore, as NetCore logs, and would be logging
+/// through itself.
+#[derive(Debug)]
+pub struct SyslogLogger {
+    level: log::LevelFilter,
+    queue: mpsc::SyncSender<(u8, String)>,
+    dropped: AtomicUsize,
+}
+
+impl SyslogLogger {
+    /// Create a logger, logging records at level or higher, starting the thread which writes them.
+    pub fn new(level: log::LevelFilter, mut writer: SyslogWriter) -> Self {
+        let (queue, records) = mpsc::sync_channel::<(u8, String)>(LOG_QUEUE_CAPACITY);
+        thread::Builder::new()
+            .name("syslog-writer".to_string())
+            .spawn(move || {
+                for (severity, msg) in records {
+                    writer.write(severity, "", &msg).ok();
+                }
+            })
+            .expect("cannot spawn syslog writer thread");
+        Self {
+            level,
+            queue,
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the number of records dropped, as the queue was full.
+    pub fn get_dropped_count(&self) -> usize { self.dropped.load(Ordering::Relaxed) }
+}
+
+impl log::Log for SyslogLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool { metadata.level() <= self.level }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let msg = format!("{}: {}", record.target(), record.args());
+            if self.queue.try_send((syslog_severity(record.level()), msg)).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    fn flush(&self) {}
+}
+
+/// The JournaldLogger is a log::Log which sends records to journald using its native protocol,
+/// retaining the target, file, and line as journal fields.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct JournaldLogger {
+    level: log::LevelFilter,
+    identifier: String,
+    socket: UnixDatagram,
+}
+
+#[cfg(unix)]
+impl JournaldLogger {
+    /// Create a logger, logging records at level or higher, tagged with identifier.
+    pub fn new(level: log::LevelFilter, identifier: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET_PATH)?;
+        Ok(Self {
+            level,
+            identifier: identifier.to_string(),
+            socket,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl log::Log for JournaldLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool { metadata.level() <= self.level }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let mut buf = Vec::new();
+            append_journal_field(&mut buf, "PRIORITY", &syslog_severity(record.level()).to_string());
+            append_journal_field(&mut buf, "SYSLOG_IDENTIFIER", &self.identifier);
+            append_journal_field(&mut buf, "TARGET", record.target());
+            if let Some(file) = record.file() {
+                append_journal_field(&mut buf, "CODE_FILE", file);
+            }
+            if let Some(line) = record.line() {
+                append_journal_field(&mut buf, "CODE_LINE", &line.to_string());
+            }
+            append_journal_field(&mut buf, "MESSAGE", &record.args().to_string());
+            self.socket.send(&buf).ok();
+        }
+    }
+    fn flush(&self) {}
+}
+
+// Append a field in the journald native format. Values containing a newline use the binary form,
+// which is the name, a newline, the little endian u64 length, and the value.
+fn append_journal_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+// The current time in RFC 3339 format, UTC with milliseconds.
+fn rfc3339_now() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    rfc3339(now.as_secs(), now.subsec_millis())
+}
+
+// Convert seconds since the epoch to RFC 3339, using the days-to-civil algorithm.
+fn rfc3339(secs: u64, millis: u32) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        millis
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_conversion() {
+        assert_eq!("1970-01-01T00:00:00.000Z", rfc3339(0, 0));
+        assert_eq!("2000-02-29T12:34:56.789Z", rfc3339(951_827_696, 789));
+    }
+
+    #[test]
+    fn journal_fields() {
+        let mut buf = Vec::new();
+        append_journal_field(&mut buf, "MESSAGE", "hello");
+        assert_eq!(b"MESSAGE=hello\n".to_vec(), buf);
+        buf.clear();
+        append_journal_field(&mut buf, "MESSAGE", "a\nb");
+        assert_eq!(b"MESSAGE\n\x03\x00\x00\x00\x00\x00\x00\x00a\nb\n".to_vec(), buf);
+    }
+
+    #[test]
+    fn syslog_over_udp() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let transport = SyslogTransport::Udp(collector.local_addr().unwrap().to_string());
+        let mut writer = SyslogWriter::connect(&transport, "echo")
+            .unwrap()
+            .with_facility(SyslogFacility::Local0);
+        writer.write(syslog_severity(log::Level::Warn), "", "hello").unwrap();
+        let mut buf = [0u8; 512];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[.. len]).to_string();
+        assert!(message.starts_with("<132>1 "));
+        assert!(message.ends_with(&format!(" echo {} - - hello", std::process::id())));
+    }
+
+    #[test]
+    fn strip_header_fields() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = SyslogTransport::Udp(collector.local_addr().unwrap().to_string());
+        let writer = SyslogWriter::connect(&transport, "echo server\n").unwrap();
+        let message = writer.format(5, "msg id", "hello world");
+        assert!(message.ends_with(&format!(" echoserver {} msgid - hello world", std::process::id())));
+        assert_eq!("-", header_field(" \t", 48));
+        assert_eq!("ab", header_field("abc", 2));
+    }
+
+    #[test]
+    fn log_from_queue() {
+        use log::Log;
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let transport = SyslogTransport::Udp(collector.local_addr().unwrap().to_string());
+        let logger = SyslogLogger::new(log::LevelFilter::Info, SyslogWriter::connect(&transport, "echo").unwrap());
+        logger.log(
+            &log::Record::builder()
+                .level(log::Level::Debug)
+                .args(format_args!("skipped"))
+                .build(),
+        );
+        logger.log(
+            &log::Record::builder()
+                .level(log::Level::Error)
+                .target("echo")
+                .args(format_args!("queued"))
+                .build(),
+        );
+        let mut buf = [0u8; 512];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[.. len]).to_string();
+        assert!(message.starts_with("<11>1 "));
+        assert!(message.ends_with(" - echo: queued"));
+        assert_eq!(0, logger.get_dropped_count());
+    }
+
+    #[test]
+    fn reconnect_over_tcp() {
+        use std::io::Read;
+        let collector = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = SyslogTransport::Tcp(collector.local_addr().unwrap().to_string());
+        let mut writer = SyslogWriter::connect(&transport, "echo").unwrap();
+        // the collector drops the connection
+        drop(collector.accept().unwrap());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while writer.write(5, "", "lost").is_ok() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(writer.connection.is_none());
+        // the writer reconnects, once the interval has passed
+        thread::sleep(RECONNECT_INTERVAL);
+        writer.write(5, "", "found").unwrap();
+        let (mut stream, _) = collector.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0u8; 512];
+        let len = stream.read(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[.. len]).ends_with(" - found"));
+    }
+}
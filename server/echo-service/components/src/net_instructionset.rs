@@ -1,5 +1,11 @@
 #![allow(dead_code)]
 use super::*;
+use crate::{
+    compression::{Compression, CompressionStats},
+    enrich::ConnEnricher,
+    front_door::FrontDoor,
+    tarpit::{TarpitPolicy, TarpitStats},
+};
 // ```sequence
 // Alice->Net: BindListner(addres, Alice:sender)
 // note right of Alice: Alice waits for a connection
@@ -92,43 +98,151 @@ use super::*;
 ///     |------------------------------------------>|
 ///     |                                           |
 /// ```
+///
+/// The instructions are split by direction. NetCmd is sent to the network, ListenerCmd is sent by
+/// the network to a listener, and ConnCmd is sent by the network to the machine bound to a
+/// connection. Each machine receives only the instructions relevant to it.
 #[derive(Debug, Clone, MachineImpl)]
 pub enum NetCmd {
     /// Stop the network. Stop is used in conjuntion with starting and stopping the
     /// Server and Network.
     Stop,
     /// Binds a TCP listener to an address, notifying the sender when a connection is accepted.
-    BindTcpListener(String, NetSender),
-    /// Bind a UDP listener to an address, notifying the sender when a connection is accepted.
-    BindUdpListener(String, NetSender),
-    /// New connection notification (connection_id, bind_addr,
-    /// connect_from, max_byte) are sent to the sender regitered via the BindListener.
-    NewConn(NetConnId, String, String),
+    BindTcpListener(String, ListenerSender),
+    /// Binds a TCP listener to an address once, for two listeners, blue and green. Each new
+    /// connection is routed to one of them by the policy, allowing a new implementation of a
+    /// service to be canaried in-process.
+    BindTcpSwitch(String, ListenerSender, ListenerSender, SwitchPolicy),
+    /// Replace the policy of the switched listener bound to an address. Connections already
+    /// accepted stay with the listener they were routed to.
+    SetSwitchPolicy(String, SwitchPolicy),
+    /// Unbind the TCP listener bound to an address, so that it stops accepting connections. The
+    /// connections already accepted are unaffected. Once it has shut down, true is sent to the
+    /// sender, or false if no listener was bound to the address.
+    UnbindTcpListener(String, channel::Sender<bool>),
+    /// Bind a UDP listener to an address, notifying the sender when a packet arrives.
+    BindUdpListener(String, ListenerSender),
     /// BindConn starts the flow of information (connection_id, sender) between the network and
     /// a sender.
-    BindConn(NetConnId, NetSender),
-    /// When sent to the network, CloseConn closes the connection, also known as a local close.
-    /// When received by the BindConn listener, CloseConn is notification that the connection
-    /// has been closed, also known as a remote close.
+    BindConn(NetConnId, ConnSender),
+    /// MirrorConn sends a copy of the bytes read from a connection to a shadow, in addition to
+    /// the sender bound to it, for validating a new implementation against real traffic. The
+    /// shadow is told when the connection closes. It is never waited upon, when its queue is full
+    /// the copy is dropped. Its responses should be sent to a NetSender which discards them, such
+    /// as NetCore::get_discarding_sender(), rather than to the network.
+    MirrorConn(NetConnId, ConnSender),
+    /// StopMirror stops sending copies of the bytes read from a connection to its shadow.
+    StopMirror(NetConnId),
+    /// SetConnData attaches the data to a connection, replacing any already attached, so that a
+    /// listener needn't keep its own map of connections to their session state.
+    SetConnData(NetConnId, ConnData),
+    /// GetConnData sends the data attached to a connection, None if there's none, or the
+    /// connection is unknown, to the sender.
+    GetConnData(NetConnId, channel::Sender<Option<ConnData>>),
+    /// SetTarpit sets the tarpit policy of the TCP listener bound to an address, or if None,
+    /// stops tarpitting. Clients matching the policy have their accepts and reads slowed, rather
+    /// than being rejected.
+    SetTarpit(String, Option<TarpitPolicy>),
+    /// SetEnricher sets the enricher of the TCP listener bound to an address, or if None, removes
+    /// it. The enricher attaches data to each connection accepted, before the listener is told
+    /// of it.
+    SetEnricher(String, Option<Arc<dyn ConnEnricher>>),
+    /// SetFrontDoor puts the TCP listener bound to an address in cluster front door mode, or if
+    /// None, takes it out of it. Each connection accepted is either handled by this node, or
+    /// proxied to the node which owns its client.
+    SetFrontDoor(String, Option<Arc<FrontDoor>>),
+    /// ReportAbuse reports the client of a connection for abuse, such as failed auth, counting
+    /// toward it being tarpitted by the listener which accepted the connection.
+    ReportAbuse(NetConnId),
+    /// GetTarpitStats sends the tarpit counters of the TCP listener bound to an address, None if
+    /// there's no listener bound, to the sender.
+    GetTarpitStats(String, channel::Sender<Option<TarpitStats>>),
+    /// SetCompression compresses the bytes of a connection on the wire, from now on, or if None,
+    /// stops compressing them. The machine bound to the connection sends, and receives, the
+    /// uncompressed bytes.
+    SetCompression(NetConnId, Option<Compression>),
+    /// GetCompressionStats sends the counts of bytes of a compressed connection, None if it
+    /// isn't compressed, or is unknown, to the sender.
+    GetCompressionStats(NetConnId, channel::Sender<Option<CompressionStats>>),
+    /// CloseConn closes the connection, also known as a local close.
     CloseConn(NetConnId),
-    /// Sent to the BindConn sender, RecvBytes provides bytes read from the connection.
-    RecvBytes(NetConnId, Vec<u8>),
-    /// Sent to UDP listener
-    /// socket id, destination, source, bytes
-    RecvPkt(NetConnId, String, String, Vec<u8>),
-    /// Sent to the network, SendBytes provides bytes to be written to the network.
+    /// SendBytes provides bytes to be written to the network.
     SendBytes(NetConnId, Vec<u8>),
     /// Send UDP packet to network
     /// socket id, destination, bytes
     SendPkt(NetConnId, String, Vec<u8>),
-    /// Sent to the BindConn sender, it provides an update to the number of bytes
-    /// available for writing to the network. A use case for this would be providing
-    /// feedback for throttling data being generated for a connection.
+}
+
+/// ListenerCmd is sent by the network to the sender registered via BindTcpListener or BindUdpListener.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum ListenerCmd {
+    /// New connection notification (connection_id, bind_addr, connect_from).
+    NewConn(NetConnId, String, String),
+    /// Notification that a connection accepted by the listener has been closed, along with the
+    /// data attached to it, if any.
+    CloseConn(NetConnId, Option<ConnData>),
+    /// Sent to UDP listener
+    /// socket id, destination, source, bytes
+    RecvPkt(NetConnId, String, String, Vec<u8>),
+}
+
+/// ConnCmd is sent by the network to the sender bound to a connection via BindConn.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum ConnCmd {
+    /// RecvBytes provides bytes read from the connection.
+    RecvBytes(NetConnId, Vec<u8>),
+    /// Notification that the connection has been closed, also known as a remote close.
+    CloseConn(NetConnId),
+    /// It provides an update to the number of bytes available for writing to the network.
+    /// A use case for this would be providing feedback for throttling data being generated
+    /// for a connection.
     SendReady(NetConnId, usize),
+    /// Notification that the network rejected a frame of the connection, as it exceeded the
+    /// maximum frame size, see NetCore::set_max_frame_size().
+    Rejected(NetConnId, FrameError),
+}
+
+/// FrameError is why the network rejected a frame of a connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameError {
+    /// The bytes of a SendBytes exceeded the maximum frame size, so none of them were sent.
+    SendTooLarge { size: usize, max: usize },
+    /// The bytes read, once decompressed, exceeded the maximum frame size, so the connection was
+    /// closed, rather than buffering them.
+    RecvTooLarge { max: usize },
 }
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::SendTooLarge { size, max } => write!(f, "frame of {} bytes exceeds max frame size {}", size, max),
+            Self::RecvTooLarge { max } => write!(f, "frame received exceeds max frame size {}", max),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// SwitchPolicy determines which of the listeners bound by BindTcpSwitch is told of a new connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SwitchPolicy {
+    /// Every new connection goes to blue.
+    Blue,
+    /// Every new connection goes to green.
+    Green,
+    /// The percentage, from 0 to 100, of new connections which go to green, the rest go to blue.
+    GreenPercent(u8),
+}
+
 /// A network connection is always expressed as a NetConnId and identifies a specific
 /// network connection.
 pub type NetConnId = usize;
+/// Opaque data, such as session state, attached to a connection via SetConnData.
+pub type ConnData = Arc<dyn std::any::Any + Send + Sync>;
 /// Shorthand for a sender, that can be sent NetCmd instructions.
 pub type NetSender = channel::Sender<NetCmd>;
 pub type NetReceiver = channel::Receiver<NetCmd>;
+/// Shorthand for a sender, that can be sent ListenerCmd instructions.
+pub type ListenerSender = channel::Sender<ListenerCmd>;
+/// Shorthand for a sender, that can be sent ConnCmd instructions.
+pub type ConnSender = channel::Sender<ConnCmd>;
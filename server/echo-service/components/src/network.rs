@@ -7,6 +7,26 @@ use smol::{
 
 use super_slab::SuperSlab;
 
+use crate::{
+    buffer_pool::BufferPool,
+    compression::{Codec, Compression},
+    enrich::ConnEnricher,
+    front_door::FrontDoor,
+    tarpit::{Tarpit, TarpitPolicy},
+};
+use crossbeam::atomic::AtomicCell;
+use machine_foundation::get_executor_thread_count;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex as SyncMutex, Once,
+    },
+    time::Duration,
+};
+
 // This is where machines meet the network.
 pub mod net {
     // this allows us to easily use ? for error handling
@@ -24,6 +44,35 @@ enum NetCoreField {
 #[allow(non_upper_case_globals)]
 static netcore: AtomicRefCell<NetCore> = AtomicRefCell::new(NetCore::new());
 
+#[allow(non_upper_case_globals)]
+// The time a connection outlives the machine it is bound to, before it is closed.
+static orphan_grace: AtomicCell<Duration> = AtomicCell::new(Duration::from_secs(1));
+
+#[allow(non_upper_case_globals)]
+// The number of reads a connection makes in a row, before yielding to the other tasks of its executor.
+static max_consecutive_reads: AtomicCell<usize> = AtomicCell::new(16);
+
+#[allow(non_upper_case_globals)]
+// The maximum size of a frame, sent, or received once decompressed, 0 if unlimited.
+static max_frame_size: AtomicCell<usize> = AtomicCell::new(1 << 20);
+
+#[allow(non_upper_case_globals)]
+// Whether listeners are bound with SO_REUSEPORT, so that processes can share them.
+static reuse_port: AtomicCell<bool> = AtomicCell::new(false);
+
+#[allow(non_upper_case_globals)]
+// The number of sockets a listener accepts on, 0 for one per executor thread.
+static accept_shards: AtomicCell<usize> = AtomicCell::new(1);
+
+// The interval at which the connections are checked for the machine they are bound to dying.
+const ORPHAN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// The connections watched for the machine they are bound to dying, along with the signal closed
+// once it has. A single task checks them all, rather than each connection waking to check itself.
+type OrphanWatch = Vec<(channel::WeakSender<ConnCmd>, channel::Sender<()>)>;
+static ORPHAN_WATCH: Lazy<SyncMutex<OrphanWatch>> = Lazy::new(|| SyncMutex::new(Vec::new()));
+static ORPHAN_CHECK: Once = Once::new();
+
 #[derive(Default)]
 pub struct NetCore {
     state: NetCoreField,
@@ -49,8 +98,9 @@ impl NetCore {
             }
         }
         let (sender, receiver) = smol::channel::unbounded::<NetCmd>();
+        // the controller is the control-plane, such as closing connections, ahead of data traffic
         get_executor()
-            .spawn(async move {
+            .spawn_high(async move {
                 let mut controller = NetController::default();
                 while let Ok(cmd) = receiver.recv().await {
                     match cmd {
@@ -60,7 +110,8 @@ impl NetCore {
                         },
                     }
                 }
-                // exit on channel close
+                // exit on channel close, tearing down every listener and connection
+                controller.shutdown().await;
             })
             .detach();
         netcore.borrow_mut().sender = NetCoreField::NetSender(sender);
@@ -82,6 +133,56 @@ impl NetCore {
         smol::channel::unbounded().0
     }
 
+    /// Get a sender which discards the instructions sent to it, for a shadow, whose responses
+    /// mustn't reach the network.
+    pub fn get_discarding_sender() -> NetSender {
+        let (sender, receiver) = smol::channel::unbounded::<NetCmd>();
+        get_executor().spawn(async move { while receiver.recv().await.is_ok() {} }).detach();
+        sender
+    }
+
+    /// Set the time a connection outlives the machine it is bound to, returning the previous value.
+    /// When the machine dies, its channel closing, the connection is closed once the grace period
+    /// passes, unless it has been bound to another machine.
+    pub fn set_orphan_grace(grace: Duration) -> Duration { orphan_grace.swap(grace) }
+    /// Get the time a connection outlives the machine it is bound to.
+    pub fn get_orphan_grace() -> Duration { orphan_grace.load() }
+
+    /// Set the number of reads a connection makes in a row, before yielding to the other tasks of
+    /// its executor, returning the previous value. A read of a connection which always has bytes
+    /// waiting never yields by itself, so without this, a connection streaming continuously would
+    /// starve the other connections sharing its executor. If 0, a connection never yields. The
+    /// default is 16.
+    pub fn set_max_consecutive_reads(reads: usize) -> usize { max_consecutive_reads.swap(reads) }
+    /// Get the number of reads a connection makes in a row, before yielding.
+    pub fn get_max_consecutive_reads() -> usize { max_consecutive_reads.load() }
+
+    /// Set the maximum size of a frame, returning the previous value. A SendBytes larger than it
+    /// isn't sent, and the bytes read from a compressed connection, once decompressed, mayn't
+    /// exceed it, else the connection is closed. Either way, the machine bound to the connection
+    /// is sent ConnCmd::Rejected. If 0, frames are unlimited. The default is 1MiB.
+    pub fn set_max_frame_size(size: usize) -> usize { max_frame_size.swap(size) }
+    /// Get the maximum size of a frame.
+    pub fn get_max_frame_size() -> usize { max_frame_size.load() }
+
+    /// Set whether listeners are bound with SO_REUSEPORT, returning the previous value. The
+    /// kernel then spreads the connections to an address across every process listening on it,
+    /// which is how the workers of a WorkerPool share their listeners. It is ignored where
+    /// SO_REUSEPORT isn't supported. The default is false.
+    pub fn set_reuse_port(reuse: bool) -> bool { reuse_port.swap(reuse) }
+    /// Get whether listeners are bound with SO_REUSEPORT.
+    pub fn get_reuse_port() -> bool { reuse_port.load() }
+
+    /// Set the number of sockets a listener accepts on, returning the previous value. With more
+    /// than one, a listener binds a socket per shard with SO_REUSEPORT, each accepting on an
+    /// executor of its own, so the kernel spreads the connections across them, rather than a
+    /// single accept loop taking them all. If 0, there is a shard per executor thread. It applies
+    /// to listeners bound from then on, and is ignored where SO_REUSEPORT isn't supported. The
+    /// default is 1.
+    pub fn set_accept_shards(shards: usize) -> usize { accept_shards.swap(shards) }
+    /// Get the number of sockets a listener accepts on.
+    pub fn get_accept_shards() -> usize { accept_shards.load() }
+
     pub fn stop() {
         if let NetCoreField::ServiceState(ref mut state) = netcore.borrow_mut().state {
             if state.can_stop() {
@@ -99,28 +200,292 @@ impl NetCore {
 struct Server {
     is_dead: bool,
     bind_addr: String,
-    listener_task: BackgroundTask,
+    listener_tasks: Vec<BackgroundHandle<()>>,
     key: usize,
 }
 
+// The listeners a server tells of new connections, either a single listener, or a switched
+// pair, blue and green, which are chosen between by a policy.
+#[derive(Debug, Clone)]
+enum ListenerRoute {
+    Single(ListenerSender),
+    Switch(ListenerSender, ListenerSender, Arc<AtomicCell<SwitchPolicy>>),
+}
+
+impl ListenerRoute {
+    // Select the listener for the nth connection accepted.
+    fn select(&self, accepted: u64) -> &ListenerSender {
+        match self {
+            Self::Single(sender) => sender,
+            Self::Switch(blue, green, policy) if policy.load().is_green(accepted) => green,
+            Self::Switch(blue, _, _) => blue,
+        }
+    }
+}
+
+impl SwitchPolicy {
+    // Return true if the nth connection accepted goes to green. A percentage is spread evenly
+    // across the connections, rather than randomly, so it holds for a small number of them.
+    fn is_green(&self, accepted: u64) -> bool {
+        match *self {
+            Self::Blue => false,
+            Self::Green => true,
+            Self::GreenPercent(percent) => {
+                let percent = u64::from(percent.min(100));
+                (accepted + 1) * percent / 100 != accepted * percent / 100
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Connection {
     stream: TcpStream,
-    listener_sender: NetSender,
-    sender: Option<NetSender>,
-    recv_task: BackgroundTask,
+    listener_sender: ListenerSender,
+    sender: Option<ConnSender>,
+    recv_task: Option<BackgroundHandle<()>>,
+    mirror: Arc<SyncMutex<Option<ConnSender>>>,
+    data: Arc<SyncMutex<Option<ConnData>>>,
+    remote: SocketAddr,
+    tarpit: Arc<Tarpit>,
+    codec: Arc<SyncMutex<Option<Codec>>>,
+}
+
+// Decompress bytes read from a connection, if it is compressed.
+fn decompress(codec: &SyncMutex<Option<Codec>>, bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    match codec.lock().unwrap_or_else(|err| err.into_inner()).as_mut() {
+        Some(codec) => {
+            let decompressed = codec.decompress(&bytes, NetCore::get_max_frame_size());
+            BufferPool::recycle(bytes);
+            decompressed
+        },
+        None => Ok(bytes),
+    }
+}
+
+// The enricher of a listener, if any.
+type Enricher = Arc<SyncMutex<Option<Arc<dyn ConnEnricher>>>>;
+
+// The front door of a listener, if any.
+type FrontDoorSlot = Arc<SyncMutex<Option<Arc<FrontDoor>>>>;
+
+// Add a connection accepted by a listener, enriching it if the listener has an enricher, and tell
+// the listener of it.
+async fn accept_conn(
+    connections: &Mutex<SuperSlab<Connection>>, stream: TcpStream, remote: SocketAddr, address: &str, sender: ListenerSender,
+    tarpit: Arc<Tarpit>, enricher: Option<Arc<dyn ConnEnricher>>,
+) {
+    let data = match enricher {
+        Some(enricher) => enricher.enrich(address, remote).await,
+        None => None,
+    };
+    let connection = Connection {
+        stream,
+        listener_sender: sender.clone(),
+        sender: None,
+        recv_task: None,
+        mirror: Arc::new(SyncMutex::new(None)),
+        data: Arc::new(SyncMutex::new(data)),
+        remote,
+        tarpit,
+        codec: Arc::new(SyncMutex::new(None)),
+    };
+    let id = {
+        let mut connections = connections.lock().await;
+        let entry = connections.vacant_entry();
+        let id: usize = entry.key();
+        entry.insert(connection);
+        id
+    };
+    sender
+        .send(ListenerCmd::NewConn(id, address.to_string(), remote.to_string()))
+        .await
+        .ok();
+}
+
+// Watch for the machine a connection is bound to dying, returning the signal which is closed once
+// it has.
+fn watch_orphan(sender: &ConnSender) -> channel::Receiver<()> {
+    let (signal, closed) = channel::bounded(1);
+    ORPHAN_WATCH
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push((sender.downgrade(), signal));
+    ORPHAN_CHECK.call_once(|| get_executor().spawn(check_orphans()).detach());
+    closed
+}
+
+// Check the connections watched, every interval, closing the signal of those whose machine has
+// died, by dropping it, and forgetting those no longer waiting on their signal.
+async fn check_orphans() {
+    loop {
+        smol::Timer::after(ORPHAN_CHECK_INTERVAL).await;
+        ORPHAN_WATCH
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|(sender, signal)| !signal.is_closed() && sender.upgrade().is_some_and(|sender| !sender.is_closed()));
+    }
+}
+
+// Complete once the machine a connection is bound to has died, and the grace period has passed.
+async fn orphaned(closed: channel::Receiver<()>) {
+    closed.recv().await.ok();
+    smol::Timer::after(NetCore::get_orphan_grace()).await;
+}
+
+// Send a copy of a read, or close, to a connection's shadow, if it has one.
+fn mirror(shadow: &SyncMutex<Option<ConnSender>>, cmd: ConnCmd) {
+    let shadow = shadow.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(shadow) = shadow.as_ref() {
+        shadow.try_send(cmd).ok();
+    }
+}
+
+// Bind a listener to the address, with SO_REUSEPORT if it is set.
+async fn bind_listener(address: &str) -> std::io::Result<smol::net::TcpListener> {
+    #[cfg(unix)]
+    {
+        if NetCore::get_reuse_port() {
+            return bind_reuse_port(resolve(address).await?);
+        }
+    }
+    smol::net::TcpListener::bind(address).await
+}
+
+// Bind the listeners of a listener's shards, each with SO_REUSEPORT if there is more than one, so
+// the kernel spreads the connections across them.
+async fn bind_listeners(address: &str, shards: usize) -> std::io::Result<Vec<smol::net::TcpListener>> {
+    let shards = match shards {
+        0 => get_executor_thread_count(),
+        shards => shards,
+    };
+    #[cfg(unix)]
+    {
+        if shards > 1 {
+            let addr = resolve(address).await?;
+            return (0 .. shards).map(|_| bind_reuse_port(addr)).collect();
+        }
+    }
+    Ok(vec![bind_listener(address).await?])
+}
+
+// Resolve the address to bind.
+async fn resolve(address: &str) -> std::io::Result<SocketAddr> {
+    smol::net::resolve(address)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to bind"))
+}
+
+// Bind a listener with SO_REUSEPORT, so that other processes can bind the address as well.
+#[cfg(unix)]
+fn bind_reuse_port(addr: SocketAddr) -> std::io::Result<smol::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    use std::convert::TryFrom;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    smol::net::TcpListener::try_from(std::net::TcpListener::from(socket))
+}
+
+// The Acceptor accepts the connections of a listener, shared by its shards.
+#[derive(Clone)]
+struct Acceptor {
+    address: String,
+    route: ListenerRoute,
+    accepted: Arc<AtomicU64>,
+    tarpit: Arc<Tarpit>,
+    enricher: Enricher,
+    front_door: FrontDoorSlot,
+    connections: Arc<Mutex<SuperSlab<Connection>>>,
+    // the tasks of the network, which the connections being accepted, and the proxies of the
+    // front door, are spawned in
+    tasks: Arc<BackgroundTaskGroup>,
+}
+
+impl Acceptor {
+    // Accept connections from the listener, forever.
+    async fn run(self, listener: smol::net::TcpListener) {
+        let address = &self.address;
+        loop {
+            if let Ok((stream, addr)) = listener.accept().await {
+                log::debug!("tcp_listener bound to local_addr={} accepted remote_addr={}", address, addr);
+                let sender = self.route.select(self.accepted.fetch_add(1, Ordering::Relaxed)).clone();
+                let enricher = self.enricher.lock().unwrap_or_else(|err| err.into_inner()).clone();
+                let front_door = self.front_door.lock().unwrap_or_else(|err| err.into_inner()).clone();
+                match (self.tarpit.on_connect(addr.ip()), enricher, front_door) {
+                    (None, None, None) => accept_conn(&self.connections, stream, addr, address, sender, self.tarpit.clone(), None).await,
+                    // a tarpitted, enriched, or routed, client waits, without holding up the others
+                    (delay, enricher, front_door) => {
+                        if delay.is_some() {
+                            log::debug!("tcp_listener bound to local_addr={} slowed remote_addr={}", address, addr);
+                        }
+                        let connections = self.connections.clone();
+                        let address = address.clone();
+                        let tarpit = self.tarpit.clone();
+                        let tasks = self.tasks.clone();
+                        // the connection is spawned in the network's tasks, so that it is stopped along with them
+                        let task = get_executor().spawn(async move {
+                            if let Some(delay) = delay {
+                                smol::Timer::after(delay).await;
+                            }
+                            let stream = match front_door {
+                                Some(front_door) => match front_door.admit(stream, addr, &tasks).await {
+                                    Some(stream) => stream,
+                                    None => return,
+                                },
+                                None => stream,
+                            };
+                            accept_conn(&connections, stream, addr, &address, sender, tarpit, enricher).await;
+                        });
+                        self.tasks.spawn(task, "accept");
+                    },
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 struct NetController {
     servers: Arc<Mutex<SuperSlab<Server>>>,
     connections: Arc<Mutex<SuperSlab<Connection>>>,
+    // the policies of the switched listeners, by address
+    switches: HashMap<String, Arc<AtomicCell<SwitchPolicy>>>,
+    // the tarpits of the listeners, by address
+    tarpits: HashMap<String, Arc<Tarpit>>,
+    // the enrichers of the listeners, by address
+    enrichers: HashMap<String, Enricher>,
+    // the front doors of the listeners, by address
+    front_doors: HashMap<String, FrontDoorSlot>,
+    // the tasks of the listeners, connections, and proxies
+    tasks: Arc<BackgroundTaskGroup>,
 }
 impl NetController {
+    // Cancel the tasks of every listener and connection, waiting for them to finish.
+    async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+        log::info!("network tasks stopped");
+    }
+
     async fn handle(&mut self, cmd: NetCmd) -> net::Result<()> {
         match cmd {
             NetCmd::BindTcpListener(address, sender) => {
-                self.bind_tcp_listener(address, sender).await.ok();
+                self.bind_tcp_listener(address, ListenerRoute::Single(sender)).await.ok();
+            },
+            NetCmd::BindTcpSwitch(address, blue, green, policy) => {
+                let route = ListenerRoute::Switch(blue, green, Arc::new(AtomicCell::new(policy)));
+                self.bind_tcp_listener(address, route).await.ok();
+            },
+            NetCmd::SetSwitchPolicy(address, policy) => {
+                self.set_switch_policy(address, policy).await.ok();
+            },
+            NetCmd::UnbindTcpListener(address, reply) => {
+                self.unbind_tcp_listener(address, reply).await.ok();
             },
             NetCmd::BindUdpListener(address, sender) => {
                 self.bind_udp_listener(address, sender).await.ok();
@@ -128,6 +493,47 @@ impl NetController {
             NetCmd::BindConn(conn_id, sender) => {
                 self.bind_conn(conn_id, sender).await.ok();
             },
+            NetCmd::MirrorConn(conn_id, shadow) => {
+                self.mirror_conn(conn_id, Some(shadow)).await.ok();
+            },
+            NetCmd::StopMirror(conn_id) => {
+                self.mirror_conn(conn_id, None).await.ok();
+            },
+            NetCmd::SetConnData(conn_id, data) => {
+                self.set_conn_data(conn_id, data).await.ok();
+            },
+            NetCmd::GetConnData(conn_id, reply) => {
+                self.get_conn_data(conn_id, reply).await.ok();
+            },
+            NetCmd::SetTarpit(address, policy) => {
+                self.set_tarpit(address, policy).await.ok();
+            },
+            NetCmd::SetEnricher(address, enricher) => {
+                self.set_enricher(address, enricher).await.ok();
+            },
+            NetCmd::SetFrontDoor(address, front_door) => {
+                self.set_front_door(address, front_door).await.ok();
+            },
+            NetCmd::ReportAbuse(conn_id) => {
+                self.report_abuse(conn_id).await.ok();
+            },
+            NetCmd::GetTarpitStats(address, reply) => {
+                reply.try_send(self.tarpits.get(&address).map(|tarpit| tarpit.get_stats())).ok();
+            },
+            NetCmd::SetCompression(conn_id, compression) => {
+                self.set_compression(conn_id, compression).await.ok();
+            },
+            NetCmd::GetCompressionStats(conn_id, reply) => {
+                let mut connections = self.connections.lock().await;
+                let stats = connections.get_mut(conn_id).and_then(|conn| {
+                    conn.codec
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .as_ref()
+                        .map(Codec::get_stats)
+                });
+                reply.try_send(stats).ok();
+            },
             NetCmd::CloseConn(conn_id) => {
                 self.close_conn(conn_id).await.ok();
             },
@@ -144,98 +550,301 @@ impl NetController {
         Ok(())
     }
     fn unknown_cmd(&mut self, _cmd: &NetCmd) {}
-    async fn bind_tcp_listener(&mut self, address: String, sender: NetSender) -> net::Result<()> {
-        let executor = get_executor();
-        let task = {
-            log::debug!("tcp_listener bound to local_addr={}", address);
-            let address = address.clone();
-            let connections = self.connections.clone();
-            executor.spawn(async move {
-                match smol::net::TcpListener::bind(address.clone()).await {
-                    Ok(listener) => loop {
-                        if let Ok((stream, addr)) = listener.accept().await {
-                            log::debug!("tcp_listener bound to local_addr={} accepted remote_addr={}", address, addr);
-                            let connection = Connection {
-                                stream,
-                                listener_sender: sender.clone(),
-                                sender: None,
-                                recv_task: BackgroundTask::default(),
-                            };
-                            let mut connections = connections.lock().await;
-                            let entry = connections.vacant_entry();
-                            let id: usize = entry.key();
-                            entry.insert(connection);
-                            sender.send(NetCmd::NewConn(id, address.clone(), addr.to_string())).await.ok();
-                        }
-                    },
-                    Err(_err) => {},
-                }
-            })
+    async fn bind_tcp_listener(&mut self, address: String, route: ListenerRoute) -> net::Result<()> {
+        if let ListenerRoute::Switch(_, _, policy) = &route {
+            self.switches.insert(address.clone(), policy.clone());
+        }
+        let tarpit = self.tarpits.entry(address.clone()).or_default().clone();
+        let enricher = self.enrichers.entry(address.clone()).or_default().clone();
+        let front_door = self.front_doors.entry(address.clone()).or_default().clone();
+        let listeners = bind_listeners(&address, NetCore::get_accept_shards()).await.map_err(|err| {
+            log::warn!("tcp_listener failed to bind local_addr={} err={}", address, err);
+            err
+        })?;
+        log::debug!("tcp_listener bound to local_addr={} shards={}", address, listeners.len());
+        let acceptor = Acceptor {
+            address: address.clone(),
+            route,
+            accepted: Arc::new(AtomicU64::new(0)),
+            tarpit,
+            enricher,
+            front_door,
+            connections: self.connections.clone(),
+            tasks: self.tasks.clone(),
         };
-
-        let task = BackgroundTask::detach(task, "listener");
+        // each shard accepts on an executor of its own
+        let tasks = listeners
+            .into_iter()
+            .map(|listener| self.tasks.spawn(get_executor().spawn(acceptor.clone().run(listener)), "listener"))
+            .collect();
         let mut servers = self.servers.lock().await;
         let entry = servers.vacant_entry();
         let key = entry.key();
         let server = Server {
             is_dead: false,
             bind_addr: address,
-            listener_task: task,
+            listener_tasks: tasks,
             key,
         };
         entry.insert(server);
         Ok(())
     }
 
-    async fn bind_udp_listener(&mut self, _address: String, _sender: NetSender) -> net::Result<()> { Ok(()) }
+    async fn unbind_tcp_listener(&mut self, address: String, reply: channel::Sender<bool>) -> net::Result<()> {
+        self.switches.remove(&address);
+        let unbound: Vec<Server> = {
+            let mut servers = self.servers.lock().await;
+            let keys: Vec<usize> = servers
+                .iter()
+                .filter(|(_, server)| server.bind_addr == address)
+                .map(|(key, _)| key)
+                .collect();
+            keys.into_iter().map(|key| servers.remove(key)).collect()
+        };
+        // the reply waits for the listeners to shut down, without holding up the controller
+        get_executor()
+            .spawn(async move {
+                let found = !unbound.is_empty();
+                for task in unbound.into_iter().flat_map(|server| server.listener_tasks) {
+                    task.cancel();
+                    task.join().await;
+                }
+                log::debug!("tcp_listener unbound from local_addr={} found={}", address, found);
+                reply.send(found).await.ok();
+            })
+            .detach();
+        Ok(())
+    }
 
-    async fn bind_conn(&mut self, conn_id: NetConnId, sender: NetSender) -> net::Result<()> {
+    async fn set_switch_policy(&mut self, address: String, policy: SwitchPolicy) -> net::Result<()> {
+        match self.switches.get(&address) {
+            Some(switch) => {
+                log::info!("tcp_listener bound to local_addr={} switched to policy={:?}", address, policy);
+                switch.store(policy);
+            },
+            None => log::warn!(
+                "tcp_listener bound to local_addr={} isn't switched, policy={:?} ignored",
+                address,
+                policy
+            ),
+        }
+        Ok(())
+    }
+
+    async fn set_tarpit(&mut self, address: String, policy: Option<TarpitPolicy>) -> net::Result<()> {
+        match self.tarpits.get(&address) {
+            Some(tarpit) => {
+                log::info!("tcp_listener bound to local_addr={} tarpit policy={:?}", address, policy);
+                tarpit.set_policy(policy);
+            },
+            None => log::warn!(
+                "no tcp_listener bound to local_addr={}, tarpit policy={:?} ignored",
+                address,
+                policy
+            ),
+        }
+        Ok(())
+    }
+
+    async fn set_enricher(&mut self, address: String, enricher: Option<Arc<dyn ConnEnricher>>) -> net::Result<()> {
+        match self.enrichers.get(&address) {
+            Some(slot) => {
+                log::info!("tcp_listener bound to local_addr={} enricher={:?}", address, enricher);
+                *slot.lock().unwrap_or_else(|err| err.into_inner()) = enricher;
+            },
+            None => log::warn!("no tcp_listener bound to local_addr={}, enricher={:?} ignored", address, enricher),
+        }
+        Ok(())
+    }
+
+    async fn set_front_door(&mut self, address: String, front_door: Option<Arc<FrontDoor>>) -> net::Result<()> {
+        match self.front_doors.get(&address) {
+            Some(slot) => {
+                log::info!("tcp_listener bound to local_addr={} front_door={:?}", address, front_door);
+                *slot.lock().unwrap_or_else(|err| err.into_inner()) = front_door;
+            },
+            None => log::warn!(
+                "no tcp_listener bound to local_addr={}, front_door={:?} ignored",
+                address,
+                front_door
+            ),
+        }
+        Ok(())
+    }
+
+    async fn report_abuse(&mut self, conn_id: NetConnId) -> net::Result<()> {
+        let mut connections = self.connections.lock().await;
+        if let Some(conn) = connections.get_mut(conn_id) {
+            log::debug!("connection id={} remote_addr={} reported for abuse", conn_id, conn.remote);
+            conn.tarpit.on_abuse(conn.remote.ip());
+        }
+        Ok(())
+    }
+
+    async fn bind_udp_listener(&mut self, _address: String, _sender: ListenerSender) -> net::Result<()> { Ok(()) }
+
+    async fn bind_conn(&mut self, conn_id: NetConnId, sender: ConnSender) -> net::Result<()> {
         let mut connections = self.connections.lock().await;
         if let Some(conn) = connections.get_mut(conn_id) {
             let mut stream = conn.stream.clone();
             let listener_sender = conn.listener_sender.clone();
+            let shadow = conn.mirror.clone();
+            let data = conn.data.clone();
+            let tarpit = conn.tarpit.clone();
+            let remote = conn.remote.ip();
+            let codec = conn.codec.clone();
+            conn.sender = Some(sender.clone());
             let recv_task = get_executor().spawn(async move {
+                let orphaned = orphaned(watch_orphan(&sender));
+                smol::pin!(orphaned);
+                let mut consecutive_reads = 0;
                 loop {
-                    let mut buf = vec![0u8; 1024];
-                    match stream.read(&mut buf).await {
-                        Ok(0) => {
-                            sender.send(NetCmd::CloseConn(conn_id)).await.ok();
-                            listener_sender.send(NetCmd::CloseConn(conn_id)).await.ok();
-                            break;
-                        },
-                        Ok(bytes_read) => {
-                            unsafe {
-                                buf.set_len(bytes_read);
+                    // yield, rather than monopolize the executor, if reading in a row
+                    let max_reads = NetCore::get_max_consecutive_reads();
+                    consecutive_reads += 1;
+                    if max_reads > 0 && consecutive_reads > max_reads {
+                        consecutive_reads = 1;
+                        smol::future::yield_now().await;
+                    }
+                    let mut buf = BufferPool::take();
+                    buf.resize(1024, 0);
+                    let mut waited = false;
+                    let read = async {
+                        if let Some(delay) = tarpit.get_read_delay(remote) {
+                            smol::Timer::after(delay).await;
+                        }
+                        let mut read = stream.read(&mut buf);
+                        match smol::future::poll_once(&mut read).await {
+                            Some(result) => Some(result),
+                            None => {
+                                waited = true;
+                                Some(read.await)
+                            },
+                        }
+                    };
+                    let orphan = async {
+                        orphaned.as_mut().await;
+                        None
+                    };
+                    let result = smol::future::or(read, orphan).await;
+                    // reads in a row are only those which didn't wait for bytes
+                    if waited {
+                        consecutive_reads = 1;
+                    }
+                    match result {
+                        Some(Ok(bytes_read)) if bytes_read > 0 => {
+                            buf.truncate(bytes_read);
+                            match decompress(&codec, buf) {
+                                // the compressed bytes read so far are incomplete
+                                Ok(buf) if buf.is_empty() => (),
+                                Ok(buf) => {
+                                    mirror(&shadow, ConnCmd::RecvBytes(conn_id, buf.clone()));
+                                    sender.send(ConnCmd::RecvBytes(conn_id, buf)).await.ok();
+                                },
+                                Err(err) => {
+                                    // the bytes which follow can't be decompressed either, so stop reading
+                                    log::warn!("connection id={} closed, failed to decompress, error={}", conn_id, err);
+                                    stream.shutdown(Shutdown::Both).ok();
+                                    if let Some(err) = err.get_ref().and_then(|err| err.downcast_ref::<FrameError>()) {
+                                        sender.send(ConnCmd::Rejected(conn_id, *err)).await.ok();
+                                    }
+                                    mirror(&shadow, ConnCmd::CloseConn(conn_id));
+                                    sender.send(ConnCmd::CloseConn(conn_id)).await.ok();
+                                    let data = data.lock().unwrap_or_else(|err| err.into_inner()).take();
+                                    listener_sender.send(ListenerCmd::CloseConn(conn_id, data)).await.ok();
+                                    break;
+                                },
                             }
-                            sender.send(NetCmd::RecvBytes(conn_id, buf)).await.ok();
                         },
-                        Err(_err) => {
-                            sender.send(NetCmd::CloseConn(conn_id)).await.ok();
-                            listener_sender.send(NetCmd::CloseConn(conn_id)).await.ok();
+                        Some(_) => {
+                            mirror(&shadow, ConnCmd::CloseConn(conn_id));
+                            sender.send(ConnCmd::CloseConn(conn_id)).await.ok();
+                            let data = data.lock().unwrap_or_else(|err| err.into_inner()).take();
+                            listener_sender.send(ListenerCmd::CloseConn(conn_id, data)).await.ok();
+                            break;
+                        },
+                        None => {
+                            // the machine has died, so there's nothing to read into
+                            log::info!("connection id={} closed, the machine it is bound to has died", conn_id);
+                            stream.shutdown(Shutdown::Both).ok();
+                            mirror(&shadow, ConnCmd::CloseConn(conn_id));
+                            let data = data.lock().unwrap_or_else(|err| err.into_inner()).take();
+                            listener_sender.send(ListenerCmd::CloseConn(conn_id, data)).await.ok();
                             break;
                         },
                     }
                 }
             });
             let label = format!("connection id={}", conn_id);
-            let recv_task = BackgroundTask::detach(recv_task, &label);
-            conn.recv_task = recv_task;
+            conn.recv_task = Some(self.tasks.spawn(recv_task, &label));
         }
         Ok(())
     }
 
+    async fn mirror_conn(&mut self, conn_id: NetConnId, shadow: Option<ConnSender>) -> net::Result<()> {
+        let mut connections = self.connections.lock().await;
+        if let Some(conn) = connections.get_mut(conn_id) {
+            log::debug!("connection id={} mirrored={}", conn_id, shadow.is_some());
+            *conn.mirror.lock().unwrap_or_else(|err| err.into_inner()) = shadow;
+        }
+        Ok(())
+    }
+
+    async fn set_conn_data(&mut self, conn_id: NetConnId, data: ConnData) -> net::Result<()> {
+        let mut connections = self.connections.lock().await;
+        if let Some(conn) = connections.get_mut(conn_id) {
+            *conn.data.lock().unwrap_or_else(|err| err.into_inner()) = Some(data);
+        }
+        Ok(())
+    }
+
+    async fn get_conn_data(&mut self, conn_id: NetConnId, reply: channel::Sender<Option<ConnData>>) -> net::Result<()> {
+        let mut connections = self.connections.lock().await;
+        let data = connections
+            .get_mut(conn_id)
+            .and_then(|conn| conn.data.lock().unwrap_or_else(|err| err.into_inner()).clone());
+        reply.try_send(data).ok();
+        Ok(())
+    }
+
     async fn close_conn(&mut self, conn_id: NetConnId) -> net::Result<()> {
         let mut connections = self.connections.lock().await;
         if let Some(conn) = connections.get_mut(conn_id) {
-            conn.recv_task.cancel();
+            if let Some(recv_task) = &conn.recv_task {
+                recv_task.cancel();
+            }
             conn.stream.shutdown(Shutdown::Both).ok();
         }
         Ok(())
     }
 
+    async fn set_compression(&mut self, conn_id: NetConnId, compression: Option<Compression>) -> net::Result<()> {
+        let mut connections = self.connections.lock().await;
+        if let Some(conn) = connections.get_mut(conn_id) {
+            log::debug!("connection id={} compression={:?}", conn_id, compression);
+            let codec = compression.map(Codec::new).transpose()?;
+            *conn.codec.lock().unwrap_or_else(|err| err.into_inner()) = codec;
+        }
+        Ok(())
+    }
+
     async fn send_bytes(&mut self, conn_id: NetConnId, mut bytes: Vec<u8>) -> net::Result<()> {
+        let max = NetCore::get_max_frame_size();
+        if max > 0 && bytes.len() > max {
+            let sender = self.connections.lock().await.get_mut(conn_id).and_then(|conn| conn.sender.clone());
+            let err = FrameError::SendTooLarge { size: bytes.len(), max };
+            log::warn!("connection id={} rejected send, error={}", conn_id, err);
+            if let Some(sender) = sender {
+                sender.send(ConnCmd::Rejected(conn_id, err)).await.ok();
+            }
+            return Ok(());
+        }
         let mut connections = self.connections.lock().await;
         if let Some(conn) = connections.get_mut(conn_id) {
+            if let Some(codec) = conn.codec.lock().unwrap_or_else(|err| err.into_inner()).as_mut() {
+                let compressed = codec.compress(&bytes)?;
+                BufferPool::recycle(std::mem::replace(&mut bytes, compressed));
+            }
             let mut remaining = bytes.len();
             while remaining > 0 {
                 if let Ok(written) = conn.stream.write(&bytes).await {
@@ -251,6 +860,7 @@ impl NetController {
                 }
             }
         }
+        BufferPool::recycle(bytes);
         Ok(())
     }
 
@@ -263,4 +873,539 @@ mod tests {
 
     #[test]
     fn test_network_start() { NetCore::start(); }
+
+    #[cfg(unix)]
+    #[test]
+    fn share_listener_with_reuse_port() {
+        let addr: SocketAddr = "127.0.0.1:47402".parse().unwrap();
+        let first = bind_reuse_port(addr).unwrap();
+        let second = bind_reuse_port(addr).unwrap();
+        assert_eq!(addr, first.local_addr().unwrap());
+        assert_eq!(addr, second.local_addr().unwrap());
+        // without it, the address is in use
+        assert!(std::net::TcpListener::bind(addr).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shard_accepts() {
+        let listeners = smol::block_on(bind_listeners("127.0.0.1:47403", 3)).unwrap();
+        assert_eq!(3, listeners.len());
+        // a connection is accepted by one of the shards
+        let _stream = std::net::TcpStream::connect("127.0.0.1:47403").unwrap();
+        let accept = listeners
+            .iter()
+            .map(|listener| Box::pin(async move { listener.accept().await.ok() }));
+        assert!(smol::block_on(futures::future::select_all(accept)).0.is_some());
+    }
+
+    #[test]
+    fn switch_policy_spreads_percentage() {
+        let green = |policy: SwitchPolicy| (0 .. 100).filter(|n| policy.is_green(*n)).count();
+        assert_eq!(0, green(SwitchPolicy::Blue));
+        assert_eq!(100, green(SwitchPolicy::Green));
+        assert_eq!(25, green(SwitchPolicy::GreenPercent(25)));
+        assert_eq!(100, green(SwitchPolicy::GreenPercent(200)));
+        // a quarter is green within every four connections
+        assert_eq!(1, (0 .. 4).filter(|n| SwitchPolicy::GreenPercent(25).is_green(*n)).count());
+    }
+
+    #[test]
+    fn switch_between_listeners() {
+        fn accept(receiver: &channel::Receiver<ListenerCmd>) -> bool {
+            let recv = async { receiver.recv().await.ok() };
+            let timeout = async {
+                smol::Timer::after(std::time::Duration::from_millis(200)).await;
+                None
+            };
+            matches!(smol::block_on(smol::future::or(recv, timeout)), Some(ListenerCmd::NewConn(..)))
+        }
+        let address = "127.0.0.1:47319".to_string();
+        let (blue, blue_receiver) = channel::unbounded();
+        let (green, green_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            let policy = SwitchPolicy::GreenPercent(50);
+            controller
+                .handle(NetCmd::BindTcpSwitch(address.clone(), blue, green, policy))
+                .await
+                .ok();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+        });
+        let connect = || smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let _first = connect();
+        assert!(accept(&blue_receiver));
+        let _second = connect();
+        assert!(accept(&green_receiver));
+
+        smol::block_on(controller.handle(NetCmd::SetSwitchPolicy(address.clone(), SwitchPolicy::Blue))).ok();
+        let _third = connect();
+        let _fourth = connect();
+        assert!(accept(&blue_receiver));
+        assert!(accept(&blue_receiver));
+        assert!(green_receiver.is_empty());
+    }
+
+    #[test]
+    fn unbind_listener() {
+        let address = "127.0.0.1:47404".to_string();
+        let (listener, _receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        let (reply, replies) = channel::unbounded();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(TcpStream::connect(address.as_str()).await.is_ok());
+            // once the reply is received, the listener has shut down
+            controller
+                .handle(NetCmd::UnbindTcpListener(address.clone(), reply.clone()))
+                .await
+                .ok();
+            assert_eq!(Ok(true), replies.recv().await);
+            assert!(TcpStream::connect(address.as_str()).await.is_err());
+            controller.handle(NetCmd::UnbindTcpListener(address.clone(), reply)).await.ok();
+            assert_eq!(Ok(false), replies.recv().await);
+        });
+    }
+
+    #[test]
+    fn shutdown_tasks() {
+        let address = "127.0.0.1:47405".to_string();
+        let (listener, receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            let _stream = TcpStream::connect(address.as_str()).await.unwrap();
+            let conn_id = match receiver.recv().await {
+                Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+                cmd => panic!("unexpected {:?}", cmd),
+            };
+            let (conn, _conn_receiver) = channel::unbounded();
+            controller.handle(NetCmd::BindConn(conn_id, conn)).await.ok();
+            assert_eq!(2, controller.tasks.len());
+            // the listener, and the connection, are torn down together
+            controller.shutdown().await;
+            assert!(controller.tasks.is_empty());
+            assert!(TcpStream::connect(address.as_str()).await.is_err());
+        });
+    }
+
+    #[test]
+    fn mirror_to_shadow() {
+        use smol::io::AsyncWriteExt;
+        fn recv(receiver: &channel::Receiver<ConnCmd>) -> Option<Vec<u8>> {
+            let recv = async { receiver.recv().await.ok() };
+            let timeout = async {
+                smol::Timer::after(std::time::Duration::from_millis(200)).await;
+                None
+            };
+            match smol::block_on(smol::future::or(recv, timeout)) {
+                Some(ConnCmd::RecvBytes(_, bytes)) => Some(bytes),
+                _ => None,
+            }
+        }
+        let address = "127.0.0.1:47320".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+        });
+        let mut client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let (primary, primary_receiver) = channel::unbounded();
+        let (shadow, shadow_receiver) = channel::unbounded();
+        smol::block_on(async {
+            controller.handle(NetCmd::MirrorConn(conn_id, shadow)).await.ok();
+            controller.handle(NetCmd::BindConn(conn_id, primary)).await.ok();
+            client.write_all(b"hello").await.unwrap();
+        });
+        assert_eq!(Some(b"hello".to_vec()), recv(&primary_receiver));
+        assert_eq!(Some(b"hello".to_vec()), recv(&shadow_receiver));
+
+        smol::block_on(async {
+            controller.handle(NetCmd::StopMirror(conn_id)).await.ok();
+            client.write_all(b"world").await.unwrap();
+        });
+        assert_eq!(Some(b"world".to_vec()), recv(&primary_receiver));
+        assert_eq!(None, recv(&shadow_receiver));
+    }
+
+    #[test]
+    fn conn_data_returned_on_close() {
+        let address = "127.0.0.1:47321".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+        });
+        let client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let (conn, _conn_receiver) = channel::unbounded();
+        let (reply, data) = channel::unbounded();
+        smol::block_on(async {
+            controller.handle(NetCmd::GetConnData(conn_id, reply.clone())).await.ok();
+            assert!(data.recv().await.unwrap().is_none());
+            controller
+                .handle(NetCmd::SetConnData(conn_id, Arc::new("alice".to_string())))
+                .await
+                .ok();
+            controller.handle(NetCmd::GetConnData(conn_id, reply)).await.ok();
+            let session = data.recv().await.unwrap().unwrap();
+            assert_eq!(Some(&"alice".to_string()), session.downcast_ref::<String>());
+            controller.handle(NetCmd::BindConn(conn_id, conn)).await.ok();
+        });
+        drop(client);
+        match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::CloseConn(id, Some(session))) => {
+                assert_eq!(conn_id, id);
+                assert_eq!(Some(&"alice".to_string()), session.downcast_ref::<String>());
+            },
+            cmd => panic!("unexpected {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn close_orphaned_conn() {
+        use smol::io::AsyncReadExt;
+        let address = "127.0.0.1:47322".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let mut client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let (conn, conn_receiver) = channel::unbounded();
+        smol::block_on(controller.handle(NetCmd::BindConn(conn_id, conn))).ok();
+        // the machine dies, closing its channel
+        drop(conn_receiver);
+        match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::CloseConn(id, None)) => assert_eq!(conn_id, id),
+            cmd => panic!("unexpected {:?}", cmd),
+        }
+        let mut buf = [0u8; 8];
+        assert_eq!(0, smol::block_on(client.read(&mut buf)).unwrap());
+    }
+
+    #[test]
+    fn tarpit_rapid_reconnects() {
+        let address = "127.0.0.1:47323".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        let policy = TarpitPolicy {
+            max_connects: 1,
+            accept_delay_ms: 300,
+            ..TarpitPolicy::default()
+        };
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            controller.handle(NetCmd::SetTarpit(address.clone(), Some(policy))).await.ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let accept = || {
+            let start = std::time::Instant::now();
+            let _client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+            match smol::block_on(listener_receiver.recv()) {
+                Ok(ListenerCmd::NewConn(..)) => start.elapsed(),
+                cmd => panic!("unexpected {:?}", cmd),
+            }
+        };
+        assert!(accept() < Duration::from_millis(300));
+        // the reconnect is accepted, slowly
+        assert!(accept() >= Duration::from_millis(300));
+
+        let (reply, stats) = channel::unbounded();
+        smol::block_on(async {
+            controller.handle(NetCmd::GetTarpitStats(address.clone(), reply.clone())).await.ok();
+            let stats = stats.recv().await.unwrap().unwrap();
+            assert_eq!(1, stats.slowed_accepts);
+            assert_eq!(1, stats.tarpitted_clients);
+            controller
+                .handle(NetCmd::GetTarpitStats("127.0.0.1:1".to_string(), reply))
+                .await
+                .ok();
+        });
+        assert_eq!(Ok(None), smol::block_on(stats.recv()));
+
+        // a connection sleeping in the tarpit is stopped along with the network
+        let _client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        smol::block_on(async {
+            smol::Timer::after(Duration::from_millis(50)).await;
+            controller.shutdown().await;
+            smol::Timer::after(Duration::from_millis(400)).await;
+        });
+        assert!(controller.tasks.is_empty());
+        assert!(listener_receiver.is_empty());
+    }
+
+    #[test]
+    fn enrich_on_accept() {
+        use crate::enrich::{AddressClass, AddressClassifier};
+        let address = "127.0.0.1:47324".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            controller
+                .handle(NetCmd::SetEnricher(address.clone(), Some(Arc::new(AddressClassifier))))
+                .await
+                .ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let _client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        // the data is attached before the listener is told of the connection
+        let (reply, data) = channel::unbounded();
+        smol::block_on(controller.handle(NetCmd::GetConnData(conn_id, reply))).ok();
+        let data = smol::block_on(data.recv()).unwrap().unwrap();
+        assert_eq!(Some(&AddressClass::Internal), data.downcast_ref::<AddressClass>());
+    }
+
+    #[test]
+    fn proxy_to_owner() {
+        use crate::front_door::{FrontDoor, HashRing, HOP_MARKER};
+        let address = "127.0.0.1:47406".to_string();
+        let owner = "127.0.0.1:47407";
+        // the owning node echoes what it is sent
+        let upstream = smol::block_on(smol::net::TcpListener::bind(owner)).unwrap();
+        get_executor()
+            .spawn(async move {
+                while let Ok((mut stream, _)) = upstream.accept().await {
+                    // the client proxied is marked, ahead of what it sent
+                    let mut marker = vec![0; HOP_MARKER.len()];
+                    stream.read_exact(&mut marker).await.ok();
+                    assert_eq!(HOP_MARKER, &marker[..]);
+                    smol::io::copy(stream.clone(), &mut stream.clone()).await.ok();
+                }
+            })
+            .detach();
+        let mut ring = HashRing::new(&address);
+        ring.add_node(owner);
+        let key = |owner: &str| {
+            (0 ..)
+                .map(|n| format!("room-{}", n))
+                .find(|key| ring.get_owner(key) == owner)
+                .unwrap()
+        };
+        let (local_key, remote_key) = (key(&address), key(owner));
+        // the key is the first line, so isn't known until the line is complete
+        let front_door = Arc::new(FrontDoor::new(Arc::new(ring), |_remote, bytes| {
+            let end = bytes.iter().position(|b| *b == b'\n')?;
+            Some(String::from_utf8_lossy(&bytes[.. end]).to_string())
+        }));
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            controller
+                .handle(NetCmd::SetFrontDoor(address.clone(), Some(front_door.clone())))
+                .await
+                .ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        smol::block_on(async {
+            // the first line is split across writes
+            let mut remote = TcpStream::connect(address.as_str()).await.unwrap();
+            let (first, rest) = remote_key.split_at(2);
+            remote.write_all(first.as_bytes()).await.unwrap();
+            smol::Timer::after(Duration::from_millis(20)).await;
+            remote.write_all(format!("{}\nhello", rest).as_bytes()).await.unwrap();
+            let mut echoed = vec![0; remote_key.len() + 6];
+            remote.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(format!("{}\nhello", remote_key).into_bytes(), echoed);
+            remote.write_all(b"!").await.unwrap();
+            remote.read_exact(&mut echoed[.. 1]).await.unwrap();
+
+            let mut local = TcpStream::connect(address.as_str()).await.unwrap();
+            local.write_all(format!("{}\n", local_key).as_bytes()).await.unwrap();
+            assert!(matches!(listener_receiver.recv().await, Ok(ListenerCmd::NewConn(..))));
+        });
+        // the listener isn't told of the connection proxied
+        assert!(listener_receiver.is_empty());
+        assert_eq!((1, 1, 0), {
+            let stats = front_door.get_stats();
+            (stats.local, stats.proxied, stats.failed)
+        });
+        // the proxy is stopped along with the network
+        smol::block_on(controller.shutdown());
+        assert!(controller.tasks.is_empty());
+    }
+
+    #[test]
+    fn proxy_once() {
+        use crate::front_door::{FrontDoor, NodeRouter};
+        // a router which routes every client to the other node, as a ring out of date might
+        #[derive(Debug)]
+        struct Other(String);
+        impl NodeRouter for Other {
+            fn route(&self, _key: &str) -> Option<String> { Some(self.0.clone()) }
+        }
+        let addresses = ["127.0.0.1:47410".to_string(), "127.0.0.1:47411".to_string()];
+        let mut controller = NetController::default();
+        let mut front_doors = Vec::new();
+        let mut listener_receivers = Vec::new();
+        for (address, other) in addresses.iter().zip(addresses.iter().rev()) {
+            let (listener, listener_receiver) = channel::unbounded();
+            let front_door = Arc::new(FrontDoor::new(Arc::new(Other(other.clone())), |_remote, bytes| {
+                bytes.contains(&b'\n').then(|| "room".to_string())
+            }));
+            smol::block_on(async {
+                controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+                controller
+                    .handle(NetCmd::SetFrontDoor(address.clone(), Some(front_door.clone())))
+                    .await
+                    .ok();
+            });
+            front_doors.push(front_door);
+            listener_receivers.push(listener_receiver);
+        }
+        smol::block_on(async {
+            smol::Timer::after(Duration::from_millis(50)).await;
+            let mut client = TcpStream::connect(addresses[0].as_str()).await.unwrap();
+            client.write_all(b"room\n").await.unwrap();
+            // the client is proxied to the other node, which handles it, rather than proxying it back
+            assert!(matches!(listener_receivers[1].recv().await, Ok(ListenerCmd::NewConn(..))));
+        });
+        assert!(listener_receivers[0].is_empty());
+        let stats: Vec<(u64, u64)> = front_doors
+            .iter()
+            .map(|front_door| (front_door.get_stats().local, front_door.get_stats().proxied))
+            .collect();
+        assert_eq!(vec![(0, 1), (1, 0)], stats);
+        smol::block_on(controller.shutdown());
+    }
+
+    #[test]
+    fn compress_conn() {
+        use smol::io::{AsyncReadExt, AsyncWriteExt};
+        let address = "127.0.0.1:47325".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let mut client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let text = b"ping ping ping ping ping ping ping ping ping ping ping ping".to_vec();
+        let mut peer = Codec::new(Compression::Deflate).unwrap();
+        let (conn, conn_receiver) = channel::unbounded();
+        let (reply, stats) = channel::unbounded();
+        smol::block_on(async {
+            controller
+                .handle(NetCmd::SetCompression(conn_id, Some(Compression::Deflate)))
+                .await
+                .ok();
+            controller.handle(NetCmd::BindConn(conn_id, conn)).await.ok();
+            client.write_all(&peer.compress(&text).unwrap()).await.unwrap();
+            match conn_receiver.recv().await {
+                Ok(ConnCmd::RecvBytes(_, bytes)) => assert_eq!(text, bytes),
+                cmd => panic!("unexpected {:?}", cmd),
+            }
+            controller.handle(NetCmd::SendBytes(conn_id, text.clone())).await.ok();
+            let mut buf = vec![0u8; 1024];
+            let len = client.read(&mut buf).await.unwrap();
+            assert_eq!(text, peer.decompress(&buf[.. len], 0).unwrap());
+
+            controller.handle(NetCmd::GetCompressionStats(conn_id, reply)).await.ok();
+            let stats = stats.recv().await.unwrap().unwrap();
+            assert_eq!(text.len() as u64, stats.sent_bytes);
+            assert_eq!(len as u64, stats.sent_wire_bytes);
+            assert!(stats.get_send_ratio() > 1.0);
+            assert!(stats.get_recv_ratio() > 1.0);
+        });
+    }
+
+    #[test]
+    fn yield_between_reads() {
+        use smol::io::AsyncWriteExt;
+        let previous = NetCore::set_max_consecutive_reads(1);
+        let address = "127.0.0.1:47326".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let mut client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let (conn, conn_receiver) = channel::unbounded();
+        let sent: Vec<u8> = (0 .. 10_000).map(|n| n as u8).collect();
+        let received = smol::block_on(async {
+            controller.handle(NetCmd::BindConn(conn_id, conn)).await.ok();
+            client.write_all(&sent).await.unwrap();
+            let mut received = Vec::new();
+            while received.len() < sent.len() {
+                match conn_receiver.recv().await {
+                    Ok(ConnCmd::RecvBytes(_, bytes)) => received.extend(bytes),
+                    cmd => panic!("unexpected {:?}", cmd),
+                }
+            }
+            received
+        });
+        NetCore::set_max_consecutive_reads(previous);
+        assert_eq!(sent, received);
+    }
+
+    #[test]
+    fn reject_large_frames() {
+        use smol::io::AsyncWriteExt;
+        let previous = NetCore::set_max_frame_size(4096);
+        let address = "127.0.0.1:47327".to_string();
+        let (listener, listener_receiver) = channel::unbounded();
+        let mut controller = NetController::default();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindTcpListener(address.clone(), listener)).await.ok();
+            smol::Timer::after(Duration::from_millis(50)).await;
+        });
+        let mut client = smol::block_on(TcpStream::connect(address.as_str())).unwrap();
+        let conn_id = match smol::block_on(listener_receiver.recv()) {
+            Ok(ListenerCmd::NewConn(conn_id, ..)) => conn_id,
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let (conn, conn_receiver) = channel::unbounded();
+        let mut peer = Codec::new(Compression::Deflate).unwrap();
+        smol::block_on(async {
+            controller.handle(NetCmd::BindConn(conn_id, conn)).await.ok();
+            controller.handle(NetCmd::SendBytes(conn_id, vec![0u8; 5000])).await.ok();
+            match conn_receiver.recv().await {
+                Ok(ConnCmd::Rejected(_, err)) => assert_eq!(FrameError::SendTooLarge { size: 5000, max: 4096 }, err),
+                cmd => panic!("unexpected {:?}", cmd),
+            }
+            // a small frame, which decompresses to a large one, closes the connection
+            controller
+                .handle(NetCmd::SetCompression(conn_id, Some(Compression::Deflate)))
+                .await
+                .ok();
+            client.write_all(&peer.compress(&vec![0u8; 1 << 20]).unwrap()).await.unwrap();
+            match conn_receiver.recv().await {
+                Ok(ConnCmd::Rejected(_, err)) => assert_eq!(FrameError::RecvTooLarge { max: 4096 }, err),
+                cmd => panic!("unexpected {:?}", cmd),
+            }
+            assert!(matches!(conn_receiver.recv().await, Ok(ConnCmd::CloseConn(_))));
+        });
+        NetCore::set_max_frame_size(previous);
+    }
 }
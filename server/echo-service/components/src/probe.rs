@@ -0,0 +1,92 @@
+use serde::Deserialize;
+use std::{
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// A Probe checks that something a service depends upon is ready, before the service runs.
+/// Probes are deserialized from the config of the service, such as:
+///
+/// ```json
+/// "probes": [
+///     { "type": "tcp", "address": "127.0.0.1:6379" },
+///     { "type": "dns", "host": "db.internal" },
+///     { "type": "file", "path": "/var/run/secrets/token" }
+/// ]
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Probe {
+    /// Ready once a TCP connection can be made to the address, such as an upstream's port.
+    Tcp { address: String },
+    /// Ready once the host resolves.
+    Dns { host: String },
+    /// Ready once the file exists.
+    File { path: PathBuf },
+}
+
+impl Probe {
+    // The time a single TCP connect may take.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Check the probe once, returning an error if it isn't ready.
+    pub fn check(&self) -> io::Result<()> {
+        match self {
+            Self::Tcp { address } => {
+                let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve", address));
+                for addr in address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, Self::CONNECT_TIMEOUT) {
+                        Ok(_) => return Ok(()),
+                        Err(err) => last_err = err,
+                    }
+                }
+                Err(last_err)
+            },
+            Self::Dns { host } => match (host.as_str(), 0).to_socket_addrs()?.next() {
+                Some(_) => Ok(()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve", host))),
+            },
+            Self::File { path } => std::fs::metadata(path).map(|_| ()),
+        }
+    }
+
+    /// Check the probe until it is ready, backing off between checks, or until the timeout
+    /// passes, returning the last error.
+    pub fn wait(&self, timeout: Duration) -> io::Result<()> {
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(50);
+        loop {
+            match self.check() {
+                Ok(()) => return Ok(()),
+                Err(err) if start.elapsed() + backoff > timeout => return Err(err),
+                Err(err) => log::debug!("probe={:?} not ready, error={}, retrying in {:?}", self, err, backoff),
+            }
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(Duration::from_secs(5));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes() {
+        let probes: Vec<Probe> = serde_json::from_str(
+            r#"[{ "type": "tcp", "address": "127.0.0.1:1" }, { "type": "dns", "host": "localhost" }, { "type": "file", "path": "." }]"#,
+        )
+        .unwrap();
+        assert!(probes[0].wait(Duration::from_millis(100)).is_err());
+        assert!(probes[1].check().is_ok());
+        assert!(probes[2].check().is_ok());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = Probe::Tcp {
+            address: listener.local_addr().unwrap().to_string(),
+        };
+        assert!(tcp.wait(Duration::from_millis(100)).is_ok());
+    }
+}
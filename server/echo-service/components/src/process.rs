@@ -0,0 +1,357 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use smol::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+use std::{collections::HashMap, io, process::ExitStatus, sync::Mutex as SyncMutex, time::Duration};
+
+/// Shorthand for a sender, that can be sent ProcessCmd instructions.
+pub type ProcessSender = channel::Sender<ProcessCmd>;
+
+/// The ProcessId is chosen by the spawner, and identifies a process across restarts.
+pub type ProcessId = usize;
+
+// The size of the buffer output is read into.
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// The RestartPolicy determines whether a process is restarted when it exits. A process which is
+/// killed is never restarted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+pub enum RestartPolicy {
+    /// The process is never restarted.
+    #[default]
+    Never,
+    /// The process is restarted, up to max_restarts times, when it exits unsuccessfully.
+    OnFailure { max_restarts: usize },
+    /// The process is restarted, up to max_restarts times, whenever it exits.
+    Always { max_restarts: usize },
+}
+
+impl RestartPolicy {
+    // Check if the process should be restarted, having been restarted restarts times already.
+    fn should_restart(&self, success: bool, restarts: usize) -> bool {
+        match *self {
+            Self::Never => false,
+            Self::OnFailure { max_restarts } => !success && restarts < max_restarts,
+            Self::Always { max_restarts } => restarts < max_restarts,
+        }
+    }
+}
+
+/// The ProcessSpec describes a process to spawn.
+#[derive(Debug, Clone, SmartDefault)]
+pub struct ProcessSpec {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    restart: RestartPolicy,
+    #[default(Duration::from_secs(1))]
+    restart_delay: Duration,
+}
+
+impl ProcessSpec {
+    /// Create a spec for running program.
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Add an argument.
+    pub fn with_arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Add an environment variable.
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the restart policy, the default is to never restart.
+    pub fn with_restart(mut self, restart: RestartPolicy) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Set the delay before restarting, the default is one second.
+    pub fn with_restart_delay(mut self, delay: Duration) -> Self {
+        self.restart_delay = delay;
+        self
+    }
+
+    fn spawn(&self) -> io::Result<Child> {
+        Command::new(&self.program)
+            .args(&self.args)
+            .envs(self.env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+}
+
+/// ProcessCmd is the instruction set for managing child processes.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum ProcessCmd {
+    /// Spawn a process, its events are sent to the sender. An existing process with the same id
+    /// is killed.
+    Spawn(ProcessId, ProcessSpec, ProcessEventSender),
+    /// Write bytes to the stdin of a process.
+    WriteStdin(ProcessId, Vec<u8>),
+    /// Close the stdin of a process.
+    CloseStdin(ProcessId),
+    /// Kill a process, it isn't restarted.
+    Kill(ProcessId),
+    /// Send a signal to a process.
+    #[cfg(unix)]
+    Signal(ProcessId, i32),
+}
+
+/// ProcessEvent is the instruction set the ProcessManager sends the spawner of a process.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum ProcessEvent {
+    /// The process started, with its OS pid.
+    Started(ProcessId, u32),
+    /// The process wrote to stdout.
+    Stdout(ProcessId, Vec<u8>),
+    /// The process wrote to stderr.
+    Stderr(ProcessId, Vec<u8>),
+    /// The process exited, with its exit code, which is None if it was ended by a signal.
+    Exited(ProcessId, Option<i32>),
+    /// The process is being restarted, for the nth time.
+    Restarting(ProcessId, usize),
+    /// The process couldn't be spawned.
+    Failed(ProcessId, String),
+}
+
+// The ProcessControl is the instruction set of a process's supervising task.
+#[derive(Debug)]
+enum ProcessControl {
+    WriteStdin(Vec<u8>),
+    CloseStdin,
+    Kill,
+    #[cfg(unix)]
+    Signal(i32),
+}
+
+// What a supervising task is woken by.
+enum Wake {
+    Exited(io::Result<ExitStatus>),
+    Control(Option<ProcessControl>),
+}
+
+/// The ProcessManager implements the ProcessCmd instruction set. Each process is run by a
+/// supervising task, which streams its stdout and stderr as ProcessEvent instructions, writes
+/// its stdin, and restarts it according to its RestartPolicy.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use machine_foundation::machine;
+///
+/// let (_manager, sender) = machine::create(ProcessManager::default());
+/// let (events, receiver) = smol::channel::unbounded::<ProcessEvent>();
+/// let spec = ProcessSpec::new("worker").with_arg("--queue=jobs").with_restart(RestartPolicy::OnFailure { max_restarts: 3 });
+/// sender.try_send(ProcessCmd::Spawn(1, spec, events)).ok();
+/// ```
+#[derive(Debug, Default)]
+pub struct ProcessManager {
+    processes: SyncMutex<HashMap<ProcessId, channel::Sender<ProcessControl>>>,
+}
+
+impl ProcessManager {
+    fn control(&self, id: ProcessId, control: ProcessControl) {
+        let processes = self.processes.lock().unwrap_or_else(|err| err.into_inner());
+        match processes.get(&id) {
+            Some(sender) if sender.try_send(control).is_ok() => (),
+            _ => log::debug!("process id={} is not running", id),
+        }
+    }
+}
+
+impl Machine<ProcessCmd> for ProcessManager {
+    fn receive(&self, cmd: ProcessCmd, _sender: &mut MachineSender) {
+        match cmd {
+            ProcessCmd::Spawn(id, spec, events) => {
+                let (control, receiver) = channel::unbounded::<ProcessControl>();
+                let mut processes = self.processes.lock().unwrap_or_else(|err| err.into_inner());
+                // the supervising task of an exited process drops its receiver
+                processes.retain(|_, control| !control.is_closed());
+                if let Some(previous) = processes.insert(id, control) {
+                    previous.try_send(ProcessControl::Kill).ok();
+                }
+                get_executor().spawn(supervise(id, spec, events, receiver)).detach();
+            },
+            ProcessCmd::WriteStdin(id, bytes) => self.control(id, ProcessControl::WriteStdin(bytes)),
+            ProcessCmd::CloseStdin(id) => self.control(id, ProcessControl::CloseStdin),
+            ProcessCmd::Kill(id) => self.control(id, ProcessControl::Kill),
+            #[cfg(unix)]
+            ProcessCmd::Signal(id, signal) => self.control(id, ProcessControl::Signal(signal)),
+        }
+    }
+    fn disconnected(&self) {
+        for (_, control) in self.processes.lock().unwrap_or_else(|err| err.into_inner()).drain() {
+            control.try_send(ProcessControl::Kill).ok();
+        }
+    }
+}
+
+// Supervise a process, running it until it exits, and restarting it according to its policy.
+async fn supervise(id: ProcessId, spec: ProcessSpec, events: ProcessEventSender, control: channel::Receiver<ProcessControl>) {
+    let mut restarts = 0;
+    loop {
+        let mut child = match spec.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                events.send(ProcessEvent::Failed(id, err.to_string())).await.ok();
+                break;
+            },
+        };
+        events.send(ProcessEvent::Started(id, child.id())).await.ok();
+        let mut stdin = child.stdin.take();
+        let stdout = child
+            .stdout
+            .take()
+            .map(|stdout| pump_output(id, stdout, events.clone(), ProcessEvent::Stdout));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|stderr| pump_output(id, stderr, events.clone(), ProcessEvent::Stderr));
+        let (status, killed) = run(&mut child, &mut stdin, &control).await;
+        // deliver all of the output before the exit
+        for pump in stdout.into_iter().chain(stderr) {
+            pump.await;
+        }
+        let code = status.as_ref().ok().and_then(|status| status.code());
+        events.send(ProcessEvent::Exited(id, code)).await.ok();
+        let success = matches!(status, Ok(status) if status.success());
+        if killed || control.is_closed() || !spec.restart.should_restart(success, restarts) {
+            break;
+        }
+        restarts += 1;
+        events.send(ProcessEvent::Restarting(id, restarts)).await.ok();
+        smol::Timer::after(spec.restart_delay).await;
+    }
+}
+
+// Run a process until it exits, handling control instructions, returning its status and whether
+// it was killed.
+async fn run(
+    child: &mut Child, stdin: &mut Option<ChildStdin>, control: &channel::Receiver<ProcessControl>,
+) -> (io::Result<ExitStatus>, bool) {
+    let mut killed = false;
+    loop {
+        let wake = smol::future::or(async { Wake::Exited(child.status().await) }, async {
+            Wake::Control(control.recv().await.ok())
+        })
+        .await;
+        match wake {
+            Wake::Exited(status) => return (status, killed),
+            Wake::Control(Some(ProcessControl::WriteStdin(bytes))) => {
+                if let Some(writer) = stdin.as_mut() {
+                    if writer.write_all(&bytes).await.is_err() || writer.flush().await.is_err() {
+                        stdin.take();
+                    }
+                }
+            },
+            Wake::Control(Some(ProcessControl::CloseStdin)) => {
+                stdin.take();
+            },
+            #[cfg(unix)]
+            Wake::Control(Some(ProcessControl::Signal(signal))) => unsafe {
+                libc::kill(child.id() as libc::pid_t, signal);
+            },
+            // the manager has gone away, or the process was killed
+            Wake::Control(Some(ProcessControl::Kill)) | Wake::Control(None) => {
+                killed = true;
+                child.kill().ok();
+                return (child.status().await, killed);
+            },
+        }
+    }
+}
+
+// Pump the output of a process into events, completing when the output is closed.
+fn pump_output<R>(id: ProcessId, mut reader: R, events: ProcessEventSender, event: fn(ProcessId, Vec<u8>) -> ProcessEvent) -> smol::Task<()>
+where
+    R: 'static + AsyncRead + Send + Unpin,
+{
+    get_executor().spawn(async move {
+        loop {
+            let mut buf = vec![0u8; READ_BUFFER_SIZE];
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_read) => {
+                    buf.truncate(bytes_read);
+                    events.send(event(id, buf)).await.ok();
+                },
+            }
+        }
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use machine_foundation::machine;
+
+    // Collect events until the supervising task completes, dropping its sender.
+    fn collect_events(receiver: &channel::Receiver<ProcessEvent>) -> (Vec<u8>, Vec<Option<i32>>, usize) {
+        smol::block_on(async {
+            let (mut output, mut exits, mut restarts) = (Vec::new(), Vec::new(), 0);
+            while let Ok(event) = receiver.recv().await {
+                match event {
+                    ProcessEvent::Stdout(_, bytes) => output.extend(bytes),
+                    ProcessEvent::Restarting(_, n) => restarts = n,
+                    ProcessEvent::Exited(_, code) => exits.push(code),
+                    ProcessEvent::Failed(_, err) => panic!("spawn failed {}", err),
+                    _ => (),
+                }
+            }
+            (output, exits, restarts)
+        })
+    }
+
+    #[test]
+    fn stdin_to_stdout() {
+        let (_manager, sender) = machine::create_unbounded(ProcessManager::default());
+        let (events, receiver) = channel::unbounded();
+        sender.try_send(ProcessCmd::Spawn(1, ProcessSpec::new("cat"), events)).ok();
+        sender.try_send(ProcessCmd::WriteStdin(1, b"hello".to_vec())).ok();
+        sender.try_send(ProcessCmd::CloseStdin(1)).ok();
+        assert_eq!((b"hello".to_vec(), vec![Some(0)], 0), collect_events(&receiver));
+    }
+
+    #[test]
+    fn restart_on_failure() {
+        let (_manager, sender) = machine::create_unbounded(ProcessManager::default());
+        let (events, receiver) = channel::unbounded();
+        let spec = ProcessSpec::new("sh")
+            .with_arg("-c")
+            .with_arg("echo $GREETING; exit 3")
+            .with_env("GREETING", "hi")
+            .with_restart(RestartPolicy::OnFailure { max_restarts: 2 })
+            .with_restart_delay(Duration::from_millis(10));
+        sender.try_send(ProcessCmd::Spawn(2, spec, events)).ok();
+        assert_eq!((b"hi\nhi\nhi\n".to_vec(), vec![Some(3); 3], 2), collect_events(&receiver));
+    }
+
+    #[test]
+    fn kill() {
+        let (_manager, sender) = machine::create_unbounded(ProcessManager::default());
+        let (events, receiver) = channel::unbounded();
+        let spec = ProcessSpec::new("sleep")
+            .with_arg("10")
+            .with_restart(RestartPolicy::Always { max_restarts: 5 });
+        sender.try_send(ProcessCmd::Spawn(3, spec, events)).ok();
+        assert!(matches!(smol::block_on(receiver.recv()), Ok(ProcessEvent::Started(3, _))));
+        sender.try_send(ProcessCmd::Kill(3)).ok();
+        assert_eq!((Vec::new(), vec![None], 0), collect_events(&receiver));
+    }
+}
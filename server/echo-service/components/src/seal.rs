@@ -0,0 +1,252 @@
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::{collections::HashMap, fmt};
+
+// The length of a nonce, which prefixes a sealed frame: a sealer id, followed by a counter. The id
+// is random, and wide enough that sealers under the same key don't share one, reusing nonces.
+const ID_LEN: usize = 16;
+const NONCE_LEN: usize = ID_LEN + 8;
+// The length of the authentication tag, which suffixes a sealed frame.
+const TAG_LEN: usize = 16;
+// The number of counters, below the highest opened, which are tracked for replay.
+const REPLAY_WINDOW: u64 = 64;
+
+/// Represents all of the possible errors that can occur when opening a sealed frame.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SealError {
+    /// The frame is too short to have been sealed.
+    Truncated,
+    /// The frame wasn't sealed with the key, or has been tampered with.
+    Forged,
+    /// The frame has already been opened, or is too old to tell.
+    Replayed,
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "sealed frame truncated"),
+            Self::Forged => write!(f, "sealed frame failed authentication"),
+            Self::Replayed => write!(f, "sealed frame replayed"),
+        }
+    }
+}
+
+impl std::error::Error for SealError {}
+
+/// A SealKey is a pre-shared key, from which the keys of each protocol, such as the remote
+/// bridge and discovery, are derived, so a frame sealed for one can't be opened by another.
+#[derive(Clone)]
+pub struct SealKey([u8; 32]);
+
+impl fmt::Debug for SealKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#SealKey {{ .. }}") }
+}
+
+impl SealKey {
+    /// Create a key from the pre-shared secret, which should be at least 32 random bytes.
+    pub fn new(secret: &[u8]) -> Self {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, secret)
+            .expand(b"rust-server-project psk", &mut key)
+            .expect("32 bytes is a valid length");
+        Self(key)
+    }
+
+    // Derive the key of a protocol.
+    fn derive(&self, protocol: &str) -> Key {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::from_prk(&self.0)
+            .expect("32 bytes is a valid length")
+            .expand(protocol.as_bytes(), &mut key)
+            .expect("32 bytes is a valid length");
+        key.into()
+    }
+}
+
+/// A Sealer seals frames, such as instructions sent between nodes, with authenticated encryption,
+/// XChaCha20-Poly1305, under a pre-shared key, for deployments without TLS. A sealed frame is the
+/// nonce, the ciphertext, and the tag. Each sealer has a random 128-bit id, which, with a counter,
+/// makes the nonces unique, however many sealers share the key. The associated data, such as a
+/// frame header, is authenticated, but not encrypted, or included in the frame.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::{Opener, SealKey, Sealer};
+///
+/// let key = SealKey::new(b"a pre-shared secret, of at least 32 bytes");
+/// let mut sealer = Sealer::new(&key, "bridge");
+/// let mut opener = Opener::new(&key, "bridge");
+/// let frame = sealer.seal(b"hello", b"header");
+/// assert_eq!(Ok(b"hello".to_vec()), opener.open(&frame, b"header"));
+/// ```
+pub struct Sealer {
+    cipher: XChaCha20Poly1305,
+    id: u128,
+    counter: u64,
+}
+
+impl fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#Sealer {{ id: {}, counter: {} }}", self.id, self.counter) }
+}
+
+impl Sealer {
+    /// Create a sealer of frames for the protocol, under the key.
+    pub fn new(key: &SealKey, protocol: &str) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&key.derive(protocol)),
+            id: {
+                let mut id = [0u8; ID_LEN];
+                OsRng.fill_bytes(&mut id);
+                u128::from_be_bytes(id)
+            },
+            counter: 0,
+        }
+    }
+
+    /// Seal the plaintext, authenticating the associated data along with it.
+    pub fn seal(&mut self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        self.counter += 1;
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[.. ID_LEN].copy_from_slice(&self.id.to_be_bytes());
+        nonce[ID_LEN ..].copy_from_slice(&self.counter.to_be_bytes());
+        let sealed = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .expect("a frame can be sealed");
+        let mut frame = Vec::with_capacity(NONCE_LEN + sealed.len());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&sealed);
+        frame
+    }
+}
+
+// The counters a sealer's frames have been opened with.
+#[derive(Debug, Default)]
+struct Seen {
+    highest: u64,
+    // bit n is set if highest - n has been opened
+    window: u64,
+}
+
+impl Seen {
+    // Record the counter, returning false if it has been opened, or is too old to tell.
+    fn record(&mut self, counter: u64) -> bool {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            self.window = if shift >= REPLAY_WINDOW { 0 } else { self.window << shift };
+            self.window |= 1;
+            self.highest = counter;
+            true
+        } else {
+            let age = self.highest - counter;
+            if age >= REPLAY_WINDOW || self.window & (1 << age) != 0 {
+                false
+            } else {
+                self.window |= 1 << age;
+                true
+            }
+        }
+    }
+}
+
+/// An Opener opens the frames sealed by the Sealers of a protocol, under the same key, rejecting
+/// those which are forged, or replayed. Frames may arrive out of order, such as over UDP, within a
+/// window of the most recent.
+pub struct Opener {
+    cipher: XChaCha20Poly1305,
+    seen: HashMap<u128, Seen>,
+}
+
+impl fmt::Debug for Opener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#Opener {{ sealers: {} }}", self.seen.len()) }
+}
+
+impl Opener {
+    /// Create an opener of frames for the protocol, under the key.
+    pub fn new(key: &SealKey, protocol: &str) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&key.derive(protocol)),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Open a sealed frame, returning the plaintext, if the frame and associated data are authentic,
+    /// and it hasn't been opened before.
+    pub fn open(&mut self, frame: &[u8], aad: &[u8]) -> Result<Vec<u8>, SealError> {
+        if frame.len() < NONCE_LEN + TAG_LEN {
+            return Err(SealError::Truncated);
+        }
+        let (nonce, sealed) = frame.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad })
+            .map_err(|_| SealError::Forged)?;
+        let mut id = [0u8; ID_LEN];
+        id.copy_from_slice(&nonce[.. ID_LEN]);
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&nonce[ID_LEN ..]);
+        // only an authentic frame is recorded, so a forgery can't advance the window
+        if self
+            .seen
+            .entry(u128::from_be_bytes(id))
+            .or_default()
+            .record(u64::from_be_bytes(counter))
+        {
+            Ok(plaintext)
+        } else {
+            Err(SealError::Replayed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = SealKey::new(b"0123456789abcdef0123456789abcdef");
+        let mut sealer = Sealer::new(&key, "bridge");
+        let mut opener = Opener::new(&key, "bridge");
+        let first = sealer.seal(b"first", b"");
+        let second = sealer.seal(b"second", b"");
+        assert_eq!(NONCE_LEN + 5 + TAG_LEN, first.len());
+        // another sealer, under the same key, has an id of its own
+        let other = Sealer::new(&key, "bridge").seal(b"first", b"");
+        assert_ne!(first[.. ID_LEN], other[.. ID_LEN]);
+        assert_eq!(first[ID_LEN .. NONCE_LEN], other[ID_LEN .. NONCE_LEN]);
+        // out of order, but only once each
+        assert_eq!(Ok(b"second".to_vec()), opener.open(&second, b""));
+        assert_eq!(Ok(b"first".to_vec()), opener.open(&first, b""));
+        assert_eq!(Err(SealError::Replayed), opener.open(&first, b""));
+
+        let frame = sealer.seal(b"third", b"header");
+        assert_eq!(Err(SealError::Forged), opener.open(&frame, b"other header"));
+        let mut tampered = frame.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(Err(SealError::Forged), opener.open(&tampered, b"header"));
+        assert_eq!(Err(SealError::Truncated), opener.open(&frame[.. 10], b"header"));
+        // neither another protocol, nor another key, can open it
+        assert_eq!(Err(SealError::Forged), Opener::new(&key, "discovery").open(&frame, b"header"));
+        let other = SealKey::new(b"fedcba9876543210fedcba9876543210");
+        assert_eq!(Err(SealError::Forged), Opener::new(&other, "bridge").open(&frame, b"header"));
+        assert_eq!(Ok(b"third".to_vec()), opener.open(&frame, b"header"));
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut seen = Seen::default();
+        assert!(seen.record(1));
+        assert!(seen.record(100));
+        assert!(!seen.record(1));
+        assert!(seen.record(99));
+        assert!(!seen.record(99));
+        assert!(!seen.record(100 - REPLAY_WINDOW));
+    }
+}
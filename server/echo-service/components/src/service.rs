@@ -1,4 +1,6 @@
 use super::*;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::{error::Error, fmt, result};
 
 /// Alias for a `Result` with the error type set to `ServiceError`.
@@ -38,14 +40,19 @@ impl Error for ServiceError {
     fn cause(&self) -> Option<&dyn Error> { None }
 }
 
-/// All services must implement ServerService
-pub trait ServerService {
+/// All services must implement ServerService. A stateful service implements Snapshot, so that it
+/// doesn't start cold after a restart, others can use the default implementation.
+pub trait ServerService: Snapshot {
     /// Get the name of the service.
     fn get_name(&self) -> &str;
+    /// Get the state of the service.
+    fn get_state(&self) -> ServiceState;
     /// Get the count of things to drain.
     fn get_drain_count(&self) -> usize;
     /// Return true if drained
     fn is_drained(&self) -> bool { self.get_drain_count() == 0 }
+    /// Get the probes which must be ready before the service runs. By default, there are none.
+    fn get_probes(&self) -> Vec<Probe> { Vec::new() }
     /// Start the service. Generally, this prepares the service for running.
     fn start(&mut self) -> ServiceResult<()>;
     /// Run the service.
@@ -54,6 +61,9 @@ pub trait ServerService {
     fn drain(&mut self) -> ServiceResult<()>;
     /// Stop the service, closing any requests or connections.
     fn stop(&mut self) -> ServiceResult<()>;
+    /// Recover from an unclean shutdown, such as a crash, before the service starts. The state is
+    /// the last state the service was known to be in. By default, there's nothing to recover.
+    fn recover(&mut self, _last_state: ServiceState) -> ServiceResult<()> { Ok(()) }
 }
 
 /// ServiceStateTransiion provides notification of a ServiceState transition.
@@ -72,8 +82,28 @@ pub trait ServiceStateTransition {
     fn will_stop(&mut self, state: &ServiceState);
 }
 
+/// AsyncServiceStateTransition provides notification of a ServiceState transition, which may
+/// be awaited, such as when draining flushes state to a store. Each will_* method is awaited
+/// before the transition is committed, and if it fails, the transition isn't made and the error
+/// is returned. By default, each method does nothing.
+#[async_trait]
+pub trait AsyncServiceStateTransition: Send {
+    /// The will_start method is awaited before transitioning to ServiceState::Started. The state
+    /// is the current state.
+    async fn will_start(&mut self, _state: &ServiceState) -> ServiceResult<()> { Ok(()) }
+    /// The will_run method is awaited before transitioning to ServiceState::Running. The state
+    /// is the current state.
+    async fn will_run(&mut self, _state: &ServiceState) -> ServiceResult<()> { Ok(()) }
+    /// The will_drain method is awaited before transitioning to ServiceState::Draining. The state
+    /// is the current state.
+    async fn will_drain(&mut self, _state: &ServiceState) -> ServiceResult<()> { Ok(()) }
+    /// The will_stop method is awaited before transitioning to ServiceState::Stopped. The state
+    /// is the current state.
+    async fn will_stop(&mut self, _state: &ServiceState) -> ServiceResult<()> { Ok(()) }
+}
+
 /// ServiceState is the state of the service.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault, Serialize, Deserialize)]
 pub enum ServiceState {
     #[default]
     Init,
@@ -146,6 +176,62 @@ impl ServiceState {
         }
     }
 
+    /// Attempt to transition to the Started state, awaiting the transition notification.
+    pub async fn start_with_async_notification(
+        &mut self, on_transition: Option<&mut dyn AsyncServiceStateTransition>,
+    ) -> ServiceResult<()> {
+        if self.can_start() {
+            if let Some(notifier) = on_transition {
+                notifier.will_start(self).await?;
+            }
+            *self = Self::Started;
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidStateTransition(*self, Self::Started))
+        }
+    }
+
+    /// Attempt to transition to the Running state, awaiting the transition notification.
+    pub async fn run_with_async_notification(&mut self, on_transition: Option<&mut dyn AsyncServiceStateTransition>) -> ServiceResult<()> {
+        if self.can_run() {
+            if let Some(notifier) = on_transition {
+                notifier.will_run(self).await?;
+            }
+            *self = Self::Running;
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidStateTransition(*self, Self::Running))
+        }
+    }
+
+    /// Attempt to transition to the Draining state, awaiting the transition notification.
+    pub async fn drain_with_async_notification(
+        &mut self, on_transition: Option<&mut dyn AsyncServiceStateTransition>,
+    ) -> ServiceResult<()> {
+        if self.can_drain() {
+            if let Some(notifier) = on_transition {
+                notifier.will_drain(self).await?;
+            }
+            *self = Self::Draining;
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidStateTransition(*self, Self::Draining))
+        }
+    }
+
+    /// Attempt to transition to the Stopped state, awaiting the transition notification.
+    pub async fn stop_with_async_notification(&mut self, on_transition: Option<&mut dyn AsyncServiceStateTransition>) -> ServiceResult<()> {
+        if self.can_stop() {
+            if let Some(notifier) = on_transition {
+                notifier.will_stop(self).await?;
+            }
+            *self = Self::Stopped;
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidStateTransition(*self, Self::Stopped))
+        }
+    }
+
     /// Return true if state can transition to Started.
     pub fn can_start(&self) -> bool { *self == Self::Init }
 
@@ -160,6 +246,10 @@ impl ServiceState {
 
     /// return trye if state is running
     pub fn is_running(&self) -> bool { *self == Self::Running }
+
+    /// Return true if a service last known to be in this state was shut down uncleanly, having
+    /// started, but not stopped.
+    pub fn is_unclean(&self) -> bool { matches!(self, Self::Started | Self::Running | Self::Draining) }
 }
 
 #[cfg(test)]
@@ -370,4 +460,45 @@ mod tests {
         assert_eq!(false, notifier.stopped);
         assert_eq!(state, test_state);
     }
+
+    #[derive(Debug, Default)]
+    struct Flusher {
+        flushed: bool,
+        fail: bool,
+    }
+    #[async_trait]
+    impl AsyncServiceStateTransition for Flusher {
+        async fn will_drain(&mut self, state: &ServiceState) -> ServiceResult<()> {
+            assert!(state.can_drain());
+            smol::Timer::after(std::time::Duration::from_millis(1)).await;
+            if self.fail {
+                return Err(ServiceError::Message("flush failed".to_string()));
+            }
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn service_state_async_notification() {
+        smol::block_on(async {
+            let mut flusher = Flusher::default();
+            let mut state = ServiceState::Running;
+            assert!(state.drain_with_async_notification(Some(&mut flusher)).await.is_ok());
+            assert!(flusher.flushed);
+            assert_eq!(state, ServiceState::Draining);
+            assert!(state.drain_with_async_notification(Some(&mut flusher)).await.is_err());
+            assert!(state.stop_with_async_notification(Some(&mut flusher)).await.is_ok());
+            assert_eq!(state, ServiceState::Stopped);
+
+            // a failed notification leaves the state as it was
+            let mut flusher = Flusher {
+                fail: true,
+                ..Flusher::default()
+            };
+            let mut state = ServiceState::Running;
+            assert!(state.drain_with_async_notification(Some(&mut flusher)).await.is_err());
+            assert_eq!(state, ServiceState::Running);
+        });
+    }
 }
@@ -0,0 +1,551 @@
+use super::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// ServiceFactory creates the service with the given name, reading its config afresh.
+pub type ServiceFactory = Box<dyn Fn(&str) -> Option<Box<dyn ServerService>> + Send>;
+
+/// The ServiceManager drives a set of services through their lifecycle. When a snapshot
+/// directory is provided, each service's snapshot is persisted once it has drained, and is
+/// offered back to the service before it next starts. Individual services can be started,
+/// drained, stopped and, when a factory is provided, restarted with their config re-read, while
+/// the others keep running. When a state directory is provided, each service's state is persisted
+/// on every transition, so that after a crash, a service whose state was left started, running or
+/// draining is asked to recover before it next starts. Before a service runs, the manager waits
+/// for its probes to be ready. When a worker pool is provided, the services run in its worker
+/// processes, which share their listeners, and the manager of the supervisor process runs the
+/// workers, rather than the services.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+///
+/// let services: Vec<Box<dyn ServerService>> = Vec::new();
+/// let mut manager = ServiceManager::new(services).with_snapshot_dir(std::env::temp_dir().join("snapshots"));
+/// manager.start();
+/// manager.run();
+/// manager.drain();
+/// manager.stop();
+///
+/// // the services of a big machine, run by 4 worker processes
+/// let services: Vec<Box<dyn ServerService>> = Vec::new();
+/// let manager = ServiceManager::new(services).with_worker_pool(WorkerPool::new(4));
+/// ```
+pub struct ServiceManager {
+    services: Vec<Box<dyn ServerService>>,
+    snapshot_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    drain_timeout: Duration,
+    probe_timeout: Duration,
+    factory: Option<ServiceFactory>,
+    worker_pool: Option<WorkerPool>,
+}
+
+impl ServiceManager {
+    /// Create a manager for the services.
+    pub fn new(services: Vec<Box<dyn ServerService>>) -> Self {
+        Self {
+            services,
+            snapshot_dir: None,
+            state_dir: None,
+            drain_timeout: Duration::from_secs(5 * 60),
+            probe_timeout: Duration::from_secs(60),
+            factory: None,
+            worker_pool: None,
+        }
+    }
+
+    /// Persist the snapshots of the services in dir.
+    pub fn with_snapshot_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.snapshot_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Persist the state of the services in dir, to detect an unclean shutdown.
+    pub fn with_state_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.state_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set how long drain() waits for the services to drain, the default is 5 minutes.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Set how long a service's probes are waited upon before it runs, the default is 1 minute.
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Provide the factory restart_service() uses to recreate a service from its config.
+    pub fn with_factory<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(&str) -> Option<Box<dyn ServerService>> + Send + 'static,
+    {
+        self.factory = Some(Box::new(factory));
+        self
+    }
+
+    /// Run the services in the worker processes of the pool. In the supervisor process, start()
+    /// starts the workers, rather than the services, and stop() stops them, run() and drain()
+    /// doing nothing. In a worker, the services are run, their listeners bound with SO_REUSEPORT,
+    /// so that the workers share them.
+    pub fn with_worker_pool(mut self, pool: WorkerPool) -> Self {
+        self.worker_pool = Some(pool);
+        self
+    }
+
+    /// Get the stats of the workers, None unless this is the supervisor of a worker pool.
+    pub fn get_worker_stats(&self) -> Option<WorkerPoolStats> { self.get_supervised_pool().map(|pool| pool.get_stats()) }
+
+    /// Get the services.
+    pub fn get_services(&self) -> &[Box<dyn ServerService>] { &self.services }
+
+    /// Get the name and state of each service.
+    pub fn get_service_states(&self) -> Vec<(String, ServiceState)> {
+        self.services.iter().map(|s| (s.get_name().to_string(), s.get_state())).collect()
+    }
+
+    /// Restore each service from its snapshot, if there is one, and start it. A service which
+    /// fails to start is stopped.
+    pub fn start(&mut self) {
+        if let Some(pool) = self.get_supervised_pool_mut() {
+            pool.start();
+            return;
+        }
+        if self.worker_pool.is_some() {
+            NetCore::set_reuse_port(true);
+        }
+        for idx in 0 .. self.services.len() {
+            self.start_at(idx).ok();
+        }
+    }
+
+    /// Get the services running, once their probes are ready. A service which fails to run, or
+    /// whose probes aren't ready in time, is stopped.
+    pub fn run(&mut self) {
+        if self.get_supervised_pool().is_some() {
+            return;
+        }
+        for idx in 0 .. self.services.len() {
+            self.run_at(idx).ok();
+        }
+    }
+
+    /// Drain the services, waiting, but not too long, for them to finish draining, then persist
+    /// their snapshots. A service which fails to drain is stopped.
+    pub fn drain(&mut self) {
+        if self.get_supervised_pool().is_some() {
+            return;
+        }
+        let all: Vec<usize> = (0 .. self.services.len()).collect();
+        for idx in &all {
+            self.begin_drain_at(*idx).ok();
+        }
+        self.finish_drain(&all);
+    }
+
+    /// Stop any services that haven't already stopped.
+    pub fn stop(&mut self) {
+        if let Some(pool) = self.get_supervised_pool_mut() {
+            pool.stop();
+            return;
+        }
+        for idx in 0 .. self.services.len() {
+            self.stop_at(idx).ok();
+        }
+    }
+
+    /// Restore the named service from its snapshot, if there is one, and start it.
+    pub fn start_service(&mut self, name: &str) -> ServiceResult<()> {
+        let idx = self.find(name)?;
+        self.start_at(idx)
+    }
+
+    /// Get the named service running.
+    pub fn run_service(&mut self, name: &str) -> ServiceResult<()> {
+        let idx = self.find(name)?;
+        self.run_at(idx)
+    }
+
+    /// Drain the named service, waiting, but not too long, for it to finish draining, then
+    /// persist its snapshot.
+    pub fn drain_service(&mut self, name: &str) -> ServiceResult<()> {
+        let idx = self.find(name)?;
+        self.begin_drain_at(idx)?;
+        self.finish_drain(&[idx]);
+        Ok(())
+    }
+
+    /// Stop the named service.
+    pub fn stop_service(&mut self, name: &str) -> ServiceResult<()> {
+        let idx = self.find(name)?;
+        self.stop_at(idx)
+    }
+
+    /// Bounce the named service. It is drained, if running, and stopped, then recreated by the
+    /// factory, re-reading its config, and is started and run.
+    pub fn restart_service(&mut self, name: &str) -> ServiceResult<()> {
+        let idx = self.find(name)?;
+        if self.services[idx].get_state().can_drain() && self.begin_drain_at(idx).is_ok() {
+            self.finish_drain(&[idx]);
+        }
+        if self.services[idx].get_state().can_stop() {
+            self.stop_at(idx).ok();
+        }
+        let factory = self
+            .factory
+            .as_ref()
+            .ok_or_else(|| ServiceError::Message(format!("Service {} can't be recreated, there's no factory", name)))?;
+        let service = factory(name).ok_or_else(|| ServiceError::Message(format!("Service {} couldn't be recreated", name)))?;
+        self.services[idx] = service;
+        self.start_at(idx)?;
+        self.run_at(idx)
+    }
+
+    // Get the worker pool, if this is its supervisor, rather than one of its workers.
+    fn get_supervised_pool(&self) -> Option<&WorkerPool> { self.worker_pool.as_ref().filter(|_| WorkerPool::get_worker_index().is_none()) }
+
+    fn get_supervised_pool_mut(&mut self) -> Option<&mut WorkerPool> {
+        self.worker_pool.as_mut().filter(|_| WorkerPool::get_worker_index().is_none())
+    }
+
+    fn find(&self, name: &str) -> ServiceResult<usize> {
+        self.services
+            .iter()
+            .position(|s| s.get_name() == name)
+            .ok_or_else(|| ServiceError::Message(format!("Service {} not found", name)))
+    }
+
+    fn start_at(&mut self, idx: usize) -> ServiceResult<()> {
+        self.recover_at(idx)?;
+        let s = &mut self.services[idx];
+        if let Some(dir) = &self.snapshot_dir {
+            match load_snapshot(dir, s.get_name()) {
+                Ok(Some(snapshot)) => s.restore(snapshot),
+                Ok(None) => (),
+                Err(err) => log::warn!("Service {} snapshot not restored, error={}", s.get_name(), err),
+            }
+        }
+        let result = s.start().map_err(|err| {
+            log::error!("Service {} failed to start, error={:#?}", s.get_name(), err);
+            s.stop().ok();
+            err
+        });
+        self.persist_state_at(idx);
+        result
+    }
+
+    // If the service's last persisted state shows an unclean shutdown, have it recover. A service
+    // which fails to recover isn't started.
+    fn recover_at(&mut self, idx: usize) -> ServiceResult<()> {
+        let dir = match &self.state_dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let s = &mut self.services[idx];
+        let last_state = match load_state(dir, s.get_name()) {
+            Ok(Some(last_state)) if last_state.is_unclean() => last_state,
+            Ok(_) => return Ok(()),
+            Err(err) => {
+                log::warn!("Service {} state not loaded, error={}", s.get_name(), err);
+                return Ok(());
+            },
+        };
+        log::warn!(
+            "Service {} shut down uncleanly, last_state={:?}, recovering",
+            s.get_name(),
+            last_state
+        );
+        s.recover(last_state).map_err(|err| {
+            log::error!("Service {} failed to recover, error={:#?}", s.get_name(), err);
+            err
+        })
+    }
+
+    // Persist the service's state, if there's a state directory.
+    fn persist_state_at(&self, idx: usize) {
+        if let Some(dir) = &self.state_dir {
+            let s = &self.services[idx];
+            if let Err(err) = save_state(dir, s.get_name(), s.get_state()) {
+                log::error!("Service {} state not saved, error={}", s.get_name(), err);
+            }
+        }
+    }
+
+    fn run_at(&mut self, idx: usize) -> ServiceResult<()> {
+        let probe_timeout = self.probe_timeout;
+        let s = &mut self.services[idx];
+        let probed = s.get_probes().iter().try_for_each(|probe| {
+            probe
+                .wait(probe_timeout)
+                .map_err(|err| ServiceError::Message(format!("Service {} probe={:?} not ready, error={}", s.get_name(), probe, err)))
+        });
+        let result = probed.and_then(|_| s.run()).map_err(|err| {
+            log::error!("Service {} failed to run, error={:#?}", s.get_name(), err);
+            s.stop().ok();
+            err
+        });
+        self.persist_state_at(idx);
+        result
+    }
+
+    fn begin_drain_at(&mut self, idx: usize) -> ServiceResult<()> {
+        let s = &mut self.services[idx];
+        let result = s.drain().map_err(|err| {
+            log::error!("Service {} failed to drain, error={:#?}", s.get_name(), err);
+            s.stop().ok();
+            err
+        });
+        self.persist_state_at(idx);
+        result
+    }
+
+    // Wait for the draining services to finish, then persist their snapshots.
+    fn finish_drain(&self, draining: &[usize]) {
+        let start = Instant::now();
+        while start.elapsed() < self.drain_timeout && !draining.iter().all(|idx| self.services[*idx].is_drained()) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if let Some(dir) = &self.snapshot_dir {
+            for s in draining.iter().map(|idx| &self.services[*idx]) {
+                if let Some(snapshot) = s.snapshot() {
+                    if let Err(err) = save_snapshot(dir, s.get_name(), &snapshot) {
+                        log::error!("Service {} snapshot not saved, error={}", s.get_name(), err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn stop_at(&mut self, idx: usize) -> ServiceResult<()> {
+        let s = &mut self.services[idx];
+        let result = s.stop().map_err(|err| {
+            log::error!("Service {} failed to stop, error={:#?}", s.get_name(), err);
+            err
+        });
+        self.persist_state_at(idx);
+        result
+    }
+}
+
+fn snapshot_path(dir: &Path, name: &str) -> PathBuf { dir.join(format!("{}.json", name)) }
+
+fn load_snapshot(dir: &Path, name: &str) -> io::Result<Option<serde_json::Value>> {
+    match fs::read_to_string(snapshot_path(dir, name)) {
+        Ok(json) => serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// The snapshot replaces the previous one only once it is complete.
+fn save_snapshot(dir: &Path, name: &str, snapshot: &serde_json::Value) -> io::Result<()> {
+    save_atomically(dir, &snapshot_path(dir, name), &snapshot.to_string())
+}
+
+fn state_path(dir: &Path, name: &str) -> PathBuf { dir.join(format!("{}.state", name)) }
+
+fn load_state(dir: &Path, name: &str) -> io::Result<Option<ServiceState>> {
+    match fs::read_to_string(state_path(dir, name)) {
+        Ok(json) => serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn save_state(dir: &Path, name: &str, state: ServiceState) -> io::Result<()> {
+    let json = serde_json::to_string(&state).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    save_atomically(dir, &state_path(dir, name), &json)
+}
+
+// Write the contents to a partial file, then rename it to path, so that a crash doesn't leave
+// path partially written.
+fn save_atomically(dir: &Path, path: &Path, contents: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let partial = path.with_extension("partial");
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as SyncMutex;
+
+    #[derive(Default)]
+    struct Rooms {
+        state: ServiceState,
+        rooms: SyncMutex<Vec<String>>,
+    }
+    impl Snapshot for Rooms {
+        fn snapshot(&self) -> Option<serde_json::Value> { Some(self.rooms.lock().unwrap().clone().into()) }
+        fn restore(&self, snapshot: serde_json::Value) {
+            *self.rooms.lock().unwrap() = serde_json::from_value(snapshot).unwrap_or_default();
+        }
+    }
+    impl ServerService for Rooms {
+        fn get_name(&self) -> &str { "rooms" }
+        fn get_state(&self) -> ServiceState { self.state }
+        fn get_drain_count(&self) -> usize { 0 }
+        fn start(&mut self) -> ServiceResult<()> { self.state.start() }
+        fn run(&mut self) -> ServiceResult<()> {
+            self.rooms.lock().unwrap().push("lobby".to_string());
+            self.state.run()
+        }
+        fn drain(&mut self) -> ServiceResult<()> { self.state.drain() }
+        fn stop(&mut self) -> ServiceResult<()> { self.state.stop() }
+    }
+
+    fn lifecycle(dir: &Path) -> serde_json::Value {
+        let mut manager = ServiceManager::new(vec![Box::new(Rooms::default())]).with_snapshot_dir(dir);
+        manager.start();
+        manager.run();
+        assert_eq!(vec![("rooms".to_string(), ServiceState::Running)], manager.get_service_states());
+        manager.drain();
+        manager.stop();
+        manager.get_services()[0].snapshot().unwrap()
+    }
+
+    #[test]
+    fn snapshot_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", uuid::Uuid::new_v4()));
+        assert_eq!(serde_json::json!(["lobby"]), lifecycle(&dir));
+        assert_eq!(serde_json::json!(["lobby", "lobby"]), lifecycle(&dir));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn restart_one_service() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", uuid::Uuid::new_v4()));
+        let mut manager = ServiceManager::new(vec![Box::new(Rooms::default())])
+            .with_snapshot_dir(&dir)
+            .with_drain_timeout(Duration::from_millis(10));
+        assert!(manager.restart_service("lobby").is_err());
+        manager.start();
+        manager.run();
+        // without a factory, the service is stopped but can't be recreated
+        assert!(manager.restart_service("rooms").is_err());
+        assert_eq!(ServiceState::Stopped, manager.get_services()[0].get_state());
+
+        let mut manager = manager.with_factory(|name| match name {
+            "rooms" => Some(Box::new(Rooms::default()) as Box<dyn ServerService>),
+            _ => None,
+        });
+        manager.restart_service("rooms").unwrap();
+        assert_eq!(vec![("rooms".to_string(), ServiceState::Running)], manager.get_service_states());
+        // the recreated service was restored from the snapshot saved when it drained
+        assert_eq!(serde_json::json!(["lobby", "lobby"]), manager.get_services()[0].snapshot().unwrap());
+        manager.stop_service("rooms").unwrap();
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn recover_after_crash() {
+        struct Ledger {
+            state: ServiceState,
+            recovered: Arc<SyncMutex<Option<ServiceState>>>,
+        }
+        impl Snapshot for Ledger {}
+        impl ServerService for Ledger {
+            fn get_name(&self) -> &str { "ledger" }
+            fn get_state(&self) -> ServiceState { self.state }
+            fn get_drain_count(&self) -> usize { 0 }
+            fn start(&mut self) -> ServiceResult<()> { self.state.start() }
+            fn run(&mut self) -> ServiceResult<()> { self.state.run() }
+            fn drain(&mut self) -> ServiceResult<()> { self.state.drain() }
+            fn stop(&mut self) -> ServiceResult<()> { self.state.stop() }
+            fn recover(&mut self, last_state: ServiceState) -> ServiceResult<()> {
+                *self.recovered.lock().unwrap() = Some(last_state);
+                Ok(())
+            }
+        }
+        let dir = std::env::temp_dir().join(format!("states-{}", uuid::Uuid::new_v4()));
+        let recovered = Arc::new(SyncMutex::new(None));
+        let manager = || {
+            let ledger = Ledger {
+                state: ServiceState::default(),
+                recovered: recovered.clone(),
+            };
+            ServiceManager::new(vec![Box::new(ledger)]).with_state_dir(&dir)
+        };
+
+        // a clean shutdown
+        let mut clean = manager();
+        clean.start();
+        clean.run();
+        clean.stop();
+        let mut restarted = manager();
+        restarted.start();
+        assert_eq!(None, *recovered.lock().unwrap());
+
+        // a crash while running
+        restarted.run();
+        drop(restarted);
+        manager().start();
+        assert_eq!(Some(ServiceState::Running), *recovered.lock().unwrap());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn probe_before_run() {
+        struct Probed {
+            state: ServiceState,
+            probes: Vec<Probe>,
+        }
+        impl Snapshot for Probed {}
+        impl ServerService for Probed {
+            fn get_name(&self) -> &str { "probed" }
+            fn get_state(&self) -> ServiceState { self.state }
+            fn get_probes(&self) -> Vec<Probe> { self.probes.clone() }
+            fn get_drain_count(&self) -> usize { 0 }
+            fn start(&mut self) -> ServiceResult<()> { self.state.start() }
+            fn run(&mut self) -> ServiceResult<()> { self.state.run() }
+            fn drain(&mut self) -> ServiceResult<()> { self.state.drain() }
+            fn stop(&mut self) -> ServiceResult<()> { self.state.stop() }
+        }
+        let probed = |path: &Path| Probed {
+            state: ServiceState::default(),
+            probes: vec![Probe::File { path: path.to_path_buf() }],
+        };
+        let path = std::env::temp_dir().join(format!("probe-{}", uuid::Uuid::new_v4()));
+        let mut manager = ServiceManager::new(vec![Box::new(probed(&path))]).with_probe_timeout(Duration::from_millis(10));
+        manager.start();
+        manager.run();
+        assert_eq!(ServiceState::Stopped, manager.get_services()[0].get_state());
+
+        fs::write(&path, "ready").unwrap();
+        let mut manager = ServiceManager::new(vec![Box::new(probed(&path))]);
+        manager.start();
+        manager.run();
+        assert_eq!(ServiceState::Running, manager.get_services()[0].get_state());
+        fs::remove_file(&path).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn supervise_worker_pool() {
+        let spec = ProcessSpec::new("sh").with_arg("-c").with_arg("echo \"@stat workers 1\"; sleep 10");
+        let mut manager = ServiceManager::new(vec![Box::new(Rooms::default())]).with_worker_pool(WorkerPool::new(2).with_spec(spec));
+        // the supervisor runs the workers, rather than the services
+        manager.start();
+        manager.run();
+        assert_eq!(ServiceState::Init, manager.get_services()[0].get_state());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.get_worker_stats().unwrap().stats.get("workers") != Some(&2) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(2, manager.get_worker_stats().unwrap().running);
+        manager.stop();
+    }
+}
@@ -0,0 +1,143 @@
+use super::*;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash},
+};
+
+/// The default number of shards.
+const DEFAULT_SHARDS: usize = 16;
+
+/// ShardedState is a map split into shards, each with its own lock. A key is routed to a shard by
+/// its hash, so tasks working on different keys rarely contend for a lock, unlike a controller
+/// where all state sits behind one Mutex. Locks are async and held only for the duration of a
+/// call, never across an await of the caller.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::ShardedState;
+///
+/// let connections: ShardedState<usize, String> = ShardedState::default();
+/// smol::block_on(async {
+///     connections.insert(1, "127.0.0.1:5000".to_string()).await;
+///     connections.update(&1, |addr| addr.map(|addr| addr.push_str(" (closing)"))).await;
+///     assert_eq!(Some("127.0.0.1:5000 (closing)".to_string()), connections.get(&1).await);
+///     assert_eq!(1, connections.len().await);
+/// });
+/// ```
+pub struct ShardedState<K, V> {
+    shards: Vec<Mutex<HashMap<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K, V> fmt::Debug for ShardedState<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#ShardedState {{ shards: {} }}", self.shards.len()) }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedState<K, V> {
+    fn default() -> Self { Self::new(DEFAULT_SHARDS) }
+}
+
+impl<K: Hash + Eq, V> ShardedState<K, V> {
+    /// Create the state with num_shards shards, at least one shard is created.
+    pub fn new(num_shards: usize) -> Self {
+        let shards = (0 .. num_shards.max(1)).map(|_| Mutex::new(HashMap::new())).collect();
+        Self {
+            shards,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Get the number of shards.
+    pub fn get_shard_count(&self) -> usize { self.shards.len() }
+
+    /// Insert a value, returning the previous value for the key.
+    pub async fn insert(&self, key: K, value: V) -> Option<V> { self.shard(&key).lock().await.insert(key, value) }
+
+    /// Remove a key, returning its value.
+    pub async fn remove(&self, key: &K) -> Option<V> { self.shard(key).lock().await.remove(key) }
+
+    /// Get a copy of the value for a key.
+    pub async fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).lock().await.get(key).cloned()
+    }
+
+    /// Check if there's a value for a key.
+    pub async fn contains_key(&self, key: &K) -> bool { self.shard(key).lock().await.contains_key(key) }
+
+    /// Update the value for a key in place, f is called with the value, if any, while the shard
+    /// is locked.
+    pub async fn update<R>(&self, key: &K, f: impl FnOnce(Option<&mut V>) -> R) -> R { f(self.shard(key).lock().await.get_mut(key)) }
+
+    /// Get the number of values, summed over the shards. As shards are locked one at a time, the
+    /// result is approximate while other tasks are modifying the state.
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in &self.shards {
+            len += shard.lock().await.len();
+        }
+        len
+    }
+
+    /// Check if there are no values.
+    pub async fn is_empty(&self) -> bool { self.len().await == 0 }
+
+    /// Remove all values.
+    pub async fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().await.clear();
+        }
+    }
+
+    // Route a key to its shard.
+    fn shard(&self, key: &K) -> &Mutex<HashMap<K, V>> { &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_update_remove() {
+        let state: ShardedState<usize, usize> = ShardedState::new(4);
+        assert_eq!(4, state.get_shard_count());
+        smol::block_on(async {
+            for key in 0 .. 100 {
+                assert_eq!(None, state.insert(key, key).await);
+            }
+            assert_eq!(100, state.len().await);
+            assert!(state.update(&7, |value| value.map(|value| *value += 1).is_some()).await);
+            assert!(!state.update(&1000, |value| value.is_some()).await);
+            assert_eq!(Some(8), state.get(&7).await);
+            assert_eq!(Some(8), state.remove(&7).await);
+            assert!(!state.contains_key(&7).await);
+            state.clear().await;
+            assert!(state.is_empty().await);
+        });
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let state: Arc<ShardedState<usize, usize>> = Arc::new(ShardedState::default());
+        let tasks: Vec<_> = (0 .. 8)
+            .map(|task| {
+                let state = state.clone();
+                get_executor().spawn(async move {
+                    for key in 0 .. 100 {
+                        state.insert(task * 100 + key, key).await;
+                    }
+                })
+            })
+            .collect();
+        smol::block_on(async {
+            for task in tasks {
+                task.await;
+            }
+            assert_eq!(800, state.len().await);
+        });
+    }
+}
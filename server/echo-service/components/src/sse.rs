@@ -0,0 +1,142 @@
+use super::*;
+use machine_foundation::MachineSender;
+
+/// An SseEvent is a single Server-Sent Event. Only the data is required, the event type, id
+/// and retry interval are optional.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::SseEvent;
+///
+/// let event = SseEvent::new("42 connections").with_event("stats").with_id("7");
+/// assert_eq!(b"id: 7\nevent: stats\ndata: 42 connections\n\n".to_vec(), event.encode());
+/// ```
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+    pub retry: Option<u64>,
+}
+
+impl SseEvent {
+    /// Create an event carrying data. Multi-line data is sent as multiple data fields, a line ending
+    /// with CR LF, CR, or LF, as each ends a field.
+    pub fn new(data: &str) -> Self {
+        Self {
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the event type, the browser dispatches it to listeners of that type. Any CR or LF is
+    /// stripped, as it would end the field.
+    pub fn with_event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    /// Set the event id, the browser reports it as Last-Event-ID when reconnecting. Any CR or LF
+    /// is stripped, as it would end the field.
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    /// Set the reconnection time, in milliseconds.
+    pub fn with_retry(mut self, retry: u64) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Encode the event in the text/event-stream format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = String::with_capacity(self.data.len() + 32);
+        if let Some(id) = &self.id {
+            res.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(event) = &self.event {
+            res.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(retry) = self.retry {
+            res.push_str(&format!("retry: {}\n", retry));
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            res.push_str(&format!("data: {}\n", line));
+        }
+        res.push('\n');
+        res.into_bytes()
+    }
+}
+
+// Strip the CR and LF from the value of a field, so that it can't inject other fields, or events.
+fn single_line(value: &str) -> String { value.chars().filter(|c| *c != '\r' && *c != '\n').collect() }
+
+/// The SseConnection wraps a NetCore connection which is used as an event stream. There's no HTTP
+/// component in the tree, so the machine bound to the connection reads the request itself, then
+/// calls start() to send the response header, after which it can push events until the
+/// connection is closed. The body is delimited by closing the connection, which the header tells
+/// the client with Connection: close, so there's no need for a chunked response.
+#[derive(Debug, Clone)]
+pub struct SseConnection {
+    conn_id: NetConnId,
+    net_sender: NetSender,
+}
+
+impl SseConnection {
+    /// The response header sent prior to any event.
+    pub const RESPONSE_HEADER: &'static str =
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+
+    /// Create an event stream for a connection.
+    pub fn new(conn_id: NetConnId, net_sender: NetSender) -> Self { Self { conn_id, net_sender } }
+
+    /// Get the connection id of the event stream.
+    pub const fn get_conn_id(&self) -> NetConnId { self.conn_id }
+
+    /// Start the event stream by sending the response header.
+    pub fn start(&self, sender: &mut MachineSender) { self.send_bytes(Self::RESPONSE_HEADER.as_bytes().to_vec(), sender); }
+
+    /// Push an event to the client.
+    pub fn send(&self, event: &SseEvent, sender: &mut MachineSender) { self.send_bytes(event.encode(), sender); }
+
+    /// Send a comment, which keeps intermediaries from timing out an idle stream.
+    pub fn keep_alive(&self, sender: &mut MachineSender) { self.send_bytes(b":\n\n".to_vec(), sender); }
+
+    /// End the event stream by closing the connection.
+    pub fn close(&self, sender: &mut MachineSender) { sender.send(self.net_sender.clone(), NetCmd::CloseConn(self.conn_id)); }
+
+    fn send_bytes(&self, bytes: Vec<u8>, sender: &mut MachineSender) {
+        sender.send(self.net_sender.clone(), NetCmd::SendBytes(self.conn_id, bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_data_only() {
+        let event = SseEvent::new("hello");
+        assert_eq!(b"data: hello\n\n".to_vec(), event.encode());
+    }
+
+    #[test]
+    fn encode_multi_line() {
+        let event = SseEvent::new("line 1\r\nline 2").with_retry(1000);
+        assert_eq!(b"retry: 1000\ndata: line 1\ndata: line 2\n\n".to_vec(), event.encode());
+    }
+
+    #[test]
+    fn strip_injected_fields() {
+        let event = SseEvent::new("hello").with_event("stats\ndata: forged").with_id("7\r\n\nevent: x");
+        assert_eq!(b"id: 7event: x\nevent: statsdata: forged\ndata: hello\n\n".to_vec(), event.encode());
+    }
+
+    #[test]
+    fn split_data_on_lone_cr() {
+        let event = SseEvent::new("a\revent: x\r\rb");
+        assert_eq!(b"data: a\ndata: event: x\ndata: \ndata: b\n\n".to_vec(), event.encode());
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+use machine_foundation::{Machine, MachineSender};
+use std::{collections::BTreeMap, fmt, io, net::UdpSocket, time::Duration};
+
+/// Shorthand for a sender, that can be sent MetricsCmd instructions.
+pub type MetricsSender = channel::Sender<MetricsCmd>;
+
+// Keep packets within a typical MTU, to avoid fragmentation.
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// MetricsCmd is the instruction set for pushing metrics.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum MetricsCmd {
+    /// Increment a counter.
+    Count(String, i64),
+    /// Set a gauge.
+    Gauge(String, f64),
+    /// Record a timing, in milliseconds.
+    Timing(String, u64),
+    /// Push everything collected since the last flush.
+    Flush,
+}
+
+/// The StatsdMachine implements the MetricsCmd instruction set. It aggregates counters and gauges
+/// between flushes, and pushes them in the DogStatsD format, which is StatsD with tags, over UDP.
+pub struct StatsdMachine {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    counters: AtomicRefCell<BTreeMap<String, i64>>,
+    gauges: AtomicRefCell<BTreeMap<String, f64>>,
+    timings: AtomicRefCell<Vec<(String, u64)>>,
+    dropped_instructions: bool,
+}
+
+impl fmt::Debug for StatsdMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#StatsdMachine {{ {:?} }}", self.socket.peer_addr()) }
+}
+
+impl StatsdMachine {
+    /// Create a StatsdMachine, which pushes to the agent at address, such as "127.0.0.1:8125".
+    pub fn new(address: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            prefix: String::new(),
+            tags: Vec::new(),
+            counters: AtomicRefCell::new(BTreeMap::new()),
+            gauges: AtomicRefCell::new(BTreeMap::new()),
+            timings: AtomicRefCell::new(Vec::new()),
+            dropped_instructions: false,
+        })
+    }
+
+    /// Prefix every metric name, a separating '.' is added.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = format!("{}.", prefix);
+        self
+    }
+
+    /// Add a tag to every metric.
+    pub fn with_tag(mut self, name: &str, value: &str) -> Self {
+        self.tags.push(format!("{}:{}", name, value));
+        self
+    }
+
+    /// Tag every metric with the service name and the environment it is running in.
+    pub fn with_service(self, service: &str, environment: &str) -> Self { self.with_tag("service", service).with_tag("env", environment) }
+
+    /// Gauge, on each flush, the number of instructions dropped for each instruction set, such as
+    /// "dropped_instructions.NetCmd", see machine_foundation::get_dropped_instructions().
+    pub fn with_dropped_instructions(mut self) -> Self {
+        self.dropped_instructions = true;
+        self
+    }
+
+    /// Start a task which sends Flush every interval. The task runs until the returned BackgroundTask
+    /// is cancelled or dropped.
+    pub fn start_flush_timer(sender: MetricsSender, interval: Duration) -> BackgroundTask {
+        let task = get_executor().spawn(async move {
+            loop {
+                smol::Timer::after(interval).await;
+                if sender.send(MetricsCmd::Flush).await.is_err() {
+                    break;
+                }
+            }
+        });
+        BackgroundTask::detach(task, "statsd flush")
+    }
+
+    // Drain the collected metrics, encoding each as a line.
+    fn drain_lines(&self) -> Vec<String> {
+        let tags = if self.tags.is_empty() {
+            String::new()
+        } else {
+            format!("|#{}", self.tags.join(","))
+        };
+        let mut lines = Vec::new();
+        for (name, value) in std::mem::take(&mut *self.counters.borrow_mut()) {
+            lines.push(format!("{}{}:{}|c{}", self.prefix, name, value, tags));
+        }
+        for (name, value) in std::mem::take(&mut *self.gauges.borrow_mut()) {
+            lines.push(format!("{}{}:{}|g{}", self.prefix, name, value, tags));
+        }
+        for (name, value) in std::mem::take(&mut *self.timings.borrow_mut()) {
+            lines.push(format!("{}{}:{}|ms{}", self.prefix, name, value, tags));
+        }
+        lines
+    }
+
+    // Gauge the instructions dropped, naming each instruction set by its type, less its path.
+    fn gauge_dropped_instructions(&self) {
+        let mut gauges = self.gauges.borrow_mut();
+        for dropped in machine_foundation::get_dropped_instructions() {
+            let name = dropped.instruction_set.split('<').next().unwrap_or_default();
+            let name = name.rsplit("::").next().unwrap_or_default();
+            gauges.insert(format!("dropped_instructions.{}", name), dropped.count as f64);
+        }
+    }
+
+    fn flush(&self) {
+        if self.dropped_instructions {
+            self.gauge_dropped_instructions();
+        }
+        let mut packet = String::new();
+        for line in self.drain_lines() {
+            if !packet.is_empty() && packet.len() + line.len() + 1 > MAX_PACKET_SIZE {
+                self.send_packet(&packet);
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.send_packet(&packet);
+        }
+    }
+
+    fn send_packet(&self, packet: &str) {
+        if let Err(err) = self.socket.send(packet.as_bytes()) {
+            log::warn!("failed to push metrics error={}", err);
+        }
+    }
+}
+
+impl Machine<MetricsCmd> for StatsdMachine {
+    fn receive(&self, cmd: MetricsCmd, _sender: &mut MachineSender) {
+        match cmd {
+            MetricsCmd::Count(name, value) => *self.counters.borrow_mut().entry(name).or_insert(0) += value,
+            MetricsCmd::Gauge(name, value) => {
+                self.gauges.borrow_mut().insert(name, value);
+            },
+            MetricsCmd::Timing(name, value) => self.timings.borrow_mut().push((name, value)),
+            MetricsCmd::Flush => self.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_and_encode() {
+        let statsd = StatsdMachine::new("127.0.0.1:8125")
+            .unwrap()
+            .with_prefix("echo")
+            .with_service("echo", "test");
+        let mut sender = MachineSender::default();
+        statsd.receive(MetricsCmd::Count("conns".to_string(), 1), &mut sender);
+        statsd.receive(MetricsCmd::Count("conns".to_string(), 2), &mut sender);
+        statsd.receive(MetricsCmd::Gauge("active".to_string(), 1.5), &mut sender);
+        statsd.receive(MetricsCmd::Timing("recv".to_string(), 7), &mut sender);
+        assert_eq!(
+            vec![
+                "echo.conns:3|c|#service:echo,env:test",
+                "echo.active:1.5|g|#service:echo,env:test",
+                "echo.recv:7|ms|#service:echo,env:test"
+            ],
+            statsd.drain_lines()
+        );
+        assert!(statsd.drain_lines().is_empty());
+    }
+
+    #[test]
+    fn flush_over_udp() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // a lost datagram fails the test, rather than hanging it
+        agent.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let statsd = StatsdMachine::new(&agent.local_addr().unwrap().to_string()).unwrap();
+        let (_statsd, sender) = machine_foundation::machine::create(statsd);
+        let _timer = StatsdMachine::start_flush_timer(sender.clone(), Duration::from_millis(10));
+        sender.try_send(MetricsCmd::Count("hits".to_string(), 1)).ok();
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"hits:1|c", &buf[.. len]);
+    }
+}
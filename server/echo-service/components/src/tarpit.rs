@@ -0,0 +1,200 @@
+use super::*;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex as SyncMutex,
+    },
+    time::{Duration, Instant},
+};
+
+// The number of clients tracked, beyond which those which are neither recent nor tarpitted are
+// forgotten.
+const MAX_CLIENTS: usize = 10_000;
+
+/// A TarpitPolicy decides which clients of a listener are tarpitted. Rather than being rejected,
+/// a tarpitted client has its connections accepted, and its reads, slowed, which wastes an
+/// abuser's time without tipping it off. A client is tarpitted for a penalty period when it
+/// connects too often, or is reported for abuse, such as failed auth, via NetCmd::ReportAbuse.
+/// Policies are deserialized from the config of the service, such as:
+///
+/// ```json
+/// "tarpit": { "max_connects": 20, "window_secs": 10, "max_failures": 3, "accept_delay_ms": 2000, "read_delay_ms": 500, "penalty_secs": 300 }
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault, Deserialize)]
+#[serde(default)]
+pub struct TarpitPolicy {
+    /// The number of connections a client may make within the window.
+    #[default = 20]
+    pub max_connects: usize,
+    /// The window, in seconds, over which connections are counted.
+    #[default = 10]
+    pub window_secs: u64,
+    /// The number of times a client may be reported for abuse.
+    #[default = 3]
+    pub max_failures: u32,
+    /// The time, in milliseconds, a tarpitted client's connection waits to be accepted.
+    #[default = 2000]
+    pub accept_delay_ms: u64,
+    /// The time, in milliseconds, a tarpitted client's connection waits before each read.
+    #[default = 500]
+    pub read_delay_ms: u64,
+    /// The time, in seconds, a client stays tarpitted after its last offense.
+    #[default = 300]
+    pub penalty_secs: u64,
+}
+
+/// TarpitStats counts the tarpitting done by a listener.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TarpitStats {
+    /// The number of connections whose accept was slowed.
+    pub slowed_accepts: u64,
+    /// The number of reads which were slowed.
+    pub slowed_reads: u64,
+    /// The number of clients which have been tarpitted.
+    pub tarpitted_clients: u64,
+}
+
+#[derive(Debug, Default)]
+struct Client {
+    connects: VecDeque<Instant>,
+    failures: u32,
+    tarpitted_until: Option<Instant>,
+}
+
+impl Client {
+    fn is_tarpitted(&self, now: Instant) -> bool { matches!(self.tarpitted_until, Some(until) if now < until) }
+}
+
+// The Tarpit of a listener, holding its policy, if any, and the record of each client.
+#[derive(Debug, Default)]
+pub(crate) struct Tarpit {
+    policy: SyncMutex<Option<TarpitPolicy>>,
+    clients: SyncMutex<HashMap<IpAddr, Client>>,
+    slowed_accepts: AtomicU64,
+    slowed_reads: AtomicU64,
+    tarpitted_clients: AtomicU64,
+}
+
+impl Tarpit {
+    // Set the policy, or if None, stop tarpitting and forget the clients.
+    pub(crate) fn set_policy(&self, policy: Option<TarpitPolicy>) {
+        if policy.is_none() {
+            self.lock_clients().clear();
+        }
+        *self.policy.lock().unwrap_or_else(|err| err.into_inner()) = policy;
+    }
+
+    // Record a client's connection, returning the time its accept is to be slowed by, if any.
+    pub(crate) fn on_connect(&self, ip: IpAddr) -> Option<Duration> {
+        let policy = self.get_policy()?;
+        let now = Instant::now();
+        let window = Duration::from_secs(policy.window_secs);
+        let mut clients = self.lock_clients();
+        if clients.len() >= MAX_CLIENTS {
+            clients.retain(|_, c| c.is_tarpitted(now) || matches!(c.connects.back(), Some(at) if now.duration_since(*at) < window));
+        }
+        let client = clients.entry(ip).or_default();
+        while matches!(client.connects.front(), Some(at) if now.duration_since(*at) >= window) {
+            client.connects.pop_front();
+        }
+        client.connects.push_back(now);
+        if client.connects.len() > policy.max_connects {
+            self.tarpit(ip, client, &policy, now);
+        }
+        if client.is_tarpitted(now) {
+            self.slowed_accepts.fetch_add(1, Ordering::Relaxed);
+            Some(Duration::from_millis(policy.accept_delay_ms))
+        } else {
+            None
+        }
+    }
+
+    // Record a client being reported for abuse.
+    pub(crate) fn on_abuse(&self, ip: IpAddr) {
+        if let Some(policy) = self.get_policy() {
+            let mut clients = self.lock_clients();
+            let client = clients.entry(ip).or_default();
+            client.failures += 1;
+            if client.failures >= policy.max_failures {
+                self.tarpit(ip, client, &policy, Instant::now());
+            }
+        }
+    }
+
+    // Get the time a client's read is to be slowed by, if any.
+    pub(crate) fn get_read_delay(&self, ip: IpAddr) -> Option<Duration> {
+        let policy = self.get_policy()?;
+        let tarpitted = matches!(self.lock_clients().get(&ip), Some(c) if c.is_tarpitted(Instant::now()));
+        if tarpitted {
+            self.slowed_reads.fetch_add(1, Ordering::Relaxed);
+            Some(Duration::from_millis(policy.read_delay_ms))
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn get_stats(&self) -> TarpitStats {
+        TarpitStats {
+            slowed_accepts: self.slowed_accepts.load(Ordering::Relaxed),
+            slowed_reads: self.slowed_reads.load(Ordering::Relaxed),
+            tarpitted_clients: self.tarpitted_clients.load(Ordering::Relaxed),
+        }
+    }
+
+    fn tarpit(&self, ip: IpAddr, client: &mut Client, policy: &TarpitPolicy, now: Instant) {
+        if !client.is_tarpitted(now) {
+            log::info!("tarpitting client={}", ip);
+            self.tarpitted_clients.fetch_add(1, Ordering::Relaxed);
+        }
+        client.tarpitted_until = Some(now + Duration::from_secs(policy.penalty_secs));
+    }
+
+    fn get_policy(&self) -> Option<TarpitPolicy> { *self.policy.lock().unwrap_or_else(|err| err.into_inner()) }
+
+    fn lock_clients(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, Client>> {
+        self.clients.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tarpit_clients() {
+        let tarpit = Tarpit::default();
+        let abuser: IpAddr = "10.0.0.1".parse().unwrap();
+        let guesser: IpAddr = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "10.0.0.3".parse().unwrap();
+        // without a policy, nobody is tarpitted
+        assert_eq!(None, tarpit.on_connect(abuser));
+
+        let policy: TarpitPolicy = serde_json::from_str(r#"{ "max_connects": 2, "accept_delay_ms": 10, "read_delay_ms": 5 }"#).unwrap();
+        tarpit.set_policy(Some(policy));
+        assert_eq!(None, tarpit.on_connect(abuser));
+        assert_eq!(None, tarpit.on_connect(abuser));
+        assert_eq!(Some(Duration::from_millis(10)), tarpit.on_connect(abuser));
+        assert_eq!(Some(Duration::from_millis(5)), tarpit.get_read_delay(abuser));
+
+        for _ in 0 .. policy.max_failures {
+            assert_eq!(None, tarpit.get_read_delay(guesser));
+            tarpit.on_abuse(guesser);
+        }
+        assert_eq!(Some(Duration::from_millis(10)), tarpit.on_connect(guesser));
+        assert_eq!(None, tarpit.on_connect(client));
+        assert_eq!(None, tarpit.get_read_delay(client));
+        assert_eq!(
+            TarpitStats {
+                slowed_accepts: 2,
+                slowed_reads: 1,
+                tarpitted_clients: 2
+            },
+            tarpit.get_stats()
+        );
+        tarpit.set_policy(None);
+        assert_eq!(None, tarpit.get_read_delay(abuser));
+    }
+}
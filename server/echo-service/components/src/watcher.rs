@@ -0,0 +1,225 @@
+use super::*;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// FileChange describes how a file changed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileChange {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// WatchEvent is the instruction set a FileWatcher sends.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum WatchEvent {
+    /// A watched file changed.
+    FileChanged(PathBuf, FileChange),
+}
+
+// What's known of a file, when it is scanned.
+type FileStamp = (Option<SystemTime>, u64);
+
+/// The FileWatcher delivers FileChanged instructions for the files in a set of paths. A path
+/// may be a file, or a directory, which is optionally watched recursively. The paths are polled,
+/// with the scanning done on the blocking pool, and changes are debounced, so a file written
+/// in several steps is reported once, after it has settled.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+/// use std::time::Duration;
+///
+/// let (sender, receiver) = smol::channel::unbounded::<WatchEvent>();
+/// let watcher = FileWatcher::new(sender)
+///     .with_path("config/certs", true)
+///     .with_path("config/default.json", false)
+///     .with_debounce(Duration::from_millis(500))
+///     .start();
+/// // cancel the watcher when done
+/// watcher.cancel();
+/// ```
+#[derive(Debug, SmartDefault)]
+pub struct FileWatcher {
+    #[default(channel::unbounded().0)]
+    sender: WatchEventSender,
+    paths: Vec<(PathBuf, bool)>,
+    #[default(Duration::from_secs(1))]
+    poll_interval: Duration,
+    #[default(Duration::from_millis(250))]
+    debounce: Duration,
+}
+
+impl FileWatcher {
+    /// Create a watcher, which sends its events to sender.
+    pub fn new(sender: WatchEventSender) -> Self { Self { sender, ..Self::default() } }
+
+    /// Watch a path, if it is a directory, recursive determines if sub-directories are watched.
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P, recursive: bool) -> Self {
+        self.paths.push((path.as_ref().to_path_buf(), recursive));
+        self
+    }
+
+    /// Set the interval between polls, the default is one second.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Set how long a file must be unchanged before its change is sent, the default is 250ms.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Start watching. The watcher runs until the returned task is cancelled, or the sender closes.
+    pub fn start(self) -> BackgroundTask {
+        let Self {
+            sender,
+            paths,
+            poll_interval,
+            debounce: settle_time,
+        } = self;
+        let task = get_executor().spawn(async move {
+            let paths = Arc::new(paths);
+            let scan_paths = paths.clone();
+            let mut stamps = smol::unblock(move || scan(&scan_paths)).await;
+            let mut pending: HashMap<PathBuf, (FileChange, Instant)> = HashMap::new();
+            loop {
+                smol::Timer::after(poll_interval).await;
+                let scan_paths = paths.clone();
+                let current = smol::unblock(move || scan(&scan_paths)).await;
+                let now = Instant::now();
+                for (path, change) in diff(&stamps, &current) {
+                    debounce(&mut pending, path, change, now);
+                }
+                stamps = current;
+                let settled: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, (_, at))| now.duration_since(*at) >= settle_time)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in settled {
+                    if let Some((change, _)) = pending.remove(&path) {
+                        if sender.send(WatchEvent::FileChanged(path, change)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        BackgroundTask::detach(task, "file watcher")
+    }
+}
+
+// Record a change, merging it with any pending change for the path.
+fn debounce(pending: &mut HashMap<PathBuf, (FileChange, Instant)>, path: PathBuf, change: FileChange, now: Instant) {
+    let merged = match (pending.get(&path).map(|(change, _)| *change), change) {
+        (Some(FileChange::Created), FileChange::Modified) => Some(FileChange::Created),
+        (Some(FileChange::Created), FileChange::Removed) => None,
+        (Some(FileChange::Removed), FileChange::Created) => Some(FileChange::Modified),
+        (_, change) => Some(change),
+    };
+    match merged {
+        Some(change) => {
+            pending.insert(path, (change, now));
+        },
+        None => {
+            pending.remove(&path);
+        },
+    }
+}
+
+// Compare two scans, returning the changes.
+fn diff(previous: &HashMap<PathBuf, FileStamp>, current: &HashMap<PathBuf, FileStamp>) -> Vec<(PathBuf, FileChange)> {
+    let mut changes: Vec<(PathBuf, FileChange)> = current
+        .iter()
+        .filter_map(|(path, stamp)| match previous.get(path) {
+            None => Some((path.clone(), FileChange::Created)),
+            Some(previous) if previous != stamp => Some((path.clone(), FileChange::Modified)),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(
+        previous
+            .keys()
+            .filter(|path| !current.contains_key(*path))
+            .map(|path| (path.clone(), FileChange::Removed)),
+    );
+    changes
+}
+
+// Scan the paths, stamping each file found.
+fn scan(paths: &[(PathBuf, bool)]) -> HashMap<PathBuf, FileStamp> {
+    let mut stamps = HashMap::new();
+    for (path, recursive) in paths {
+        scan_path(path, *recursive, true, &mut stamps);
+    }
+    stamps
+}
+
+fn scan_path(path: &Path, recursive: bool, top: bool, stamps: &mut HashMap<PathBuf, FileStamp>) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    if metadata.is_file() {
+        stamps.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+    } else if metadata.is_dir() && (top || recursive) {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                scan_path(&entry.path(), recursive, false, stamps);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next_event(receiver: &channel::Receiver<WatchEvent>) -> (PathBuf, FileChange) {
+        let WatchEvent::FileChanged(path, change) = smol::block_on(receiver.recv()).unwrap();
+        (path, change)
+    }
+
+    #[test]
+    fn debounce_merges_changes() {
+        let mut pending = HashMap::new();
+        let (path, now) = (PathBuf::from("a"), Instant::now());
+        debounce(&mut pending, path.clone(), FileChange::Created, now);
+        debounce(&mut pending, path.clone(), FileChange::Modified, now);
+        assert_eq!(Some(&(FileChange::Created, now)), pending.get(&path));
+        debounce(&mut pending, path.clone(), FileChange::Removed, now);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn watch_recursive() {
+        let dir = std::env::temp_dir().join(format!("watcher-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let (sender, receiver) = channel::unbounded();
+        let watcher = FileWatcher::new(sender)
+            .with_path(&dir, true)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_debounce(Duration::from_millis(30))
+            .start();
+        std::thread::sleep(Duration::from_millis(50));
+
+        let file = dir.join("sub").join("cert.pem");
+        fs::write(&file, "first").unwrap();
+        assert_eq!((file.clone(), FileChange::Created), next_event(&receiver));
+        fs::write(&file, "second version").unwrap();
+        assert_eq!((file.clone(), FileChange::Modified), next_event(&receiver));
+        fs::remove_file(&file).unwrap();
+        assert_eq!((file, FileChange::Removed), next_event(&receiver));
+
+        watcher.cancel();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
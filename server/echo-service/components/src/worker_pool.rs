@@ -0,0 +1,264 @@
+use super::*;
+use machine_foundation::machine;
+use std::{collections::HashMap, sync::Mutex as SyncMutex};
+
+/// The environment variable which tells a worker process its index in the pool.
+pub const WORKER_INDEX_ENV: &str = "SERVER_WORKER_INDEX";
+
+// The prefix of a line, written by a worker to stdout, which reports a stat.
+const STAT_PREFIX: &str = "@stat ";
+
+/// The WorkerStats of a worker process.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct WorkerStats {
+    /// The index of the worker in the pool.
+    pub index: usize,
+    /// The OS pid of the worker, the latest if it was restarted.
+    pub pid: Option<u32>,
+    /// Whether the worker is running.
+    pub running: bool,
+    /// The number of times the worker has been restarted.
+    pub restarts: usize,
+    /// The exit code of the worker, when it last exited.
+    pub exit_code: Option<i32>,
+    /// The latest value of each stat reported by the worker.
+    pub stats: HashMap<String, i64>,
+}
+
+/// The WorkerPoolStats aggregate the stats of the workers of a pool.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct WorkerPoolStats {
+    /// The stats of each worker, by index.
+    pub workers: Vec<WorkerStats>,
+    /// The number of workers running.
+    pub running: usize,
+    /// The number of restarts, across the workers.
+    pub restarts: usize,
+    /// The sum of each stat, across the workers.
+    pub stats: HashMap<String, i64>,
+}
+
+// The stats of a worker, and the partial line of stdout which hasn't been parsed yet.
+#[derive(Debug, Default)]
+struct WorkerState {
+    stats: WorkerStats,
+    line: Vec<u8>,
+}
+
+/// The WorkerPool runs a server as a supervisor process, and a number of worker processes, pre-fork
+/// style, for deployments which want process-level isolation on big machines. Each worker is a copy
+/// of the current executable, with the same arguments, and WORKER_INDEX_ENV set to its index. A
+/// worker sets NetCore::set_reuse_port() before binding its listeners, so the workers share them,
+/// the kernel spreading the connections across them. The supervisor restarts a worker when it
+/// exits, and aggregates the stats each reports with WorkerPool::report_stat(). The rest of a
+/// worker's output is logged by the supervisor.
+///
+/// Examples:
+///
+/// ```rust
+/// use components::*;
+///
+/// match WorkerPool::get_worker_index() {
+///     Some(_index) => {
+///         // a worker runs the services, sharing its listeners with the other workers
+///         NetCore::set_reuse_port(true);
+///         WorkerPool::report_stat("connections", 0);
+///     },
+///     None => {
+///         // the supervisor runs the workers, until it is stopped
+///         let mut pool = WorkerPool::new(0);
+///         pool.start();
+///         println!("{:?}", pool.get_stats());
+///         pool.stop();
+///     },
+/// }
+/// ```
+pub struct WorkerPool {
+    workers: usize,
+    spec: ProcessSpec,
+    manager: Option<(Arc<ProcessManager>, ProcessSender)>,
+    state: Arc<SyncMutex<Vec<WorkerState>>>,
+}
+
+impl WorkerPool {
+    /// Create a pool of the number of workers, each running the current executable, with the same
+    /// arguments. A worker is always restarted when it exits, after a second.
+    pub fn new(workers: usize) -> Self {
+        let program = std::env::current_exe().unwrap_or_default().to_string_lossy().to_string();
+        let spec = std::env::args()
+            .skip(1)
+            .fold(ProcessSpec::new(&program), |spec, arg| spec.with_arg(&arg));
+        Self {
+            workers,
+            spec: spec.with_restart(RestartPolicy::Always { max_restarts: usize::MAX }),
+            manager: None,
+            state: Arc::new(SyncMutex::new(Vec::new())),
+        }
+    }
+
+    /// Run each worker from the spec, rather than the current executable. The worker is still
+    /// always restarted when it exits, after the spec's restart delay.
+    pub fn with_spec(mut self, spec: ProcessSpec) -> Self {
+        self.spec = spec.with_restart(RestartPolicy::Always { max_restarts: usize::MAX });
+        self
+    }
+
+    /// Get the index of the current process in its pool, None if it isn't a worker.
+    pub fn get_worker_index() -> Option<usize> { std::env::var(WORKER_INDEX_ENV).ok()?.parse().ok() }
+
+    /// Report a stat of the current worker to the supervisor, which sums the latest value reported
+    /// by each worker. It is written to stdout, as a line of its own.
+    pub fn report_stat(name: &str, value: i64) {
+        println!("{}{} {}", STAT_PREFIX, name, value);
+    }
+
+    /// Start the workers, if not already started.
+    pub fn start(&mut self) {
+        if self.manager.is_some() {
+            return;
+        }
+        let (manager, sender) = machine::create_unbounded(ProcessManager::default());
+        let (events, receiver) = channel::unbounded::<ProcessEvent>();
+        *self.lock_state() = (0 .. self.workers)
+            .map(|index| WorkerState {
+                stats: WorkerStats {
+                    index,
+                    ..WorkerStats::default()
+                },
+                line: Vec::new(),
+            })
+            .collect();
+        for index in 0 .. self.workers {
+            let spec = self.spec.clone().with_env(WORKER_INDEX_ENV, &index.to_string());
+            sender.try_send(ProcessCmd::Spawn(index, spec, events.clone())).ok();
+        }
+        log::info!("started worker pool workers={}", self.workers);
+        let state = self.state.clone();
+        get_executor()
+            .spawn(async move {
+                while let Ok(event) = receiver.recv().await {
+                    let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+                    update(&mut state, event);
+                }
+            })
+            .detach();
+        self.manager = Some((manager, sender));
+    }
+
+    /// Stop the workers, killing them, they aren't restarted.
+    pub fn stop(&mut self) {
+        if let Some((_manager, sender)) = self.manager.take() {
+            for index in 0 .. self.workers {
+                sender.try_send(ProcessCmd::Kill(index)).ok();
+            }
+            log::info!("stopped worker pool workers={}", self.workers);
+        }
+    }
+
+    /// Get the stats of the workers, and their aggregate.
+    pub fn get_stats(&self) -> WorkerPoolStats {
+        let state = self.lock_state();
+        let mut stats = WorkerPoolStats::default();
+        for worker in state.iter().map(|worker| &worker.stats) {
+            stats.running += worker.running as usize;
+            stats.restarts += worker.restarts;
+            for (name, value) in &worker.stats {
+                *stats.stats.entry(name.clone()).or_default() += value;
+            }
+            stats.workers.push(worker.clone());
+        }
+        stats
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, Vec<WorkerState>> { self.state.lock().unwrap_or_else(|err| err.into_inner()) }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) { self.stop(); }
+}
+
+// Update the state of the workers from an event of the ProcessManager.
+fn update(state: &mut [WorkerState], event: ProcessEvent) {
+    match event {
+        ProcessEvent::Started(index, pid) => {
+            if let Some(worker) = state.get_mut(index) {
+                worker.stats.pid = Some(pid);
+                worker.stats.running = true;
+            }
+        },
+        ProcessEvent::Stdout(index, bytes) => {
+            if let Some(worker) = state.get_mut(index) {
+                worker.line.extend(bytes);
+                while let Some(end) = worker.line.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = worker.line.drain(..= end).collect();
+                    parse_line(&mut worker.stats, String::from_utf8_lossy(&line).trim_end());
+                }
+            }
+        },
+        ProcessEvent::Stderr(index, bytes) => {
+            log::warn!("worker {}: {}", index, String::from_utf8_lossy(&bytes).trim_end());
+        },
+        ProcessEvent::Exited(index, code) => {
+            if let Some(worker) = state.get_mut(index) {
+                worker.stats.running = false;
+                worker.stats.exit_code = code;
+            }
+            log::warn!("worker {} exited code={:?}", index, code);
+        },
+        ProcessEvent::Restarting(index, restarts) => {
+            if let Some(worker) = state.get_mut(index) {
+                worker.stats.restarts = restarts;
+            }
+            log::info!("worker {} restarting restarts={}", index, restarts);
+        },
+        ProcessEvent::Failed(index, err) => log::error!("worker {} failed to spawn: {}", index, err),
+    }
+}
+
+// Parse a line a worker wrote to stdout, keeping a stat it reports, or logging it otherwise.
+fn parse_line(stats: &mut WorkerStats, line: &str) {
+    let stat = line.strip_prefix(STAT_PREFIX).and_then(|stat| {
+        let mut parts = stat.split_whitespace();
+        match (parts.next(), parts.next().and_then(|value| value.parse::<i64>().ok())) {
+            (Some(name), Some(value)) => Some((name.to_string(), value)),
+            _ => None,
+        }
+    });
+    match stat {
+        Some((name, value)) => {
+            stats.stats.insert(name, value);
+        },
+        None => log::info!("worker {}: {}", stats.index, line),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn aggregate_worker_stats() {
+        let spec = ProcessSpec::new("sh")
+            .with_arg("-c")
+            .with_arg("echo \"@stat requests $((SERVER_WORKER_INDEX + 1))\"; echo hello; exit 2")
+            .with_restart_delay(Duration::from_millis(10));
+        let mut pool = WorkerPool::new(3).with_spec(spec);
+        pool.start();
+        // each worker is restarted when it exits
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.get_stats().workers.iter().any(|worker| worker.restarts < 2) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let stats = pool.get_stats();
+        assert!(stats.restarts >= 6);
+        assert_eq!(3, stats.workers.len());
+        assert!(stats
+            .workers
+            .iter()
+            .all(|worker| worker.pid.is_some() && worker.exit_code == Some(2)));
+        assert_eq!(Some(&2), stats.workers[1].stats.get("requests"));
+        assert_eq!(Some(&6), stats.stats.get("requests"));
+        pool.stop();
+    }
+}
@@ -1,6 +1,6 @@
 mod server_config;
 mod server_settings;
-pub use server_config::{ConfigBuilder, ConfigMerger, ConfigMetaData};
+pub use server_config::{ConfigBuilder, ConfigLayer, ConfigMerger, ConfigMetaData};
 pub use server_settings::{Environment, Log, MergedConfig, ServerSettings};
 
 #[cfg(test)]
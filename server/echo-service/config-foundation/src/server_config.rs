@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
+use smart_default::SmartDefault;
 
 /// The ConfigMerger is passed into settings and provides custom handling of config
 /// fields. After each config file is merged, the merge_from() method is called which
@@ -34,12 +35,49 @@ const CONFIG_ENV_VAR_SERVER_FLAVOR_DEFAULT: &str = "";
 /// The name of the default configuration.
 const CONFIG_DEFAULT_NAME: &str = "default";
 
+/// A ConfigLayer is a source of config which the ConfigBuilder merges. Each layer overrides the
+/// layers merged before it. The file layers merge the toml and then the json file of that name.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub enum ConfigLayer {
+    /// The default config files, in the config folder.
+    Default,
+    /// The config files for the operating environment, in the config folder.
+    Env,
+    /// The default config files of the server flavor, in the flavor's folder.
+    FlavorDefault,
+    /// The config files for the operating environment of the server flavor, in the flavor's folder.
+    FlavorEnv,
+    /// The config files of the name, such as "site", in the config folder.
+    File(String),
+    /// The config of the ConfigMerger, which it has gathered from the layers merged before it.
+    Merger,
+    /// The environment variables having the config_env_prefix.
+    EnvVars,
+}
+
+impl ConfigLayer {
+    /// Get the default layers: default, env, the flavor's default and env, merger, and env vars.
+    pub fn default_layers() -> Vec<ConfigLayer> {
+        vec![
+            Self::Default,
+            Self::Env,
+            Self::FlavorDefault,
+            Self::FlavorEnv,
+            Self::Merger,
+            Self::EnvVars,
+        ]
+    }
+}
+
 /// The ConfigBuilder provides a default set of config parameter, which are used
 /// in locating config information in files and the environment. It also provides a
-/// means of setting individual fields.
-#[derive(Debug, Default)]
+/// means of setting individual fields, and of changing the layers of config which are
+/// merged, and their order.
+#[derive(Debug, SmartDefault)]
 pub struct ConfigBuilder {
     config: ConfigMetaData,
+    #[default(ConfigLayer::default_layers())]
+    layers: Vec<ConfigLayer>,
 }
 
 #[allow(dead_code)]
@@ -86,6 +124,18 @@ impl ConfigBuilder {
         self
     }
 
+    /// Override the layers of config, which are merged in order, each overriding those before it.
+    pub fn with_layers(mut self, layers: Vec<ConfigLayer>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Add a layer of config, which overrides the layers before it.
+    pub fn with_layer(mut self, layer: ConfigLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
     /// Build the Config database, returing it, along with the meta environment used to
     /// produce it.
     pub fn build(&self, merger: &mut dyn ConfigMerger) -> Result<(ConfigMetaData, Config), ConfigError> {
@@ -112,10 +162,10 @@ impl ConfigBuilder {
         Ok(())
     }
 
-    /// Creates a Config database by merging configuration files. The default toml and then json are merged. Then
-    /// the environment variant toml and json are merged. Then, if a server flavor has been specified that flavor's
-    /// folder is merged. First the default toml and json and then the environment variant toml and json files.
-    /// Lastly, environment settings are merged.
+    /// Creates a Config database by merging the layers of configuration, in order. By default, the default toml and
+    /// then json are merged. Then the environment variant toml and json are merged. Then, if a server flavor has been
+    /// specified that flavor's folder is merged. First the default toml and json and then the environment variant toml
+    /// and json files. Lastly, environment settings are merged.
     ///
     /// The enviroment variant represents where the server is being tested, geenrally, this is a Development, Test,
     /// Stage, or Production environment. However, it can be changed by the user.
@@ -125,7 +175,8 @@ impl ConfigBuilder {
     /// image to be deployed everywhere, but fufilling different roles. For example, a single server might support
     /// any number of micro-services, however only a few may be desirable for each
     /// particular instance, by introducing flavors, you only need to set the enviroment properly or add it to the
-    /// default when installing the server in the environment rather than having to edit many fields at once time.   
+    /// default when installing the server in the environment rather than having to edit many fields at once time.
+    /// The flavor is taken from the config merged before the first flavor layer.
     fn create(&self, merger: &mut dyn ConfigMerger) -> Result<Config, ConfigError> {
         // Determine where we are located, default it if unknown
         let config_env_default = self.config.config_env_default.clone();
@@ -135,37 +186,51 @@ impl ConfigBuilder {
         let mut s = Config::default();
         s.set("env", env.clone())?;
 
-        // merge in the default toml and json config files
-        let config_filepath = format!("{}{}", self.config.config_folder_path, self.config.config_default_name);
-        self.merge_filepath(&mut s, &config_filepath, merger)?;
-
-        // Merge in the ENV dependent toml and json config files
-        let config_filepath = format!("{}{}", self.config.config_folder_path, env);
-        self.merge_filepath(&mut s, &config_filepath, merger)?;
+        let mut server_flavor: Option<String> = None;
+        for layer in &self.layers {
+            match layer {
+                ConfigLayer::Default => {
+                    let config_filepath = format!("{}{}", self.config.config_folder_path, self.config.config_default_name);
+                    self.merge_filepath(&mut s, &config_filepath, merger)?;
+                },
+                ConfigLayer::Env => {
+                    let config_filepath = format!("{}{}", self.config.config_folder_path, env);
+                    self.merge_filepath(&mut s, &config_filepath, merger)?;
+                },
+                ConfigLayer::FlavorDefault | ConfigLayer::FlavorEnv => {
+                    // Try to deterime the server's flavor, once, and load its config
+                    let server_flavor = server_flavor.get_or_insert_with(|| self.get_server_flavor(&s));
+                    if server_flavor.is_empty() {
+                        continue;
+                    }
+                    let name = if *layer == ConfigLayer::FlavorDefault {
+                        &self.config.config_default_name
+                    } else {
+                        &env
+                    };
+                    let config_filepath = format!("{}{}/{}", self.config.config_folder_path, server_flavor, name);
+                    self.merge_filepath(&mut s, &config_filepath, merger)?;
+                },
+                ConfigLayer::File(name) => {
+                    let config_filepath = format!("{}{}", self.config.config_folder_path, name);
+                    self.merge_filepath(&mut s, &config_filepath, merger)?;
+                },
+                ConfigLayer::Merger => merger.merge_into(&mut s)?,
+                ConfigLayer::EnvVars => {
+                    // Merge in environment overrides
+                    s.merge(Environment::with_prefix(&self.config.config_env_prefix).separator(&self.config.config_env_separator))?;
+                },
+            }
+        }
+        Ok(s)
+    }
 
-        // Try to deterime the server's flavor and load its config
+    // Get the server flavor from the environment, or the config, or default it.
+    fn get_server_flavor(&self, config: &Config) -> String {
         let var = self.config.config_env_var_server_flavor.clone();
         let default = self.config.config_env_var_server_flavor_default.clone();
-        let default = s.get(&var.to_lowercase()).unwrap_or(default);
-        let server_flavor = std::env::var(var).unwrap_or(default);
-
-        if !server_flavor.is_empty() {
-            // merge in the default toml and json config files
-            let config_filepath = format!(
-                "{}{}/{}",
-                self.config.config_folder_path, server_flavor, self.config.config_default_name
-            );
-            self.merge_filepath(&mut s, &config_filepath, merger)?;
-
-            // Merge in the ENV dependent toml and json config files
-            let config_filepath = format!("{}{}/{}", self.config.config_folder_path, server_flavor, env);
-            self.merge_filepath(&mut s, &config_filepath, merger)?;
-        }
-        merger.merge_into(&mut s)?;
-
-        // Merge in environment overrides
-        s.merge(Environment::with_prefix(&self.config.config_env_prefix).separator(&self.config.config_env_separator))?;
-        Ok(s)
+        let default = config.get(&var.to_lowercase()).unwrap_or(default);
+        std::env::var(var).unwrap_or(default)
     }
 }
 
@@ -202,3 +267,40 @@ impl Default for ConfigMetaData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MergedConfig;
+
+    fn name(builder: ConfigBuilder) -> String {
+        let (_, config) = builder.build(&mut MergedConfig::default()).unwrap();
+        config.get("name").unwrap()
+    }
+
+    #[test]
+    fn layer_precedence() {
+        let dir = std::env::temp_dir().join(format!("config-layers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("default.json"), r#"{ "name": "default" }"#).unwrap();
+        std::fs::write(dir.join("site.json"), r#"{ "name": "site" }"#).unwrap();
+        std::env::set_var("LAYERTEST_NAME", "env-var");
+        let builder = || {
+            ConfigBuilder::default()
+                .with_config_folder_path(&format!("{}/", dir.display()))
+                .with_config_env_prefix("layertest")
+        };
+        assert_eq!("env-var", name(builder()));
+        // the site layer overrides everything before it
+        assert_eq!("site", name(builder().with_layer(ConfigLayer::File("site".to_string()))));
+        // env vars before files
+        let layers = vec![ConfigLayer::EnvVars, ConfigLayer::Default, ConfigLayer::File("site".to_string())];
+        assert_eq!("site", name(builder().with_layers(layers)));
+        assert_eq!(
+            "default",
+            name(builder().with_layers(vec![ConfigLayer::EnvVars, ConfigLayer::Default]))
+        );
+        std::env::remove_var("LAYERTEST_NAME");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,63 +1,72 @@
 use super::*;
-use smart_default::*;
 
 use config::{Config, ConfigError, Source, Value};
 use log::{self};
 use serde::Deserialize;
 use serde_with::*;
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 
-/// These are some commonly used environment settings. You are free to
-/// use them, or not.
-
-/// The Environment enum is required, however the fields can be changed.
-/// An environment variable is queried to determine the evironment
-/// which the server is running on, that is used to pull in config
-/// files matching the environment.
-#[derive(Clone, Debug, SmartDefault, Deserialize)]
-pub enum Environment {
-    Development,
-    Testing,
-    Stage,
-    #[default]
-    Production,
+/// The Environment is the name of the environment which the server is running in. An environment
+/// variable is queried to determine it, and it is used to pull in the config files matching the
+/// environment. Some commonly used environments are provided as constants, you are free to use
+/// them, or not, any other name is equally valid.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(from = "String")]
+pub struct Environment(Cow<'static, str>);
+
+impl Environment {
+    pub const DEVELOPMENT: Environment = Environment(Cow::Borrowed("Development"));
+    pub const TESTING: Environment = Environment(Cow::Borrowed("Testing"));
+    pub const STAGE: Environment = Environment(Cow::Borrowed("Stage"));
+    pub const PRODUCTION: Environment = Environment(Cow::Borrowed("Production"));
+
+    /// Get the name of the environment.
+    pub fn as_str(&self) -> &str { &self.0 }
+}
+
+impl Default for Environment {
+    fn default() -> Self { Self::PRODUCTION }
 }
 
 impl fmt::Display for Environment {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Environment::Development => write!(f, "Development"),
-            Environment::Testing => write!(f, "Testing"),
-            Environment::Stage => write!(f, "Stage"),
-            Environment::Production => write!(f, "Production"),
-            #[allow(unreachable_patterns)]
-            _ => panic!("Need to update Display for ENV"),
-        }
-    }
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.0) }
 }
 
 impl From<&str> for Environment {
-    fn from(env: &str) -> Self {
-        match env {
-            "Development" => Environment::Development,
-            "Testing" => Environment::Testing,
-            "Stage" => Environment::Stage,
-            "Production" => Environment::Production,
-            _ => panic!("Need to update From for Environment"),
-        }
-    }
+    fn from(env: &str) -> Self { Self(Cow::Owned(env.to_string())) }
+}
+
+impl From<String> for Environment {
+    fn from(env: String) -> Self { Self(Cow::Owned(env)) }
 }
 
 /// This is the log filter. Notice that we're able to use the Display and FromStr impl.
 /// Normally, we'd have to use From<&str>, but serde_as has our back and provides a means
-/// to use Display and FromStr
+/// to use Display and FromStr. When the level is absent, it defaults by environment. The
+/// targets override the level for the modules they name, and the modules within them.
 #[serde_as]
-#[derive(Debug, Deserialize, SmartDefault, Copy, Clone)]
+#[derive(Debug, Deserialize, Default, Clone)]
 pub struct Log {
-    #[serde_as(as = "DisplayFromStr")]
-    #[default(log::LevelFilter::Warn)]
-    pub level: log::LevelFilter,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub level: Option<log::LevelFilter>,
+    #[serde_as(as = "HashMap<_, DisplayFromStr>")]
+    #[serde(default)]
+    pub targets: HashMap<String, log::LevelFilter>,
+}
+
+impl Log {
+    /// Get the level, or if there's none, the default for the environment: Debug for Development,
+    /// Info for Testing, and Warn for any other.
+    pub fn get_level(&self, env: &Environment) -> log::LevelFilter {
+        self.level.unwrap_or_else(|| match env {
+            env if *env == Environment::DEVELOPMENT => log::LevelFilter::Debug,
+            env if *env == Environment::TESTING => log::LevelFilter::Info,
+            _ => log::LevelFilter::Warn,
+        })
+    }
 }
 
 /// Usually, you'd have your own ServerSettings, but maybe this is good
@@ -65,6 +74,7 @@ pub struct Log {
 #[derive(Debug, Default, Deserialize, Clone)]
 pub struct ServerSettings {
     pub env: Environment,
+    #[serde(default)]
     pub log: Log,
     pub server_flavor: String,
     pub features: HashSet<String>,
@@ -121,6 +131,33 @@ impl config::Source for MergedConfig {
 mod tests {
     use super::*;
 
+    #[test]
+    fn open_environment() {
+        assert_eq!(Environment::PRODUCTION, Environment::default());
+        assert_eq!(Environment::STAGE, Environment::from("Stage"));
+        let mut config = Config::default();
+        config.set("env", "QA").unwrap();
+        let env: Environment = config.get("env").unwrap();
+        assert_eq!("QA", env.as_str());
+        assert_eq!("QA", env.to_string());
+    }
+
+    #[test]
+    fn log_level_by_environment() {
+        let mut config = Config::default();
+        let json = r#"{ "targets": { "components::network": "trace" } }"#;
+        config.merge(config::File::from_str(json, config::FileFormat::Json)).unwrap();
+        let log: Log = config.try_into().unwrap();
+        assert_eq!(log::LevelFilter::Debug, log.get_level(&Environment::DEVELOPMENT));
+        assert_eq!(log::LevelFilter::Warn, log.get_level(&Environment::from("QA")));
+        assert_eq!(Some(&log::LevelFilter::Trace), log.targets.get("components::network"));
+        let log = Log {
+            level: Some(log::LevelFilter::Error),
+            ..Log::default()
+        };
+        assert_eq!(log::LevelFilter::Error, log.get_level(&Environment::DEVELOPMENT));
+    }
+
     #[test]
     fn default_config() {
         let res = ServerSettings::load();
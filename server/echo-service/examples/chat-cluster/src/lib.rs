@@ -0,0 +1,254 @@
+// A chat service, whose rooms are partitioned across the nodes of a cluster. Each room is owned by
+// a single node, the front door of every node proxying a client to the node owning its room, so
+// that clients connected to any node share the rooms.
+use server_prelude::{
+    components::{FrontDoor, FrontDoorStats, HashRing},
+    machine_foundation::{lookup, register_name, unregister_name},
+    *,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+/// ChatCmd is the instruction set the chat connections, and the node, use to talk with the
+/// controller.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum ChatCmd {
+    /// Bytes were received from a client.
+    Recv(NetConnId, Vec<u8>),
+    /// Get each room, and its number of members, which are sent to the sender.
+    GetRooms(smol::channel::Sender<Vec<(String, usize)>>),
+}
+
+/// The ChatNode is a node of the chat cluster. Its listener is in front door mode, a client's
+/// first line names the room it joins, which is consistently hashed onto the nodes, the client
+/// being proxied to the node owning the room. Each line a client sends after that is sent to the
+/// other members of the room. The rooms are registered by name, as chat@address, with the
+/// machine registry.
+///
+/// The nodes are given up front, a membership layer, which discovers them, and tells of those
+/// which die, would keep the ring up to date instead. As there's no discovery, nor remote bridge,
+/// in the tree, the rooms are shared by proxying clients, rather than by bridging their lines
+/// between the nodes, see the README.
+#[derive(Debug)]
+pub struct ChatNode {
+    address: String,
+    chat_sender: ChatCmdSender,
+    front_door: Arc<FrontDoor>,
+}
+
+impl ChatNode {
+    /// Start a node, listening on the address, with the addresses of the other nodes.
+    pub fn start(address: &str, peers: &[String]) -> Self {
+        NetCore::start();
+        let net_sender = NetCore::get_sender();
+        let name = format!("chat@{}", address);
+        let controller = ControllerMachine::new(ChatController::new(net_sender.clone(), &name));
+        let (controller, listener) = machine::create_unbounded::<ListenerCmd, _>(controller);
+        let chat_sender = machine::extend_unbounded::<ChatCmd, _>(&controller);
+        register_name(&name, chat_sender.clone());
+        let mut ring = HashRing::new(address);
+        for peer in peers {
+            ring.add_node(peer);
+        }
+        let front_door = Arc::new(FrontDoor::new(Arc::new(ring), |_remote, bytes| get_room(bytes)));
+        smol::block_on(async {
+            net_sender.send(NetCmd::BindTcpListener(address.to_string(), listener)).await.ok();
+            net_sender
+                .send(NetCmd::SetFrontDoor(address.to_string(), Some(front_door.clone())))
+                .await
+                .ok();
+        });
+        log::info!("chat node started address={} peers={:?}", address, peers);
+        Self {
+            address: address.to_string(),
+            chat_sender,
+            front_door,
+        }
+    }
+
+    /// Get the address of the node.
+    pub fn get_address(&self) -> &str { &self.address }
+
+    /// Get the rooms owned by the node, and their number of members, ordered by room.
+    pub fn get_rooms(&self) -> Vec<(String, usize)> {
+        let (sender, receiver) = smol::channel::bounded(1);
+        smol::block_on(async {
+            self.chat_sender.send(ChatCmd::GetRooms(sender)).await.ok();
+            receiver.recv().await.unwrap_or_default()
+        })
+    }
+
+    /// Get the counts of the clients handled by the node, and proxied to the others.
+    pub fn get_front_door_stats(&self) -> FrontDoorStats { self.front_door.get_stats() }
+
+    /// Stop the node, unbinding its listener.
+    pub fn stop(&self) {
+        unregister_name(&format!("chat@{}", self.address));
+        let (sender, receiver) = smol::channel::bounded(1);
+        smol::block_on(async {
+            NetCore::get_sender()
+                .send(NetCmd::UnbindTcpListener(self.address.clone(), sender))
+                .await
+                .ok();
+            receiver.recv().await.ok();
+        });
+    }
+}
+
+// Get the room a client joins, from the first bytes it sent, None until its first line is complete.
+fn get_room(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|b| *b == b'\n')?;
+    Some(String::from_utf8_lossy(&bytes[.. end]).trim().to_string())
+}
+
+// The ChatController owns the rooms of the node, and the connections of their members.
+#[derive(Debug)]
+struct ChatController {
+    net_sender: NetSender,
+    // the name the controller is registered by, which its connections look it up by
+    name: String,
+    // the bytes received from a client, which aren't yet a line
+    pending: HashMap<NetConnId, Vec<u8>>,
+    // the room each client has joined
+    joined: HashMap<NetConnId, String>,
+    // the members of each room
+    rooms: HashMap<String, Vec<NetConnId>>,
+}
+
+impl ChatController {
+    fn new(net_sender: NetSender, name: &str) -> Self {
+        Self {
+            net_sender,
+            name: name.to_string(),
+            pending: HashMap::new(),
+            joined: HashMap::new(),
+            rooms: HashMap::new(),
+        }
+    }
+
+    fn recv(&mut self, conn_id: NetConnId, bytes: Vec<u8>, sender: &mut MachineSender) {
+        let pending = self.pending.entry(conn_id).or_default();
+        pending.extend(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            lines.push(pending.drain(..= end).collect::<Vec<u8>>());
+        }
+        for line in lines {
+            match self.joined.get(&conn_id) {
+                Some(room) => {
+                    for member in self.rooms.get(room).into_iter().flatten().filter(|member| **member != conn_id) {
+                        sender.send(self.net_sender.clone(), NetCmd::SendBytes(*member, line.clone()));
+                    }
+                },
+                None => {
+                    let room = get_room(&line).unwrap_or_default();
+                    log::debug!("conn_id={} joined room={}", conn_id, room);
+                    self.rooms.entry(room.clone()).or_default().push(conn_id);
+                    self.joined.insert(conn_id, room);
+                },
+            }
+        }
+    }
+
+    fn leave(&mut self, conn_id: NetConnId) {
+        self.pending.remove(&conn_id);
+        if let Some(room) = self.joined.remove(&conn_id) {
+            log::debug!("conn_id={} left room={}", conn_id, room);
+            if let Some(members) = self.rooms.get_mut(&room) {
+                members.retain(|member| *member != conn_id);
+                if members.is_empty() {
+                    self.rooms.remove(&room);
+                }
+            }
+        }
+    }
+}
+
+impl Controller<ListenerCmd> for ChatController {
+    fn handle(&mut self, cmd: ListenerCmd, sender: &mut MachineSender) {
+        match cmd {
+            ListenerCmd::NewConn(conn_id, _local_addr, remote_addr) => {
+                log::debug!("new connection conn_id={}, remote_addr={}", conn_id, remote_addr);
+                if let Some(chat_sender) = lookup::<ChatCmd>(&self.name) {
+                    let (_, conn_sender) = machine::create(ChatConnection { chat_sender });
+                    sender.send(self.net_sender.clone(), NetCmd::BindConn(conn_id, conn_sender));
+                }
+            },
+            ListenerCmd::CloseConn(conn_id, _) => self.leave(conn_id),
+            ListenerCmd::RecvPkt(..) => (),
+        }
+    }
+}
+
+impl Controller<ChatCmd> for ChatController {
+    fn handle(&mut self, cmd: ChatCmd, sender: &mut MachineSender) {
+        match cmd {
+            ChatCmd::Recv(conn_id, bytes) => self.recv(conn_id, bytes, sender),
+            ChatCmd::GetRooms(reply) => {
+                let mut rooms: Vec<(String, usize)> = self.rooms.iter().map(|(room, members)| (room.clone(), members.len())).collect();
+                rooms.sort();
+                reply.try_send(rooms).ok();
+            },
+        }
+    }
+}
+
+// A ChatConnection passes the bytes received from its client to the controller.
+struct ChatConnection {
+    chat_sender: ChatCmdSender,
+}
+
+impl Machine<ConnCmd> for ChatConnection {
+    fn receive(&self, cmd: ConnCmd, sender: &mut MachineSender) {
+        if let ConnCmd::RecvBytes(conn_id, bytes) = cmd {
+            sender.send(self.chat_sender.clone(), ChatCmd::Recv(conn_id, bytes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn share_rooms_across_nodes() {
+        let (a, b) = ("127.0.0.1:47408".to_string(), "127.0.0.1:47409".to_string());
+        let node_a = ChatNode::start(&a, std::slice::from_ref(&b));
+        let node_b = ChatNode::start(&b, std::slice::from_ref(&a));
+        std::thread::sleep(Duration::from_millis(50));
+        // a room owned by b, joined by a client of each node
+        let mut ring = HashRing::new(&a);
+        ring.add_node(&b);
+        let room = (0 ..)
+            .map(|n| format!("room-{}", n))
+            .find(|room| ring.get_owner(room) == b)
+            .unwrap();
+        let join = |address: &str| {
+            let mut client = smol::block_on(TcpStream::connect(address)).unwrap();
+            smol::block_on(client.write_all(format!("{}\n", room).as_bytes())).unwrap();
+            client
+        };
+        let mut alice = join(&a);
+        let mut bob = join(&b);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while node_b.get_rooms() != vec![(room.clone(), 2)] && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec![(room.clone(), 2)], node_b.get_rooms());
+        assert!(node_a.get_rooms().is_empty());
+
+        smol::block_on(alice.write_all(b"hello bob\n")).unwrap();
+        let mut line = vec![0; 10];
+        smol::block_on(bob.read_exact(&mut line)).unwrap();
+        assert_eq!(b"hello bob\n".to_vec(), line);
+        assert_eq!(1, node_a.get_front_door_stats().proxied);
+        assert_eq!(2, node_b.get_front_door_stats().local);
+        node_a.stop();
+        node_b.stop();
+    }
+}
@@ -0,0 +1,34 @@
+use chat_cluster::ChatNode;
+use server_prelude::*;
+use simplelog::{Config, TermLogger, TerminalMode};
+use std::{error::Error, time::Duration};
+
+// Run a node of the chat cluster, until it is killed. Run a node for each address, on one host:
+//
+//   example-chat-cluster 127.0.0.1:7000 127.0.0.1:7001
+//   example-chat-cluster 127.0.0.1:7001 127.0.0.1:7000
+//
+// then join a room, on either node, with the first line sent, and chat with the lines after it:
+//
+//   nc 127.0.0.1 7000
+//   lobby
+//   hello
+fn main() { main_().ok(); }
+
+fn main_() -> Result<(), Box<dyn Error>> {
+    TermLogger::init(log::LevelFilter::Info, Config::default(), TerminalMode::Mixed)?;
+    install_panic_hook(None);
+    let mut args = std::env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:7000".to_string());
+    let peers: Vec<String> = args.collect();
+    let node = ChatNode::start(&address, &peers);
+    loop {
+        std::thread::sleep(Duration::from_secs(10));
+        log::info!(
+            "chat node address={} rooms={:?} front_door={:?}",
+            node.get_address(),
+            node.get_rooms(),
+            node.get_front_door_stats()
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use components::{Probe, ScalingRule, TarpitPolicy};
 use config::{ConfigError, Value};
 use config_foundation::{ConfigBuilder, ConfigMetaData, MergedConfig, ServerSettings};
 use serde::Deserialize;
@@ -6,7 +7,7 @@ use serde::Deserialize;
 /// each service to have its own config, whic h may be similar or distinct from other service
 /// configs.
 use smart_default::*;
-use std::{collections::HashMap, convert::TryFrom, fmt};
+use std::{convert::TryFrom, fmt};
 
 /// The config for a server connection
 #[derive(Debug, Default, Deserialize, Clone)]
@@ -20,6 +21,18 @@ pub struct Server {
 pub struct Service {
     pub server: Server,
     pub max_sessions: usize,
+    /// The scheduling weight of the service's machines, if absent or 0 the default weight is used.
+    #[serde(default)]
+    pub weight: usize,
+    /// The rules for autoscaling the service, if any.
+    #[serde(default)]
+    pub autoscale: Vec<ScalingRule>,
+    /// The probes which must be ready before the service runs, if any.
+    #[serde(default)]
+    pub probes: Vec<Probe>,
+    /// The policy for tarpitting abusive clients of the service's listener, if any.
+    #[serde(default)]
+    pub tarpit: Option<TarpitPolicy>,
 }
 
 /// The services. Each variant can have its own config.
@@ -47,6 +60,32 @@ impl TryFrom<(String, config::Value)> for ServiceConfig {
     }
 }
 
+impl ServiceConfig {
+    /// Get the type name of the service, as it appears in the config.
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            ServiceConfig::EchoService(_) => "EchoService",
+            ServiceConfig::ChatService(_) => "ChatService",
+        }
+    }
+
+    /// Get the config common to every type of service.
+    pub fn get_service(&self) -> &Service {
+        match self {
+            ServiceConfig::EchoService(service) | ServiceConfig::ChatService(service) => service,
+        }
+    }
+}
+
+/// An instance of a service. There may be several instances of the same type of service, each
+/// with its own name and config.
+#[derive(Debug, Default, Clone)]
+pub struct ServiceInstance {
+    /// The name of the instance, unique among the services.
+    pub name: String,
+    pub config: ServiceConfig,
+}
+
 impl fmt::Display for ServiceConfig {
     fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -62,9 +101,12 @@ impl fmt::Display for ServiceConfig {
 pub struct Settings {
     pub meta_config: ConfigMetaData,
     pub server_config: ServerSettings,
-    pub service_config: HashMap<String, ServiceConfig>,
+    pub service_config: Vec<ServiceInstance>,
 }
 impl Settings {
+    /// Get the service instance with the name.
+    pub fn get_service(&self, name: &str) -> Option<&ServiceInstance> { self.service_config.iter().find(|s| s.name == name) }
+
     /// Load the settings
     pub fn load() -> Result<Self, ConfigError> {
         let mut merger = MergedConfig::default();
@@ -72,12 +114,7 @@ impl Settings {
             .with_config_folder_path("../config-service/config/")
             .build(&mut merger)?;
         // try validating all of the service configs
-        let services: HashMap<String, Value> = config.get("services")?;
-        let mut service_config: HashMap<String, ServiceConfig> = HashMap::new();
-        for (key, value) in services {
-            let cfg: ServiceConfig = ServiceConfig::try_from((key.clone(), value))?;
-            service_config.insert(key, cfg);
-        }
+        let service_config = parse_services(config.get("services")?)?;
         let server_config = config.try_into()?;
         Ok(Self {
             meta_config,
@@ -87,9 +124,81 @@ impl Settings {
     }
 }
 
+// The services are either an array of instances, each having a type and a name, which defaults
+// to the type, or, as before there could be several instances, a map of type to config.
+fn parse_services(services: Value) -> Result<Vec<ServiceInstance>, ConfigError> {
+    let instances: Vec<(String, String, Value)> = match services.clone().into_array() {
+        Ok(array) => array
+            .into_iter()
+            .map(|value| {
+                let table = value.clone().into_table()?;
+                let kind = table
+                    .get("type")
+                    .cloned()
+                    .ok_or_else(|| ConfigError::NotFound("services.type".to_string()))?
+                    .into_str()?;
+                let name = match table.get("name").cloned() {
+                    Some(name) => name.into_str()?,
+                    None => kind.clone(),
+                };
+                Ok((name, kind, value))
+            })
+            .collect::<Result<_, ConfigError>>()?,
+        Err(_) => services
+            .into_table()?
+            .into_iter()
+            .map(|(kind, value)| (kind.clone(), kind, value))
+            .collect(),
+    };
+    let mut service_config: Vec<ServiceInstance> = Vec::new();
+    for (name, kind, value) in instances {
+        if service_config.iter().any(|s| s.name == name) {
+            return Err(ConfigError::Message(format!("Service name={} is not unique", name)));
+        }
+        let config = ServiceConfig::try_from((kind, value))?;
+        service_config.push(ServiceInstance { name, config });
+    }
+    Ok(service_config)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Settings;
+    use super::*;
+    use config::{Config, File, FileFormat};
+
+    fn services(json: &str) -> Result<Vec<ServiceInstance>, ConfigError> {
+        let mut config = Config::default();
+        config.merge(File::from_str(json, FileFormat::Json))?;
+        parse_services(config.get("services")?)
+    }
+
+    #[test]
+    fn test_service_instances() {
+        let json = r#"{ "services": [
+            { "type": "EchoService", "name": "echo-a", "max_sessions": 10, "server": { "port": 8080, "url": "" } },
+            { "type": "EchoService", "name": "echo-b", "max_sessions": 20, "server": { "port": 8081, "url": "" } },
+            { "type": "ChatService", "max_sessions": 5, "server": { "port": 8082, "url": "" } }
+        ] }"#;
+        let instances = services(json).unwrap();
+        let names: Vec<&str> = instances.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(vec!["echo-a", "echo-b", "ChatService"], names);
+        match &instances[1].config {
+            ServiceConfig::EchoService(service) => assert_eq!((20, 8081), (service.max_sessions, service.server.port)),
+            config => panic!("unexpected {:?}", config),
+        }
+
+        let json = r#"{ "services": { "EchoService": { "max_sessions": 10, "server": { "port": 8080, "url": "" } } } }"#;
+        let instances = services(json).unwrap();
+        assert_eq!("EchoService", instances[0].name);
+        assert_eq!("EchoService", instances[0].config.get_type());
+
+        let json = r#"{ "services": [
+            { "type": "EchoService", "name": "echo", "max_sessions": 10, "server": { "port": 8080, "url": "" } },
+            { "type": "EchoService", "name": "echo", "max_sessions": 10, "server": { "port": 8081, "url": "" } }
+        ] }"#;
+        assert!(services(json).is_err());
+    }
+
     #[test]
     fn test_service_load() {
         match Settings::load() {
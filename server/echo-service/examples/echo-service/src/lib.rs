@@ -1,79 +1,111 @@
 // This could be made a lot simpler, however, we're going to illustrate running an instruction set.
-use components::{NetCmd, NetConnId, NetCore, NetSender, ServerService, ServiceResult, ServiceState};
-use machine_foundation::{get_executor, machine, Machine, MachineSender};
+use server_prelude::*;
 
 // piggy-back on the config-service example
-use config_service::{Service, ServiceConfig, Settings};
+use config_service::{Service, ServiceConfig, ServiceInstance, Settings};
 
-use smol::lock::Mutex;
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
+/// EchoCmd is the instruction set the service uses to talk with its controller.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum EchoCmd {
+    /// The service has changed state.
+    SetState(ServiceState),
+    /// Get the number of connections, which is sent to the sender.
+    GetConnectionCount(smol::channel::Sender<usize>),
+}
+
+/// The EchoService owns no connection state, that belongs to its controller, which is a machine.
 #[derive(Debug)]
 pub struct EchoService {
-    controller: Arc<Mutex<Controller>>,
+    name: String,
+    controller: ListenerSender,
+    echo_sender: EchoCmdSender,
     config: Service,
-    state: Arc<Mutex<ServiceState>>,
+    state: ServiceState,
 }
 
+// The connections don't survive a restart, so there's nothing to snapshot.
+impl Snapshot for EchoService {}
+
 impl ServerService for EchoService {
-    fn get_name(&self) -> &str { "echo-service" }
-    fn get_drain_count(&self) -> usize { smol::block_on(async { self.controller.lock().await.get_connection_count() }) }
+    fn get_name(&self) -> &str { &self.name }
+    fn get_state(&self) -> ServiceState { self.state }
+    fn get_probes(&self) -> Vec<Probe> { self.config.probes.clone() }
+    fn get_drain_count(&self) -> usize {
+        let (sender, receiver) = smol::channel::bounded(1);
+        smol::block_on(async {
+            self.echo_sender.send(EchoCmd::GetConnectionCount(sender)).await.ok();
+            receiver.recv().await.unwrap_or(0)
+        })
+    }
     fn start(&mut self) -> ServiceResult<()> {
         log::debug!("echo service preparing to start");
+        let res = self.state.start();
+        self.notify_state();
         let address = format!("127.0.0.1:{}", self.config.server.port);
-        let state = self.state.clone();
-        let executor = get_executor();
-        let controller = self.controller.clone();
-        executor
-            .spawn(async move {
-                let net_sender = NetCore::get_sender();
-                let (sender, receiver) = smol::channel::unbounded::<NetCmd>();
-                net_sender.send(NetCmd::BindTcpListener(address, sender)).await.ok();
-                while let Ok(cmd) = receiver.recv().await {
-                    let state = state.lock().await;
-                    if *state == ServiceState::Stopped {
-                        break;
-                    }
-                    controller.lock().await.handle_cmd(cmd, &*state).await;
-                }
-            })
-            .detach();
         smol::block_on(async {
-            let mut state = self.state.lock().await;
-            state.start()
-        })
+            let sender = NetCore::get_sender();
+            sender
+                .send(NetCmd::BindTcpListener(address.clone(), self.controller.clone()))
+                .await
+                .ok();
+            if let Some(policy) = self.config.tarpit {
+                sender.send(NetCmd::SetTarpit(address, Some(policy))).await.ok();
+            }
+        });
+        res
     }
 
     fn run(&mut self) -> ServiceResult<()> {
         log::debug!("echo service preparing to run");
-        smol::block_on(async { self.state.lock().await.run() })
+        let res = self.state.run();
+        self.notify_state();
+        res
     }
 
     fn drain(&mut self) -> ServiceResult<()> {
         log::debug!("echo service preparing to drain, connection_count={}", self.get_drain_count());
-        smol::block_on(async { self.state.lock().await.drain() })
+        // stop accepting, and wait for the listener to shut down, before reporting the drain
+        let address = format!("127.0.0.1:{}", self.config.server.port);
+        let (sender, receiver) = smol::channel::bounded(1);
+        smol::block_on(async {
+            NetCore::get_sender().send(NetCmd::UnbindTcpListener(address, sender)).await.ok();
+            receiver.recv().await.ok();
+        });
+        let res = self.state.drain();
+        self.notify_state();
+        res
     }
 
     fn stop(&mut self) -> ServiceResult<()> {
         log::debug!("echo service preparing to stop");
-        smol::block_on(async { self.state.lock().await.stop() })
+        let res = self.state.stop();
+        self.notify_state();
+        res
     }
 }
 
 #[allow(dead_code)]
 impl EchoService {
-    /// Create the service. The config parameter is configuration for the service, while the settings
-    /// parameter is settings for the server. Generally, it can be ignored, however there may be
-    /// services which need to know features, the envionment, or other settings.
-    pub fn create(config: &ServiceConfig, _settings: &Settings) -> Option<Box<dyn ServerService>> {
+    /// Create an instance of the service. The instance parameter is the name and configuration of
+    /// the instance, while the settings parameter is settings for the server. Generally, it can be
+    /// ignored, however there may be services which need to know features, the envionment, or
+    /// other settings.
+    pub fn create(instance: &ServiceInstance, _settings: &Settings) -> Option<Box<dyn ServerService>> {
         NetCore::start();
-        if let ServiceConfig::EchoService(config) = config {
+        if let ServiceConfig::EchoService(config) = &instance.config {
             let net_sender = NetCore::get_sender();
-            let controller = Arc::new(Mutex::new(Controller::new(net_sender)));
+            let weight = if config.weight == 0 { DEFAULT_WEIGHT } else { config.weight };
+            let controller = ControllerMachine::new(EchoController::new(net_sender, weight, config.max_sessions));
+            let (controller, sender) = machine::create_unbounded::<ListenerCmd, _>(controller);
+            let echo_sender = machine::extend_unbounded::<EchoCmd, _>(&controller);
             let res = Self {
-                controller,
+                name: instance.name.clone(),
+                controller: sender,
+                echo_sender,
                 config: config.clone(),
-                state: Arc::new(Mutex::new(ServiceState::default())),
+                state: ServiceState::default(),
             };
             let res = Box::new(res) as Box<dyn ServerService>;
             Some(res)
@@ -81,71 +113,100 @@ impl EchoService {
             None
         }
     }
+
+    // Let the controller know the state of the service.
+    fn notify_state(&self) { smol::block_on(self.echo_sender.send(EchoCmd::SetState(self.state))).ok(); }
 }
 
+// The EchoController is the reference implementation of a controller. It exclusively owns the
+// connections, and is only accessed via the instructions it receives.
 #[derive(Debug)]
-struct Controller {
+struct EchoController {
     net_sender: NetSender,
-    connections: HashMap<NetConnId, NetSender>,
+    weight: usize,
+    // the most connections there may be, 0 if there's no limit
+    max_sessions: usize,
+    state: ServiceState,
+    connections: HashMap<NetConnId, ConnSender>,
 }
-impl Controller {
-    fn new(net_sender: NetSender) -> Self {
+impl EchoController {
+    fn new(net_sender: NetSender, weight: usize, max_sessions: usize) -> Self {
         Self {
             net_sender,
+            weight,
+            max_sessions,
+            state: ServiceState::default(),
             connections: HashMap::new(),
         }
     }
+}
 
-    fn get_connection_count(&self) -> usize { self.connections.len() }
+impl EchoController {
+    fn is_full(&self) -> bool { self.max_sessions != 0 && self.connections.len() >= self.max_sessions }
+}
 
-    async fn handle_cmd(&mut self, cmd: NetCmd, state: &ServiceState) {
+impl Controller<ListenerCmd> for EchoController {
+    fn handle(&mut self, cmd: ListenerCmd, sender: &mut MachineSender) {
+        if self.state == ServiceState::Stopped {
+            return;
+        }
         match cmd {
-            NetCmd::NewConn(conn_id, local_addr, remote_addr) if state.is_running() => {
+            ListenerCmd::NewConn(conn_id, local_addr, remote_addr) if self.state.is_running() && !self.is_full() => {
                 log::debug!(
                     "new connection conn_id={}, local_addr={}, remote_addr={}",
                     conn_id,
                     local_addr,
                     remote_addr
                 );
-                let connection = EchoConnection::new(self.net_sender.clone());
-                let (_, sender) = machine::create(connection);
-                self.connections.insert(conn_id, sender.clone());
+                let connection = EchoConnection::new(self.net_sender.clone(), self.weight);
+                let (_, conn_sender) = machine::create(connection);
+                self.connections.insert(conn_id, conn_sender.clone());
                 log::info!("connection_count={}", self.connections.len());
-                match self.net_sender.send(NetCmd::BindConn(conn_id, sender)).await {
-                    Ok(()) => (),
-                    Err(err) => log::warn!("failed to send to net_sender error={}", err),
-                }
+                sender.send(self.net_sender.clone(), NetCmd::BindConn(conn_id, conn_sender));
             },
-            NetCmd::NewConn(conn_id, _, _) => {
-                log::debug!("closing conn_id={} state={:#?}", conn_id, state);
-                self.net_sender.send(NetCmd::CloseConn(conn_id)).await.ok();
+            ListenerCmd::NewConn(conn_id, _, _) => {
+                log::debug!("closing conn_id={} state={:#?}", conn_id, self.state);
+                sender.send(self.net_sender.clone(), NetCmd::CloseConn(conn_id));
             },
-            NetCmd::CloseConn(conn_id) => {
+            ListenerCmd::CloseConn(conn_id, _) => {
                 log::debug!("removing connection conn_id={}", conn_id,);
                 self.connections.remove(&conn_id);
                 log::info!("connection_count={}", self.connections.len());
             },
-            _ => (),
+            ListenerCmd::RecvPkt(..) => (),
+        }
+    }
+}
+
+impl Controller<EchoCmd> for EchoController {
+    fn handle(&mut self, cmd: EchoCmd, _sender: &mut MachineSender) {
+        match cmd {
+            EchoCmd::SetState(state) => self.state = state,
+            EchoCmd::GetConnectionCount(reply) => {
+                reply.try_send(self.connections.len()).ok();
+            },
         }
     }
 }
 
 struct EchoConnection {
     net_sender: NetSender,
+    weight: usize,
 }
 impl EchoConnection {
-    fn new(net_sender: NetSender) -> Self { Self { net_sender } }
+    fn new(net_sender: NetSender, weight: usize) -> Self { Self { net_sender, weight } }
 }
-impl Machine<NetCmd> for EchoConnection {
-    fn receive(&self, cmd: NetCmd, sender: &mut MachineSender) {
+impl Machine<ConnCmd> for EchoConnection {
+    fn weight(&self) -> usize { self.weight }
+    fn receive(&self, cmd: ConnCmd, sender: &mut MachineSender) {
         match cmd {
-            NetCmd::RecvBytes(conn_id, buf) => {
+            ConnCmd::RecvBytes(conn_id, buf) => {
                 sender.send(self.net_sender.clone(), NetCmd::SendBytes(conn_id, buf));
             },
-            NetCmd::CloseConn(conn_id) => {
+            ConnCmd::CloseConn(conn_id) => {
                 log::debug!("remote close conn_id={}", conn_id,);
             },
-            _ => (),
+            ConnCmd::SendReady(..) | ConnCmd::Rejected(..) => (),
         }
     }
 }
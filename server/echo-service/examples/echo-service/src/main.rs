@@ -1,72 +1,84 @@
-use components::*;
 use config::ConfigError;
 use config_service::Settings;
 use echo_service::EchoService;
-use simplelog::{CombinedLogger, Config, TermLogger, TerminalMode};
-use std::{error::Error, str::FromStr};
+use server_prelude::{
+    components::{Autoscaler, LevelFilteredLogger, LogLevels},
+    *,
+};
+use simplelog::{Config, TermLogger, TerminalMode};
+use std::error::Error;
 
 fn main() { main_().ok(); }
 
 fn main_() -> Result<(), Box<dyn Error>> {
-    let level_filter = <log::LevelFilter as FromStr>::from_str("debug").unwrap();
-    CombinedLogger::init(vec![TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed)]).unwrap();
-
-    let mut services = load_services()?;
-    for s in services.iter_mut() {
-        if let Err(err) = s.start() {
-            println!("Service {} failed to start, error={:#?}", s.get_name(), err);
-            s.stop().ok();
-        }
-    }
+    // The levels are from config, and can be changed at runtime by sending LogCmd to log_sender.
+    let settings = Settings::load()?;
+    let log = &settings.server_config.log;
+    let levels = LogLevels::new(log.get_level(&settings.server_config.env)).with_targets(&log.targets);
+    let (levels, _log_sender) = machine::create(levels);
+    let logger = TermLogger::new(log::LevelFilter::Trace, Config::default(), TerminalMode::Mixed);
+    LevelFilteredLogger::new(levels, logger).init()?;
+    install_panic_hook(None);
 
+    // A service bounced at runtime is recreated from a fresh read of its config.
+    let mut manager = ServiceManager::new(load_services()?).with_factory(create_service);
+    manager.start();
     // Get the services running.
-    for s in services.iter_mut() {
-        if let Err(err) = s.run() {
-            println!("Service {} failed to run, error={:#?}", s.get_name(), err);
-            s.stop().ok();
-        }
+    manager.run();
+    // Sit here for a while while clients come and go, watching the load on the services.
+    let autoscaler = create_autoscaler()?;
+    for _ in 0 .. 10 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        autoscaler.observe_services(&manager);
     }
+    // Drain the services, then stop any that haven't already stopped, before the network, and the
+    // executors, are stopped.
+    let mut shutdown = Shutdown::new();
+    shutdown.register(ShutdownStage::Services, "services", move || async move {
+        manager.drain();
+        manager.stop();
+    });
+    shutdown.register(ShutdownStage::Network, "network", || async { NetCore::stop() });
+    shutdown.register_executors();
+    shutdown.shutdown_and_wait();
+    Ok(())
+}
 
-    // Sit here for a while while clients come and go.
-    std::thread::sleep(std::time::Duration::from_secs(10));
-    // Drain the services.
-    for s in services.iter_mut() {
-        if let Err(err) = s.drain() {
-            println!("Service {} failed to drain, error={:#?}", s.get_name(), err);
-            s.stop().ok();
-        }
+fn create_service(name: &str) -> Option<Box<dyn ServerService>> {
+    let settings = Settings::load().ok()?;
+    let instance = settings.get_service(name)?;
+    match instance.config.get_type() {
+        "EchoService" => EchoService::create(instance, &settings),
+        _ => None,
     }
-    // Wait for services to finish draining, but not too long.
-    let start = std::time::Instant::now();
-    while start.elapsed() < std::time::Duration::from_secs(5 * 60) {
-        let drained = services.iter().all(|service| service.is_drained());
-        if drained {
-            break;
-        } else {
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        }
-    }
-    // Stop any that haven't already stopped.
-    for s in services.iter_mut() {
-        if let Err(err) = s.stop() {
-            println!("Service {} failed to stop, error={:#?}", s.get_name(), err);
-        }
-    }
-    Ok(())
 }
 
+// Create the autoscaler from the rules of each service. There's nothing to scale in the example,
+// a real server would grow or shrink the service, or ask its orchestrator to.
+fn create_autoscaler() -> Result<Autoscaler, ConfigError> {
+    let settings = Settings::load()?;
+    let autoscaler = settings.service_config.iter().fold(Autoscaler::new(), |autoscaler, s| {
+        autoscaler.with_rules(&s.name, &s.config.get_service().autoscale)
+    });
+    Ok(autoscaler.with_action(|event| log::info!("service {} should scale {:?}", event.service, event.direction)))
+}
+
+// Create an instance of each service whose type is a feature of the server.
 fn load_services() -> Result<Vec<Box<dyn ServerService>>, ConfigError> {
     let mut services: Vec<Box<dyn ServerService>> = Vec::new();
     let settings = Settings::load()?;
-    for f in &settings.server_config.features {
-        match f.as_str() {
+    for instance in &settings.service_config {
+        let kind = instance.config.get_type();
+        if !settings.server_config.features.iter().any(|f| f == kind) {
+            continue;
+        }
+        match kind {
             "EchoService" => {
-                let cfg = settings.service_config.get(f).ok_or_else(|| ConfigError::NotFound(f.clone()))?;
-                let svc = EchoService::create(cfg, &settings)
-                    .ok_or_else(|| ConfigError::Message("Incorrect settings for EchoService".to_string()))?;
+                let svc = EchoService::create(instance, &settings)
+                    .ok_or_else(|| ConfigError::Message(format!("Incorrect settings for {}", instance.name)))?;
                 services.push(svc);
             },
-            &_ => (),
+            _ => (),
         }
     }
     Ok(services)
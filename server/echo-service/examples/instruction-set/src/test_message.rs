@@ -2,11 +2,13 @@ use super::*;
 
 /// TestMessage is an instuction set used in testing. The [`Forwarder`] and other machines implement it.
 #[derive(Debug, Clone, MachineImpl)]
+#[machine_impl(describe)]
 pub enum TestMessage {
     /// Test is a unit-like instruction with no parameters
     Test,
-    /// TestData has a single parameter, as a tuple
-    TestData(usize),
+    /// TestData has two parameters, as a tuple: the source of the data, such as a run of a driver, and the data,
+    /// such as a sequence number, which is validated per source
+    TestData(usize, usize),
     /// TestStruct is an example of passing a structure
     TestStruct(TestStruct),
     /// TestCallback illustrates passing a sender and a structure to be sent back to the sender
@@ -119,6 +121,29 @@ mod tests {
             assert_eq!(true, false)
         }
     }
+    #[test]
+    fn test_instruction_set_description() {
+        let description = TestMessage::INSTRUCTION_SET_DESCRIPTION;
+        assert!(description.starts_with(r#"{"name":"TestMessage","doc":"TestMessage is an instuction set used in testing."#));
+        assert!(
+            description.contains(r#"{"name":"Test","doc":"Test is a unit-like instruction with no parameters","kind":"unit","fields":[]}"#)
+        );
+        assert!(description.contains(r#"{"name":"AddSenders","doc":"AddSenders can be implemented to push a vec of senders onto a list of senders","kind":"tuple","fields":[{"type":"Vec<TestMessageSender>","doc":""}]}"#));
+        assert!(description.contains(r#"{"name":"counter","type":"u32","doc":"A counter which is either incremented or decremented"}"#));
+    }
+    #[test]
+    fn test_variant_name() {
+        use server_core::MachineImpl;
+        assert_eq!("Test", TestMessage::Test.variant_name());
+        assert_eq!("TestData", TestMessage::TestData(0, 1).variant_name());
+        let v = TestMessage::ChaosMonkey {
+            counter: 0,
+            max: 1,
+            mutation: ChaosMonkeyMutation::Increment,
+        };
+        assert_eq!("ChaosMonkey", v.variant_name());
+    }
+
     #[test]
     fn test_advance() {
         let v = TestMessage::ChaosMonkey {
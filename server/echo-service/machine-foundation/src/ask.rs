@@ -0,0 +1,289 @@
+//! The ask pattern, sending a request, carrying a reply sender, to a machine and waiting for its
+//! reply. A request may be retried when no reply arrives in time, which is only safe if the
+//! machine doesn't execute it twice. For exactly-once execution, the caller attaches an
+//! IdempotencyKey, which stays the same across retries, and the machine keeps a ResponseCache,
+//! replying to a retried request with the response it cached, rather than executing it again.
+//! A request with a typed reply, a `Result`, can have its ask plumbing generated by the
+//! MachineImpl derive, with `#[machine_impl(request(response = "T", error = "E"))]`.
+//!
+//! Examples:
+//!
+//! ```rust
+//! use machine_foundation::{ask::*, *};
+//! use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+//!
+//! #[derive(Debug, Clone, machine_impl::MachineImpl)]
+//! pub enum AccountCmd {
+//!     Withdraw(IdempotencyKey, u64, smol::channel::Sender<u64>),
+//! }
+//!
+//! struct Account {
+//!     balance: AtomicU64,
+//!     responses: ResponseCache<u64>,
+//! }
+//! impl Machine<AccountCmd> for Account {
+//!     fn receive(&self, cmd: AccountCmd, _sender: &mut MachineSender) {
+//!         let AccountCmd::Withdraw(key, amount, reply) = cmd;
+//!         self.responses.reply(key, &reply, || self.balance.fetch_sub(amount, Ordering::SeqCst) - amount);
+//!     }
+//! }
+//!
+//! let account = Account { balance: AtomicU64::new(100), responses: ResponseCache::new(1000) };
+//! let (_account, sender) = machine::create(account);
+//! let key = IdempotencyKey::new();
+//! let request = |reply| AccountCmd::Withdraw(key, 10, reply);
+//! let balance = smol::block_on(ask_with_retry(&sender, 3, Duration::from_secs(1), request));
+//! assert_eq!(Some(90), balance);
+//! // asking again, with the same key, doesn't withdraw again
+//! assert_eq!(Some(90), smol::block_on(ask(&sender, request)));
+//! ```
+use super::*;
+use parking_lot::Mutex;
+use smol::channel::{self, Sender};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// An IdempotencyKey identifies a request, a retried request keeps the key of the original.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct IdempotencyKey(uuid::Uuid);
+
+impl IdempotencyKey {
+    /// Create a new, unique, key.
+    pub fn new() -> Self { Self(uuid::Uuid::new_v4()) }
+}
+
+impl Default for IdempotencyKey {
+    fn default() -> Self { Self::new() }
+}
+
+/// Send a request to a machine and wait for its reply. The request is built by the caller from
+/// the reply sender. Returns None if the machine is gone, or drops the reply sender without
+/// replying.
+pub async fn ask<T, R>(sender: &Sender<T>, request: impl FnOnce(Sender<R>) -> T) -> Option<R>
+where
+    T: MachineImpl,
+{
+    let (reply, receiver) = channel::bounded(1);
+    sender.send(request(reply)).await.ok()?;
+    receiver.recv().await.ok()
+}
+
+/// Send a request to a machine, waiting up to timeout for its reply, and sending it again, up to
+/// attempts times in all, if there's none. The request is built from the reply sender each time it
+/// is sent, so it should carry the same IdempotencyKey every time.
+pub async fn ask_with_retry<T, R>(sender: &Sender<T>, attempts: usize, timeout: Duration, request: impl Fn(Sender<R>) -> T) -> Option<R>
+where
+    T: MachineImpl,
+{
+    for attempt in 1 ..= attempts {
+        let reply = smol::future::or(ask(sender, &request), async {
+            smol::Timer::after(timeout).await;
+            None
+        })
+        .await;
+        if reply.is_some() {
+            return reply;
+        }
+        log::debug!("ask attempt {} of {} failed", attempt, attempts);
+    }
+    None
+}
+
+struct Responses<R> {
+    cached: HashMap<IdempotencyKey, R>,
+    order: VecDeque<(IdempotencyKey, Instant)>,
+}
+
+/// The ResponseCache is kept by a machine which handles idempotent requests. It holds the
+/// responses to the most recent requests, up to a capacity and, optionally, for a time-to-live.
+pub struct ResponseCache<R> {
+    responses: Mutex<Responses<R>>,
+    capacity: usize,
+    ttl: Option<Duration>,
+}
+
+impl<R> std::fmt::Debug for ResponseCache<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#ResponseCache {{ capacity: {}, ttl: {:?} }}", self.capacity, self.ttl)
+    }
+}
+
+impl<R: Clone> ResponseCache<R> {
+    /// Create a cache, holding up to capacity responses.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            responses: Mutex::new(Responses {
+                cached: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            capacity: capacity.max(1),
+            ttl: None,
+        }
+    }
+
+    /// Forget responses once they've been cached for ttl.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Get the number of responses cached.
+    pub fn len(&self) -> usize { self.responses.lock().cached.len() }
+
+    /// Returns true if no responses are cached.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Get the response cached for a key, or execute the request, caching its response.
+    pub fn get_or_execute(&self, key: IdempotencyKey, execute: impl FnOnce() -> R) -> R {
+        let now = Instant::now();
+        let mut responses = self.responses.lock();
+        if let Some(ttl) = self.ttl {
+            while matches!(responses.order.front(), Some((_, at)) if now.duration_since(*at) >= ttl) {
+                responses.forget_oldest();
+            }
+        }
+        if let Some(response) = responses.cached.get(&key) {
+            return response.clone();
+        }
+        if responses.order.len() >= self.capacity {
+            responses.forget_oldest();
+        }
+        let response = execute();
+        responses.cached.insert(key, response.clone());
+        responses.order.push_back((key, now));
+        response
+    }
+
+    /// Reply to a request with the response cached for its key, or by executing it.
+    pub fn reply(&self, key: IdempotencyKey, reply: &Sender<R>, execute: impl FnOnce() -> R) {
+        let response = self.get_or_execute(key, execute);
+        if reply.try_send(response).is_err() {
+            log::debug!("response to {:?} dropped, the asker is gone", key);
+        }
+    }
+}
+
+impl<R> Responses<R> {
+    fn forget_oldest(&mut self) {
+        if let Some((oldest, _)) = self.order.pop_front() {
+            self.cached.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone, machine_impl::MachineImpl)]
+    enum CounterCmd {
+        Increment(IdempotencyKey, Sender<usize>),
+    }
+
+    struct Counter {
+        count: AtomicUsize,
+        responses: ResponseCache<usize>,
+        // the number of replies to drop, as though lost
+        lose: AtomicUsize,
+    }
+    impl Machine<CounterCmd> for Counter {
+        fn receive(&self, cmd: CounterCmd, _sender: &mut MachineSender) {
+            let CounterCmd::Increment(key, reply) = cmd;
+            let count = self.responses.get_or_execute(key, || self.count.fetch_add(1, Ordering::SeqCst) + 1);
+            if self.lose.load(Ordering::SeqCst) > 0 {
+                self.lose.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+            reply.try_send(count).ok();
+        }
+    }
+
+    fn counter(lose: usize) -> Counter {
+        Counter {
+            count: AtomicUsize::new(0),
+            responses: ResponseCache::new(2),
+            lose: AtomicUsize::new(lose),
+        }
+    }
+
+    #[test]
+    fn retry_executes_once() {
+        let (counter, sender) = machine::create_unbounded(counter(2));
+        let key = IdempotencyKey::new();
+        let request = |reply| CounterCmd::Increment(key, reply);
+        let count = smol::block_on(ask_with_retry(&sender, 3, Duration::from_millis(20), request));
+        assert_eq!(Some(1), count);
+        assert_eq!(1, counter.count.load(Ordering::SeqCst));
+        // a new key executes again
+        let count = smol::block_on(ask(&sender, |reply| CounterCmd::Increment(IdempotencyKey::new(), reply)));
+        assert_eq!(Some(2), count);
+    }
+
+    #[derive(Debug, Clone, machine_impl::MachineImpl)]
+    enum LedgerCmd {
+        #[machine_impl(request(response = "u64", error = "String"))]
+        GetEntry { index: usize, reply: Sender<Result<u64, String>> },
+    }
+
+    #[derive(Debug, Clone, machine_impl::MachineImpl)]
+    enum ClockCmd {
+        #[machine_impl(request(response = "u64", error = "String"))]
+        Now(Sender<Result<u64, String>>),
+    }
+
+    struct Clock;
+    impl Machine<ClockCmd> for Clock {
+        fn receive(&self, cmd: ClockCmd, _sender: &mut MachineSender) {
+            let ClockCmd::Now(reply) = cmd;
+            reply.try_send(Ok(42)).ok();
+        }
+    }
+
+    struct Ledger {
+        entries: Vec<u64>,
+    }
+    impl Machine<LedgerCmd> for Ledger {
+        fn receive(&self, cmd: LedgerCmd, _sender: &mut MachineSender) {
+            let LedgerCmd::GetEntry { index, reply } = cmd;
+            let entry = self.entries.get(index).copied().ok_or_else(|| format!("no entry {}", index));
+            reply.try_send(entry).ok();
+        }
+    }
+
+    #[test]
+    fn ask_typed_request() {
+        let (_ledger, sender) = machine::create(Ledger { entries: vec![5, 7] });
+        assert_eq!(Some(Ok(7)), smol::block_on(LedgerCmd::ask_get_entry(&sender, 1)));
+        assert_eq!(
+            Some(Err("no entry 2".to_string())),
+            smol::block_on(LedgerCmd::ask_get_entry(&sender, 2))
+        );
+        // once the machine is gone, there's no reply
+        sender.close();
+        assert_eq!(None, smol::block_on(LedgerCmd::ask_get_entry(&sender, 0)));
+    }
+
+    #[test]
+    fn ask_reply_only_request() {
+        let (_clock, sender) = machine::create(Clock);
+        assert_eq!(Some(Ok(42)), smol::block_on(ClockCmd::ask_now(&sender)));
+        let (reply, _replies) = smol::channel::bounded(1);
+        assert!(matches!(ClockCmd::now(reply), ClockCmd::Now(_)));
+    }
+
+    #[test]
+    fn cache_bounded() {
+        let cache = ResponseCache::new(2).with_ttl(Duration::from_secs(60));
+        let keys: Vec<IdempotencyKey> = (0 .. 3).map(|_| IdempotencyKey::new()).collect();
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(n, cache.get_or_execute(*key, || n));
+        }
+        assert_eq!(2, cache.len());
+        // the first key was forgotten, so is executed again
+        assert_eq!(10, cache.get_or_execute(keys[0], || 10));
+        assert_eq!(2, cache.get_or_execute(keys[2], || 20));
+    }
+}
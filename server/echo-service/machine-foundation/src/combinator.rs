@@ -0,0 +1,215 @@
+use super::*;
+
+/// The MapMachine is a pipeline stage which transforms each instruction it receives with a
+/// function, sending the result out of its output port.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// #[derive(Clone, machine_impl::MachineImpl)]
+/// pub enum Celsius {
+///     Reading(f64),
+/// }
+/// #[derive(Clone, machine_impl::MachineImpl)]
+/// pub enum Fahrenheit {
+///     Reading(f64),
+/// }
+///
+/// let convert = MapMachine::new(|Celsius::Reading(c)| Fahrenheit::Reading(c * 1.8 + 32.0));
+/// let (sender, receiver) = smol::channel::unbounded::<Fahrenheit>();
+/// convert.get_output().connect(sender).unwrap();
+/// let (_convert, convert_sender) = machine::try_create::<Celsius, _>(convert).unwrap();
+/// convert_sender.try_send(Celsius::Reading(100.0)).ok();
+/// assert!(matches!(smol::block_on(receiver.recv()), Ok(Fahrenheit::Reading(f)) if f == 212.0));
+/// ```
+pub struct MapMachine<O, F> {
+    f: F,
+    output: Port<O>,
+}
+
+impl<O: MachineImpl, F> MapMachine<O, F> {
+    /// Create the machine from the function which maps an instruction.
+    pub fn new(f: F) -> Self {
+        Self {
+            f,
+            output: Port::new("output"),
+        }
+    }
+
+    /// Get the port which is sent the mapped instructions.
+    pub fn get_output(&self) -> &Port<O> { &self.output }
+}
+
+impl<I, O, F> Machine<I> for MapMachine<O, F>
+where
+    I: 'static + Send + Sync,
+    O: MachineImpl,
+    F: Fn(I) -> O + Send + Sync,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) { self.output.send(sender, (self.f)(cmd)); }
+}
+
+impl<O, F> Ports for MapMachine<O, F> {
+    fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.output] }
+}
+
+/// The FilterMachine is a pipeline stage which sends out of its output port only those
+/// instructions accepted by a predicate, the others are dropped.
+pub struct FilterMachine<T, P> {
+    predicate: P,
+    output: Port<T>,
+}
+
+impl<T: MachineImpl, P> FilterMachine<T, P> {
+    /// Create the machine from the predicate which accepts an instruction.
+    pub fn new(predicate: P) -> Self {
+        Self {
+            predicate,
+            output: Port::new("output"),
+        }
+    }
+
+    /// Get the port which is sent the accepted instructions.
+    pub fn get_output(&self) -> &Port<T> { &self.output }
+}
+
+impl<T, P> Machine<T> for FilterMachine<T, P>
+where
+    T: MachineImpl,
+    P: Fn(&T) -> bool + Send + Sync,
+{
+    fn receive(&self, cmd: T, sender: &mut MachineSender) {
+        if (self.predicate)(&cmd) {
+            self.output.send(sender, cmd);
+        }
+    }
+}
+
+impl<T, P> Ports for FilterMachine<T, P> {
+    fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.output] }
+}
+
+/// The SplitMachine is a pipeline stage with two output ports. Instructions accepted by a
+/// predicate are sent out of the matched port, the others out of the unmatched port.
+pub struct SplitMachine<T, P> {
+    predicate: P,
+    matched: Port<T>,
+    unmatched: Port<T>,
+}
+
+impl<T: MachineImpl, P> SplitMachine<T, P> {
+    /// Create the machine from the predicate which splits the instructions.
+    pub fn new(predicate: P) -> Self {
+        Self {
+            predicate,
+            matched: Port::new("matched"),
+            unmatched: Port::new("unmatched"),
+        }
+    }
+
+    /// Get the port which is sent the accepted instructions.
+    pub fn get_matched(&self) -> &Port<T> { &self.matched }
+
+    /// Get the port which is sent the rejected instructions.
+    pub fn get_unmatched(&self) -> &Port<T> { &self.unmatched }
+}
+
+impl<T, P> Machine<T> for SplitMachine<T, P>
+where
+    T: MachineImpl,
+    P: Fn(&T) -> bool + Send + Sync,
+{
+    fn receive(&self, cmd: T, sender: &mut MachineSender) {
+        if (self.predicate)(&cmd) {
+            self.matched.send(sender, cmd);
+        } else {
+            self.unmatched.send(sender, cmd);
+        }
+    }
+}
+
+impl<T, P> Ports for SplitMachine<T, P> {
+    fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.matched, &self.unmatched] }
+}
+
+/// The MergeMachine is a pipeline stage which sends every instruction it receives out of its
+/// output port. Any instruction set which converts, via Into, to the output instruction set can
+/// be merged, use machine::extend() to add an input for each.
+pub struct MergeMachine<O> {
+    output: Port<O>,
+}
+
+impl<O: MachineImpl> Default for MergeMachine<O> {
+    fn default() -> Self {
+        Self {
+            output: Port::new("output"),
+        }
+    }
+}
+
+impl<O: MachineImpl> MergeMachine<O> {
+    /// Get the port which is sent the merged instructions.
+    pub fn get_output(&self) -> &Port<O> { &self.output }
+}
+
+impl<I, O> Machine<I> for MergeMachine<O>
+where
+    I: 'static + Send + Sync + Into<O>,
+    O: MachineImpl,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) { self.output.send(sender, cmd.into()); }
+}
+
+impl<O> Ports for MergeMachine<O> {
+    fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.output] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::TestMessage;
+
+    #[test]
+    fn pipeline() {
+        // map -> filter -> split -> (even, odd) -> merge
+        let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
+        let merge = MergeMachine::<TestMessage>::default();
+        merge.get_output().connect(sender).unwrap();
+        let (merge, even_sender) = machine::try_create::<TestMessage, _>(merge).unwrap();
+        let odd_sender = machine::extend::<TestMessage, _>(&merge);
+
+        let split = SplitMachine::new(|cmd: &TestMessage| matches!(cmd, TestMessage::TestData(_, n) if n % 2 == 0));
+        split.get_matched().connect(even_sender).unwrap();
+        split.get_unmatched().connect(odd_sender).unwrap();
+        let (_split, split_sender) = machine::try_create::<TestMessage, _>(split).unwrap();
+
+        let filter = FilterMachine::new(|cmd: &TestMessage| matches!(cmd, TestMessage::TestData(_, n) if *n < 30));
+        filter.get_output().connect(split_sender).unwrap();
+        let (_filter, filter_sender) = machine::try_create::<TestMessage, _>(filter).unwrap();
+
+        let map = MapMachine::new(|cmd: TestMessage| match cmd {
+            TestMessage::TestData(source, n) => TestMessage::TestData(source, n * 3),
+            cmd => cmd,
+        });
+        assert_eq!(Err(PortError::NotConnected("output")), map.check_ports());
+        map.get_output().connect(filter_sender).unwrap();
+        let (_map, map_sender) = machine::try_create::<TestMessage, _>(map).unwrap();
+
+        for n in 0 .. 20 {
+            smol::block_on(map_sender.send(TestMessage::TestData(0, n))).ok();
+        }
+        let mut received: Vec<usize> = smol::block_on(async {
+            let mut received = Vec::new();
+            for _ in 0 .. 10 {
+                if let Ok(TestMessage::TestData(_, n)) = receiver.recv().await {
+                    received.push(n);
+                }
+            }
+            received
+        });
+        received.sort_unstable();
+        assert_eq!((0 .. 10).map(|n| n * 3).collect::<Vec<usize>>(), received);
+    }
+}
@@ -0,0 +1,63 @@
+use super::*;
+use machine_impl::*;
+
+/// GovernorCmd is the control instruction set of a Governor. A governor can be created as a
+/// machine, so that its rate can be adjusted at runtime by sending it an instruction.
+#[derive(Debug, Clone, MachineImpl)]
+pub enum GovernorCmd {
+    /// Set the maximum number of instructions per second, 0 is unlimited.
+    SetMaxRate(u32),
+}
+
+impl Machine<GovernorCmd> for Governor {
+    fn receive(&self, cmd: GovernorCmd, _sender: &mut MachineSender) {
+        match cmd {
+            GovernorCmd::SetMaxRate(max_rate) => {
+                log::debug!("governor max_rate changed from {} to {}", self.get_max_rate(), max_rate);
+                self.set_max_rate(max_rate)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::TestMessage;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    struct Alice {
+        governor: Governor,
+        receive_count: AtomicUsize,
+    }
+    impl Machine<TestMessage> for Alice {
+        fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) { self.receive_count.fetch_add(1, Ordering::SeqCst); }
+        fn governor(&self) -> Option<Governor> { Some(self.governor.clone()) }
+    }
+
+    #[test]
+    fn governed_machine() {
+        let governor = Governor::new(20);
+        let alice = Alice {
+            governor: governor.clone(),
+            receive_count: AtomicUsize::new(0),
+        };
+        let (alice, sender) = machine::create_unbounded::<TestMessage, _>(alice);
+        let (_governor, control) = machine::create::<GovernorCmd, _>(governor.clone());
+        for _ in 0 .. 10 {
+            sender.try_send(TestMessage::Test).ok();
+        }
+        thread::sleep(Duration::from_millis(120));
+        let paced = alice.receive_count.load(Ordering::SeqCst);
+        assert!(paced < 10);
+
+        control.try_send(GovernorCmd::SetMaxRate(0)).ok();
+        thread::sleep(Duration::from_millis(120));
+        assert_eq!(0, governor.get_max_rate());
+        assert_eq!(10, alice.receive_count.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,212 @@
+use super::*;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+type Closer = Box<dyn Fn() + Send + Sync>;
+
+/// The MachineGroup shares a lifetime among the machines created through it. Closing the group
+/// closes the channel of each member, and waits for each to be disconnected, letting a service
+/// tear down its per-connection machines in one call when it is stopped. The group holds a sender
+/// of each member, so that a member lives until the group is closed or dropped, regardless of the
+/// senders dropped elsewhere. Dropping the group closes the members, without waiting.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// #[derive(Clone, machine_impl::MachineImpl)]
+/// pub enum Ping {
+///     Ping,
+/// }
+///
+/// struct Pong;
+/// impl Machine<Ping> for Pong {
+///     fn receive(&self, _cmd: Ping, _sender: &mut MachineSender) {}
+///     fn disconnected(&self) { println!("disconnected") }
+/// }
+///
+/// let group = MachineGroup::new();
+/// let (_pong, sender) = group.create(Pong);
+/// sender.try_send(Ping::Ping).ok();
+/// smol::block_on(group.close());
+/// assert!(sender.is_closed());
+/// ```
+pub struct MachineGroup {
+    closers: Mutex<Vec<Closer>>,
+    disconnected: (smol::channel::Sender<()>, smol::channel::Receiver<()>),
+}
+
+impl std::fmt::Debug for MachineGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "#MachineGroup {{ members: {} }}", self.len()) }
+}
+
+impl Default for MachineGroup {
+    fn default() -> Self {
+        Self {
+            closers: Mutex::new(Vec::new()),
+            disconnected: smol::channel::unbounded(),
+        }
+    }
+}
+
+// The Member wraps a machine, signalling the group once the machine is disconnected.
+struct Member<T> {
+    machine: Arc<T>,
+    disconnected: smol::channel::Sender<()>,
+}
+
+impl<I, T> Machine<I> for Member<T>
+where
+    I: 'static + Send + Sync,
+    T: Machine<I>,
+{
+    fn receive(&self, cmd: I, sender: &mut MachineSender) { self.machine.receive(cmd, sender) }
+    fn disconnected(&self) {
+        self.machine.disconnected();
+        self.disconnected.try_send(()).ok();
+    }
+    fn connected(&self, uuid: uuid::Uuid) { self.machine.connected(uuid) }
+    fn governor(&self) -> Option<Governor> { self.machine.governor() }
+    fn weight(&self) -> usize { self.machine.weight() }
+}
+
+impl MachineGroup {
+    /// Create an empty group.
+    pub fn new() -> Self { Self::default() }
+
+    /// Create a member of the group, with a default queue capacity. The Machine and Sender for the
+    /// machine are returned.
+    pub fn create<I, T>(
+        &self, machine: T,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+        I: MachineImpl,
+        <I as MachineImpl>::Adapter: MachineBuilder,
+    {
+        self.create_with_capacity::<I, T>(machine, get_default_channel_max())
+    }
+
+    /// Create a member of the group, with a specified queue capacity. The Machine and Sender for
+    /// the machine are returned.
+    pub fn create_with_capacity<I, T>(
+        &self, machine: T, capacity: usize,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+        I: MachineImpl,
+        <I as MachineImpl>::Adapter: MachineBuilder,
+    {
+        let (member, machine) = self.prepare_member(machine);
+        let (_member, sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::bounded(member, capacity);
+        self.add_closer(sender.clone());
+        (machine, sender)
+    }
+
+    /// Create a member of the group, with an unbounded queue capacity. The Machine and Sender for
+    /// the machine are returned.
+    pub fn create_unbounded<I, T>(
+        &self, machine: T,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+        I: MachineImpl,
+        <I as MachineImpl>::Adapter: MachineBuilder,
+    {
+        let (member, machine) = self.prepare_member(machine);
+        let (_member, sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::unbounded(member);
+        self.add_closer(sender.clone());
+        (machine, sender)
+    }
+
+    /// Get the number of members which haven't been closed.
+    pub fn len(&self) -> usize { self.closers.lock().len() }
+
+    /// Check if the group has no members, which haven't been closed.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Close the channel of each member, returning once each has been disconnected. Machines created
+    /// after the close begins are members of the group, until it is next closed.
+    pub async fn close(&self) {
+        let closers: Vec<Closer> = self.closers.lock().drain(..).collect();
+        for closer in &closers {
+            closer();
+        }
+        for _ in 0 .. closers.len() {
+            if self.disconnected.1.recv().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn prepare_member<T>(&self, machine: T) -> (Member<T>, SharedMachine<T>) {
+        let machine = Arc::new(machine);
+        let member = Member {
+            machine: machine.clone(),
+            disconnected: self.disconnected.0.clone(),
+        };
+        (member, machine)
+    }
+
+    fn add_closer<T: 'static + Send>(&self, sender: ::smol::channel::Sender<T>) {
+        self.closers.lock().push(Box::new(move || {
+            sender.close();
+        }));
+    }
+}
+
+impl Drop for MachineGroup {
+    fn drop(&mut self) {
+        for closer in self.closers.get_mut().drain(..) {
+            closer();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        received: AtomicUsize,
+        disconnected: AtomicBool,
+    }
+    impl Machine<TestMessage> for Counter {
+        fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) { self.received.fetch_add(1, Ordering::SeqCst); }
+        fn disconnected(&self) { self.disconnected.store(true, Ordering::SeqCst); }
+    }
+
+    #[test]
+    fn close_members() {
+        let group = MachineGroup::new();
+        let members: Vec<_> = (0 .. 10).map(|_| group.create_unbounded(Counter::default())).collect();
+        // a member outlives the senders dropped elsewhere
+        let (dropped, sender) = group.create(Counter::default());
+        drop(sender);
+        assert_eq!(11, group.len());
+        for (_, sender) in &members {
+            sender.try_send(TestMessage::Test).unwrap();
+        }
+        smol::block_on(group.close());
+        assert!(group.is_empty());
+        assert!(dropped.disconnected.load(Ordering::SeqCst));
+        for (counter, sender) in &members {
+            assert!(sender.is_closed());
+            assert!(counter.disconnected.load(Ordering::SeqCst));
+            assert_eq!(1, counter.received.load(Ordering::SeqCst));
+        }
+    }
+}
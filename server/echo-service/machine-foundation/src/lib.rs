@@ -1,12 +1,45 @@
+// the ask_ functions generated by the MachineImpl derive refer to the crate by name
+extern crate self as machine_foundation;
+
+pub mod ask;
+mod combinator;
 mod executor;
+mod governor;
+mod group;
 pub mod machine;
 mod machine_adapter;
+mod port;
+mod shutdown;
+pub mod stream;
+pub mod timer;
+mod topology;
+mod watchdog;
 
+pub use combinator::{FilterMachine, MapMachine, MergeMachine, SplitMachine};
+pub use governor::{GovernorCmd, GovernorCmdSender};
+pub use group::MachineGroup;
 pub use machine_adapter::{get_default_channel_max, set_default_channel_max};
+pub use port::{Port, PortError, PortInfo, Ports};
+pub use shutdown::{Shutdown, ShutdownStage};
+pub use topology::{NodeId, Topology, TopologyBuilder};
+pub use watchdog::{StalledReceive, Watchdog};
 
+#[cfg(feature = "tokio")] pub use server_core::TokioRuntime;
 pub use server_core::{
-    get_default_num_threads, get_executor, set_default_num_threads, BackgroundTask, Machine, MachineBuilder, MachineImpl, MachineSender,
-    SharedMachine,
+    add_executor_threads, clear_trace, current_machine, evict_node, get_adaptive_channel_max, get_blocking_max_threads,
+    get_blocking_thread_count, get_correlation, get_default_num_threads, get_dropped_instructions, get_executor, get_executor_affinity,
+    get_executor_profile, get_executor_selection, get_executor_stats, get_executor_thread_count, get_instruction_sets,
+    get_log_dropped_instructions, get_machines, get_names, get_receive_time_slice, get_receive_warning_threshold, get_receives_in_progress,
+    get_runtime, get_scratch_allocated, get_trace, get_trace_capacity, get_watch_receives, get_work_stealing, install_panic_hook,
+    list_background_tasks, lookup, machine_channel, record_trace, register_instruction_set, register_name, remove_executor_threads,
+    set_blocking_max_threads, set_correlation, set_crash_dir, set_default_num_threads, set_executor_affinity, set_executor_profile,
+    set_executor_selection, set_executor_supervisor, set_log_dropped_instructions, set_panic_supervisor, set_receive_time_slice,
+    set_receive_warning_threshold, set_remote_resolver, set_runtime, set_trace_capacity, set_watch_receives, set_work_stealing,
+    spawn_blocking, unregister_name, with_scratch, BackgroundHandle, BackgroundTask, BackgroundTaskGroup, BackgroundTaskInfo,
+    DroppedInstructions, ExecutorGroup, ExecutorProfile, ExecutorSelection, ExecutorStats, ExecutorThreadDeath, Governor,
+    InstructionSetInfo, InstructionSetRegistration, InstrumentedExecutor, LocalExecutorThread, LocalMachine, Machine, MachineBuilder,
+    MachineContext, MachineImpl, MachineInfo, MachinePanic, MachineSender, NamedMachine, Priority, Runtime, SequenceDiagram, SharedMachine,
+    SmolRuntime, TraceEvent, DEFAULT_WEIGHT,
 };
 
 #[cfg(test)]
@@ -59,6 +59,61 @@ where
     (machine, sender)
 }
 
+/// Create a machine from a model with a default queue capacity, run by the executors of the group,
+/// rather than the global pool. The Machine and Sender for the machine are returned.
+pub fn create_in_group<I, T>(
+    group: &ExecutorGroup, machine: T,
+) -> (
+    SharedMachine<T>,
+    ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+)
+where
+    T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    create_in_group_with_capacity::<I, T>(group, machine, get_default_channel_max())
+}
+
+/// Create a machine from a model with a specified queue capacity, run by the executors of the
+/// group. The Machine and Sender for the machine are returned.
+pub fn create_in_group_with_capacity<I, T>(
+    group: &ExecutorGroup, machine: T, capacity: usize,
+) -> (
+    SharedMachine<T>,
+    ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+)
+where
+    T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    let channel = machine_channel(Some(capacity));
+    let (machine, sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::create_on(machine, channel, group.get_executor());
+    (machine, sender)
+}
+
+/// Create a machine from a model with a default queue capacity, after checking that all of its
+/// ports are connected. The Machine and Sender for the machine are returned.
+#[allow(clippy::type_complexity)]
+pub fn try_create<I, T>(
+    machine: T,
+) -> Result<
+    (
+        SharedMachine<T>,
+        ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    ),
+    PortError,
+>
+where
+    T: 'static + Ports + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    machine.check_ports()?;
+    Ok(create::<I, T>(machine))
+}
+
 /// Extend a machine with an additional instruction set and a default queue capacity. The Sender for the
 /// machine is returned.
 pub fn extend<I, T>(machine: &Arc<T>) -> ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>
@@ -72,6 +127,55 @@ where
     sender
 }
 
+/// Extend a machine with an additional instruction set and a default queue capacity, run by the
+/// executors of the group. The Sender for the machine is returned.
+pub fn extend_in_group<I, T>(
+    group: &ExecutorGroup, machine: &Arc<T>,
+) -> ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>
+where
+    T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    let channel = machine_channel(Some(get_default_channel_max()));
+    let (sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::extend_on(machine, channel, group.get_executor());
+    sender
+}
+
+/// Create a machine from a model with a default queue capacity, whose instructions are received
+/// with the priority given, such as High for a machine of the control-plane. The Machine and
+/// Sender for the machine are returned.
+pub fn create_with_priority<I, T>(
+    machine: T, priority: Priority,
+) -> (
+    SharedMachine<T>,
+    ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+)
+where
+    T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    let channel = machine_channel(Some(get_default_channel_max()));
+    let (machine, sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::create_with_priority(machine, channel, priority);
+    (machine, sender)
+}
+
+/// Extend a machine with an additional instruction set and a default queue capacity, whose
+/// instructions are received with the priority given. The Sender for the machine is returned.
+pub fn extend_with_priority<I, T>(
+    machine: &Arc<T>, priority: Priority,
+) -> ::smol::channel::Sender<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>
+where
+    T: 'static + Machine<I> + Machine<<<I as MachineImpl>::Adapter as MachineBuilder>::InstructionSet>,
+    I: MachineImpl,
+    <I as MachineImpl>::Adapter: MachineBuilder,
+{
+    let channel = machine_channel(Some(get_default_channel_max()));
+    let (sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::extend_with_priority(machine, channel, priority);
+    sender
+}
+
 /// Extend a machine with an additional instruction set and a specified queue capacity. The Sender for the
 /// machine is returned.
 pub fn extend_with_capacity<I, T>(
@@ -97,3 +201,77 @@ where
     let (sender, _adapter) = <<I as MachineImpl>::Adapter as MachineBuilder>::extend_unbounded(machine);
     sender
 }
+
+/// Create a machine which isn't Send, upon the local executor thread, from a factory called on
+/// that thread, with a default queue capacity. The Sender for the machine is returned.
+pub fn create_local<I, T, F>(thread: &LocalExecutorThread, factory: F) -> ::smol::channel::Sender<I>
+where
+    T: LocalMachine<I>,
+    I: MachineImpl,
+    F: FnOnce() -> T + Send + 'static,
+{
+    thread.create(machine_channel(Some(get_default_channel_max())), factory)
+}
+
+/// Create a machine which isn't Send, upon the local executor thread, from a factory called on
+/// that thread, with an unbounded queue capacity. The Sender for the machine is returned.
+pub fn create_local_unbounded<I, T, F>(thread: &LocalExecutorThread, factory: F) -> ::smol::channel::Sender<I>
+where
+    T: LocalMachine<I>,
+    I: MachineImpl,
+    F: FnOnce() -> T + Send + 'static,
+{
+    thread.create(machine_channel(None), factory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::*;
+
+    // Tells the notifier of the thread receiving each instruction.
+    struct ThreadReporter(::smol::channel::Sender<String>);
+    impl Machine<TestMessage> for ThreadReporter {
+        fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) {
+            let name = std::thread::current().name().unwrap_or_default().to_string();
+            self.0.try_send(name).ok();
+        }
+    }
+
+    // Counts the instructions it receives, in an Rc, so it isn't Send.
+    struct LocalCounter(std::rc::Rc<std::cell::Cell<usize>>, ::smol::channel::Sender<(String, usize)>);
+    impl LocalMachine<TestMessage> for LocalCounter {
+        fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) {
+            self.0.set(self.0.get() + 1);
+            let name = std::thread::current().name().unwrap_or_default().to_string();
+            self.1.try_send((name, self.0.get())).ok();
+        }
+    }
+
+    #[test]
+    fn run_local() {
+        let thread = LocalExecutorThread::new("ffi");
+        let (notifier, counts) = ::smol::channel::unbounded();
+        let sender = create_local(&thread, move || LocalCounter(std::rc::Rc::default(), notifier));
+        for _ in 0 .. 3 {
+            sender.try_send(TestMessage::Test).unwrap();
+        }
+        for count in 1 ..= 3 {
+            assert_eq!(Ok(("ffi".to_string(), count)), ::smol::block_on(counts.recv()));
+        }
+    }
+
+    #[test]
+    fn run_in_group() {
+        let group = ExecutorGroup::new("net", 2);
+        let (notifier, names) = ::smol::channel::unbounded();
+        let (_machine, sender) = create_in_group(&group, ThreadReporter(notifier));
+        for _ in 0 .. 4 {
+            sender.try_send(TestMessage::Test).unwrap();
+        }
+        for _ in 0 .. 4 {
+            let name = ::smol::block_on(names.recv()).unwrap();
+            assert!(name.starts_with("net-"), "{}", name);
+        }
+    }
+}
@@ -25,7 +25,10 @@ mod tests {
     use crossbeam::atomic::AtomicCell;
     use instruction_set::*;
     use std::{
-        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
         thread,
     };
     use uuid::Uuid;
@@ -185,4 +188,93 @@ mod tests {
         thread::sleep(std::time::Duration::from_millis(20));
         assert_eq!(StateTable::Stop, alice.state.load());
     }
+
+    #[test]
+    fn alice_current_machine() {
+        // A machine which reports the machine context it is received in
+        struct Bob {
+            sender: ::smol::channel::Sender<Option<MachineContext>>,
+        }
+        impl Machine<TestMessage> for Bob {
+            fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) { self.sender.try_send(current_machine()).ok(); }
+        }
+        let (sender, receiver) = ::smol::channel::unbounded();
+        let (_bob, bob_sender) = create::<TestMessage, _>(Bob { sender });
+        bob_sender.try_send(TestMessage::Test).ok();
+        let context = smol::block_on(receiver.recv()).unwrap().unwrap();
+        assert!(context.instruction_set.ends_with("TestMessage"));
+        assert_eq!(None, current_machine());
+    }
+
+    #[test]
+    fn alice_weighted_turns() {
+        // A machine with a zero weight, which is treated as the minimum, still receives everything
+        struct Carol {
+            receive_count: AtomicUsize,
+        }
+        impl Machine<TestMessage> for Carol {
+            fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) { self.receive_count.fetch_add(1, Ordering::SeqCst); }
+            fn weight(&self) -> usize { 0 }
+        }
+        let (carol, sender) = create_unbounded::<TestMessage, _>(Carol {
+            receive_count: AtomicUsize::new(0),
+        });
+        for _ in 0 .. 100 {
+            sender.try_send(TestMessage::Test).ok();
+        }
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(100, carol.receive_count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn alice_weighted_turns_share_executor() {
+        // Saturated machines sharing a single executor receive in proportion to their weight
+        struct Weighted {
+            id: usize,
+            weight: usize,
+            received: Arc<Mutex<Vec<usize>>>,
+        }
+        impl Machine<TestMessage> for Weighted {
+            fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) {
+                self.received.lock().unwrap_or_else(|err| err.into_inner()).push(self.id);
+            }
+            fn weight(&self) -> usize { self.weight }
+        }
+        let group = ExecutorGroup::new("weighted", 1);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let weighted = |id, weight| Weighted {
+            id,
+            weight,
+            received: received.clone(),
+        };
+        let (_light, light) = create_in_group_with_capacity::<TestMessage, _>(&group, weighted(0, 1), 4000);
+        let (_heavy, heavy) = create_in_group_with_capacity::<TestMessage, _>(&group, weighted(1, 3), 4000);
+        // hold the executor's thread while both queues are filled
+        let (blocked, wait) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        group
+            .get_executor()
+            .spawn(async move {
+                blocked.send(()).ok();
+                released.recv().ok();
+            })
+            .detach();
+        wait.recv().unwrap();
+        for _ in 0 .. 3000 {
+            light.try_send(TestMessage::Test).unwrap();
+            heavy.try_send(TestMessage::Test).unwrap();
+        }
+        release.send(()).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while received.lock().unwrap_or_else(|err| err.into_inner()).len() < 6000 && std::time::Instant::now() < deadline {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // until the heavy machine's queue ran out, it received about three times as many
+        let received = received.lock().unwrap_or_else(|err| err.into_inner());
+        assert_eq!(6000, received.len());
+        let last_heavy = received.iter().rposition(|id| *id == 1).unwrap();
+        let light_count = received[.. last_heavy].iter().filter(|id| **id == 0).count();
+        let ratio = 3000.0 / light_count as f64;
+        assert!(ratio > 2.5 && ratio < 3.5, "ratio={}", ratio);
+    }
 }
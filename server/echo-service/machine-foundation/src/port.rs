@@ -0,0 +1,160 @@
+use super::*;
+use once_cell::sync::OnceCell;
+use std::fmt;
+
+/// Represents the errors which can occur when wiring ports.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PortError {
+    /// The named port was used, or the machine created, before the port was connected.
+    NotConnected(&'static str),
+    /// The named port has already been connected.
+    AlreadyConnected(&'static str),
+}
+
+impl fmt::Display for PortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotConnected(name) => write!(f, "port {} is not connected", name),
+            Self::AlreadyConnected(name) => write!(f, "port {} is already connected", name),
+        }
+    }
+}
+
+impl std::error::Error for PortError {}
+
+/// A Port is a named output of a machine, which is connected, exactly once, to the sender of
+/// another machine. Unlike an AddSender-style instruction, an unconnected port is detected when
+/// the machine is created via machine::try_create(), rather than by instructions going missing.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// #[derive(Clone, machine_impl::MachineImpl)]
+/// pub enum Example {
+///     Red,
+/// }
+///
+/// struct Alice {
+///     output: Port<Example>,
+/// }
+/// impl Machine<Example> for Alice {
+///     fn receive(&self, cmd: Example, sender: &mut MachineSender) { self.output.send(sender, cmd); }
+/// }
+/// impl Ports for Alice {
+///     fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.output] }
+/// }
+///
+/// let alice = Alice { output: Port::new("output") };
+/// assert!(machine::try_create::<Example, _>(alice).is_err());
+///
+/// let (bob_sender, _bob_receiver) = smol::channel::unbounded::<Example>();
+/// let alice = Alice { output: Port::new("output") };
+/// alice.output.connect(bob_sender).unwrap();
+/// let (_alice, _sender) = machine::try_create::<Example, _>(alice).unwrap();
+/// ```
+pub struct Port<T> {
+    name: &'static str,
+    sender: OnceCell<smol::channel::Sender<T>>,
+}
+
+impl<T> fmt::Debug for Port<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#Port {{ name: {}, connected: {} }}", self.name, self.sender.get().is_some())
+    }
+}
+
+impl<T: MachineImpl> Port<T> {
+    /// Create an unconnected port.
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            sender: OnceCell::new(),
+        }
+    }
+
+    /// Connect the port to a sender, a port can only be connected once.
+    pub fn connect(&self, sender: smol::channel::Sender<T>) -> Result<(), PortError> {
+        self.sender.set(sender).map_err(|_| PortError::AlreadyConnected(self.name))
+    }
+
+    /// Get the sender the port is connected to.
+    pub fn get_sender(&self) -> Result<&smol::channel::Sender<T>, PortError> { self.sender.get().ok_or(PortError::NotConnected(self.name)) }
+
+    /// Send an instruction out of the port, via MachineSender::send_now(). It is an error to send
+    /// via an unconnected port, which is logged, as the instruction is lost.
+    pub fn send(&self, sender: &mut MachineSender, cmd: T) {
+        match self.get_sender() {
+            Ok(port_sender) => sender.send_now(port_sender, cmd),
+            Err(err) => log::error!("dropping instruction, {}", err),
+        }
+    }
+}
+
+/// PortInfo describes a port, independent of its instruction set.
+pub trait PortInfo {
+    /// Get the name of the port.
+    fn get_name(&self) -> &'static str;
+    /// Check if the port has been connected.
+    fn is_connected(&self) -> bool;
+}
+
+impl<T> PortInfo for Port<T> {
+    fn get_name(&self) -> &'static str { self.name }
+    fn is_connected(&self) -> bool { self.sender.get().is_some() }
+}
+
+/// Ports is implemented by a machine to declare its required ports.
+pub trait Ports {
+    /// Get the ports of the machine.
+    fn get_ports(&self) -> Vec<&dyn PortInfo>;
+
+    /// Check that all of the ports are connected, returning the first which isn't.
+    fn check_ports(&self) -> Result<(), PortError> {
+        match self.get_ports().iter().find(|port| !port.is_connected()) {
+            Some(port) => Err(PortError::NotConnected(port.get_name())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::TestMessage;
+
+    struct Alice {
+        first: Port<TestMessage>,
+        second: Port<TestMessage>,
+    }
+    impl Machine<TestMessage> for Alice {
+        fn receive(&self, cmd: TestMessage, sender: &mut MachineSender) {
+            self.first.send(sender, cmd.clone());
+            self.second.send(sender, cmd);
+        }
+    }
+    impl Ports for Alice {
+        fn get_ports(&self) -> Vec<&dyn PortInfo> { vec![&self.first, &self.second] }
+    }
+
+    #[test]
+    fn port_wiring() {
+        let alice = Alice {
+            first: Port::new("first"),
+            second: Port::new("second"),
+        };
+        let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
+        assert_eq!(Ok(()), alice.first.connect(sender.clone()));
+        assert_eq!(Err(PortError::AlreadyConnected("first")), alice.first.connect(sender.clone()));
+        assert_eq!(Err(PortError::NotConnected("second")), alice.check_ports());
+        alice.second.connect(sender).ok();
+
+        let (_alice, alice_sender) = machine::try_create::<TestMessage, _>(alice).unwrap();
+        alice_sender.try_send(TestMessage::Test).ok();
+        smol::block_on(async {
+            assert!(matches!(receiver.recv().await, Ok(TestMessage::Test)));
+            assert!(matches!(receiver.recv().await, Ok(TestMessage::Test)));
+        });
+    }
+}
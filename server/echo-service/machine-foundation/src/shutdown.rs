@@ -0,0 +1,156 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+// A teardown callback, returning the future which completes the teardown.
+type Teardown = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>>>;
+
+/// The ShutdownStage orders the teardown of a server, from the services down to the executors
+/// which run everything else.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ShutdownStage {
+    /// The services, which are drained, then stopped, while the network is still up.
+    Services,
+    /// The network, such as NetCore, once the services no longer need it.
+    Network,
+    /// The background tasks, and machines, which outlive the services.
+    Tasks,
+    /// The executors, once every task has completed.
+    Executors,
+}
+
+// A registered teardown.
+struct Step {
+    stage: ShutdownStage,
+    name: String,
+    teardown: Teardown,
+}
+
+/// The Shutdown coordinates the teardown of a server. Each subsystem registers a teardown callback
+/// for its stage, and shutdown() runs them stage by stage, awaiting each in turn, those of a stage
+/// in the order they were registered. A teardown which doesn't complete within the timeout is
+/// abandoned, so that a stuck subsystem can't keep the server from stopping.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// let mut shutdown = Shutdown::new();
+/// shutdown.register(ShutdownStage::Network, "network", || async { println!("network stopped") });
+/// shutdown.register(ShutdownStage::Services, "echo", || async { println!("echo stopped") });
+/// assert!(shutdown.shutdown_and_wait().is_empty());
+/// ```
+pub struct Shutdown {
+    steps: Vec<Step>,
+    timeout: Duration,
+}
+
+impl std::fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#Shutdown {{ steps: {}, timeout: {:#?} }}", self.steps.len(), self.timeout)
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Create a shutdown, without any teardown registered.
+    pub fn new() -> Self { Self::default() }
+
+    /// Set how long each teardown is awaited, the default is 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Register a teardown, with a name for logging, for the stage. The callback is called once the
+    /// stages before it have been torn down, and the future it returns is awaited.
+    pub fn register<F, Fut>(&mut self, stage: ShutdownStage, name: &str, teardown: F)
+    where
+        F: 'static + FnOnce() -> Fut,
+        Fut: 'static + Future<Output = ()>,
+    {
+        self.steps.push(Step {
+            stage,
+            name: name.to_string(),
+            teardown: Box::new(move || Box::pin(teardown())),
+        });
+    }
+
+    /// Register stopping the executors, waiting for their tasks to complete, and their threads to
+    /// exit, within the timeout.
+    pub fn register_executors(&mut self) {
+        let timeout = self.timeout;
+        self.register(ShutdownStage::Executors, "executors", move || async move {
+            server_core::stop_executors_and_wait(timeout);
+        });
+    }
+
+    /// Get the number of teardowns registered.
+    pub fn len(&self) -> usize { self.steps.len() }
+
+    /// Check if no teardown is registered.
+    pub fn is_empty(&self) -> bool { self.steps.is_empty() }
+
+    /// Run the teardowns, stage by stage, returning the names of those which timed out.
+    pub async fn shutdown(mut self) -> Vec<String> {
+        // the sort is stable, keeping the order of registration within a stage
+        self.steps.sort_by_key(|step| step.stage);
+        let mut timed_out = Vec::new();
+        let timeout = self.timeout;
+        for Step { stage, name, teardown } in self.steps {
+            log::info!("shutdown stage={:?} name={}", stage, name);
+            let completed = async {
+                teardown().await;
+                true
+            };
+            let expired = async {
+                smol::Timer::after(timeout).await;
+                false
+            };
+            if !smol::future::or(completed, expired).await {
+                log::warn!("shutdown stage={:?} name={} timed out after {:#?}", stage, name, timeout);
+                timed_out.push(name);
+            }
+        }
+        timed_out
+    }
+
+    /// Run the teardowns, blocking until they have completed, or timed out, returning the names of
+    /// those which timed out.
+    pub fn shutdown_and_wait(self) -> Vec<String> { smol::block_on(self.shutdown()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn teardown_in_stage_order() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut shutdown = Shutdown::new().with_timeout(Duration::from_millis(100));
+        for (stage, name) in &[
+            (ShutdownStage::Tasks, "tasks"),
+            (ShutdownStage::Network, "network"),
+            (ShutdownStage::Services, "echo"),
+            (ShutdownStage::Services, "chat"),
+        ] {
+            let order = order.clone();
+            shutdown.register(*stage, name, move || async move {
+                smol::Timer::after(Duration::from_millis(10)).await;
+                order.borrow_mut().push(name.to_string());
+            });
+        }
+        shutdown.register(ShutdownStage::Network, "stuck", smol::future::pending::<()>);
+        assert_eq!(5, shutdown.len());
+        assert_eq!(vec!["stuck".to_string()], shutdown.shutdown_and_wait());
+        assert_eq!(vec!["echo", "chat", "network", "tasks"], *order.borrow());
+    }
+}
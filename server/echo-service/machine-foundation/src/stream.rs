@@ -0,0 +1,97 @@
+//! Bridges between machines and streams, allowing machines to interoperate with stream-based
+//! libraries, such as codecs and websockets, without hand-written glue tasks.
+//!
+//! Examples:
+//!
+//! ```rust
+//! use machine_foundation::*;
+//! use smol::stream::StreamExt;
+//!
+//! #[derive(Clone, machine_impl::MachineImpl)]
+//! pub enum Line {
+//!     Text(String),
+//! }
+//!
+//! let upper = MapMachine::new(|Line::Text(text)| Line::Text(text.to_uppercase()));
+//! let output = stream::connect_port(upper.get_output(), 10).unwrap();
+//! let (_upper, sender) = machine::try_create::<Line, _>(upper).unwrap();
+//!
+//! let input = smol::stream::iter(vec!["hello", "world"]).map(|text| Line::Text(text.to_string()));
+//! let _task = stream::spawn_forward(input, sender);
+//! let lines: Vec<String> = smol::block_on(output.take(2).map(|Line::Text(text)| text).collect());
+//! assert_eq!(vec!["HELLO", "WORLD"], lines);
+//! ```
+use super::*;
+use smol::stream::{Stream, StreamExt};
+
+/// Connect a port to a new channel, returning its receiver, which is a Stream of the instructions
+/// sent out of the port. The channel is bounded by capacity, when the stream isn't keeping up, the
+/// machine waits, as it would when sending to another machine.
+pub fn connect_port<T: MachineImpl>(port: &Port<T>, capacity: usize) -> Result<smol::channel::Receiver<T>, PortError> {
+    let (sender, receiver) = smol::channel::bounded::<T>(capacity.max(1));
+    port.connect(sender)?;
+    Ok(receiver)
+}
+
+/// Forward the items of a stream to a sender, waiting whenever the receiving machine's queue is
+/// full. Completes, returning the number of items forwarded, when the stream ends or the sender
+/// is closed.
+pub async fn forward<T, S>(stream: S, sender: &smol::channel::Sender<T>) -> usize
+where
+    T: MachineImpl,
+    S: Stream<Item = T>,
+{
+    smol::pin!(stream);
+    let mut count = 0;
+    while let Some(cmd) = stream.next().await {
+        if sender.send(cmd).await.is_err() {
+            log::debug!("stream forward stopped, sender closed after {} instructions", count);
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Spawn a task which forwards the items of a stream to a sender. The forwarding is cancelled
+/// when the returned task is cancelled.
+pub fn spawn_forward<T, S>(stream: S, sender: smol::channel::Sender<T>) -> BackgroundTask
+where
+    T: MachineImpl,
+    S: 'static + Stream<Item = T> + Send,
+{
+    let task = get_executor().spawn(async move { forward(stream, &sender).await });
+    BackgroundTask::detach(task, "stream forward")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::TestMessage;
+
+    #[test]
+    fn forward_with_backpressure() {
+        let filter = FilterMachine::new(|cmd: &TestMessage| matches!(cmd, TestMessage::TestData(_, n) if n % 2 == 1));
+        // a small capacity, so that the machine, and in turn the forward, waits on the stream
+        let output = connect_port(filter.get_output(), 1).unwrap();
+        assert_eq!(
+            Err(PortError::AlreadyConnected("output")),
+            connect_port(filter.get_output(), 1).map(|_| ())
+        );
+        let (_filter, sender) = machine::create_with_capacity::<TestMessage, _>(filter, 2);
+
+        let input = smol::stream::iter(0 .. 100).map(|n| TestMessage::TestData(0, n));
+        let forwarded = get_executor().spawn(async move { forward(input, &sender).await });
+        let received: Vec<usize> = smol::block_on(
+            output
+                .take(50)
+                .filter_map(|cmd| match cmd {
+                    TestMessage::TestData(_, n) => Some(n),
+                    _ => None,
+                })
+                .collect(),
+        );
+        assert_eq!((0 .. 50).map(|n| n * 2 + 1).collect::<Vec<usize>>(), received);
+        assert_eq!(100, smol::block_on(forwarded));
+    }
+}
@@ -0,0 +1,236 @@
+//! Timers, which send an instruction to a machine after a delay, or periodically, so that a machine
+//! can schedule future work, such as an idle timeout, or periodic housekeeping, without a sleep
+//! loop of its own. The timers are kept in a hashed timer wheel, driven by a single thread, which
+//! ticks every 10ms, so a timer fires within a tick of when it is due.
+//!
+//! Examples:
+//!
+//! ```rust
+//! use machine_foundation::timer;
+//! use std::time::Duration;
+//!
+//! #[derive(Debug, Clone, machine_impl::MachineImpl)]
+//! pub enum Housekeeping {
+//!     Sweep,
+//! }
+//!
+//! let (sender, receiver) = smol::channel::unbounded::<Housekeeping>();
+//! let sweeper = timer::send_interval(&sender, Housekeeping::Sweep, Duration::from_millis(20));
+//! smol::block_on(receiver.recv()).unwrap();
+//! smol::block_on(receiver.recv()).unwrap();
+//! sweeper.cancel();
+//! ```
+use super::*;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use smol::channel::{Sender, TrySendError};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// The resolution of the timer wheel.
+const TICK: Duration = Duration::from_millis(10);
+// The number of slots in the timer wheel, a timer further out than this goes around more than once.
+const SLOTS: u64 = 256;
+
+/// A TimerHandle cancels the timer it was returned for. Dropping it doesn't cancel the timer.
+#[derive(Debug, Clone, Default)]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    /// Cancel the timer, it won't send again, though an instruction it has already sent may not
+    /// yet have been received.
+    pub fn cancel(&self) { self.cancelled.store(true, Ordering::SeqCst) }
+
+    /// Check if the timer has been cancelled.
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::SeqCst) }
+}
+
+/// Send the instruction to the sender once the delay has passed.
+pub fn send_after<T: MachineImpl>(sender: &Sender<T>, cmd: T, delay: Duration) -> TimerHandle {
+    let sender = sender.clone();
+    let mut cmd = Some(cmd);
+    schedule(delay, None, move || {
+        if let Some(cmd) = cmd.take() {
+            deliver(&sender, cmd);
+        }
+        false
+    })
+}
+
+/// Send the instruction to the sender every period, until the handle is cancelled, or the sender
+/// is closed.
+pub fn send_interval<T: MachineImpl + Clone>(sender: &Sender<T>, cmd: T, period: Duration) -> TimerHandle {
+    let sender = sender.clone();
+    schedule(period, Some(period), move || deliver(&sender, cmd.clone()))
+}
+
+// Deliver an instruction, waiting for room, rather than holding up the wheel, if the receiver is
+// full. Returns false if the receiver is gone.
+fn deliver<T: MachineImpl>(sender: &Sender<T>, cmd: T) -> bool {
+    match sender.try_send(cmd) {
+        Ok(()) => true,
+        Err(TrySendError::Full(cmd)) => {
+            let sender = sender.clone();
+            get_executor().spawn(async move { sender.send(cmd).await.ok() }).detach();
+            true
+        },
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+// A timer in the wheel, its fire returns false once it shouldn't fire again.
+struct Timer {
+    rounds: u64,
+    period: Option<u64>,
+    cancelled: Arc<AtomicBool>,
+    fire: Box<dyn FnMut() -> bool + Send>,
+}
+
+struct Wheel {
+    slots: Vec<Vec<Timer>>,
+    tick: u64,
+}
+
+impl Wheel {
+    fn new() -> Self {
+        Self {
+            slots: (0 .. SLOTS).map(|_| Vec::new()).collect(),
+            tick: 0,
+        }
+    }
+
+    // Insert a timer, to fire once the wheel has advanced by ticks.
+    fn insert(&mut self, ticks: u64, mut timer: Timer) {
+        let ticks = ticks.max(1);
+        timer.rounds = (ticks - 1) / SLOTS;
+        self.slots[((self.tick + ticks) % SLOTS) as usize].push(timer);
+    }
+
+    // Advance by a tick, firing the timers which are due, and rescheduling those which are periodic.
+    fn advance(&mut self) {
+        self.tick += 1;
+        let slot = std::mem::take(&mut self.slots[(self.tick % SLOTS) as usize]);
+        for mut timer in slot {
+            if timer.cancelled.load(Ordering::SeqCst) {
+                continue;
+            }
+            if timer.rounds > 0 {
+                timer.rounds -= 1;
+                self.slots[(self.tick % SLOTS) as usize].push(timer);
+                continue;
+            }
+            if (timer.fire)() {
+                if let Some(period) = timer.period {
+                    self.insert(period, timer);
+                }
+            }
+        }
+    }
+}
+
+// The timer wheel, and the thread which drives it.
+static WHEEL: Lazy<Arc<Mutex<Wheel>>> = Lazy::new(|| {
+    let wheel = Arc::new(Mutex::new(Wheel::new()));
+    let driven = wheel.clone();
+    thread::Builder::new()
+        .name("timer".to_string())
+        .spawn(move || {
+            let mut next = Instant::now();
+            loop {
+                next += TICK;
+                let now = Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                }
+                driven.lock().advance();
+            }
+        })
+        .expect("cannot spawn timer thread");
+    wheel
+});
+
+// Schedule a timer, to fire after the delay, and then every period, if there is one.
+fn schedule(delay: Duration, period: Option<Duration>, fire: impl FnMut() -> bool + Send + 'static) -> TimerHandle {
+    let handle = TimerHandle::default();
+    let timer = Timer {
+        rounds: 0,
+        period: period.map(to_ticks),
+        cancelled: handle.cancelled.clone(),
+        fire: Box::new(fire),
+    };
+    // the wheel may be part way to its next tick, which mustn't count toward the delay
+    WHEEL.lock().insert(to_ticks(delay) + 1, timer);
+    handle
+}
+
+// Convert a duration to ticks, rounding up.
+fn to_ticks(duration: Duration) -> u64 { duration.as_nanos().div_ceil(TICK.as_nanos()) as u64 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::TestMessage;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn wheel_rounds() {
+        let mut wheel = Wheel::new();
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = fired.clone();
+        let timer = Timer {
+            rounds: 0,
+            period: Some(SLOTS + 1),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            fire: Box::new(move || counter.fetch_add(1, Ordering::SeqCst) < 1),
+        };
+        wheel.insert(SLOTS + 1, timer);
+        for _ in 0 .. SLOTS {
+            wheel.advance();
+        }
+        assert_eq!(0, fired.load(Ordering::SeqCst));
+        wheel.advance();
+        assert_eq!(1, fired.load(Ordering::SeqCst));
+        for _ in 0 .. 2 * (SLOTS + 1) {
+            wheel.advance();
+        }
+        // it stopped once it returned false
+        assert_eq!(2, fired.load(Ordering::SeqCst));
+        assert!(wheel.slots.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn send_after_delay() {
+        let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
+        let start = Instant::now();
+        send_after(&sender, TestMessage::Test, Duration::from_millis(50));
+        let cancelled = send_after(&sender, TestMessage::TestData(0, 1), Duration::from_millis(30));
+        cancelled.cancel();
+        assert!(matches!(smol::block_on(receiver.recv()), Ok(TestMessage::Test)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn send_periodically() {
+        let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
+        let start = Instant::now();
+        let handle = send_interval(&sender, TestMessage::Test, Duration::from_millis(20));
+        for _ in 0 .. 3 {
+            smol::block_on(receiver.recv()).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        handle.cancel();
+        thread::sleep(Duration::from_millis(50));
+        while receiver.try_recv().is_ok() {}
+        thread::sleep(Duration::from_millis(50));
+        assert!(receiver.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use super::*;
+use std::sync::Arc;
+
+/// Identifies a node, a machine, in a topology.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct NodeId(usize);
+
+/// The TopologyBuilder declaratively constructs a network of machines, all receiving the
+/// instruction set T. Nodes are added and connected with edges, an edge from a node to another
+/// means the former sends to the latter. When built, the machines are created and each node is
+/// sent the instructions which wire it to the senders of its edges, in the order the edges were
+/// added.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// #[derive(Clone, machine_impl::MachineImpl)]
+/// pub enum Example {
+///     AddSender(ExampleSender),
+/// }
+///
+/// struct Alice;
+/// impl Machine<Example> for Alice {
+///     fn receive(&self, _cmd: Example, _sender: &mut MachineSender) {}
+/// }
+///
+/// let mut builder = TopologyBuilder::default();
+/// let nodes: Vec<NodeId> = (0 .. 3).map(|_| builder.add_node(Alice)).collect();
+/// builder.add_chain(&nodes);
+/// let topology = smol::block_on(builder.build(|senders| senders.into_iter().map(Example::AddSender).collect()));
+/// assert_eq!(3, topology.get_senders().len());
+/// ```
+pub struct TopologyBuilder<T, M> {
+    nodes: Vec<M>,
+    edges: Vec<(NodeId, NodeId)>,
+    capacity: Option<usize>,
+    _instruction_set: std::marker::PhantomData<fn(T)>,
+}
+
+impl<T, M> Default for TopologyBuilder<T, M> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            capacity: Some(get_default_channel_max()),
+            _instruction_set: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, M> TopologyBuilder<T, M>
+where
+    T: MachineImpl<Adapter = T> + MachineBuilder<InstructionSet = T>,
+    M: 'static + Machine<T>,
+{
+    /// Create the machines with a specified queue capacity, rather than the default.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Create the machines with unbounded queues.
+    pub fn with_unbounded(mut self) -> Self {
+        self.capacity = None;
+        self
+    }
+
+    /// Add a node, returning its id.
+    pub fn add_node(&mut self, machine: M) -> NodeId {
+        self.nodes.push(machine);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Add an edge, from sends to to.
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId) { self.edges.push((from, to)); }
+
+    /// Add edges which chain the nodes, each sending to the next.
+    pub fn add_chain(&mut self, nodes: &[NodeId]) {
+        for pair in nodes.windows(2) {
+            self.add_edge(pair[0], pair[1]);
+        }
+    }
+
+    /// Add edges which completely connect the nodes, each sending to all, including itself.
+    pub fn add_complete(&mut self, nodes: &[NodeId]) {
+        for from in nodes {
+            for to in nodes {
+                self.add_edge(*from, *to);
+            }
+        }
+    }
+
+    /// Build the topology. For each node with edges, wire is called with the senders of the
+    /// edges, and returns the instructions which are sent to the node.
+    pub async fn build(self, wire: impl Fn(Vec<smol::channel::Sender<T>>) -> Vec<T>) -> Topology<T, M> {
+        let mut machines = Vec::with_capacity(self.nodes.len());
+        let mut senders = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes {
+            let (machine, sender) = match self.capacity {
+                Some(capacity) => machine::create_with_capacity::<T, M>(node, capacity),
+                None => machine::create_unbounded::<T, M>(node),
+            };
+            machines.push(machine);
+            senders.push(sender);
+        }
+        let mut outputs: Vec<Vec<smol::channel::Sender<T>>> = vec![Vec::new(); senders.len()];
+        for (from, to) in self.edges {
+            outputs[from.0].push(senders[to.0].clone());
+        }
+        for (idx, output) in outputs.into_iter().enumerate() {
+            if output.is_empty() {
+                continue;
+            }
+            for cmd in wire(output) {
+                senders[idx].send(cmd).await.ok();
+            }
+        }
+        Topology { machines, senders }
+    }
+}
+
+/// The Topology is the result of building, it holds the machines and their senders.
+pub struct Topology<T, M> {
+    machines: Vec<Arc<M>>,
+    senders: Vec<smol::channel::Sender<T>>,
+}
+
+impl<T, M> Topology<T, M> {
+    /// Get the machine of a node.
+    pub fn get_machine(&self, node: NodeId) -> &Arc<M> { &self.machines[node.0] }
+
+    /// Get the sender of a node.
+    pub fn get_sender(&self, node: NodeId) -> &smol::channel::Sender<T> { &self.senders[node.0] }
+
+    /// Get all of the machines, in the order the nodes were added.
+    pub fn get_machines(&self) -> &[Arc<M>] { &self.machines }
+
+    /// Get all of the senders, in the order the nodes were added.
+    pub fn get_senders(&self) -> &[smol::channel::Sender<T>] { &self.senders }
+
+    /// Take the machines and senders.
+    pub fn into_parts(self) -> (Vec<Arc<M>>, Vec<smol::channel::Sender<T>>) { (self.machines, self.senders) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction_set::{TestMessage, TestMessageSender};
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct Alice {
+        senders: Mutex<Vec<TestMessageSender>>,
+    }
+    impl Machine<TestMessage> for Alice {
+        fn receive(&self, cmd: TestMessage, _sender: &mut MachineSender) {
+            match cmd {
+                TestMessage::AddSender(sender) => self.senders.lock().push(sender),
+                TestMessage::AddSenders(senders) => self.senders.lock().extend(senders),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn chain_and_complete() {
+        let mut builder = TopologyBuilder::default().with_unbounded();
+        let chain: Vec<NodeId> = (0 .. 3).map(|_| builder.add_node(Alice::default())).collect();
+        let complete: Vec<NodeId> = (0 .. 2).map(|_| builder.add_node(Alice::default())).collect();
+        builder.add_chain(&chain);
+        builder.add_complete(&complete);
+        let topology = smol::block_on(builder.build(|senders| vec![TestMessage::AddSenders(senders)]));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let counts: Vec<usize> = topology.get_machines().iter().map(|alice| alice.senders.lock().len()).collect();
+        assert_eq!(vec![1, 1, 0, 2, 2], counts);
+    }
+}
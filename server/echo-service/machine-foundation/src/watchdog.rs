@@ -0,0 +1,165 @@
+use server_core::{get_receives_in_progress, set_watch_receives, MachineContext};
+use std::{collections::HashSet, fmt, sync::mpsc, thread, time::Duration};
+use uuid::Uuid;
+
+/// A StalledReceive is a machine which has been receiving an instruction for longer than the
+/// threshold of the Watchdog.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StalledReceive {
+    /// The machine, and the instruction it is receiving.
+    pub machine: MachineContext,
+    /// How long it has been receiving the instruction, when flagged.
+    pub elapsed: Duration,
+}
+
+type StallAction = Box<dyn Fn(&StalledReceive) + Send>;
+
+/// The Watchdog flags any machine whose receive() has been running for longer than a threshold,
+/// such as a forwarder deadlocked on a Mutex, logging the machine's id, and the instruction it is
+/// receiving. It runs on a thread of its own, rather than on an executor, so that it still runs
+/// when every executor is stuck. A stalled receive is flagged once, and the receives are only
+/// watched while a watchdog is running.
+///
+/// Examples:
+///
+/// ```rust
+/// use machine_foundation::*;
+///
+/// let watchdog = Watchdog::new(std::time::Duration::from_secs(1))
+///     .with_action(|stalled| eprintln!("{:?} is stuck", stalled.machine))
+///     .start();
+/// // run the server, then stop the watchdog, by dropping it
+/// drop(watchdog);
+/// ```
+pub struct Watchdog {
+    threshold: Duration,
+    action: Option<StallAction>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+    watched: bool,
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#Watchdog {{ threshold: {:#?}, running: {} }}",
+            self.threshold,
+            self.thread.is_some()
+        )
+    }
+}
+
+impl Watchdog {
+    /// Create a watchdog, flagging a receive which has been running for longer than the threshold.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            action: None,
+            stop: None,
+            thread: None,
+            watched: false,
+        }
+    }
+
+    /// Call the action for each receive flagged, as well as logging it.
+    pub fn with_action<F>(mut self, action: F) -> Self
+    where
+        F: Fn(&StalledReceive) + Send + 'static,
+    {
+        self.action = Some(Box::new(action));
+        self
+    }
+
+    /// Start the watchdog, which runs until stopped, or dropped.
+    pub fn start(mut self) -> Self {
+        if self.thread.is_some() {
+            return self;
+        }
+        self.watched = set_watch_receives(true);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let threshold = self.threshold;
+        let action = self.action.take();
+        let thread = thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || {
+                // check a few times per threshold, so a stall is flagged soon after it passes
+                let interval = (threshold / 4).max(Duration::from_millis(10));
+                let mut flagged = HashSet::<(Uuid, &'static str)>::new();
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let receives = get_receives_in_progress();
+                    flagged.retain(|key| receives.iter().any(|(machine, _)| *key == (machine.id, machine.instruction)));
+                    for (machine, elapsed) in receives {
+                        if elapsed < threshold || !flagged.insert((machine.id, machine.instruction)) {
+                            continue;
+                        }
+                        log::warn!(
+                            "stalled receive machine={} instruction_set={} instruction={} elapsed={:?}",
+                            machine.id,
+                            machine.instruction_set,
+                            machine.instruction,
+                            elapsed
+                        );
+                        if let Some(action) = &action {
+                            action(&StalledReceive { machine, elapsed });
+                        }
+                    }
+                }
+            })
+            .expect("cannot spawn watchdog thread");
+        self.stop = Some(stop);
+        self.thread = Some(thread);
+        self
+    }
+
+    /// Stop the watchdog, waiting for its thread to exit.
+    pub fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.take();
+            thread.join().ok();
+            set_watch_receives(self.watched);
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) { self.stop(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{machine, Machine, MachineSender};
+    use instruction_set::TestMessage;
+    use std::sync::{Arc, Mutex};
+
+    // A forwarder, which deadlocks on a Mutex held by the test.
+    struct Forwarder {
+        lock: Arc<Mutex<()>>,
+    }
+    impl Machine<TestMessage> for Forwarder {
+        fn receive(&self, _cmd: TestMessage, _sender: &mut MachineSender) { drop(self.lock.lock()); }
+    }
+
+    #[test]
+    fn flag_stalled_receive() {
+        let (flagged, stalls) = smol::channel::unbounded();
+        let watchdog = Watchdog::new(Duration::from_millis(50))
+            .with_action(move |stalled| {
+                flagged.try_send(*stalled).ok();
+            })
+            .start();
+        let lock = Arc::new(Mutex::new(()));
+        let held = lock.lock().unwrap();
+        let (_forwarder, sender) = machine::create(Forwarder { lock: lock.clone() });
+        sender.try_send(TestMessage::Test).ok();
+        let stalled = smol::block_on(stalls.recv()).unwrap();
+        assert!(stalled.elapsed >= Duration::from_millis(50));
+        assert!(stalled.machine.instruction_set.ends_with("TestMessage"));
+        assert_eq!("Test", stalled.machine.instruction);
+        drop(held);
+        drop(watchdog);
+        // once released, the stall isn't flagged again
+        assert!(stalls.try_recv().is_err());
+    }
+}
@@ -2,7 +2,7 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
 use syn::parse_macro_input;
-use syn::DeriveInput;
+use syn::{Attribute, Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
 
 /// # MachineImpl
 ///
@@ -30,14 +30,22 @@ use syn::DeriveInput;
 /// impl MachineImpl for Example {
 ///     type Adapter = Example;
 ///     type InstructionSet = Example;
+///     fn variant_name(&self) -> &'static str {
+///         match self { Example::Red { .. } => "Red", Example::Green { .. } => "Green", Example::Yellow { .. } => "Yellow" }
+///     }
+///     fn variant_count() -> usize { 3 }
 /// }
 /// ```
-/// Finally, it will provide an implementation for the MachineBuilder trait:
+/// It will provide an implementation for the MachineBuilder trait:
 /// ```ignore, rust
 /// impl MachineBuilder for Example {
 ///     type InstructionSet = Example;          
 /// }
 /// ```
+/// Finally, it registers the instruction set as the binary loads, for get_instruction_sets():
+/// ```ignore, rust
+/// server_core::inventory::submit! { server_core::InstructionSetRegistration::new::<Example>() }
+/// ```
 /// This all leads to building a machine that implements the instruction set.
 /// ```
 /// # use machine_impl::*;
@@ -53,15 +61,80 @@ use syn::DeriveInput;
 /// impl Machine<Example> for Alice {
 ///     fn receive(&self, cmd: Example, sender: &mut MachineSender) {}
 /// }
+/// // the instruction set is registered as the binary loads
+/// let example = get_instruction_sets().into_iter().find(|info| info.name.ends_with("Example")).unwrap();
+/// assert_eq!(3, example.variants);
 /// let (alice, sender) = machine::create(Alice {});
 /// ::smol::block_on(async {sender.send(Example::Red).await.ok()});
 /// ```
-#[proc_macro_derive(MachineImpl)]
+///
+/// # Options
+///
+/// Adding `#[machine_impl(describe)]` to the enum emits a machine-readable description of the
+/// instruction set as JSON. The description contains the variants, their field types and the doc
+/// comments, and is built when the enum is compiled. It can be served by tooling as-is:
+/// ```
+/// use machine_impl::*;
+///
+/// #[derive(Clone, MachineImpl)]
+/// #[machine_impl(describe)]
+/// pub enum Example {
+///     /// Stop and wait
+///     Red,
+///     Blink(u32),
+/// }
+/// assert!(Example::INSTRUCTION_SET_DESCRIPTION.starts_with(r#"{"name":"Example""#));
+/// ```
+///
+/// Adding `#[machine_impl(request(response = "T", error = "E"))]` to a variant makes it a request,
+/// whose last field is the sender of its reply, a `Sender<Result<T, E>>`. A constructor, named for
+/// the variant in snake case, is generated, taking the fields and the reply sender, along with an
+/// ask_ function, which sends the request with `machine_foundation::ask::ask` and waits for its
+/// reply. It returns None if the machine is gone, or drops the reply sender without replying:
+/// ```
+/// use machine_impl::*;
+/// use machine_foundation::*;
+///
+/// #[derive(Debug, Clone, PartialEq)]
+/// pub enum AccountError {
+///     Overdrawn,
+/// }
+///
+/// #[derive(Debug, Clone, MachineImpl)]
+/// pub enum AccountCmd {
+///     #[machine_impl(request(response = "u64", error = "AccountError"))]
+///     Withdraw(u64, smol::channel::Sender<Result<u64, AccountError>>),
+/// }
+///
+/// struct Account;
+/// impl Machine<AccountCmd> for Account {
+///     fn receive(&self, cmd: AccountCmd, _sender: &mut MachineSender) {
+///         let AccountCmd::Withdraw(amount, reply) = cmd;
+///         reply.try_send(100u64.checked_sub(amount).ok_or(AccountError::Overdrawn)).ok();
+///     }
+/// }
+///
+/// let (_account, sender) = machine::create(Account);
+/// assert_eq!(Some(Ok(90)), smol::block_on(AccountCmd::ask_withdraw(&sender, 10)));
+/// assert_eq!(Some(Err(AccountError::Overdrawn)), smol::block_on(AccountCmd::ask_withdraw(&sender, 200)));
+/// let (reply, _replies) = smol::channel::bounded(1);
+/// assert!(matches!(AccountCmd::withdraw(10, reply), AccountCmd::Withdraw(10, _)));
+/// ```
+#[proc_macro_derive(MachineImpl, attributes(machine_impl))]
 pub fn derive_machine_impl_fn(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    let options = match MachineImplOptions::parse(&input.attrs) {
+        Ok(options) => options,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
     let name = &input.ident;
     let sender_ident = format_ident!("{}Sender", name);
     let receiver_ident = format_ident!("{}Receiver", name);
+    let variant_name = variant_name_body(&input);
+    let variant_count = match &input.data {
+        Data::Enum(data) => data.variants.len(),
+        _ => 1,
+    };
     let expanded = quote! {
         #[automatically_derived]
         #[allow(unused_qualifications)]
@@ -72,6 +145,8 @@ pub fn derive_machine_impl_fn(input: TokenStream) -> TokenStream {
         impl server_core::MachineImpl for #name {
             type Adapter = #name;
             type InstructionSet = #name;
+            fn variant_name(&self) -> &'static str { #variant_name }
+            fn variant_count() -> usize { #variant_count }
         }
 
         #[automatically_derived]
@@ -79,6 +154,284 @@ pub fn derive_machine_impl_fn(input: TokenStream) -> TokenStream {
         impl server_core::MachineBuilder for #name {
             type InstructionSet = #name;
         }
+
+        server_core::inventory::submit! {
+            server_core::InstructionSetRegistration::new::<#name>()
+        }
     };
-    TokenStream::from(expanded)
+    let description = if options.describe {
+        match describe_instruction_set(&input) {
+            Ok(json) => quote! {
+                #[automatically_derived]
+                impl #name {
+                    /// A machine-readable (JSON) description of the instruction set.
+                    pub const INSTRUCTION_SET_DESCRIPTION: &'static str = #json;
+                }
+            },
+            Err(err) => err.to_compile_error(),
+        }
+    } else {
+        quote! {}
+    };
+    let requests = request_helpers(&input).unwrap_or_else(|err| err.to_compile_error());
+    TokenStream::from(quote! { #expanded #description #requests })
+}
+
+// Build the constructor, and the ask_ function, of each variant which is a request.
+fn request_helpers(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => return Ok(quote! {}),
+    };
+    let name = &input.ident;
+    let vis = &input.vis;
+    let mut helpers = Vec::new();
+    for variant in data.variants.iter() {
+        let (response, error) = match parse_request(&variant.attrs)? {
+            Some(request) => request,
+            None => continue,
+        };
+        let ident = &variant.ident;
+        let fields: Vec<&syn::Field> = variant.fields.iter().collect();
+        let (reply_field, fields) = match fields.split_last() {
+            Some(split) => split,
+            None => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "a request requires its reply sender as its last field",
+                ))
+            },
+        };
+        let args: Vec<syn::Ident> = fields
+            .iter()
+            .enumerate()
+            .map(|(n, field)| field.ident.clone().unwrap_or_else(|| format_ident!("arg{}", n)))
+            .collect();
+        let types = fields.iter().map(|field| &field.ty);
+        // each parameter has its own trailing comma, so a request with only a reply sender works
+        let params = quote! { #(#args: #types,)* };
+        let construct = match &reply_field.ident {
+            Some(reply_ident) => quote! { Self::#ident { #(#args,)* #reply_ident: reply } },
+            None => quote! { Self::#ident(#(#args,)* reply) },
+        };
+        let constructor = format_ident!("{}", snake_case(&ident.to_string()));
+        let ask = format_ident!("ask_{}", constructor);
+        let constructor_doc = format!("Create a {} request, with the sender of its reply.", ident);
+        let ask_doc = format!(
+            "Send a {} request, and wait for its reply, None if the machine is gone, or drops the reply sender.",
+            ident
+        );
+        helpers.push(quote! {
+            #[doc = #constructor_doc]
+            #vis fn #constructor(#params reply: ::smol::channel::Sender<Result<#response, #error>>) -> Self { #construct }
+
+            #[doc = #ask_doc]
+            #vis async fn #ask(sender: &::smol::channel::Sender<#name>, #params) -> Option<Result<#response, #error>> {
+                machine_foundation::ask::ask(sender, |reply| #construct).await
+            }
+        });
+    }
+    if helpers.is_empty() {
+        return Ok(quote! {});
+    }
+    Ok(quote! {
+        #[automatically_derived]
+        #[allow(unused_qualifications, clippy::too_many_arguments)]
+        impl #name {
+            #(#helpers)*
+        }
+    })
+}
+
+// Parse the response, and error, types of a variant which is a request, from
+// #[machine_impl(request(response = "T", error = "E"))].
+fn parse_request(attrs: &[Attribute]) -> syn::Result<Option<(syn::Type, syn::Type)>> {
+    let attr = match attrs.iter().find(|attr| attr.path.is_ident("machine_impl")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let request = match attr.parse_meta()? {
+        Meta::List(list) if list.nested.len() == 1 => match list.nested.first() {
+            Some(NestedMeta::Meta(Meta::List(request))) if request.path.is_ident("request") => request.clone(),
+            nested => return Err(syn::Error::new_spanned(nested, "unknown machine_impl variant option")),
+        },
+        meta => return Err(syn::Error::new_spanned(meta, "expected #[machine_impl(request(...))]")),
+    };
+    let (mut response, mut error) = (None, None);
+    for nested in request.nested.iter() {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(meta)) => {
+                let ty = match &meta.lit {
+                    Lit::Str(ty) => ty.parse::<syn::Type>()?,
+                    lit => return Err(syn::Error::new_spanned(lit, "expected a type, as a string")),
+                };
+                if meta.path.is_ident("response") {
+                    response = Some(ty);
+                } else if meta.path.is_ident("error") {
+                    error = Some(ty);
+                } else {
+                    return Err(syn::Error::new_spanned(&meta.path, "expected response, or error"));
+                }
+            },
+            _ => return Err(syn::Error::new_spanned(nested, "expected response = \"T\", or error = \"E\"")),
+        }
+    }
+    match (response, error) {
+        (Some(response), Some(error)) => Ok(Some((response, error))),
+        _ => Err(syn::Error::new_spanned(
+            request,
+            "a request requires both response, and error, types",
+        )),
+    }
+}
+
+// Convert the name of a variant, in camel case, to snake case.
+fn snake_case(name: &str) -> String {
+    let mut res = String::with_capacity(name.len() + 4);
+    for (n, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if n > 0 {
+                res.push('_');
+            }
+            res.extend(c.to_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+// Build the body of variant_name(), matching each variant to its name.
+fn variant_name_body(input: &DeriveInput) -> proc_macro2::TokenStream {
+    match &input.data {
+        Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let ident = &variant.ident;
+                let name = ident.to_string();
+                quote! { Self::#ident { .. } => #name, }
+            });
+            quote! { match self { #(#arms)* } }
+        },
+        _ => {
+            let name = input.ident.to_string();
+            quote! { #name }
+        },
+    }
+}
+
+// The options which can be provided via #[machine_impl(...)].
+#[derive(Default)]
+struct MachineImplOptions {
+    describe: bool,
+}
+
+impl MachineImplOptions {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("machine_impl")) {
+            match attr.parse_meta()? {
+                Meta::List(list) => {
+                    for nested in list.nested.iter() {
+                        match nested {
+                            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("describe") => options.describe = true,
+                            _ => return Err(syn::Error::new_spanned(nested, "unknown machine_impl option")),
+                        }
+                    }
+                },
+                meta => return Err(syn::Error::new_spanned(meta, "expected #[machine_impl(...)]")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// Build the JSON description of an instruction set.
+fn describe_instruction_set(input: &DeriveInput) -> syn::Result<String> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => return Err(syn::Error::new_spanned(&input.ident, "machine_impl(describe) requires an enum")),
+    };
+    let mut variants: Vec<String> = Vec::new();
+    for variant in data.variants.iter() {
+        let (kind, fields) = match &variant.fields {
+            Fields::Unit => ("unit", Vec::new()),
+            Fields::Unnamed(fields) => ("tuple", fields.unnamed.iter().map(describe_field).collect()),
+            Fields::Named(fields) => ("struct", fields.named.iter().map(describe_field).collect()),
+        };
+        variants.push(format!(
+            r#"{{"name":{},"doc":{},"kind":"{}","fields":[{}]}}"#,
+            json_string(&variant.ident.to_string()),
+            json_string(&doc_comment(&variant.attrs)),
+            kind,
+            fields.join(",")
+        ));
+    }
+    Ok(format!(
+        r#"{{"name":{},"doc":{},"variants":[{}]}}"#,
+        json_string(&input.ident.to_string()),
+        json_string(&doc_comment(&input.attrs)),
+        variants.join(",")
+    ))
+}
+
+// Describe a single variant field, named or unnamed.
+fn describe_field(field: &syn::Field) -> String {
+    let ty = &field.ty;
+    let ty = quote!(#ty)
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" < ", "<")
+        .replace(" <", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+        .replace("& ", "&");
+    match &field.ident {
+        Some(ident) => format!(
+            r#"{{"name":{},"type":{},"doc":{}}}"#,
+            json_string(&ident.to_string()),
+            json_string(&ty),
+            json_string(&doc_comment(&field.attrs))
+        ),
+        None => format!(
+            r#"{{"type":{},"doc":{}}}"#,
+            json_string(&ty),
+            json_string(&doc_comment(&field.attrs))
+        ),
+    }
+}
+
+// Collect the doc comments, one line per #[doc = "..."] attribute.
+fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(meta)) => match meta.lit {
+                Lit::Str(doc) => Some(doc.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Quote and escape a string as a JSON string.
+fn json_string(value: &str) -> String {
+    let mut res = String::with_capacity(value.len() + 2);
+    res.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
 }
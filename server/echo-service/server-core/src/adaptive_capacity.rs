@@ -0,0 +1,262 @@
+use super::*;
+use std::collections::VecDeque;
+
+// The number of instructions received between decisions to grow, or shrink, the capacity.
+const WINDOW: usize = 64;
+
+#[allow(non_upper_case_globals)]
+// The capacity a bounded machine's queue may grow to, 0 if it is fixed.
+static adaptive_channel_max: AtomicCell<usize> = AtomicCell::new(0);
+
+/// Set the capacity a bounded machine's queue may grow to, returning the previous value. If 0, the
+/// default, the capacity of a machine is fixed, as it was created. Otherwise, the capacity adapts
+/// between the capacity created with, and this, for machines created from then on. It doubles when
+/// the queue is found to be full for most of the instructions received, and halves when it is found
+/// empty for all of them. Each decision is logged. Sizing the queues of a large service can then be
+/// left to the framework, rather than tuned, such as via set_default_channel_max(). A machine
+/// created with MachineBuilder::bounded_adaptive() has its own limit, rather than this.
+pub fn set_adaptive_channel_max(capacity: usize) -> usize { adaptive_channel_max.swap(capacity) }
+/// Get the capacity a bounded machine's queue may grow to, 0 if it is fixed.
+pub fn get_adaptive_channel_max() -> usize { adaptive_channel_max.load() }
+
+// The QueueStats of an adapter's queue, shared with the registry.
+#[derive(Debug, Default)]
+pub(crate) struct QueueStats {
+    // The number of instructions staged by the adapter, having been taken from the channel.
+    pub(crate) staged: AtomicUsize,
+    // The capacity of the queue, the channel's capacity plus the instructions which may be staged.
+    pub(crate) capacity: AtomicCell<Option<usize>>,
+}
+
+// The AdaptiveQueue is the queue of an adapter. As a channel's capacity is fixed once created, the
+// capacity is grown by taking instructions from the channel, while it is full, and staging them,
+// up to an allowance, which grows and shrinks. The instructions staged are received first, so
+// their order is kept.
+pub(crate) struct AdaptiveQueue<T> {
+    receiver: smol::channel::Receiver<T>,
+    staged: VecDeque<T>,
+    stats: Arc<QueueStats>,
+    context: MachineContext,
+    // the number of instructions which may be staged, and its limit
+    allowance: usize,
+    max_allowance: usize,
+    // the instructions received in the window, and of those, when the queue was full, or empty
+    received: usize,
+    full: usize,
+    empty: usize,
+}
+
+impl<T: MachineImpl> AdaptiveQueue<T> {
+    // Create the queue, whose capacity may grow to adaptive_max, if bounded, and fixed if 0.
+    pub(crate) fn new(receiver: smol::channel::Receiver<T>, context: MachineContext, adaptive_max: usize) -> Self {
+        let capacity = receiver.capacity();
+        let max_allowance = capacity.map_or(0, |capacity| adaptive_max.saturating_sub(capacity));
+        let stats = Arc::new(QueueStats::default());
+        stats.capacity.store(capacity);
+        Self {
+            receiver,
+            staged: VecDeque::new(),
+            stats,
+            context,
+            allowance: 0,
+            max_allowance,
+            received: 0,
+            full: 0,
+            empty: 0,
+        }
+    }
+
+    pub(crate) fn get_stats(&self) -> Arc<QueueStats> { self.stats.clone() }
+
+    // Check if there are no instructions waiting, staged or in the channel.
+    pub(crate) fn is_empty(&self) -> bool { self.staged.is_empty() && self.receiver.is_empty() }
+
+    // Receive the next instruction, None once the channel is closed, and drained.
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        if self.max_allowance > 0 {
+            self.adapt();
+            while self.staged.len() < self.allowance {
+                match self.receiver.try_recv() {
+                    Ok(cmd) => self.staged.push_back(cmd),
+                    Err(_) => break,
+                }
+            }
+        }
+        match self.staged.pop_front() {
+            Some(cmd) => {
+                self.stats.staged.store(self.staged.len(), Ordering::SeqCst);
+                Some(cmd)
+            },
+            None => self.receiver.recv().await.ok(),
+        }
+    }
+
+    // Sample the queue, and once the window is complete, grow, or shrink, the allowance.
+    fn adapt(&mut self) {
+        self.received += 1;
+        if self.receiver.is_full() {
+            self.full += 1;
+        } else if self.receiver.is_empty() && self.staged.is_empty() {
+            self.empty += 1;
+        }
+        if self.received < WINDOW {
+            return;
+        }
+        let capacity = self.receiver.capacity().unwrap_or_default();
+        let allowance = if self.full * 2 > WINDOW {
+            (self.allowance * 2).max(capacity).min(self.max_allowance)
+        } else if self.empty == WINDOW {
+            self.allowance / 2
+        } else {
+            self.allowance
+        };
+        if allowance != self.allowance {
+            log::info!(
+                "channel capacity {} machine={} instruction_set={} from={} to={}",
+                if allowance > self.allowance { "grown" } else { "shrunk" },
+                self.context.id,
+                self.context.instruction_set,
+                capacity + self.allowance,
+                capacity + allowance
+            );
+            self.allowance = allowance;
+            self.stats.capacity.store(Some(capacity + allowance));
+        }
+        self.received = 0;
+        self.full = 0;
+        self.empty = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_adpter::MachineAdapter;
+
+    #[derive(Debug, Clone)]
+    enum Load {
+        Work(Duration),
+        Ping(smol::channel::Sender<()>),
+    }
+    impl MachineImpl for Load {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+    impl MachineBuilder for Load {
+        type InstructionSet = Self;
+    }
+
+    struct Worker;
+    impl Machine<Load> for Worker {
+        fn receive(&self, cmd: Load, _sender: &mut MachineSender) {
+            match cmd {
+                Load::Work(duration) => thread::sleep(duration),
+                Load::Ping(reply) => {
+                    reply.try_send(()).ok();
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        // the limit is the machine's, rather than set for all, so other tests' machines are fixed
+        let (_worker, sender, adapter) = Load::bounded_adaptive(Worker, 4, 64);
+        let capacity = || {
+            get_machines()
+                .into_iter()
+                .find(|info| info.id == adapter.get_id())
+                .and_then(|info| info.capacity)
+        };
+        assert_eq!(Some(4), capacity());
+        // saturate it, until it has grown to the limit
+        smol::block_on(async {
+            for _ in 0 .. 1000 {
+                sender.send(Load::Work(Duration::from_micros(100))).await.ok();
+                if capacity() == Some(64) {
+                    break;
+                }
+            }
+        });
+        assert_eq!(Some(64), capacity());
+        // idle it, until it has shrunk back
+        let (reply, replies) = smol::channel::unbounded();
+        smol::block_on(async {
+            for _ in 0 .. 1000 {
+                sender.send(Load::Ping(reply.clone())).await.ok();
+                replies.recv().await.ok();
+                if capacity() == Some(4) {
+                    break;
+                }
+            }
+        });
+        assert_eq!(Some(4), capacity());
+    }
+
+    // A machine which counts the instructions it receives in a row, without another receiving.
+    struct Turns {
+        id: usize,
+        last: Arc<AtomicUsize>,
+        run: AtomicUsize,
+        max_run: AtomicUsize,
+        received: AtomicUsize,
+    }
+    impl Machine<Load> for Turns {
+        fn receive(&self, cmd: Load, _sender: &mut MachineSender) {
+            let run = if self.last.swap(self.id, Ordering::SeqCst) == self.id {
+                self.run.fetch_add(1, Ordering::SeqCst) + 1
+            } else {
+                self.run.store(1, Ordering::SeqCst);
+                1
+            };
+            self.max_run.fetch_max(run, Ordering::SeqCst);
+            self.received.fetch_add(1, Ordering::SeqCst);
+            if let Load::Work(duration) = cmd {
+                thread::sleep(duration);
+            }
+        }
+    }
+
+    #[test]
+    fn yield_with_staged_backlog() {
+        // a single executor, so the turns of the machines are taken one after another
+        let group = ExecutorGroup::new("staged", 1);
+        let last = Arc::new(AtomicUsize::new(0));
+        let turns = |id| Turns {
+            id,
+            last: last.clone(),
+            run: AtomicUsize::new(0),
+            max_run: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+        };
+        let (sender, receiver) = smol::channel::bounded(4);
+        let staged = Arc::new(turns(1));
+        let adapter = MachineAdapter::new(staged.clone(), group.get_executor(), receiver)
+            .with_adaptive_max(64)
+            .start();
+        // the other machine always has instructions waiting, so is ready for a turn
+        let (busy_sender, busy_receiver) = smol::channel::unbounded();
+        for _ in 0 .. 20_000 {
+            busy_sender.try_send(Load::Work(Duration::from_micros(10))).ok();
+        }
+        let busy = Arc::new(turns(2));
+        let _busy = MachineAdapter::new(busy.clone(), group.get_executor(), busy_receiver).start();
+        // send faster than received, so the capacity grows, then stop, leaving a staged backlog,
+        // while the channel is empty
+        for _ in 0 .. 400 {
+            smol::block_on(sender.send(Load::Work(Duration::from_micros(100)))).ok();
+        }
+        let capacity = get_machines()
+            .into_iter()
+            .find(|info| info.id == adapter.get_id())
+            .and_then(|info| info.capacity);
+        assert!(capacity > Some(4), "capacity={:?}", capacity);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while staged.received.load(Ordering::SeqCst) < 400 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(400, staged.received.load(Ordering::SeqCst));
+        // the quota of the default weight
+        assert!(staged.max_run.load(Ordering::SeqCst) <= 16, "max_run={:?}", staged.max_run);
+    }
+}
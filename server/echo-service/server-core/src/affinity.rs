@@ -0,0 +1,84 @@
+use super::*;
+use std::io;
+
+#[allow(non_upper_case_globals)]
+// If true, each executor thread is pinned to a CPU core.
+static executor_affinity: AtomicCell<bool> = AtomicCell::new(false);
+
+/// Set whether each executor thread of the pool is pinned to a CPU core, returning the previous
+/// value. Pinning keeps a thread from migrating between cores, and losing its cache. The nth
+/// executor thread is pinned to the nth of the cores the process may run on, wrapping around
+/// if there are more threads than cores. Like set_default_num_threads(), it must be set before
+/// the executors are first used. Pinning is only supported on Linux, elsewhere a warning is logged.
+pub fn set_executor_affinity(enabled: bool) -> bool { executor_affinity.swap(enabled) }
+/// Get whether each executor thread of the pool is pinned to a CPU core.
+pub fn get_executor_affinity() -> bool { executor_affinity.load() }
+
+// Pin the current thread, which runs the nth executor of the pool, to a core, if pinning is on.
+pub(crate) fn pin_executor_thread(idx: usize) {
+    if get_executor_affinity() {
+        match get_cores().and_then(|cores| pin_to_core(cores[idx % cores.len()])) {
+            Ok(core) => log::debug!("executor-{} thread pinned to core={}", idx + 1, core),
+            Err(err) => log::warn!("executor-{} thread couldn't be pinned, error={}", idx + 1, err),
+        }
+    }
+}
+
+// Get the cores the process may run on.
+#[cfg(target_os = "linux")]
+fn get_cores() -> io::Result<Vec<usize>> {
+    let set = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        set
+    };
+    let cores: Vec<usize> = (0 .. libc::CPU_SETSIZE as usize)
+        .filter(|core| unsafe { libc::CPU_ISSET(*core, &set) })
+        .collect();
+    match cores.is_empty() {
+        true => Err(io::Error::new(io::ErrorKind::NotFound, "no cores")),
+        false => Ok(cores),
+    }
+}
+
+// Pin the current thread to the core, returning the core.
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) -> io::Result<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(core)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_cores() -> io::Result<Vec<usize>> { Err(io::Error::new(io::ErrorKind::Other, "cpu affinity is unsupported")) }
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core: usize) -> io::Result<usize> { Err(io::Error::new(io::ErrorKind::Other, "cpu affinity is unsupported")) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pin_thread() {
+        let cores = get_cores().unwrap();
+        let last = *cores.last().unwrap();
+        // pin a thread of its own, rather than the test's
+        let pinned = thread::spawn(move || {
+            pin_to_core(last).unwrap();
+            get_cores().unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(vec![last], pinned);
+        assert!(!get_executor_affinity());
+    }
+}
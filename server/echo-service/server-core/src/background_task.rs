@@ -2,6 +2,54 @@ use super::*;
 use smart_default::*;
 
 use futures::{future::FutureExt, pin_mut, select};
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicU64, Mutex},
+};
+
+/// BackgroundTaskInfo describes a background task which is still running, see list_background_tasks().
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BackgroundTaskInfo {
+    /// The id of the task, which increases in the order tasks are created.
+    pub id: u64,
+    /// The logging label of the task.
+    pub label: String,
+    /// When the task was created.
+    pub created: Instant,
+    /// The index of the executor running the task, None if it isn't one of the pool.
+    pub executor: Option<usize>,
+}
+
+static TASKS: Lazy<Mutex<BTreeMap<u64, BackgroundTaskInfo>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+fn lock_tasks() -> std::sync::MutexGuard<'static, BTreeMap<u64, BackgroundTaskInfo>> { TASKS.lock().unwrap_or_else(|err| err.into_inner()) }
+
+// The guard is owned by the task wrapping a background task, the task is listed until the wrapper
+// completes, or is dropped, such as when its executor is stopped.
+struct TaskRegistration(u64);
+
+impl TaskRegistration {
+    fn new(label: &str, executor: &Arc<InstrumentedExecutor>) -> Self {
+        let id = NEXT_TASK_ID.fetch_add(1, Ordering::SeqCst);
+        let info = BackgroundTaskInfo {
+            id,
+            label: label.to_string(),
+            created: Instant::now(),
+            executor: EXECUTOR.get_executors().iter().position(|e| Arc::ptr_eq(e, executor)),
+        };
+        lock_tasks().insert(id, info);
+        Self(id)
+    }
+}
+
+impl Drop for TaskRegistration {
+    fn drop(&mut self) { lock_tasks().remove(&self.0); }
+}
+
+/// Get the background tasks which are still running, oldest first, such as to report what's
+/// keeping a drain from completing.
+pub fn list_background_tasks() -> Vec<BackgroundTaskInfo> { lock_tasks().values().cloned().collect() }
 
 /// BackgroundTask is a task wrapper allowing a task to run detached, while also allowing it to be cancelled.
 ///
@@ -51,9 +99,11 @@ impl BackgroundTask {
         let executor = get_executor();
         let t1 = executor.spawn(async move { receiver.recv().await.unwrap_or(()) }).fuse();
         let t2 = task.fuse();
+        let registration = TaskRegistration::new(label, &executor);
         let label = label.to_string();
         executor
             .spawn(async move {
+                let _registration = registration;
                 pin_mut!(t1, t2);
                 select! {
                     () = t1 => log::trace!("{} closed", label), // completes when sender is closed
@@ -65,13 +115,245 @@ impl BackgroundTask {
         Self { sender }
     }
 
+    /// Detach the task, as detach() does, cancelling it once the timeout has passed, unless it has
+    /// completed, or been cancelled, by then. The label of an expired task is logged, so a
+    /// handshake, or a drain, which overruns is bounded, and reported.
+    pub fn detach_with_timeout<T: 'static + Send>(task: smol::Task<T>, label: &str, timeout: Duration) -> Self {
+        let (sender, receiver) = smol::channel::unbounded::<()>();
+        let executor = get_executor();
+        let registration = TaskRegistration::new(label, &executor);
+        let label = label.to_string();
+        executor
+            .spawn(async move {
+                let _registration = registration;
+                let completed = async {
+                    task.await;
+                    log::trace!("{} task completed", label);
+                };
+                let cancelled = async {
+                    receiver.recv().await.ok();
+                    log::trace!("{} closed", label);
+                };
+                let expired = async {
+                    smol::Timer::after(timeout).await;
+                    log::warn!("{} expired after {:#?}", label, timeout);
+                };
+                smol::future::or(completed, smol::future::or(cancelled, expired)).await;
+                log::debug!("{} completed", label);
+            })
+            .detach();
+        Self { sender }
+    }
+
+    /// Spawn the task, detached, as detach() does, and provide a logging label. The handle returned
+    /// can cancel it, tell if it has finished, and join it, for its output. Dropping the handle
+    /// cancels the task.
+    pub fn spawn<T: 'static + Send>(task: smol::Task<T>, label: &str) -> BackgroundHandle<T> { Self::spawn_tracked(task, label).0 }
+
+    // Spawn the task, as spawn() does, also returning a receiver, which is closed once it finishes.
+    fn spawn_tracked<T: 'static + Send>(task: smol::Task<T>, label: &str) -> (BackgroundHandle<T>, smol::channel::Receiver<()>) {
+        let (sender, receiver) = smol::channel::unbounded::<()>();
+        let (finished, finished_receiver) = smol::channel::unbounded::<()>();
+        let executor = get_executor();
+        let registration = TaskRegistration::new(label, &executor);
+        let label = label.to_string();
+        let task = executor.spawn(async move {
+            let _registration = registration;
+            let mut task = task;
+            let cancelled = async {
+                receiver.recv().await.ok();
+                None
+            };
+            let output = smol::future::or(cancelled, async { Some((&mut task).await) }).await;
+            if output.is_none() {
+                // wait for the task to be dropped, so that it is gone once joined
+                task.cancel().await;
+                log::trace!("{} closed", label);
+            }
+            log::debug!("{} completed", label);
+            drop(finished);
+            output
+        });
+        (BackgroundHandle { sender, task: Some(task) }, finished_receiver)
+    }
+
     /// Cancel the detached task.
     pub fn cancel(&self) { self.sender.close(); }
 }
 
+/// The BackgroundHandle of a task spawned with BackgroundTask::spawn().
+#[derive(Debug)]
+pub struct BackgroundHandle<T> {
+    sender: smol::channel::Sender<()>,
+    task: Option<smol::Task<Option<T>>>,
+}
+
+impl<T> BackgroundHandle<T> {
+    /// Cancel the task.
+    pub fn cancel(&self) { self.sender.close(); }
+
+    /// Check if the task has finished, having completed, or been cancelled.
+    pub fn is_finished(&self) -> bool { self.task.as_ref().is_none_or(|task| task.is_finished()) }
+
+    /// Wait for the task to finish, returning its output, or None if it was cancelled.
+    pub async fn join(mut self) -> Option<T> {
+        match self.task.take() {
+            Some(task) => task.await,
+            None => None,
+        }
+    }
+}
+
+impl<T> Drop for BackgroundHandle<T> {
+    // the task is cancelled by closing the sender, which is closed once dropped, unless shared
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.detach();
+        }
+    }
+}
+
+// A member of a BackgroundTaskGroup, the sender which cancels it, and the receiver which is closed
+// once it has finished.
+#[derive(Debug)]
+struct Member {
+    cancel: smol::channel::Sender<()>,
+    finished: smol::channel::Receiver<()>,
+}
+
+/// The BackgroundTaskGroup is a cancellation scope for many background tasks, such as the tasks of
+/// the listeners and connections of the network, so that they can be torn down together. The group
+/// shares the cancelling of each task it spawns with the task's handle, so a task runs until it, or
+/// the group, is cancelled, regardless of the handle being dropped. Dropping the group cancels its
+/// tasks, without waiting.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::{get_executor, BackgroundTaskGroup};
+///
+/// let group = BackgroundTaskGroup::new();
+/// for _ in 0 .. 3 {
+///     group.spawn(get_executor().spawn(smol::future::pending::<()>()), "pending");
+/// }
+/// assert_eq!(3, group.len());
+/// smol::block_on(group.shutdown());
+/// assert!(group.is_empty());
+/// ```
+#[derive(Debug, Default)]
+pub struct BackgroundTaskGroup {
+    members: Mutex<Vec<Member>>,
+}
+
+impl BackgroundTaskGroup {
+    /// Create an empty group.
+    pub fn new() -> Self { Self::default() }
+
+    /// Spawn the task in the group, as BackgroundTask::spawn() does, and provide a logging label.
+    pub fn spawn<T: 'static + Send>(&self, task: smol::Task<T>, label: &str) -> BackgroundHandle<T> {
+        let (handle, finished) = BackgroundTask::spawn_tracked(task, label);
+        let mut members = self.lock_members();
+        members.retain(|member| !member.finished.is_closed());
+        members.push(Member {
+            cancel: handle.sender.clone(),
+            finished,
+        });
+        handle
+    }
+
+    /// Get the number of tasks in the group which haven't finished.
+    pub fn len(&self) -> usize { self.lock_members().iter().filter(|member| !member.finished.is_closed()).count() }
+
+    /// Check if every task in the group has finished.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Cancel every task in the group.
+    pub fn cancel_all(&self) {
+        for member in self.lock_members().iter() {
+            member.cancel.close();
+        }
+    }
+
+    /// Wait for every task in the group to finish, having completed, or been cancelled. Tasks
+    /// spawned once the waiting has begun aren't waited upon.
+    pub async fn join_all(&self) {
+        let members: Vec<Member> = self.lock_members().drain(..).collect();
+        for member in members {
+            member.finished.recv().await.ok();
+        }
+    }
+
+    /// Cancel every task in the group, and wait for them to finish.
+    pub async fn shutdown(&self) {
+        self.cancel_all();
+        self.join_all().await;
+    }
+
+    fn lock_members(&self) -> std::sync::MutexGuard<'_, Vec<Member>> { self.members.lock().unwrap_or_else(|err| err.into_inner()) }
+}
+
+impl Drop for BackgroundTaskGroup {
+    fn drop(&mut self) { self.cancel_all(); }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn join_background_task() {
+        let task = BackgroundTask::spawn(get_executor().spawn(async { 7 }), "answer");
+        assert_eq!(Some(7), smol::block_on(task.join()));
+        // a cancelled task has no output
+        let task = BackgroundTask::spawn(get_executor().spawn(smol::future::pending::<usize>()), "pending");
+        assert!(!task.is_finished());
+        task.cancel();
+        assert_eq!(None, smol::block_on(task.join()));
+    }
+
+    #[test]
+    fn cancel_on_timeout() {
+        let (done, finished) = smol::channel::unbounded::<()>();
+        // once expired, the task is dropped, along with the sender it holds
+        let task = get_executor().spawn(async move {
+            smol::future::pending::<()>().await;
+            drop(done);
+        });
+        let _task = BackgroundTask::detach_with_timeout(task, "handshake", Duration::from_millis(20));
+        let start = Instant::now();
+        assert!(smol::block_on(finished.recv()).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn cancel_group() {
+        let group = BackgroundTaskGroup::new();
+        let handles: Vec<BackgroundHandle<()>> = (0 .. 3)
+            .map(|_| group.spawn(get_executor().spawn(smol::future::pending::<()>()), "pending"))
+            .collect();
+        let done = group.spawn(get_executor().spawn(async { 1 }), "done");
+        assert_eq!(Some(1), smol::block_on(done.join()));
+        // dropping a handle doesn't cancel the task, the group does
+        drop(handles);
+        assert_eq!(3, group.len());
+        group.cancel_all();
+        smol::block_on(group.join_all());
+        assert!(group.is_empty());
+    }
+
+    #[test]
+    fn list_running_tasks() {
+        let start = Instant::now();
+        let task = BackgroundTask::spawn(get_executor().spawn(smol::future::pending::<()>()), "introspected");
+        let listed = || list_background_tasks().into_iter().find(|info| info.label == "introspected");
+        let info = listed().unwrap();
+        assert!(info.created >= start);
+        assert!(info.executor.is_some());
+        task.cancel();
+        smol::block_on(task.join());
+        assert_eq!(None, listed());
+    }
+
     #[test]
     #[ignore]
     fn test_main() {
@@ -0,0 +1,139 @@
+use super::*;
+use crossbeam::channel;
+
+// A blocking job, run by a thread of the pool.
+type Job = Box<dyn FnOnce() + Send>;
+
+#[allow(non_upper_case_globals)]
+// The maximum number of threads in the blocking pool.
+static blocking_max_threads: AtomicCell<usize> = AtomicCell::new(64);
+
+// The time an idle thread of the blocking pool waits for a job, before exiting.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The blocking pool, threads are spawned as jobs need them, up to the maximum, and exit when idle.
+static BLOCKING_POOL: Lazy<BlockingPool> = Lazy::new(|| {
+    let (sender, receiver) = channel::unbounded();
+    BlockingPool {
+        sender,
+        receiver,
+        threads: AtomicUsize::new(0),
+        idle: AtomicUsize::new(0),
+        spawned: AtomicUsize::new(0),
+    }
+});
+
+/// Set the maximum number of threads in the blocking pool, returning the previous value. Once
+/// every thread is busy, further jobs wait for one of them. The default is 64.
+pub fn set_blocking_max_threads(max_threads: usize) -> usize { blocking_max_threads.swap(max_threads.max(1)) }
+/// Get the maximum number of threads in the blocking pool.
+pub fn get_blocking_max_threads() -> usize { blocking_max_threads.load() }
+
+/// Get the number of threads currently in the blocking pool.
+pub fn get_blocking_thread_count() -> usize { BLOCKING_POOL.threads.load(Ordering::SeqCst) }
+
+/// Run blocking work, such as file IO, DNS, or crypto, on the blocking pool, rather than stalling
+/// an executor thread, returning a task which completes with its result. A Machine::receive can
+/// spawn the task, sending the result to itself once it completes. Should the work panic, so does
+/// the task.
+///
+/// Examples:
+///
+/// ```rust
+/// let exists = smol::block_on(server_core::spawn_blocking(|| std::path::Path::new("Cargo.toml").exists()));
+/// assert!(exists);
+/// ```
+pub fn spawn_blocking<T, F>(f: F) -> smol::Task<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = smol::channel::bounded(1);
+    BLOCKING_POOL.submit(Box::new(move || {
+        sender.try_send(f()).ok();
+    }));
+    get_executor().spawn(async move { receiver.recv().await.expect("blocking work panicked") })
+}
+
+struct BlockingPool {
+    sender: channel::Sender<Job>,
+    receiver: channel::Receiver<Job>,
+    threads: AtomicUsize,
+    idle: AtomicUsize,
+    spawned: AtomicUsize,
+}
+
+impl BlockingPool {
+    // Queue a job, spawning a thread for it if none are idle, and there's room for another.
+    fn submit(&'static self, job: Job) {
+        self.sender.send(job).ok();
+        if self.idle.load(Ordering::SeqCst) == 0 {
+            let max = get_blocking_max_threads();
+            if self
+                .threads
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| if n < max { Some(n + 1) } else { None })
+                .is_ok()
+            {
+                let idx = self.spawned.fetch_add(1, Ordering::SeqCst);
+                thread::Builder::new()
+                    .name(format!("blocking-{}", idx + 1))
+                    .spawn(move || self.run())
+                    .expect("cannot spawn blocking thread");
+            }
+        }
+    }
+
+    // Run jobs until there are none for a while.
+    fn run(&self) {
+        loop {
+            self.idle.fetch_add(1, Ordering::SeqCst);
+            let job = self.receiver.recv_timeout(IDLE_TIMEOUT);
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+            match job {
+                Ok(job) => {
+                    if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log::warn!("blocking thread caught a panic, continuing");
+                    }
+                },
+                Err(_) => {
+                    self.threads.fetch_sub(1, Ordering::SeqCst);
+                    // a job may have been queued, as this thread was timing out, expecting it to run it
+                    if self.receiver.is_empty() {
+                        break;
+                    }
+                    self.threads.fetch_add(1, Ordering::SeqCst);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_blocking_work() {
+        let previous = set_blocking_max_threads(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<smol::Task<usize>> = (0 .. 6)
+            .map(|n| {
+                let running = running.clone();
+                let most_running = most_running.clone();
+                spawn_blocking(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    n
+                })
+            })
+            .collect();
+        let total: usize = tasks.into_iter().map(smol::block_on).sum();
+        assert_eq!(15, total);
+        assert!(most_running.load(Ordering::SeqCst) <= 2);
+        assert!(get_blocking_thread_count() <= 2);
+        set_blocking_max_threads(previous);
+    }
+}
@@ -0,0 +1,85 @@
+use super::*;
+use std::{collections::HashMap, sync::Mutex as SyncMutex};
+
+// The number of instructions dropped, by the type of their instruction set.
+static DROPPED: Lazy<SyncMutex<HashMap<&'static str, usize>>> = Lazy::new(|| SyncMutex::new(HashMap::new()));
+
+#[allow(non_upper_case_globals)]
+// If true, each dropped instruction is logged.
+static log_dropped: AtomicCell<bool> = AtomicCell::new(false);
+
+/// Set whether each instruction a MachineSender drops is logged as a warning, returning the
+/// previous value. Counting is always on, logging is off by default.
+pub fn set_log_dropped_instructions(log: bool) -> bool { log_dropped.swap(log) }
+/// Get whether each instruction a MachineSender drops is logged as a warning.
+pub fn get_log_dropped_instructions() -> bool { log_dropped.load() }
+
+/// DroppedInstructions is the number of instructions of an instruction set which a MachineSender
+/// dropped, because the channel they were sent to was closed. A non-zero count usually means a
+/// machine is sending to one which has gone away, which is a wiring bug.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DroppedInstructions {
+    /// The type name of the instruction set.
+    pub instruction_set: &'static str,
+    /// The number of instructions dropped since the start.
+    pub count: usize,
+}
+
+/// Get the number of instructions dropped, for each instruction set which has had any dropped,
+/// ordered by instruction set.
+pub fn get_dropped_instructions() -> Vec<DroppedInstructions> {
+    let dropped = DROPPED.lock().unwrap_or_else(|err| err.into_inner());
+    let mut dropped: Vec<DroppedInstructions> = dropped
+        .iter()
+        .map(|(instruction_set, count)| DroppedInstructions {
+            instruction_set,
+            count: *count,
+        })
+        .collect();
+    dropped.sort_by_key(|d| d.instruction_set);
+    dropped
+}
+
+// Record an instruction dropped because the channel it was sent to was closed.
+pub(crate) fn record_dropped<T: MachineImpl>(cmd: &T) {
+    let instruction_set = std::any::type_name::<T>();
+    *DROPPED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(instruction_set)
+        .or_insert(0) += 1;
+    if get_log_dropped_instructions() {
+        log::warn!(
+            "dropped instruction={} instruction_set={}, the channel is closed",
+            cmd.variant_name(),
+            instruction_set
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    enum Dropped {
+        Test,
+    }
+    impl MachineImpl for Dropped {
+        type Adapter = Dropped;
+        type InstructionSet = Dropped;
+    }
+
+    #[test]
+    fn count_dropped() {
+        let (sender, receiver) = smol::channel::unbounded::<Dropped>();
+        drop(receiver);
+        let mut machine_sender = MachineSender::default();
+        machine_sender.send_now(&sender, Dropped::Test);
+        machine_sender.send(sender, Dropped::Test);
+        smol::block_on(machine_sender.flush());
+        let name = std::any::type_name::<Dropped>();
+        let dropped = get_dropped_instructions().into_iter().find(|d| d.instruction_set == name);
+        assert_eq!(Some(2), dropped.map(|d| d.count));
+    }
+}
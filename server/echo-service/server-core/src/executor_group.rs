@@ -0,0 +1,97 @@
+use super::*;
+
+/// An ExecutorGroup is a named pool of executors, with threads of its own, which is separate from
+/// the global pool. Machines created in a group run only on its threads, so a noisy service's
+/// machines, sharing the global pool, can't starve latency-sensitive machines isolated in a group,
+/// or the other way around. When work stealing is on, the threads of a group steal only from the
+/// executors of the group. The threads are stopped when the group is dropped, along with any
+/// machines still running in it.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::ExecutorGroup;
+///
+/// let net = ExecutorGroup::new("net", 2);
+/// assert_eq!("net", net.get_name());
+/// assert_eq!(7, smol::block_on(net.get_executor().spawn(async { 7 })));
+/// ```
+pub struct ExecutorGroup {
+    name: String,
+    executors: Arc<Vec<Arc<InstrumentedExecutor>>>,
+    seed: AtomicUsize,
+    stop: smol::channel::Sender<()>,
+}
+
+impl fmt::Debug for ExecutorGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#ExecutorGroup {{ name: {}, executors: {} }}", self.name, self.executors.len())
+    }
+}
+
+impl ExecutorGroup {
+    /// Create a group with the name, and number of executor threads, which is at least 1. Its
+    /// threads are named after the group.
+    pub fn new(name: &str, num_threads: usize) -> Self {
+        let (stop, stopped) = smol::channel::unbounded::<()>();
+        let executors: Arc<Vec<Arc<InstrumentedExecutor>>> =
+            Arc::new((0 .. num_threads.max(1)).map(|_| Arc::new(InstrumentedExecutor::new())).collect());
+        for idx in 0 .. executors.len() {
+            let executors = executors.clone();
+            let stopped = stopped.clone();
+            thread::Builder::new()
+                .name(format!("{}-{}", name, idx + 1))
+                .spawn(move || executor_monitor::run_executor(&executors[idx], &stopped, Some(&executors)))
+                .expect("cannot spawn executor group thread");
+        }
+        Self {
+            name: name.to_string(),
+            executors,
+            seed: AtomicUsize::new(0),
+            stop,
+        }
+    }
+
+    /// Get the name of the group.
+    pub fn get_name(&self) -> &str { &self.name }
+
+    /// Get the number of executors in the group.
+    pub fn len(&self) -> usize { self.executors.len() }
+
+    /// Get whether the group has no executors, which it never does.
+    pub fn is_empty(&self) -> bool { self.executors.is_empty() }
+
+    /// Get an executor, selecting one of the executors of the group.
+    pub fn get_executor(&self) -> Arc<InstrumentedExecutor> {
+        let next = self.seed.fetch_add(1, Ordering::SeqCst);
+        self.executors[next % self.executors.len()].clone()
+    }
+
+    /// Get the number of tasks spawned on the group which haven't yet completed.
+    pub fn get_pending_count(&self) -> usize { self.executors.iter().map(|e| e.get_pending_count()).sum() }
+}
+
+impl Drop for ExecutorGroup {
+    fn drop(&mut self) { self.stop.close(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_on_group_threads() {
+        let group = ExecutorGroup::new("isolated", 2);
+        assert_eq!(2, group.len());
+        let names: Vec<String> = (0 .. 4)
+            .map(|_| {
+                let task = group
+                    .get_executor()
+                    .spawn(async { thread::current().name().unwrap_or_default().to_string() });
+                smol::block_on(task)
+            })
+            .collect();
+        assert!(names.iter().all(|name| name.starts_with("isolated-")), "{:?}", names);
+        assert_eq!(0, group.get_pending_count());
+    }
+}
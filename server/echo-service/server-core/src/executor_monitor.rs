@@ -0,0 +1,233 @@
+use super::*;
+use std::{
+    sync::{Mutex, Weak},
+    thread::JoinHandle,
+};
+
+// The interval at which a draining executor thread checks if it has been drained.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+// The number of executor threads which have been respawned.
+static EXECUTOR_RESTARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the number of executor threads which have died and been respawned.
+pub fn get_executor_restart_count() -> usize { EXECUTOR_RESTARTS.load(Ordering::SeqCst) }
+
+/// An ExecutorThreadDeath is sent to the supervisor when the thread running an executor dies,
+/// outside of the catch_unwind around its tasks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExecutorThreadDeath {
+    /// The index of the executor, the thread being named executor-<index + 1>.
+    pub idx: usize,
+    /// Whether the thread was respawned, which it isn't if the executor was retired, or stopped.
+    pub respawned: bool,
+}
+
+// The supervisor, which is notified of each executor thread which dies.
+static SUPERVISOR: Lazy<Mutex<Option<smol::channel::Sender<ExecutorThreadDeath>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Set the supervisor, returning the previous one. The supervisor is sent an ExecutorThreadDeath
+/// for each executor thread which dies, such that it can alert, or shut down the server, rather
+/// than relying upon the log. It is never blocked upon, should it be full, the notification is
+/// dropped.
+pub fn set_executor_supervisor(
+    supervisor: Option<smol::channel::Sender<ExecutorThreadDeath>>,
+) -> Option<smol::channel::Sender<ExecutorThreadDeath>> {
+    std::mem::replace(&mut *SUPERVISOR.lock().unwrap_or_else(|err| err.into_inner()), supervisor)
+}
+
+// Notify the supervisor, if there is one, of the death of an executor thread.
+fn notify_supervisor(death: ExecutorThreadDeath) {
+    if let Some(supervisor) = &*SUPERVISOR.lock().unwrap_or_else(|err| err.into_inner()) {
+        supervisor.try_send(death).ok();
+    }
+}
+
+// The guard is owned by an executor thread, if the thread unwinds it reports the death.
+struct ExecutorThreadGuard {
+    idx: usize,
+    deaths: smol::channel::Sender<usize>,
+}
+
+impl Drop for ExecutorThreadGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.deaths.try_send(self.idx).ok();
+        }
+    }
+}
+
+// Spawn a thread to run an executor, reporting its death on deaths. The thread runs until retired
+// is closed, then until the executor is drained, or stop is closed. Panics within a task are
+// caught, and the executor is run again. Should the thread die anyway, the monitor is told. Unless
+// steal is true, the thread doesn't steal from the global pool.
+pub(crate) fn spawn_executor_thread(
+    idx: usize, executor: Arc<InstrumentedExecutor>, retired: smol::channel::Receiver<()>, stop: smol::channel::Receiver<()>,
+    deaths: smol::channel::Sender<usize>, steal: bool,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(format!("executor-{}", idx + 1))
+        .spawn(move || {
+            let _guard = ExecutorThreadGuard { idx, deaths };
+            #[cfg(test)]
+            die_if_killed(&executor);
+            affinity::pin_executor_thread(idx);
+            run_executor(&executor, &retired, if steal { None } else { Some(&[]) });
+            drain_executor(&executor, &stop);
+        })
+        .expect("cannot spawn executor thread")
+}
+
+// The executors, by address, the next thread of which dies as it starts. A panic within a task is
+// caught by the task, so an executor thread can't otherwise be made to die, to test the monitor.
+#[cfg(test)]
+static KILLED: Lazy<Mutex<Vec<usize>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+// Kill the next thread which runs the executor.
+#[cfg(test)]
+fn kill_executor_thread(executor: &Arc<InstrumentedExecutor>) {
+    KILLED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Arc::as_ptr(executor) as usize);
+}
+
+// Panic, if the next thread which runs the executor was killed.
+#[cfg(test)]
+fn die_if_killed(executor: &Arc<InstrumentedExecutor>) {
+    let mut killed = KILLED.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(pos) = killed.iter().position(|killed| *killed == Arc::as_ptr(executor) as usize) {
+        killed.remove(pos);
+        drop(killed);
+        panic!("executor thread killed");
+    }
+}
+
+// Join the threads which finish by the deadline, returning the number which didn't.
+pub(crate) fn join_threads(mut handles: Vec<JoinHandle<()>>, deadline: Instant) -> usize {
+    loop {
+        let (finished, running): (Vec<_>, Vec<_>) = handles.into_iter().partition(|handle| handle.is_finished());
+        finished.into_iter().for_each(|handle| {
+            handle.join().ok();
+        });
+        handles = running;
+        if handles.is_empty() || Instant::now() >= deadline {
+            return handles.len();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Run an executor until stop is closed, running it again should a task panic.
+pub(crate) fn run_executor(
+    executor: &InstrumentedExecutor, stop: &smol::channel::Receiver<()>, pool: Option<&[Arc<InstrumentedExecutor>]>,
+) {
+    loop {
+        match catch_unwind(AssertUnwindSafe(|| executor_profile::run(executor, stop, pool))) {
+            Ok(_) => break,
+            Err(_) => log::warn!("executor caught a panic, continuing"),
+        }
+    }
+}
+
+// Run a retired executor until the tasks spawned on it have completed, or stop is closed. Its
+// machines aren't moved, they run here until they disconnect.
+fn drain_executor(executor: &InstrumentedExecutor, stop: &smol::channel::Receiver<()>) {
+    let high = executor.get_high();
+    while executor.get_pending_count() > 0 && !stop.is_closed() {
+        let drained = async {
+            while executor.get_pending_count() > 0 && !stop.is_closed() {
+                smol::Timer::after(DRAIN_INTERVAL).await;
+            }
+        };
+        if catch_unwind(AssertUnwindSafe(|| smol::future::block_on(executor.run(high.run(drained))))).is_err() {
+            log::warn!("executor caught a panic, continuing");
+        }
+    }
+}
+
+// Start the monitor of a pool, which respawns its dead executor threads, until deaths is closed. As
+// machines are tasks of an executor, rather than of the thread running it, a respawned thread
+// resumes running them.
+pub(crate) fn start_monitor(pool: Weak<executor_pool::ExecutorPool>, deaths: smol::channel::Receiver<usize>) {
+    thread::Builder::new()
+        .name("executor-monitor".to_string())
+        .spawn(move || {
+            while let Ok(idx) = smol::future::block_on(deaths.recv()) {
+                let respawned = match pool.upgrade() {
+                    Some(pool) => pool.respawn(idx),
+                    None => break,
+                };
+                if respawned {
+                    log::error!("executor-{} thread died, respawned it", idx + 1);
+                    EXECUTOR_RESTARTS.fetch_add(1, Ordering::SeqCst);
+                }
+                notify_supervisor(ExecutorThreadDeath { idx, respawned });
+            }
+        })
+        .expect("cannot spawn executor monitor thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_reports_death() {
+        let (sender, receiver) = smol::channel::unbounded();
+        let guard_sender = sender.clone();
+        thread::spawn(move || {
+            let _guard = ExecutorThreadGuard {
+                idx: 7,
+                deaths: guard_sender,
+            };
+            panic!("executor thread died");
+        })
+        .join()
+        .ok();
+        thread::spawn(move || {
+            let _guard = ExecutorThreadGuard { idx: 8, deaths: sender };
+        })
+        .join()
+        .ok();
+        assert_eq!(Ok(7), receiver.try_recv());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn respawn_dead_thread() {
+        let (supervisor, notifications) = smol::channel::unbounded();
+        let previous = set_executor_supervisor(Some(supervisor));
+        let restarts = get_executor_restart_count();
+        // the revived executor's thread dies as it starts
+        let pool = executor_pool::ExecutorPool::start(2, false);
+        assert_eq!(1, pool.remove(1));
+        let executor = pool.get_executors()[1].clone();
+        kill_executor_thread(&executor);
+        assert_eq!(2, pool.add(1));
+        let death = smol::block_on(smol::future::or(async { notifications.recv().await.ok() }, async {
+            smol::Timer::after(Duration::from_secs(5)).await;
+            None
+        }));
+        set_executor_supervisor(previous);
+        assert_eq!(Some(ExecutorThreadDeath { idx: 1, respawned: true }), death);
+        assert!(get_executor_restart_count() > restarts);
+        // the respawned thread runs the executor, and its machines
+        assert_eq!(1, smol::block_on(executor.spawn(async { 1 })));
+        pool.stop();
+        assert_eq!(0, pool.join(Instant::now() + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn join_finished_threads() {
+        let (stop, stopped) = smol::channel::unbounded::<()>();
+        let finished = thread::spawn(|| {});
+        let running = thread::spawn(move || {
+            smol::block_on(stopped.recv()).ok();
+        });
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(1, join_threads(vec![finished, running], deadline));
+        assert!(Instant::now() >= deadline);
+        stop.close();
+    }
+}
@@ -0,0 +1,248 @@
+use super::*;
+use smart_default::*;
+use std::{
+    sync::{Mutex, RwLock},
+    thread::JoinHandle,
+};
+
+/// The ExecutorSelection selects the executor of the pool which get_executor() returns, and so
+/// which a machine runs on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+pub enum ExecutorSelection {
+    /// Select the executor with the fewest pending tasks, so that machines are placed on the
+    /// executors with the least load. Ties are broken round-robin.
+    #[default]
+    LeastLoaded,
+    /// Select each executor in turn, which places machines by the order they are created.
+    RoundRobin,
+}
+
+#[allow(non_upper_case_globals)]
+static executor_selection: AtomicCell<ExecutorSelection> = AtomicCell::new(ExecutorSelection::LeastLoaded);
+
+/// Set how get_executor() selects an executor of the pool, returning the previous selection.
+pub fn set_executor_selection(selection: ExecutorSelection) -> ExecutorSelection { executor_selection.swap(selection) }
+/// Get how get_executor() selects an executor of the pool.
+pub fn get_executor_selection() -> ExecutorSelection { executor_selection.load() }
+
+// The channel which retires the thread running an executor, once the sender is closed.
+struct Retirement {
+    retire: smol::channel::Sender<()>,
+    retired: smol::channel::Receiver<()>,
+}
+
+// The ExecutorPool is a pool of executors, each run by a thread of its own, the global pool being
+// the one get_executor() selects from. It can be resized while running. The executors are kept by
+// index, those below the active count are active, and new machines are spread across them. Those
+// above are retired, their threads drain them, and exit once the machines running on them have
+// disconnected. Adding threads revives the retired executors before creating new ones.
+pub(crate) struct ExecutorPool {
+    executors: RwLock<Arc<Vec<Arc<InstrumentedExecutor>>>>,
+    active: AtomicUsize,
+    retirements: Mutex<Vec<Retirement>>,
+    // The threads which have been spawned, for joining once stopped. A revived executor may still
+    // be drained by the thread which ran it before, both are kept.
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    // The executor threads report their death to the monitor of the pool, until stopped.
+    deaths: smol::channel::Sender<usize>,
+    // Whether the pool is the global pool, which executor threads steal from.
+    global: bool,
+    stop: smol::channel::Sender<()>,
+    stopped: smol::channel::Receiver<()>,
+}
+
+impl ExecutorPool {
+    // Start a pool, running the number of executor threads, which is at least 1, and its monitor.
+    // Only the threads of the global pool steal, and only from the global pool.
+    pub(crate) fn start(num_threads: usize, global: bool) -> Arc<Self> {
+        let (stop, stopped) = smol::channel::unbounded::<()>();
+        let (deaths, died) = smol::channel::unbounded::<usize>();
+        let pool = Arc::new(Self {
+            executors: RwLock::new(Arc::new(Vec::new())),
+            active: AtomicUsize::new(0),
+            retirements: Mutex::new(Vec::new()),
+            threads: Mutex::new(Vec::new()),
+            deaths,
+            global,
+            stop,
+            stopped,
+        });
+        pool.add(num_threads.max(1));
+        executor_monitor::start_monitor(Arc::downgrade(&pool), died);
+        pool
+    }
+
+    // Get an active executor, as selected by the executor selection, the seed being the turn.
+    pub(crate) fn get_executor(&self, seed: usize) -> Arc<InstrumentedExecutor> {
+        let executors = self.get_executors();
+        select(&executors[.. self.get_active().max(1)], seed, get_executor_selection()).clone()
+    }
+
+    // Get every executor of the pool, including those retired, by index.
+    pub(crate) fn get_executors(&self) -> Arc<Vec<Arc<InstrumentedExecutor>>> {
+        self.executors.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    // Get the number of active executors.
+    pub(crate) fn get_active(&self) -> usize { self.active.load(Ordering::SeqCst) }
+
+    // Add executor threads, returning the number active. Once stopped, none are added.
+    pub(crate) fn add(&self, num_threads: usize) -> usize {
+        let mut retirements = self.lock_retirements();
+        let active = self.get_active();
+        if self.stopped.is_closed() {
+            return active;
+        }
+        let mut executors = self.get_executors().as_ref().clone();
+        for idx in active .. active + num_threads {
+            if idx == executors.len() {
+                executors.push(Arc::new(InstrumentedExecutor::new()));
+            }
+            let (retire, retired) = smol::channel::unbounded::<()>();
+            self.spawn(idx, executors[idx].clone(), retired.clone());
+            let retirement = Retirement { retire, retired };
+            match retirements.get_mut(idx) {
+                Some(previous) => *previous = retirement,
+                None => retirements.push(retirement),
+            }
+        }
+        let executors = Arc::new(executors);
+        *self.executors.write().unwrap_or_else(|err| err.into_inner()) = executors.clone();
+        if self.global {
+            work_stealing::set_executors(executors);
+        }
+        self.active.store(active + num_threads, Ordering::SeqCst);
+        active + num_threads
+    }
+
+    // Retire executor threads, the most recently added first, returning the number active, which is
+    // at least 1.
+    pub(crate) fn remove(&self, num_threads: usize) -> usize {
+        let retirements = self.lock_retirements();
+        let active = self.get_active();
+        let remaining = active.saturating_sub(num_threads).max(1);
+        self.active.store(remaining, Ordering::SeqCst);
+        retirements[remaining .. active].iter().for_each(|retirement| {
+            retirement.retire.close();
+        });
+        remaining
+    }
+
+    // Respawn the thread of an executor, which died, unless it was retired, or the pool stopped.
+    pub(crate) fn respawn(&self, idx: usize) -> bool {
+        let retirements = self.lock_retirements();
+        if idx >= self.get_active() || self.stopped.is_closed() {
+            return false;
+        }
+        self.spawn(idx, self.get_executors()[idx].clone(), retirements[idx].retired.clone());
+        true
+    }
+
+    // Stop the executor threads, and the monitor, retired threads stop draining.
+    pub(crate) fn stop(&self) {
+        let retirements = self.lock_retirements();
+        self.stop.close();
+        self.deaths.close();
+        retirements.iter().for_each(|retirement| {
+            retirement.retire.close();
+        });
+    }
+
+    // Join the executor threads, once stopped, waiting until the deadline for them to finish.
+    // Returns the number which hadn't finished.
+    pub(crate) fn join(&self, deadline: Instant) -> usize {
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap_or_else(|err| err.into_inner()));
+        executor_monitor::join_threads(threads, deadline)
+    }
+
+    // Spawn a thread to run an executor, dropping the handles of those which have finished.
+    fn spawn(&self, idx: usize, executor: Arc<InstrumentedExecutor>, retired: smol::channel::Receiver<()>) {
+        let thread =
+            executor_monitor::spawn_executor_thread(idx, executor, retired, self.stopped.clone(), self.deaths.clone(), self.global);
+        let mut threads = self.threads.lock().unwrap_or_else(|err| err.into_inner());
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(thread);
+    }
+
+    fn lock_retirements(&self) -> std::sync::MutexGuard<'_, Vec<Retirement>> {
+        self.retirements.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Select one of the executors, the seed being the turn.
+fn select(executors: &[Arc<InstrumentedExecutor>], seed: usize, selection: ExecutorSelection) -> &Arc<InstrumentedExecutor> {
+    let start = seed % executors.len();
+    match selection {
+        ExecutorSelection::RoundRobin => &executors[start],
+        ExecutorSelection::LeastLoaded => (0 .. executors.len())
+            .map(|n| &executors[(start + n) % executors.len()])
+            .min_by_key(|executor| executor.get_pending_count())
+            .unwrap_or(&executors[start]),
+    }
+}
+
+/// Add threads to the pool of executors, each running an executor of its own, returning the number
+/// of executor threads. A server can scale up for a spike in traffic, machines created from then on
+/// are spread across the added executors as well.
+pub fn add_executor_threads(num_threads: usize) -> usize { EXECUTOR.add(num_threads) }
+
+/// Remove threads from the pool of executors, the most recently added first, returning the number
+/// of executor threads, which is at least 1. No new machines are created on the executors of the
+/// removed threads, and each thread exits once the machines on its executor have disconnected.
+/// Until then, idle threads steal from it when work stealing is on.
+pub fn remove_executor_threads(num_threads: usize) -> usize { EXECUTOR.remove(num_threads) }
+
+/// Get the number of executor threads in the pool of executors, not counting those draining.
+pub fn get_executor_thread_count() -> usize { EXECUTOR.get_active() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_pool() {
+        let pool = ExecutorPool::start(1, false);
+        assert_eq!(3, pool.add(2));
+        let executors = pool.get_executors();
+        assert_eq!(3, executors.len());
+        // a long-lived task keeps the last executor draining, once its thread is removed
+        let (release, wait) = smol::channel::unbounded::<()>();
+        let draining = executors[2].clone();
+        draining.spawn(async move { wait.recv().await.ok() }).detach();
+        assert_eq!(1, pool.remove(2));
+        assert!((0 .. 10).all(|seed| Arc::ptr_eq(&executors[0], &pool.get_executor(seed))));
+        assert_eq!(1, smol::block_on(draining.spawn(async { 1 })));
+        assert!(!pool.respawn(2));
+        // the removed executors are revived, rather than new ones created, and the thread still
+        // draining is kept for joining
+        assert_eq!(3, pool.add(2));
+        assert_eq!(3, pool.get_executors().len());
+        let named = |name| {
+            let threads = pool.threads.lock().unwrap_or_else(|err| err.into_inner());
+            threads.iter().filter(|thread| thread.thread().name() == Some(name)).count()
+        };
+        assert_eq!(2, named("executor-3"));
+        release.close();
+        pool.stop();
+        assert_eq!(0, pool.join(Instant::now() + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn select_least_loaded() {
+        let executors: Vec<Arc<InstrumentedExecutor>> = (0 .. 3).map(|_| Arc::new(InstrumentedExecutor::new())).collect();
+        let (_release, wait) = smol::channel::unbounded::<()>();
+        for (idx, count) in [2, 1, 2].iter().enumerate() {
+            for _ in 0 .. *count {
+                let wait = wait.clone();
+                executors[idx].spawn(async move { wait.recv().await.ok() }).detach();
+            }
+        }
+        let selected = |seed, selection| executors.iter().position(|e| Arc::ptr_eq(e, select(&executors, seed, selection)));
+        assert!((0 .. 3).all(|seed| selected(seed, ExecutorSelection::LeastLoaded) == Some(1)));
+        // ties are broken by the seed
+        executors[1].spawn(async { smol::future::pending::<()>().await }).detach();
+        assert_eq!(Some(2), selected(2, ExecutorSelection::LeastLoaded));
+        assert_eq!(Some(0), selected(0, ExecutorSelection::LeastLoaded));
+        assert_eq!(Some(2), selected(5, ExecutorSelection::RoundRobin));
+    }
+}
@@ -0,0 +1,136 @@
+use super::*;
+use smart_default::*;
+
+/// The ExecutorProfile selects how the executor threads run their executors, trading throughput
+/// against latency. When an executor runs out of tasks, its thread may spin, checking for a task,
+/// before parking until one is woken. Spinning costs CPU, but saves the wake-up latency of
+/// parking. Once woken, the thread polls a batch of tasks before checking whether it should stop.
+/// When work stealing is on, a thread steals a batch of tasks from the other executors before
+/// parking, and while parked, wakes periodically to steal.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+pub enum ExecutorProfile {
+    /// Run as smol does by default, which is neither spinning nor batching beyond smol's own. When
+    /// work stealing is on, it doesn't spin, and polls moderate batches.
+    #[default]
+    Balanced,
+    /// Poll large batches of tasks, and spin briefly before parking.
+    Throughput,
+    /// Poll small batches of tasks, and spin for longer before parking.
+    Latency,
+    /// Spin checking for a task the number of times given, and poll batches of the size given.
+    Custom { spins: u32, batch: u32 },
+}
+
+impl ExecutorProfile {
+    // The number of spins before parking, and the batch size, of Balanced when work stealing.
+    const BALANCED_KNOBS: (u32, u32) = (0, 64);
+
+    // Get the number of spins before parking, and the batch size, None for smol's own loop.
+    fn get_knobs(self) -> Option<(u32, u32)> {
+        match self {
+            Self::Balanced => None,
+            Self::Throughput => Some((64, 256)),
+            Self::Latency => Some((4096, 16)),
+            Self::Custom { spins, batch } => Some((spins, batch.max(1))),
+        }
+    }
+}
+
+#[allow(non_upper_case_globals)]
+static executor_profile: AtomicCell<ExecutorProfile> = AtomicCell::new(ExecutorProfile::Balanced);
+
+/// Set the profile of the executor threads, returning the previous profile. The threads pick up
+/// the change the next time they park.
+pub fn set_executor_profile(profile: ExecutorProfile) -> ExecutorProfile { executor_profile.swap(profile) }
+/// Get the profile of the executor threads.
+pub fn get_executor_profile() -> ExecutorProfile { executor_profile.load() }
+
+// Run an executor until stop is closed, as the current profile has it, stealing from the executors
+// of its pool, the global pool if None. The executor's high priority tasks are run ahead of its
+// low priority tasks, other than by Balanced, without work stealing, which runs smol's own loop
+// for each, taking turns.
+pub(crate) fn run(executor: &InstrumentedExecutor, stop: &smol::channel::Receiver<()>, pool: Option<&[Arc<InstrumentedExecutor>]>) {
+    let high = executor.get_high();
+    while !stop.is_closed() {
+        let stealing = work_stealing::get_work_stealing();
+        match get_executor_profile().get_knobs() {
+            None if !stealing => {
+                // run until stopped, or the profile or work stealing changes
+                let changed = async {
+                    while get_executor_profile() == ExecutorProfile::Balanced && !work_stealing::get_work_stealing() {
+                        smol::Timer::after(Duration::from_millis(100)).await;
+                    }
+                    Ok::<(), smol::channel::RecvError>(())
+                };
+                smol::future::block_on(executor.run(high.run(smol::future::or(stop.recv(), changed)))).ok();
+            },
+            knobs => {
+                let (spins, batch) = knobs.unwrap_or(ExecutorProfile::BALANCED_KNOBS);
+                let mut polled = 0;
+                while polled < batch && (high.try_tick() || executor.try_tick()) {
+                    polled += 1;
+                }
+                if polled > 0 {
+                    continue;
+                }
+                if (0 .. spins).any(|_| {
+                    std::hint::spin_loop();
+                    high.try_tick() || executor.try_tick()
+                }) {
+                    continue;
+                }
+                if work_stealing::steal_from_pool(executor, pool, batch) {
+                    continue;
+                }
+                // park until a task is woken, or stopped, waking periodically to steal
+                let tick = async {
+                    smol::future::or(high.tick(), executor.tick()).await;
+                    Ok(())
+                };
+                let steal = async {
+                    match stealing {
+                        true => smol::Timer::after(work_stealing::STEAL_INTERVAL).await,
+                        false => smol::future::pending().await,
+                    };
+                    Ok(())
+                };
+                smol::future::block_on(smol::future::or(smol::future::or(tick, steal), stop.recv())).ok();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(profile: ExecutorProfile) {
+        let executor = Arc::new(InstrumentedExecutor::new());
+        let (stop, stopped) = smol::channel::unbounded::<()>();
+        let runner = {
+            let executor = executor.clone();
+            thread::spawn(move || {
+                set_executor_profile(profile);
+                run(&executor, &stopped, None);
+            })
+        };
+        let (sender, receiver) = smol::channel::unbounded();
+        for n in 0 .. 100 {
+            let sender = sender.clone();
+            executor.spawn(async move { sender.send(n).await.ok() }).detach();
+        }
+        let total: usize = (0 .. 100).map(|_| smol::block_on(receiver.recv()).unwrap()).sum();
+        assert_eq!(4950, total);
+        stop.close();
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn run_each_profile() {
+        run_with(ExecutorProfile::Throughput);
+        run_with(ExecutorProfile::Custom { spins: 0, batch: 0 });
+        run_with(ExecutorProfile::Latency);
+        run_with(ExecutorProfile::Balanced);
+        assert_eq!(ExecutorProfile::Balanced, get_executor_profile());
+    }
+}
@@ -0,0 +1,97 @@
+use super::*;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The Governor paces the instructions a machine receives, to a maximum number per second. The
+/// adapter acquires a slot from the governor before pulling each instruction, so when over budget
+/// instructions are left queued, and the queue provides the back-pressure. The governor is shared,
+/// the maximum rate can be adjusted at any time, with 0 being unlimited.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::*;
+///
+/// struct Alice {
+///     governor: Governor,
+/// }
+/// impl Machine<()> for Alice {
+///     fn receive(&self, _cmd: (), _sender: &mut MachineSender) {}
+///     fn governor(&self) -> Option<Governor> { Some(self.governor.clone()) }
+/// }
+/// let alice = Alice { governor: Governor::new(100) };
+/// alice.governor.set_max_rate(10);
+/// assert_eq!(10, alice.governor.get_max_rate());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Governor {
+    inner: Arc<GovernorInner>,
+}
+
+#[derive(Debug, Default)]
+struct GovernorInner {
+    max_rate: AtomicCell<u32>,
+    next: Mutex<Option<Instant>>,
+}
+
+impl Governor {
+    /// Create a governor allowing max_rate instructions per second.
+    pub fn new(max_rate: u32) -> Self {
+        let governor = Self::default();
+        governor.set_max_rate(max_rate);
+        governor
+    }
+
+    /// Set the maximum number of instructions per second, 0 is unlimited.
+    pub fn set_max_rate(&self, max_rate: u32) { self.inner.max_rate.store(max_rate); }
+
+    /// Get the maximum number of instructions per second.
+    pub fn get_max_rate(&self) -> u32 { self.inner.max_rate.load() }
+
+    /// Acquire a slot, waiting until the rate allows it.
+    pub async fn acquire(&self) {
+        let max_rate = self.get_max_rate();
+        if max_rate == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(1) / max_rate;
+        let delay = {
+            let mut next = self.inner.next.lock().unwrap_or_else(|err| err.into_inner());
+            let now = Instant::now();
+            let at = next.map_or(now, |next| next.max(now));
+            *next = Some(at + interval);
+            at - now
+        };
+        if delay > Duration::from_millis(0) {
+            runtime::get_runtime().sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_acquisitions() {
+        let governor = Governor::new(50);
+        let start = Instant::now();
+        smol::block_on(async {
+            for _ in 0 .. 5 {
+                governor.acquire().await;
+            }
+        });
+        assert!(start.elapsed() >= Duration::from_millis(80));
+
+        governor.set_max_rate(0);
+        let start = Instant::now();
+        smol::block_on(async {
+            for _ in 0 .. 100 {
+                governor.acquire().await;
+            }
+        });
+        assert!(start.elapsed() < Duration::from_millis(80));
+    }
+}
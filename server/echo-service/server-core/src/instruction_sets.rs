@@ -0,0 +1,117 @@
+use super::*;
+use std::{any::TypeId, collections::HashMap, sync::Mutex as SyncMutex};
+
+// The instruction sets of the running binary, by their TypeId.
+static INSTRUCTION_SETS: Lazy<SyncMutex<HashMap<TypeId, InstructionSetInfo>>> = Lazy::new(|| SyncMutex::new(HashMap::new()));
+
+/// InstructionSetInfo describes an instruction set of the running binary, such as for an admin
+/// console to show which message types exist, and their memory footprint.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InstructionSetInfo {
+    /// The type name of the instruction set.
+    pub name: &'static str,
+    /// The number of variants of the instruction set, 0 if unknown, as it wasn't derived.
+    pub variants: usize,
+    /// The size of an instruction, in bytes, which each instruction queued occupies.
+    pub size: usize,
+}
+
+/// An InstructionSetRegistration is submitted, by the MachineImpl derive, for each instruction set
+/// derived, so that it is registered as the binary loads, rather than once it is used.
+#[derive(Debug)]
+pub struct InstructionSetRegistration {
+    id: fn() -> TypeId,
+    info: fn() -> InstructionSetInfo,
+}
+
+impl InstructionSetRegistration {
+    /// Create the registration of an instruction set.
+    pub const fn new<T: MachineImpl>() -> Self {
+        Self {
+            id: TypeId::of::<T>,
+            info: instruction_set_info::<T>,
+        }
+    }
+}
+
+inventory::collect!(InstructionSetRegistration);
+
+fn instruction_set_info<T: MachineImpl>() -> InstructionSetInfo {
+    InstructionSetInfo {
+        name: std::any::type_name::<T>(),
+        variants: T::variant_count(),
+        size: std::mem::size_of::<T>(),
+    }
+}
+
+/// Register an instruction set, if not already registered. A derived instruction set is registered
+/// as the binary loads, one whose MachineImpl is written by hand is registered once a machine
+/// receiving it is started, or with this.
+pub fn register_instruction_set<T: MachineImpl>() {
+    INSTRUCTION_SETS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(TypeId::of::<T>())
+        .or_insert_with(instruction_set_info::<T>);
+}
+
+/// Get the instruction sets registered, ordered by name.
+pub fn get_instruction_sets() -> Vec<InstructionSetInfo> {
+    let mut instruction_sets = INSTRUCTION_SETS.lock().unwrap_or_else(|err| err.into_inner());
+    for registration in inventory::iter::<InstructionSetRegistration> {
+        instruction_sets.entry((registration.id)()).or_insert_with(registration.info);
+    }
+    let mut instruction_sets: Vec<InstructionSetInfo> = instruction_sets.values().copied().collect();
+    instruction_sets.sort_by_key(|info| info.name);
+    instruction_sets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    #[allow(dead_code)]
+    enum Shape {
+        Circle(f64),
+        Square(u8),
+    }
+    impl MachineImpl for Shape {
+        type Adapter = Self;
+        type InstructionSet = Self;
+        fn variant_count() -> usize { 2 }
+    }
+
+    inventory::submit! { InstructionSetRegistration::new::<Circle>() }
+
+    #[derive(Debug, Clone)]
+    struct Circle;
+    impl MachineImpl for Circle {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+
+    #[test]
+    fn register_on_load() {
+        // registered, though no machine receiving it was started
+        assert!(get_instruction_sets().iter().any(|info| info.name.ends_with("::Circle")));
+    }
+
+    #[test]
+    fn register_once() {
+        register_instruction_set::<Shape>();
+        register_instruction_set::<Shape>();
+        let shapes: Vec<InstructionSetInfo> = get_instruction_sets()
+            .into_iter()
+            .filter(|info| info.name.ends_with("::Shape"))
+            .collect();
+        assert_eq!(
+            vec![InstructionSetInfo {
+                name: std::any::type_name::<Shape>(),
+                variants: 2,
+                size: std::mem::size_of::<Shape>(),
+            }],
+            shapes
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use super::*;
+use smart_default::*;
+use std::{future::Future, ops::Deref, sync::atomic::AtomicU64};
+
+/// The Priority of a task. When an executor is saturated, its high priority tasks, such as those
+/// of the control-plane, run ahead of its low priority tasks, such as those of bulk data traffic.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, SmartDefault)]
+pub enum Priority {
+    /// Run ahead of the low priority tasks.
+    High,
+    /// Run once there are no high priority tasks to run, the priority of most tasks.
+    #[default]
+    Low,
+}
+
+/// The InstrumentedExecutor is a thin wrapper around a smol Executor, which counts the tasks
+/// spawned on it that haven't yet completed, those which have, and the time spent polling them.
+/// It has a second Executor, for its high priority tasks. Anything else is done by the Executor,
+/// of its low priority tasks, which it derefs to.
+#[derive(Default)]
+pub struct InstrumentedExecutor {
+    executor: smol::Executor<'static>,
+    high: smol::Executor<'static>,
+    counters: Arc<TaskCounters>,
+    spawned: AtomicUsize,
+}
+
+// The counters updated by the tasks spawned on an executor.
+#[derive(Default)]
+struct TaskCounters {
+    pending: AtomicUsize,
+    completed: AtomicUsize,
+    busy_nanos: AtomicU64,
+}
+
+impl fmt::Debug for InstrumentedExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#InstrumentedExecutor {{ pending: {} }}", self.get_pending_count())
+    }
+}
+
+// The guard is owned by a task's future, when the task completes, or is cancelled, it is dropped.
+struct PendingGuard(Arc<TaskCounters>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) { self.0.pending.fetch_sub(1, Ordering::SeqCst); }
+}
+
+impl InstrumentedExecutor {
+    /// Create an executor.
+    pub fn new() -> Self { Self::default() }
+
+    /// Spawn a low priority task, which is counted as pending until it completes or is cancelled.
+    /// The time spent polling it is added to the busy time of the executor.
+    pub fn spawn<T: Send + 'static>(&self, future: impl Future<Output = T> + Send + 'static) -> smol::Task<T> {
+        self.spawn_with_priority(Priority::Low, future)
+    }
+
+    /// Spawn a high priority task, which is otherwise as spawn().
+    pub fn spawn_high<T: Send + 'static>(&self, future: impl Future<Output = T> + Send + 'static) -> smol::Task<T> {
+        self.spawn_with_priority(Priority::High, future)
+    }
+
+    /// Spawn a task with the priority given, which is otherwise as spawn().
+    pub fn spawn_with_priority<T: Send + 'static>(
+        &self, priority: Priority, future: impl Future<Output = T> + Send + 'static,
+    ) -> smol::Task<T> {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        self.counters.pending.fetch_add(1, Ordering::SeqCst);
+        let counters = self.counters.clone();
+        let guard = PendingGuard(self.counters.clone());
+        let executor = match priority {
+            Priority::High => &self.high,
+            Priority::Low => &self.executor,
+        };
+        executor.spawn(async move {
+            let _guard = guard;
+            smol::pin!(future);
+            let output = smol::future::poll_fn(|cx| {
+                let start = Instant::now();
+                let poll = future.as_mut().poll(cx);
+                counters.busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                poll
+            })
+            .await;
+            counters.completed.fetch_add(1, Ordering::SeqCst);
+            output
+        })
+    }
+
+    /// Get the number of tasks which have been spawned and haven't yet completed.
+    pub fn get_pending_count(&self) -> usize { self.counters.pending.load(Ordering::SeqCst) }
+
+    /// Get the number of tasks which have completed, a cancelled task isn't counted.
+    pub fn get_completed_count(&self) -> usize { self.counters.completed.load(Ordering::SeqCst) }
+
+    /// Get the approximate time spent polling the tasks, which is the time the executor has been
+    /// busy, across whichever threads ran it.
+    pub fn get_busy_time(&self) -> Duration { Duration::from_nanos(self.counters.busy_nanos.load(Ordering::Relaxed)) }
+
+    /// Get the number of tasks which have been spawned.
+    pub fn get_spawned_count(&self) -> usize { self.spawned.load(Ordering::SeqCst) }
+
+    // Get the executor of the high priority tasks.
+    pub(crate) fn get_high(&self) -> &smol::Executor<'static> { &self.high }
+}
+
+impl Deref for InstrumentedExecutor {
+    type Target = smol::Executor<'static>;
+    fn deref(&self) -> &Self::Target { &self.executor }
+}
+
+/// ExecutorStats describes the load on one of the pool of executors.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExecutorStats {
+    /// The index of the executor in the pool.
+    pub index: usize,
+    /// The number of tasks spawned on the executor which haven't yet completed. Each machine
+    /// adapter is a task, for as long as the machine is connected.
+    pub pending_tasks: usize,
+    /// The number of tasks which have been spawned on the executor.
+    pub spawned_tasks: usize,
+    /// The number of tasks spawned on the executor which have completed.
+    pub completed_tasks: usize,
+    /// The approximate time the executor has spent polling its tasks. Sampled periodically, and
+    /// compared with the time elapsed, it is the utilization of the executor, showing whether it
+    /// is pegged while others idle.
+    pub busy_time: Duration,
+    /// The number of instructions waiting in the queues of the machines the executor runs.
+    pub queued_instructions: usize,
+}
+
+/// Get the load on each of the pool of executors.
+pub fn get_executor_stats() -> Vec<ExecutorStats> {
+    let machines = get_machines();
+    EXECUTOR
+        .get_executors()
+        .iter()
+        .enumerate()
+        .map(|(index, executor)| ExecutorStats {
+            index,
+            pending_tasks: executor.get_pending_count(),
+            spawned_tasks: executor.get_spawned_count(),
+            completed_tasks: executor.get_completed_count(),
+            busy_time: executor.get_busy_time(),
+            queued_instructions: machines.iter().filter(|m| m.executor == Some(index)).map(|m| m.queue_len).sum(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_pending_tasks() {
+        let executor = InstrumentedExecutor::new();
+        let (sender, receiver) = smol::channel::unbounded::<()>();
+        let waiting = executor.spawn(async move { receiver.recv().await.ok() });
+        let done = executor.spawn(async { 1 });
+        assert_eq!(2, executor.get_pending_count());
+        assert_eq!(1, smol::block_on(executor.run(done)));
+        assert_eq!(1, executor.get_pending_count());
+        // a cancelled task is no longer pending
+        drop(waiting);
+        drop(sender);
+        while executor.try_tick() {}
+        assert_eq!(0, executor.get_pending_count());
+        assert_eq!(2, executor.get_spawned_count());
+        assert!(!get_executor_stats().is_empty());
+    }
+
+    #[test]
+    fn measure_busy_time() {
+        let executor = InstrumentedExecutor::new();
+        let busy = executor.spawn(async { thread::sleep(Duration::from_millis(20)) });
+        smol::block_on(executor.run(busy));
+        assert_eq!(1, executor.get_completed_count());
+        assert!(executor.get_busy_time() >= Duration::from_millis(20));
+        // a cancelled task isn't completed
+        drop(executor.spawn(smol::future::pending::<()>()));
+        assert_eq!(1, executor.get_completed_count());
+    }
+
+    #[test]
+    fn run_high_priority_first() {
+        let executor = Arc::new(InstrumentedExecutor::new());
+        let (sender, receiver) = smol::channel::unbounded();
+        for n in 0 .. 10 {
+            let sender = sender.clone();
+            let priority = if n % 2 == 0 { Priority::Low } else { Priority::High };
+            executor
+                .spawn_with_priority(priority, async move { sender.send(priority).await.ok() })
+                .detach();
+        }
+        assert_eq!(10, executor.get_pending_count());
+        let (stop, stopped) = smol::channel::unbounded::<()>();
+        let runner = {
+            let executor = executor.clone();
+            thread::spawn(move || executor_profile::run(&executor, &stopped, None))
+        };
+        let order: Vec<Priority> = (0 .. 10).map(|_| smol::block_on(receiver.recv()).unwrap()).collect();
+        stop.close();
+        runner.join().unwrap();
+        assert_eq!(vec![Priority::High; 5], order[.. 5].to_vec());
+        assert_eq!(vec![Priority::Low; 5], order[5 ..].to_vec());
+    }
+}
@@ -1,24 +1,77 @@
-use async_trait::async_trait;
 use crossbeam::atomic::AtomicCell;
 use once_cell::sync::Lazy;
 use smol::{self};
 use std::{
+    any::{Any, TypeId},
+    collections::VecDeque,
     fmt,
-    panic::catch_unwind,
+    panic::{catch_unwind, AssertUnwindSafe},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
     thread,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
+mod adaptive_capacity;
+mod affinity;
 mod background_task;
+mod blocking_pool;
+mod dropped_instructions;
+mod executor_group;
+mod executor_monitor;
+mod executor_pool;
+mod executor_profile;
+mod governor;
+mod instruction_sets;
+mod instrumented_executor;
+mod local_executor;
 mod machine_adpter;
 mod machine_builder;
+mod machine_context;
+mod machine_registry;
+mod panic_hook;
+mod receive_watch;
+mod runtime;
+mod scratch;
+mod trace;
+mod work_stealing;
 
-pub use background_task::BackgroundTask;
+pub use adaptive_capacity::{get_adaptive_channel_max, set_adaptive_channel_max};
+pub use affinity::{get_executor_affinity, set_executor_affinity};
+pub use background_task::{list_background_tasks, BackgroundHandle, BackgroundTask, BackgroundTaskGroup, BackgroundTaskInfo};
+pub use blocking_pool::{get_blocking_max_threads, get_blocking_thread_count, set_blocking_max_threads, spawn_blocking};
+pub use dropped_instructions::{get_dropped_instructions, get_log_dropped_instructions, set_log_dropped_instructions, DroppedInstructions};
+pub use executor_group::ExecutorGroup;
+pub use executor_monitor::{get_executor_restart_count, set_executor_supervisor, ExecutorThreadDeath};
+pub use executor_pool::{
+    add_executor_threads, get_executor_selection, get_executor_thread_count, remove_executor_threads, set_executor_selection,
+    ExecutorSelection,
+};
+pub use executor_profile::{get_executor_profile, set_executor_profile, ExecutorProfile};
+pub use governor::Governor;
+pub use instruction_sets::{get_instruction_sets, register_instruction_set, InstructionSetInfo, InstructionSetRegistration};
+// the MachineImpl derive submits the registration of each instruction set with it
+pub use instrumented_executor::{get_executor_stats, ExecutorStats, InstrumentedExecutor, Priority};
+#[doc(hidden)] pub use inventory;
+pub use local_executor::{LocalExecutorThread, LocalMachine};
 pub use machine_builder::MachineBuilder;
+pub use machine_context::{current_machine, MachineContext, MachineContextGuard};
+pub use machine_registry::{
+    evict_node, get_machines, get_names, lookup, register_name, set_remote_resolver, unregister_name, MachineInfo, NamedMachine,
+};
+pub use panic_hook::{install_panic_hook, set_crash_dir, set_panic_supervisor, MachinePanic};
+pub use receive_watch::{get_receives_in_progress, get_watch_receives, set_watch_receives};
+#[cfg(feature = "tokio")] pub use runtime::TokioRuntime;
+pub use runtime::{get_runtime, machine_channel, set_runtime, BoxFuture, Runtime, SmolRuntime};
+pub use scratch::{get_scratch_allocated, with_scratch};
+pub use trace::{
+    clear_trace, get_correlation, get_trace, get_trace_capacity, record_trace, set_correlation, set_trace_capacity, SequenceDiagram,
+    TraceEvent,
+};
+pub use work_stealing::{get_work_stealing, set_work_stealing};
 
 /// The server-core library is the lowest layer. It is dependent upon external
 /// crates and the core library. If you get a circular dependency error, it is
@@ -28,6 +81,13 @@ pub use machine_builder::MachineBuilder;
 pub trait MachineImpl: 'static + Send + Sync {
     type Adapter;
     type InstructionSet: Send + Sync + Clone;
+
+    /// Get the name of the instruction's variant, used when reporting on the instruction.
+    fn variant_name(&self) -> &'static str { std::any::type_name::<Self>() }
+
+    /// Get the number of variants of the instruction set, 0 if unknown. It is provided by the
+    /// MachineImpl derive, and reported by get_instruction_sets().
+    fn variant_count() -> usize { 0 }
 }
 
 /// The Machine trait must be implemented by each machine model for each instruction set that
@@ -39,41 +99,148 @@ where
     fn receive(&self, cmd: T, sender: &mut MachineSender);
     fn disconnected(&self) {}
     fn connected(&self, _uuid: uuid::Uuid) {}
+    /// Provide a governor to pace the instructions received, by default there is none.
+    fn governor(&self) -> Option<Governor> { None }
+    /// Provide the scheduling weight of the machine, by default it is DEFAULT_WEIGHT.
+    fn weight(&self) -> usize { DEFAULT_WEIGHT }
 }
 
-/// The AsyncSender trait exposes an async fn for sending an instruction to a sender.
-#[async_trait]
-trait AsyncSender: Send + Sync {
-    async fn do_send(&mut self);
+/// The default scheduling weight of a machine. When an executor is saturated, each machine
+/// receives, per turn, a number of instructions proportional to its weight before yielding
+/// to other machines.
+pub const DEFAULT_WEIGHT: usize = 1;
+
+// A SendBuffer holds the instructions a machine has sent, of a single instruction set, until its
+// receive returns. The buffers are kept by the MachineSender, so their allocations are reused from
+// one receive to the next.
+trait SendBuffer: Send + Sync {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    // Send the oldest instruction held, returning the future which completes the send, should its
+    // receiver be full.
+    fn send_next(&mut self) -> Option<runtime::BoxFuture>;
+    fn clear(&mut self);
 }
 
 /// The SharedMachine wraps a machine
 pub type SharedMachine<T> = Arc<T>;
 
 /// The MachineSender object is opaque, exposing a single send method. It is used by the receiver to send
-/// instructions to other machines.
+/// instructions to other machines. Additionally, it allows a long-running receiver to cooperate with
+/// the adapter, by checking if it has used up its time slice and asking the adapter to yield.
 #[derive(Default)]
 pub struct MachineSender {
-    queue: Vec<Box<dyn AsyncSender>>,
+    // the instructions sent, in order, each the index of the buffer holding it
+    queue: Vec<usize>,
+    // the buffer of each instruction set sent, by TypeId
+    buffers: Vec<(TypeId, Box<dyn SendBuffer>)>,
+    received_at: Option<Instant>,
+    yield_requested: bool,
 }
 impl MachineSender {
-    /// Send an instruction to another machine.
+    /// Send an instruction to another machine. Should its channel be closed, the instruction is
+    /// dropped and counted, see get_dropped_instructions().
     pub fn send<T: MachineImpl>(&mut self, sender: smol::channel::Sender<T>, cmd: T) {
-        let sender = Box::new(SendContext(sender, Some(cmd))) as Box<dyn AsyncSender>;
-        self.queue.push(sender);
+        trace::record_send(&cmd);
+        self.enqueue(sender, cmd);
+    }
+
+    // Queue an instruction, to be sent once receive returns, in the buffer of its instruction set.
+    fn enqueue<T: MachineImpl>(&mut self, sender: smol::channel::Sender<T>, cmd: T) {
+        let type_id = TypeId::of::<T>();
+        let index = match self.buffers.iter().position(|(id, _)| *id == type_id) {
+            Some(index) => index,
+            None => {
+                self.buffers.push((type_id, Box::new(SendContext::<T>::default())));
+                self.buffers.len() - 1
+            },
+        };
+        if let Some(buffer) = self.buffers[index].1.as_any_mut().downcast_mut::<SendContext<T>>() {
+            buffer.0.push_back((sender, cmd));
+            self.queue.push(index);
+        }
+    }
+
+    // Send the instructions queued, in the order they were sent. An instruction is sent without
+    // waiting, or allocating, unless its receiver is full.
+    async fn flush(&mut self) {
+        let Self { queue, buffers, .. } = self;
+        for index in queue.drain(..) {
+            if let Some(send) = buffers[index].1.send_next() {
+                send.await;
+            }
+        }
+    }
+
+    /// Send an instruction to another machine. When nothing is queued and the receiver has room, the
+    /// instruction is sent immediately, otherwise it is queued as with send(), so that the order of
+    /// instructions is preserved.
+    pub fn send_now<T: MachineImpl>(&mut self, sender: &smol::channel::Sender<T>, cmd: T) {
+        trace::record_send(&cmd);
+        if self.queue.is_empty() {
+            match sender.try_send(cmd) {
+                Err(smol::channel::TrySendError::Full(cmd)) => self.enqueue(sender.clone(), cmd),
+                Err(smol::channel::TrySendError::Closed(cmd)) => dropped_instructions::record_dropped(&cmd),
+                Ok(()) => (),
+            }
+        } else {
+            self.enqueue(sender.clone(), cmd);
+        }
+    }
+
+    /// Ask the adapter to yield once receive returns, letting other machines on the executor run
+    /// before this machine receives its next instruction.
+    pub fn yield_now(&mut self) { self.yield_requested = true; }
+
+    /// Check if receive has run longer than the receive time slice. A long-running receiver can
+    /// use this to split its work, sending the remainder to itself and calling yield_now().
+    pub fn checkpoint(&self) -> bool { self.get_elapsed() >= get_receive_time_slice() }
+
+    /// Get the time spent in the current receive.
+    pub fn get_elapsed(&self) -> Duration { self.received_at.map_or_else(|| Duration::from_secs(0), |at| at.elapsed()) }
+
+    // Prepare for receiving an instruction.
+    fn begin_receive(&mut self) {
+        self.queue.clear();
+        for (_, buffer) in self.buffers.iter_mut() {
+            buffer.clear();
+        }
+        self.yield_requested = false;
+        self.received_at = Some(Instant::now());
     }
 }
 
-// The SendContext contains a Sender and Instruction. Its used by the MachineSender.
-struct SendContext<T: MachineImpl>(::smol::channel::Sender<T>, Option<T>);
+// The SendContext holds the instructions of an instruction set, and their Senders, queued by the
+// MachineSender.
+struct SendContext<T: MachineImpl>(VecDeque<(smol::channel::Sender<T>, T)>);
+
+impl<T: MachineImpl> Default for SendContext<T> {
+    fn default() -> Self { Self(VecDeque::new()) }
+}
 
 // The implementation of SendContext, which erases the generic type T.
-#[async_trait]
-impl<T> AsyncSender for SendContext<T>
+impl<T> SendBuffer for SendContext<T>
 where
     T: MachineImpl,
 {
-    async fn do_send(&mut self) { self.0.send(self.1.take().unwrap()).await.ok(); }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+
+    fn send_next(&mut self) -> Option<runtime::BoxFuture> {
+        let (sender, cmd) = self.0.pop_front()?;
+        match sender.try_send(cmd) {
+            Ok(()) => None,
+            Err(smol::channel::TrySendError::Closed(cmd)) => {
+                dropped_instructions::record_dropped(&cmd);
+                None
+            },
+            Err(smol::channel::TrySendError::Full(cmd)) => Some(Box::pin(async move {
+                if let Err(err) = sender.send(cmd).await {
+                    dropped_instructions::record_dropped(&err.0);
+                }
+            })),
+        }
+    }
+
+    fn clear(&mut self) { self.0.clear(); }
 }
 
 // Seed for dispersing machines across executors.
@@ -83,40 +250,40 @@ static EXECUTOR_SEED: AtomicUsize = AtomicUsize::new(0);
 // The default number of threads to use. If 0, it will default to the number of CPUs available.
 static default_num_threads: AtomicCell<usize> = AtomicCell::new(0);
 
-/// The executors, as a tupple of: executors, join handles, and a sender.
-/// When the sender is closed the executors will terminate.
-#[allow(clippy::type_complexity)]
-static EXECUTOR: Lazy<(
-    Vec<Arc<::smol::Executor<'_>>>,
-    Vec<thread::JoinHandle<()>>,
-    smol::channel::Sender<()>,
-)> = Lazy::new(|| {
-    let handles: Vec<thread::JoinHandle<()>> = Vec::new();
-    let (s, r) = ::smol::channel::unbounded::<()>();
-    let mut executors: Vec<Arc<::smol::Executor<'_>>> = Vec::new();
+/// The pool of executors, which is started with the default number of threads, and can then be
+/// resized. When stopped the executors will terminate.
+static EXECUTOR: Lazy<Arc<executor_pool::ExecutorPool>> = Lazy::new(|| {
     let mut num_threads = default_num_threads.load();
     if num_threads == 0 {
         num_threads = num_cpus::get();
     }
-
-    for n in 1 ..= num_threads {
-        let e = Arc::new(::smol::Executor::new());
-        let r = r.clone();
-        executors.push(e.clone());
-        thread::Builder::new()
-            .name(format!("executor-{}", n))
-            .spawn(move || loop {
-                catch_unwind(|| ::smol::future::block_on(e.run(async { r.recv().await }))).ok();
-            })
-            .expect("cannot spawn executor thread");
-    }
-    (executors.clone(), handles, s)
+    executor_pool::ExecutorPool::start(num_threads, true)
 });
 
 // core functions begin here
 
+#[allow(non_upper_case_globals)]
+// The time a receive may run before checkpoint() suggests yielding.
+static receive_time_slice: AtomicCell<Duration> = AtomicCell::new(Duration::from_millis(10));
+
+#[allow(non_upper_case_globals)]
+// The time a receive may run before a warning is logged.
+static receive_warning_threshold: AtomicCell<Duration> = AtomicCell::new(Duration::from_millis(100));
+
+/// Set the time a receive may run before MachineSender::checkpoint() returns true, returning the
+/// previous value.
+pub fn set_receive_time_slice(time_slice: Duration) -> Duration { receive_time_slice.swap(time_slice) }
+/// Get the time a receive may run before MachineSender::checkpoint() returns true.
+pub fn get_receive_time_slice() -> Duration { receive_time_slice.load() }
+
+/// Set the time a receive may run before the adapter logs a warning, returning the previous value.
+pub fn set_receive_warning_threshold(threshold: Duration) -> Duration { receive_warning_threshold.swap(threshold) }
+/// Get the time a receive may run before the adapter logs a warning.
+pub fn get_receive_warning_threshold() -> Duration { receive_warning_threshold.load() }
+
 /// Set the default number of threads to use, returning the previous value. If 0, the framework will default to the
-/// number of CPUs available.
+/// number of CPUs available. Once the executors have started, use add_executor_threads() and
+/// remove_executor_threads() instead.
 pub fn set_default_num_threads(num_threads: usize) -> usize {
     let res = get_default_num_threads();
     default_num_threads.store(num_threads);
@@ -126,16 +293,120 @@ pub fn set_default_num_threads(num_threads: usize) -> usize {
 /// number of CPUs available.
 pub fn get_default_num_threads() -> usize { default_num_threads.load() }
 
-/// Get an executor, selecting one of the executors in the pool of executors.
-pub fn get_executor() -> Arc<smol::Executor<'static>> {
-    let next = EXECUTOR_SEED.fetch_add(1, Ordering::SeqCst);
-    let idx = next % EXECUTOR.0.len();
-    EXECUTOR.0[idx].clone()
-}
+/// Get an executor, selecting one of the executors in the pool of executors, see
+/// set_executor_selection().
+pub fn get_executor() -> Arc<InstrumentedExecutor> { EXECUTOR.get_executor(EXECUTOR_SEED.fetch_add(1, Ordering::SeqCst)) }
 
-pub fn stop_executors() { EXECUTOR.2.close(); }
+pub fn stop_executors() { EXECUTOR.stop(); }
+
+/// Stop the executors gracefully, waiting for their in-flight tasks to complete before stopping
+/// them, as stop_executors() does, then joining their threads. The waiting ends once the timeout
+/// has passed. Returns the number of tasks abandoned, which hadn't completed. A machine is a task
+/// for as long as it is connected, so machines should be disconnected beforehand.
+pub fn stop_executors_and_wait(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    let get_pending = || {
+        EXECUTOR
+            .get_executors()
+            .iter()
+            .map(|executor| executor.get_pending_count())
+            .sum::<usize>()
+    };
+    while get_pending() > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    stop_executors();
+    let running = EXECUTOR.join(deadline);
+    let abandoned = get_pending();
+    if abandoned > 0 || running > 0 {
+        log::warn!(
+            "executors stopped, abandoned_tasks={} unjoined_threads={} after timeout={:#?}",
+            abandoned,
+            running,
+            timeout
+        );
+    }
+    abandoned
+}
 
 #[cfg(test)]
 mod tests {
-    // While its unlikely there will be any tests, it doesn't hurt to leave this here.
+    use super::*;
+
+    #[test]
+    fn sender_checkpoint() {
+        let mut sender = MachineSender::default();
+        assert!(!sender.checkpoint());
+        sender.begin_receive();
+        assert!(!sender.checkpoint());
+        sender.received_at = Some(Instant::now() - get_receive_time_slice());
+        assert!(sender.checkpoint());
+        sender.yield_now();
+        assert!(sender.yield_requested);
+        sender.begin_receive();
+        assert!(!sender.yield_requested);
+    }
+
+    #[derive(Clone)]
+    struct Bob;
+    impl MachineImpl for Bob {
+        type Adapter = ();
+        type InstructionSet = Bob;
+    }
+
+    #[test]
+    fn sender_send_now() {
+        let mut sender = MachineSender::default();
+        let (bob, receiver) = smol::channel::bounded::<Bob>(1);
+        sender.send_now(&bob, Bob);
+        assert_eq!(0, sender.queue.len());
+        assert_eq!(1, receiver.len());
+        sender.send_now(&bob, Bob);
+        assert_eq!(1, sender.queue.len());
+        receiver.try_recv().ok();
+        // once something is queued, the order is preserved by queuing
+        sender.send_now(&bob, Bob);
+        assert_eq!(2, sender.queue.len());
+        assert_eq!(0, receiver.len());
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Carol(usize);
+    impl MachineImpl for Carol {
+        type Adapter = ();
+        type InstructionSet = Carol;
+    }
+
+    #[test]
+    fn reuse_send_buffers() {
+        let mut sender = MachineSender::default();
+        let (bob, bobs) = smol::channel::unbounded::<Bob>();
+        let (carol, carols) = smol::channel::bounded::<Carol>(1);
+        for round in 0 .. 2 {
+            sender.begin_receive();
+            for n in 0 .. 3 {
+                sender.send(carol.clone(), Carol(n));
+                sender.send(bob.clone(), Bob);
+            }
+            assert_eq!(6, sender.queue.len());
+            // carol is full after the first, the rest are sent once she has room
+            let ((), received) = smol::block_on(async {
+                let flush = sender.flush();
+                let receive = async {
+                    let mut received = Vec::new();
+                    while received.len() < 3 {
+                        received.push(carols.recv().await.unwrap());
+                    }
+                    received
+                };
+                smol::future::zip(flush, receive).await
+            });
+            assert_eq!(vec![Carol(0), Carol(1), Carol(2)], received, "round {}", round);
+            assert_eq!(3, bobs.len());
+            while bobs.try_recv().is_ok() {}
+            // a buffer for each instruction set, kept from one receive to the next
+            assert_eq!(2, sender.buffers.len());
+            assert!(sender.queue.is_empty());
+        }
+    }
 }
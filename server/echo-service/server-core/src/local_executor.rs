@@ -0,0 +1,188 @@
+use super::*;
+
+/// The LocalMachine trait is implemented by a machine model which isn't Send, or Sync, such as one
+/// wrapping an Rc based parser, or an FFI handle, for each instruction set that it supports. Such
+/// a machine is created upon a LocalExecutorThread, and stays on that thread, while its Sender
+/// is used as any other machine's.
+pub trait LocalMachine<T>: 'static
+where
+    T: 'static + Send + Sync,
+{
+    fn receive(&self, cmd: T, sender: &mut MachineSender);
+    fn disconnected(&self) {}
+    fn connected(&self, _uuid: uuid::Uuid) {}
+}
+
+// A job run by a local executor thread, which creates a machine upon it.
+type LocalJob = Box<dyn FnOnce(&smol::LocalExecutor<'static>) + Send>;
+
+/// A LocalExecutorThread is a dedicated thread, running a smol LocalExecutor, for machines which
+/// aren't Send. A machine is created upon the thread, from a factory, and runs there until its
+/// channel is closed. The thread is stopped when dropped, along with any machines still running
+/// on it.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::{LocalExecutorThread, LocalMachine, MachineImpl, MachineSender};
+/// use std::{cell::Cell, rc::Rc};
+///
+/// #[derive(Debug, Clone)]
+/// struct Count(smol::channel::Sender<usize>);
+/// impl MachineImpl for Count {
+///     type Adapter = Self;
+///     type InstructionSet = Self;
+/// }
+///
+/// struct Counter(Rc<Cell<usize>>);
+/// impl LocalMachine<Count> for Counter {
+///     fn receive(&self, cmd: Count, _sender: &mut MachineSender) {
+///         self.0.set(self.0.get() + 1);
+///         cmd.0.try_send(self.0.get()).ok();
+///     }
+/// }
+///
+/// let thread = LocalExecutorThread::new("parser");
+/// let counter = thread.create(smol::channel::unbounded(), || Counter(Rc::new(Cell::new(0))));
+/// let (reply, replies) = smol::channel::unbounded();
+/// smol::block_on(async {
+///     counter.send(Count(reply.clone())).await.ok();
+///     counter.send(Count(reply)).await.ok();
+///     assert_eq!(Ok(1), replies.recv().await);
+///     assert_eq!(Ok(2), replies.recv().await);
+/// });
+/// ```
+pub struct LocalExecutorThread {
+    name: String,
+    jobs: smol::channel::Sender<LocalJob>,
+}
+
+impl fmt::Debug for LocalExecutorThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "#LocalExecutorThread {{ name: {} }}", self.name) }
+}
+
+impl LocalExecutorThread {
+    /// Create a thread, with the name, to run machines which aren't Send.
+    pub fn new(name: &str) -> Self {
+        let (jobs, pending) = smol::channel::unbounded::<LocalJob>();
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let executor = smol::LocalExecutor::new();
+                smol::future::block_on(executor.run(async {
+                    while let Ok(job) = pending.recv().await {
+                        job(&executor);
+                    }
+                }));
+            })
+            .expect("cannot spawn local executor thread");
+        Self {
+            name: name.to_string(),
+            jobs,
+        }
+    }
+
+    /// Get the name of the thread.
+    pub fn get_name(&self) -> &str { &self.name }
+
+    /// Create a machine upon the thread, from the factory, which is called on the thread. The
+    /// Sender for the machine is returned, the instructions sent are queued until it is created.
+    pub fn create<T, M, F>(&self, channel: (smol::channel::Sender<T>, smol::channel::Receiver<T>), factory: F) -> smol::channel::Sender<T>
+    where
+        T: MachineImpl,
+        M: LocalMachine<T>,
+        F: FnOnce() -> M + Send + 'static,
+    {
+        let (sender, receiver) = channel;
+        let job: LocalJob = Box::new(move |executor: &smol::LocalExecutor<'static>| {
+            let machine = factory();
+            executor.spawn(run_local_machine(machine, receiver)).detach();
+        });
+        if self.jobs.try_send(job).is_err() {
+            log::warn!("local executor thread {} has stopped, machine not created", self.name);
+        }
+        sender
+    }
+}
+
+impl Drop for LocalExecutorThread {
+    fn drop(&mut self) { self.jobs.close(); }
+}
+
+// Run a local machine, as the adapter runs a machine, until its channel is closed.
+async fn run_local_machine<T: MachineImpl, M: LocalMachine<T>>(machine: M, receiver: smol::channel::Receiver<T>) {
+    let id = Uuid::new_v4();
+    let context = MachineContext::new::<T>(id);
+    let mut queue = adaptive_capacity::AdaptiveQueue::new(receiver.clone(), context, get_adaptive_channel_max());
+    let _registration = machine_registry::register(id, None, receiver, queue.get_stats());
+    context.enter_with(|| machine.connected(id));
+    let mut sender = MachineSender::default();
+    while let Some(cmd) = queue.recv().await {
+        sender.begin_receive();
+        let context = context.with_instruction(cmd.variant_name());
+        context.in_span(|| receive_watch::watch(context, || context.enter_with(|| machine.receive(cmd, &mut sender))));
+        trace::set_correlation(None);
+        scratch::reset_scratch();
+        sender.flush().await;
+        if sender.yield_requested || !queue.is_empty() {
+            smol::future::yield_now().await;
+        }
+    }
+    context.enter_with(|| machine.disconnected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Debug, Clone)]
+    enum Parse {
+        Line(String, smol::channel::Sender<(String, Vec<String>)>),
+    }
+    impl MachineImpl for Parse {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+
+    // The parser keeps its lines in an Rc, so it isn't Send.
+    struct Parser {
+        lines: Rc<RefCell<Vec<String>>>,
+    }
+    impl LocalMachine<Parse> for Parser {
+        fn receive(&self, cmd: Parse, _sender: &mut MachineSender) {
+            let Parse::Line(line, reply) = cmd;
+            self.lines.borrow_mut().push(line);
+            let thread = thread::current().name().unwrap_or_default().to_string();
+            reply.try_send((thread, self.lines.borrow().clone())).ok();
+        }
+    }
+
+    #[test]
+    fn create_local_machine() {
+        let local = LocalExecutorThread::new("local-parser");
+        let parser = local.create(smol::channel::bounded(4), || Parser {
+            lines: Rc::new(RefCell::new(Vec::new())),
+        });
+        let (reply, replies) = smol::channel::unbounded();
+        smol::block_on(async {
+            parser.send(Parse::Line("a".to_string(), reply.clone())).await.ok();
+            parser.send(Parse::Line("b".to_string(), reply.clone())).await.ok();
+            assert_eq!(Ok(("local-parser".to_string(), vec!["a".to_string()])), replies.recv().await);
+            assert_eq!(
+                Ok(("local-parser".to_string(), vec!["a".to_string(), "b".to_string()])),
+                replies.recv().await
+            );
+        });
+        assert!(get_machines()
+            .iter()
+            .any(|info| info.instruction_set.ends_with("Parse") && info.executor.is_none()));
+        // once the thread is stopped, the machine is gone with it
+        drop(local);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !parser.is_closed() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(parser.is_closed());
+    }
+}
@@ -1,12 +1,17 @@
 #![allow(dead_code)]
 use super::*;
 
+// The number of instructions a machine may receive per turn, for each unit of weight.
+const INSTRUCTIONS_PER_WEIGHT: usize = 16;
+
 /// The MachineAdapter binds the machine, its receiver, and an executor together.
 pub struct MachineAdapter<T: MachineImpl> {
     id: Uuid,
     pub machine: Arc<dyn Machine<T>>,
-    pub executor: Arc<::smol::Executor<'static>>,
+    pub executor: Arc<InstrumentedExecutor>,
     pub receiver: smol::channel::Receiver<T>,
+    pub priority: Priority,
+    pub adaptive_max: Option<usize>,
 }
 
 impl<T: MachineImpl> std::fmt::Debug for MachineAdapter<T> {
@@ -15,37 +20,91 @@ impl<T: MachineImpl> std::fmt::Debug for MachineAdapter<T> {
 
 impl<T: MachineImpl> MachineAdapter<T> {
     // Construct a new MachineAdpter from its components.
-    pub fn new(machine: Arc<dyn Machine<T>>, executor: Arc<::smol::Executor<'static>>, receiver: ::smol::channel::Receiver<T>) -> Self {
+    pub fn new(machine: Arc<dyn Machine<T>>, executor: Arc<InstrumentedExecutor>, receiver: ::smol::channel::Receiver<T>) -> Self {
         let id = Uuid::new_v4();
         Self {
             id,
             machine,
             executor,
             receiver,
+            priority: Priority::default(),
+            adaptive_max: None,
         }
     }
 
+    /// Set the priority of the task which drives the machine, once started.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the capacity the queue may grow to, if bounded, rather than that set by
+    /// set_adaptive_channel_max().
+    pub fn with_adaptive_max(mut self, capacity: usize) -> Self {
+        self.adaptive_max = Some(capacity);
+        self
+    }
+
+    /// Get the id of the adapter, as passed to connected().
+    pub fn get_id(&self) -> Uuid { self.id }
+
     // Start a Machine running. Once started, it runs until its receiver is closed.
     pub fn start(self) -> Arc<MachineAdapter<T>> {
         let r = self.receiver.clone();
         let machine = self.machine.clone();
         let id = self.id;
+        let context = MachineContext::new::<T>(id);
         let adapter = Arc::new(self);
-        adapter
-            .executor
-            .spawn(async move {
-                machine.connected(id);
-                let mut sender = MachineSender::default();
-                while let Ok(cmd) = r.recv().await {
-                    sender.queue.clear();
-                    machine.receive(cmd, &mut sender);
-                    for s in sender.queue.iter_mut() {
-                        s.do_send().await;
-                    }
+        let adaptive_max = adapter.adaptive_max.unwrap_or_else(get_adaptive_channel_max);
+        let mut queue = adaptive_capacity::AdaptiveQueue::new(r.clone(), context, adaptive_max);
+        let registration = machine_registry::register(id, Some(&adapter.executor), r.clone(), queue.get_stats());
+        let task = async move {
+            context.enter_with(|| machine.connected(id));
+            let governor = machine.governor();
+            let quota = machine.weight().max(1) * INSTRUCTIONS_PER_WEIGHT;
+            let mut received = 0;
+            let mut sender = MachineSender::default();
+            loop {
+                if let Some(governor) = &governor {
+                    governor.acquire().await;
+                }
+                let cmd = match queue.recv().await {
+                    Some(cmd) => cmd,
+                    None => break,
+                };
+                sender.begin_receive();
+                let context = context.with_instruction(cmd.variant_name());
+                context.in_span(|| receive_watch::watch(context, || context.enter_with(|| machine.receive(cmd, &mut sender))));
+                trace::set_correlation(None);
+                scratch::reset_scratch();
+                let elapsed = sender.get_elapsed();
+                if elapsed > get_receive_warning_threshold() {
+                    log::warn!(
+                        "slow receive machine={} instruction_set={} instruction={} elapsed={:?}",
+                        context.id,
+                        context.instruction_set,
+                        context.instruction,
+                        elapsed
+                    );
+                }
+                sender.flush().await;
+                // A turn ends when the machine asks to yield, the queue is empty, or the quota
+                // is used up. Other than an empty queue, yield so other machines get their turn.
+                received += 1;
+                if sender.yield_requested {
+                    received = 0;
+                    smol::future::yield_now().await;
+                } else if queue.is_empty() {
+                    received = 0;
+                } else if received >= quota {
+                    received = 0;
+                    smol::future::yield_now().await;
                 }
-                machine.disconnected();
-            })
-            .detach();
+            }
+            context.enter_with(|| machine.disconnected());
+            drop(registration);
+        };
+        runtime::get_runtime().spawn_machine(&adapter.executor, adapter.priority, Box::pin(task));
         adapter
     }
 }
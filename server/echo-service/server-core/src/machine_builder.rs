@@ -23,10 +23,30 @@ pub trait MachineBuilder {
         T: 'static + Machine<Self::InstructionSet>,
         <Self as MachineBuilder>::InstructionSet: Send,
     {
-        let channel = ::smol::channel::bounded::<Self::InstructionSet>(capacity);
+        let channel = runtime::machine_channel::<Self::InstructionSet>(Some(capacity));
         Self::prepare_create(machine, channel)
     }
 
+    /// Create a machine with a bounded queue, whose capacity adapts between capacity and max, rather
+    /// than the limit set by set_adaptive_channel_max().
+    fn bounded_adaptive<T>(
+        machine: T, capacity: usize, max: usize,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<Self::InstructionSet>,
+        SharedMachineAdapter<Self::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<Self::InstructionSet>,
+        <Self as MachineBuilder>::InstructionSet: Send,
+    {
+        let (s, r) = runtime::machine_channel::<Self::InstructionSet>(Some(capacity));
+        let machine: SharedMachine<T> = Arc::new(machine);
+        let shared = Arc::clone(&machine) as Arc<dyn Machine<Self::InstructionSet>>;
+        let adapter = MachineAdapter::new(shared, get_executor(), r).with_adaptive_max(max).start();
+        (machine, s, adapter)
+    }
+
     /// Extend a created machine with an additional instruction set, with a bounded queue.
     fn extend_bounded<T>(
         machine: &Arc<T>, capacity: usize,
@@ -38,7 +58,7 @@ pub trait MachineBuilder {
         T: 'static + Machine<Self::InstructionSet>,
         <Self as MachineBuilder>::InstructionSet: Send,
     {
-        let channel = ::smol::channel::bounded::<Self::InstructionSet>(capacity);
+        let channel = runtime::machine_channel::<Self::InstructionSet>(Some(capacity));
         Self::prepare_extend(machine, channel)
     }
 
@@ -54,7 +74,7 @@ pub trait MachineBuilder {
         T: 'static + Machine<Self::InstructionSet>,
         <Self as MachineBuilder>::InstructionSet: Send,
     {
-        let channel = ::smol::channel::unbounded::<Self::InstructionSet>();
+        let channel = runtime::machine_channel::<Self::InstructionSet>(None);
         Self::prepare_create(machine, channel)
     }
 
@@ -69,7 +89,7 @@ pub trait MachineBuilder {
         T: 'static + Machine<Self::InstructionSet>,
         <Self as MachineBuilder>::InstructionSet: Send,
     {
-        let channel = ::smol::channel::unbounded::<Self::InstructionSet>();
+        let channel = runtime::machine_channel::<Self::InstructionSet>(None);
         Self::prepare_extend(machine, channel)
     }
 
@@ -129,7 +149,90 @@ pub trait MachineBuilder {
     {
         let machine = Arc::clone(machine) as Arc<dyn Machine<Self::InstructionSet>>;
         let executor = get_executor();
-        Self::create_adapter(machine, channel, executor)
+        Self::create_adapter(machine, channel, executor, Priority::default())
+    }
+
+    /// Create a machine, run by the executor given, such as one of an ExecutorGroup, rather than
+    /// one of the global pool.
+    fn create_on<T>(
+        machine: T,
+        channel: (
+            ::smol::channel::Sender<Self::InstructionSet>,
+            ::smol::channel::Receiver<Self::InstructionSet>,
+        ),
+        executor: Arc<InstrumentedExecutor>,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<Self::InstructionSet>,
+        SharedMachineAdapter<Self::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<Self::InstructionSet>,
+    {
+        let machine: SharedMachine<T> = Arc::new(machine);
+        let (sender, adapter) = Self::extend_on(&machine, channel, executor);
+        (machine, sender, adapter)
+    }
+
+    /// Extend a created machine with an additional instruction set, run by the executor given.
+    fn extend_on<T>(
+        machine: &Arc<T>,
+        channel: (
+            ::smol::channel::Sender<Self::InstructionSet>,
+            ::smol::channel::Receiver<Self::InstructionSet>,
+        ),
+        executor: Arc<InstrumentedExecutor>,
+    ) -> (
+        ::smol::channel::Sender<Self::InstructionSet>,
+        SharedMachineAdapter<Self::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<Self::InstructionSet>,
+    {
+        let machine = Arc::clone(machine) as Arc<dyn Machine<Self::InstructionSet>>;
+        Self::create_adapter(machine, channel, executor, Priority::default())
+    }
+
+    /// Create a machine, whose adapter runs with the priority given, such as High for a machine of
+    /// the control-plane, whose instructions should be received ahead of bulk data traffic.
+    fn create_with_priority<T>(
+        machine: T,
+        channel: (
+            ::smol::channel::Sender<Self::InstructionSet>,
+            ::smol::channel::Receiver<Self::InstructionSet>,
+        ),
+        priority: Priority,
+    ) -> (
+        SharedMachine<T>,
+        ::smol::channel::Sender<Self::InstructionSet>,
+        SharedMachineAdapter<Self::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<Self::InstructionSet>,
+    {
+        let machine: SharedMachine<T> = Arc::new(machine);
+        let (sender, adapter) = Self::extend_with_priority(&machine, channel, priority);
+        (machine, sender, adapter)
+    }
+
+    /// Extend a created machine with an additional instruction set, whose adapter runs with the
+    /// priority given.
+    fn extend_with_priority<T>(
+        machine: &Arc<T>,
+        channel: (
+            ::smol::channel::Sender<Self::InstructionSet>,
+            ::smol::channel::Receiver<Self::InstructionSet>,
+        ),
+        priority: Priority,
+    ) -> (
+        ::smol::channel::Sender<Self::InstructionSet>,
+        SharedMachineAdapter<Self::InstructionSet>,
+    )
+    where
+        T: 'static + Machine<Self::InstructionSet>,
+    {
+        let machine = Arc::clone(machine) as Arc<dyn Machine<Self::InstructionSet>>;
+        Self::create_adapter(machine, channel, get_executor(), priority)
     }
 
     /// Create the adapter, which drives received instructions into the machine.
@@ -139,13 +242,13 @@ pub trait MachineBuilder {
             ::smol::channel::Sender<Self::InstructionSet>,
             ::smol::channel::Receiver<Self::InstructionSet>,
         ),
-        executor: Arc<::smol::Executor<'static>>,
+        executor: Arc<InstrumentedExecutor>, priority: Priority,
     ) -> (
         ::smol::channel::Sender<Self::InstructionSet>,
         SharedMachineAdapter<Self::InstructionSet>,
     ) {
         let (s, r) = channel;
-        let adapter = MachineAdapter::new(machine, executor, r);
+        let adapter = MachineAdapter::new(machine, executor, r).with_priority(priority);
         let adapter = adapter.start();
         (s, adapter)
     }
@@ -0,0 +1,151 @@
+use super::*;
+use std::cell::Cell;
+
+/// The MachineContext identifies the machine running on the current thread. The adapter sets
+/// it while the machine is handling an instruction, so that profilers, panic hooks and logging
+/// can attribute work to a machine rather than to an anonymous async block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MachineContext {
+    /// The id of the machine, as passed to connected().
+    pub id: Uuid,
+    /// The type name of the instruction set being received.
+    pub instruction_set: &'static str,
+    /// The variant of the instruction being received, empty if not receiving an instruction.
+    pub instruction: &'static str,
+}
+
+thread_local! {
+    static CURRENT_MACHINE: Cell<Option<MachineContext>> = const { Cell::new(None) };
+}
+
+impl MachineContext {
+    /// Create a context for the machine with id, receiving the instruction set T.
+    pub fn new<T>(id: Uuid) -> Self {
+        Self {
+            id,
+            instruction_set: std::any::type_name::<T>(),
+            instruction: "",
+        }
+    }
+
+    /// Set the variant of the instruction being received.
+    pub fn with_instruction(mut self, instruction: &'static str) -> Self {
+        self.instruction = instruction;
+        self
+    }
+
+    /// Make this the current machine, until the returned guard is dropped.
+    pub fn enter(self) -> MachineContextGuard {
+        let previous = CURRENT_MACHINE.with(|current| current.replace(Some(self)));
+        MachineContextGuard { previous }
+    }
+
+    /// Run f with this as the current machine.
+    pub fn enter_with<R>(self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.enter();
+        f()
+    }
+
+    /// Run f, the machine receiving its instruction, within a span, named receive, which carries
+    /// the machine's id, its instruction set, and the instruction, for tracing subscribers.
+    #[cfg(feature = "tracing")]
+    pub fn in_span<R>(self, f: impl FnOnce() -> R) -> R {
+        tracing::debug_span!(
+            "receive",
+            machine = %self.id,
+            instruction_set = self.instruction_set,
+            instruction = self.instruction
+        )
+        .in_scope(f)
+    }
+
+    /// Run f, the machine receiving its instruction. It is within a span if the tracing feature is
+    /// enabled.
+    #[cfg(not(feature = "tracing"))]
+    pub fn in_span<R>(self, f: impl FnOnce() -> R) -> R { f() }
+}
+
+/// Get the machine running on the current thread, if any.
+pub fn current_machine() -> Option<MachineContext> { CURRENT_MACHINE.with(|current| current.get()) }
+
+/// The MachineContextGuard restores the previous machine context when dropped. It should not
+/// be held across an await, as the task may resume on another thread.
+#[derive(Debug)]
+pub struct MachineContextGuard {
+    previous: Option<MachineContext>,
+}
+
+impl Drop for MachineContextGuard {
+    fn drop(&mut self) { CURRENT_MACHINE.with(|current| current.set(self.previous)); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enter_and_restore() {
+        assert_eq!(None, current_machine());
+        let outer = MachineContext::new::<u8>(Uuid::new_v4());
+        let inner = MachineContext::new::<String>(Uuid::new_v4());
+        {
+            let _outer = outer.enter();
+            assert_eq!(Some(outer), current_machine());
+            {
+                let _inner = inner.enter();
+                assert_eq!("alloc::string::String", current_machine().unwrap().instruction_set);
+            }
+            assert_eq!(Some(outer), current_machine());
+        }
+        assert_eq!(None, current_machine());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn receive_in_span() {
+        use std::sync::Mutex;
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        type Spans = Arc<Mutex<Vec<(String, Vec<(String, String)>)>>>;
+        #[derive(Default)]
+        struct Fields(Vec<(String, String)>);
+        impl Visit for Fields {
+            fn record_str(&mut self, field: &Field, value: &str) { self.0.push((field.name().to_string(), value.to_string())); }
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0.push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+        // records the name, and fields, of each span created
+        #[derive(Default)]
+        struct Recorder(Spans);
+        impl Subscriber for Recorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool { true }
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name().to_string(), fields.0));
+                span::Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+            fn event(&self, _event: &Event<'_>) {}
+            fn enter(&self, _span: &span::Id) {}
+            fn exit(&self, _span: &span::Id) {}
+        }
+
+        let id = Uuid::new_v4();
+        let context = MachineContext::new::<u8>(id).with_instruction("Ping");
+        let recorder = Recorder::default();
+        let spans = recorder.0.clone();
+        tracing::subscriber::with_default(recorder, || assert_eq!(3, context.in_span(|| 3)));
+        let spans = spans.lock().unwrap();
+        assert_eq!(1, spans.len());
+        assert_eq!("receive", spans[0].0);
+        assert!(spans[0].1.contains(&("machine".to_string(), id.to_string())));
+        assert!(spans[0].1.contains(&("instruction".to_string(), "Ping".to_string())));
+    }
+}
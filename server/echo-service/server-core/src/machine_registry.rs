@@ -0,0 +1,321 @@
+use super::*;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::Mutex,
+};
+
+/// MachineInfo describes a live machine adapter. A machine which has been extended with
+/// additional instruction sets has an adapter for each of them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MachineInfo {
+    /// The id of the adapter, as passed to connected().
+    pub id: Uuid,
+    /// The type name of the instruction set the adapter receives.
+    pub instruction_set: &'static str,
+    /// The number of instructions waiting in the adapter's queue.
+    pub queue_len: usize,
+    /// The capacity of the adapter's queue, None if it is unbounded. It changes if adaptive, see
+    /// set_adaptive_channel_max().
+    pub capacity: Option<usize>,
+    /// The index of the executor running the adapter, None if it isn't one of the pool, such as
+    /// one of an ExecutorGroup, or a LocalExecutorThread.
+    pub executor: Option<usize>,
+}
+
+// What's kept of a live adapter, the queue length is read when asked for.
+struct Registration {
+    instruction_set: &'static str,
+    executor: Option<usize>,
+    queue_len: Box<dyn Fn() -> usize + Send>,
+    stats: Arc<adaptive_capacity::QueueStats>,
+}
+
+static MACHINES: Lazy<Mutex<HashMap<Uuid, Registration>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn lock_machines() -> std::sync::MutexGuard<'static, HashMap<Uuid, Registration>> { MACHINES.lock().unwrap_or_else(|err| err.into_inner()) }
+
+// The guard is owned by an adapter's task, when the task ends, or is dropped after a panic, the
+// adapter is deregistered, releasing the receiver the registry holds.
+pub(crate) struct RegistrationGuard(Uuid);
+
+impl Drop for RegistrationGuard {
+    fn drop(&mut self) { lock_machines().remove(&self.0); }
+}
+
+// Register a started adapter, which stays registered until the returned guard is dropped.
+pub(crate) fn register<T: MachineImpl>(
+    id: Uuid, executor: Option<&Arc<InstrumentedExecutor>>, receiver: smol::channel::Receiver<T>, stats: Arc<adaptive_capacity::QueueStats>,
+) -> RegistrationGuard {
+    instruction_sets::register_instruction_set::<T>();
+    let staged = stats.clone();
+    let registration = Registration {
+        instruction_set: std::any::type_name::<T>(),
+        executor: executor.and_then(|executor| EXECUTOR.get_executors().iter().position(|e| Arc::ptr_eq(e, executor))),
+        queue_len: Box::new(move || receiver.len() + staged.staged.load(Ordering::SeqCst)),
+        stats,
+    };
+    lock_machines().insert(id, registration);
+    RegistrationGuard(id)
+}
+
+/// Get the live machine adapters, ordered by instruction set.
+pub fn get_machines() -> Vec<MachineInfo> {
+    let mut machines: Vec<MachineInfo> = lock_machines()
+        .iter()
+        .map(|(id, registration)| MachineInfo {
+            id: *id,
+            instruction_set: registration.instruction_set,
+            queue_len: (registration.queue_len)(),
+            capacity: registration.stats.capacity.load(),
+            executor: registration.executor,
+        })
+        .collect();
+    machines.sort_by(|a, b| a.instruction_set.cmp(b.instruction_set).then(a.id.cmp(&b.id)));
+    machines
+}
+
+/// NamedMachine describes a sender registered by name, either local, or resolved for a machine on
+/// another node.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NamedMachine {
+    /// The name the sender is registered by.
+    pub name: String,
+    /// The type name of the instruction set the sender sends.
+    pub instruction_set: &'static str,
+    /// The node the machine is on, None if it is local.
+    pub node: Option<String>,
+}
+
+// What's kept of a named sender, the sender is a Sender<T> of the instruction set.
+struct Named {
+    instruction_set: &'static str,
+    node: Option<String>,
+    sender: Box<dyn Any + Send + Sync>,
+    is_closed: Box<dyn Fn() -> bool + Send + Sync>,
+    close: Box<dyn Fn() + Send + Sync>,
+}
+
+// A resolver of names to the senders of machines on other nodes, for an instruction set.
+type Resolver = Arc<dyn Fn(&str) -> Option<(String, Box<dyn Any + Send + Sync>)> + Send + Sync>;
+
+static NAMES: Lazy<Mutex<HashMap<String, Named>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static RESOLVERS: Lazy<Mutex<HashMap<TypeId, Resolver>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn lock_names() -> std::sync::MutexGuard<'static, HashMap<String, Named>> { NAMES.lock().unwrap_or_else(|err| err.into_inner()) }
+
+fn named<T: MachineImpl>(sender: smol::channel::Sender<T>, node: Option<String>) -> Named {
+    let closed = sender.clone();
+    let close = sender.clone();
+    Named {
+        instruction_set: std::any::type_name::<T>(),
+        node,
+        sender: Box::new(sender),
+        is_closed: Box::new(move || closed.is_closed()),
+        close: Box::new(move || {
+            close.close();
+        }),
+    }
+}
+
+/// Register the sender of a local machine by name, replacing any sender registered by it. The name
+/// is released once the machine's channel is closed, or by unregister_name().
+pub fn register_name<T: MachineImpl>(name: &str, sender: smol::channel::Sender<T>) {
+    lock_names().insert(name.to_string(), named(sender, None));
+}
+
+/// Release a name, whether of a local machine, or one resolved on another node.
+pub fn unregister_name(name: &str) { lock_names().remove(name); }
+
+/// Set the resolver of the names of machines on other nodes, for the instruction set, returning the
+/// node, and a sender which routes to the machine, such as via the bridge to the node. A service can
+/// then look up a peer by name, whether it's local, or remote. The sender resolved is kept until its
+/// channel is closed, or the name is released.
+pub fn set_remote_resolver<T, F>(resolver: F)
+where
+    T: MachineImpl,
+    F: Fn(&str) -> Option<(String, smol::channel::Sender<T>)> + Send + Sync + 'static,
+{
+    let resolver: Resolver =
+        Arc::new(move |name: &str| resolver(name).map(|(node, sender)| (node, Box::new(sender) as Box<dyn Any + Send + Sync>)));
+    RESOLVERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(TypeId::of::<T>(), resolver);
+}
+
+/// Look up the sender of the machine registered by name, for the instruction set. A local machine is
+/// found first, otherwise the name is resolved on the other nodes, if there is a resolver for the
+/// instruction set. None if there is no such machine, or it is of another instruction set.
+pub fn lookup<T: MachineImpl>(name: &str) -> Option<smol::channel::Sender<T>> {
+    {
+        let mut names = lock_names();
+        match names.get(name) {
+            Some(named) if (named.is_closed)() => {
+                names.remove(name);
+            },
+            Some(named) => return named.sender.downcast_ref::<smol::channel::Sender<T>>().cloned(),
+            None => (),
+        }
+    }
+    // the resolver is called without the lock, as it may take a while
+    let resolver = RESOLVERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get(&TypeId::of::<T>())
+        .cloned()?;
+    let (node, sender) = resolver(name)?;
+    let sender = sender.downcast::<smol::channel::Sender<T>>().ok()?;
+    log::debug!("machine name={} resolved on node={}", name, node);
+    lock_names().insert(name.to_string(), named(sender.as_ref().clone(), Some(node)));
+    Some(*sender)
+}
+
+/// Evict the machines resolved on a node, such as when the failure detector of the membership layer
+/// marks the node dead, returning the number evicted. The channel of each sender resolved is closed,
+/// so a holder's sends fail, rather than queueing for a node which is gone, and those sent via a
+/// MachineSender are counted as dropped, see get_dropped_instructions(). A name evicted is resolved
+/// afresh when next looked up, such as to the node which has taken over the machine.
+pub fn evict_node(node: &str) -> usize {
+    let mut names = lock_names();
+    let evicted: Vec<String> = names
+        .iter()
+        .filter(|(_, named)| named.node.as_deref() == Some(node))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in &evicted {
+        if let Some(named) = names.remove(name) {
+            (named.close)();
+        }
+    }
+    if !evicted.is_empty() {
+        log::warn!("node={} evicted, machines={:?}", node, evicted);
+    }
+    evicted.len()
+}
+
+/// Get the senders registered by name, ordered by name, less those whose channel has closed.
+pub fn get_names() -> Vec<NamedMachine> {
+    let mut names = lock_names();
+    names.retain(|_, named| !(named.is_closed)());
+    let mut names: Vec<NamedMachine> = names
+        .iter()
+        .map(|(name, named)| NamedMachine {
+            name: name.clone(),
+            instruction_set: named.instruction_set,
+            node: named.node.clone(),
+        })
+        .collect();
+    names.sort_by(|a, b| a.name.cmp(&b.name));
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    enum Probe {
+        Wait(smol::channel::Receiver<()>),
+    }
+    impl MachineImpl for Probe {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+    impl MachineBuilder for Probe {
+        type InstructionSet = Self;
+    }
+
+    struct Prober;
+    impl Machine<Probe> for Prober {
+        fn receive(&self, cmd: Probe, _sender: &mut MachineSender) {
+            let Probe::Wait(release) = cmd;
+            smol::block_on(release.recv()).ok();
+        }
+    }
+
+    #[test]
+    fn lists_live_machines() {
+        let (_machine, sender, adapter) = <Probe as MachineBuilder>::unbounded(Prober);
+        let (release, wait) = smol::channel::unbounded();
+        // the first blocks the machine, so the second is queued
+        sender.try_send(Probe::Wait(wait.clone())).ok();
+        sender.try_send(Probe::Wait(wait)).ok();
+        std::thread::sleep(Duration::from_millis(20));
+        let info = get_machines().into_iter().find(|info| info.id == adapter.get_id()).unwrap();
+        assert!(info.instruction_set.ends_with("Probe"));
+        assert_eq!(1, info.queue_len);
+        assert!(info.executor.is_some());
+
+        release.close();
+        drop(sender);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(get_machines().iter().all(|info| info.id != adapter.get_id()));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Greet {
+        Hello,
+    }
+    impl MachineImpl for Greet {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+
+    #[test]
+    fn lookup_local_and_remote() {
+        let (local, local_receiver) = smol::channel::unbounded::<Greet>();
+        register_name("greeter-local", local);
+        assert!(lookup::<Greet>("greeter-local").unwrap().try_send(Greet::Hello).is_ok());
+        assert!(local_receiver.try_recv().is_ok());
+        // a name of another instruction set isn't found
+        assert!(lookup::<Probe>("greeter-local").is_none());
+
+        let (remote, remote_receiver) = smol::channel::unbounded::<Greet>();
+        set_remote_resolver::<Greet, _>(move |name| match name {
+            "greeter-remote" => Some(("node-2".to_string(), remote.clone())),
+            _ => None,
+        });
+        assert!(lookup::<Greet>("greeter-remote").unwrap().try_send(Greet::Hello).is_ok());
+        assert!(remote_receiver.try_recv().is_ok());
+        assert!(lookup::<Greet>("greeter-missing").is_none());
+        let names: Vec<NamedMachine> = get_names().into_iter().filter(|n| n.name.starts_with("greeter")).collect();
+        assert_eq!(2, names.len());
+        assert_eq!((None, Some("node-2".to_string())), (names[0].node.clone(), names[1].node.clone()));
+
+        // a closed machine releases its name
+        local_receiver.close();
+        assert!(lookup::<Greet>("greeter-local").is_none());
+        unregister_name("greeter-remote");
+        assert!(get_names().iter().all(|n| !n.name.starts_with("greeter")));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Relay {
+        Forward,
+    }
+    impl MachineImpl for Relay {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+
+    #[test]
+    fn evict_dead_node() {
+        let (remote, _remote_receiver) = smol::channel::unbounded::<Relay>();
+        set_remote_resolver::<Relay, _>(move |name| Some((format!("node-{}", name), remote.clone())));
+        let relay = lookup::<Relay>("relay").unwrap();
+        let (local, _local_receiver) = smol::channel::unbounded::<Relay>();
+        register_name("relay-local", local.clone());
+        assert_eq!(0, evict_node("node-other"));
+        assert_eq!(1, evict_node("node-relay"));
+        // the holder's sends fail, while the local machine is untouched
+        assert!(relay.try_send(Relay::Forward).is_err());
+        assert!(local.try_send(Relay::Forward).is_ok());
+        let mut sender = MachineSender::default();
+        sender.send_now(&relay, Relay::Forward);
+        assert!(get_dropped_instructions()
+            .iter()
+            .any(|dropped| dropped.instruction_set.ends_with("Relay") && dropped.count == 1));
+        unregister_name("relay-local");
+    }
+}
@@ -0,0 +1,182 @@
+use super::*;
+use std::{
+    fs, panic,
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A MachinePanic is sent to the supervisor when a machine panics in receive, identifying the
+/// machine, and the instruction it was receiving.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MachinePanic {
+    /// The id of the machine, as passed to connected().
+    pub id: Uuid,
+    /// The type name of the instruction set being received.
+    pub instruction_set: &'static str,
+    /// The variant of the instruction being received.
+    pub instruction: &'static str,
+    /// The location of the panic.
+    pub location: String,
+    /// The panic message.
+    pub message: String,
+}
+
+// The supervisor, which is notified of each machine which panics.
+static SUPERVISOR: Lazy<Mutex<Option<smol::channel::Sender<MachinePanic>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Set the supervisor, returning the previous one. Once the panic hook is installed, the supervisor
+/// is sent a MachinePanic for each panic inside a machine, such that it can restart the machine, or
+/// shut down the server. It is never blocked upon, should it be full, the notification is dropped.
+pub fn set_panic_supervisor(supervisor: Option<smol::channel::Sender<MachinePanic>>) -> Option<smol::channel::Sender<MachinePanic>> {
+    std::mem::replace(&mut *SUPERVISOR.lock().unwrap_or_else(|err| err.into_inner()), supervisor)
+}
+
+// The directory crash reports are written to, if any.
+static CRASH_DIR: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+// The panic hook is installed once, however many times install_panic_hook() is called.
+static INSTALL: Once = Once::new();
+
+/// Set the directory crash reports are written to, returning the previous one. If None, no crash
+/// reports are written.
+pub fn set_crash_dir(crash_dir: Option<PathBuf>) -> Option<PathBuf> {
+    std::mem::replace(&mut *CRASH_DIR.lock().unwrap_or_else(|err| err.into_inner()), crash_dir)
+}
+
+/// Install a panic hook which logs the panic along with the context of the machine that was
+/// running, its id, instruction set and the instruction being received. Without it, a panic
+/// inside an executor is caught and the executor carries on, leaving little trace of the cause.
+/// If crash_dir is provided, a crash report is also written there, see set_crash_dir(), and if a
+/// supervisor is set, it is notified. The previously installed hook is still called. The hook is
+/// installed once, calling this again only sets the crash dir, if provided.
+pub fn install_panic_hook(crash_dir: Option<PathBuf>) {
+    if crash_dir.is_some() {
+        set_crash_dir(crash_dir);
+    }
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = info.payload().downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown".to_string()
+            };
+            let location = info.location().map_or_else(|| "unknown".to_string(), |l| l.to_string());
+            let report = crash_report(&message, &location);
+            log::error!("{}", report);
+            let crash_dir = CRASH_DIR.lock().unwrap_or_else(|err| err.into_inner()).clone();
+            if let Some(dir) = crash_dir {
+                write_crash_report(&dir, &report);
+            }
+            notify_supervisor(&message, &location);
+            previous(info);
+        }));
+    });
+}
+
+// Build a report of the panic.
+fn crash_report(message: &str, location: &str) -> String {
+    let thread = thread::current().name().unwrap_or("unnamed").to_string();
+    match current_machine() {
+        Some(context) => format!(
+            "panic thread={} machine={} instruction_set={} instruction={} location={} message={}",
+            thread, context.id, context.instruction_set, context.instruction, location, message
+        ),
+        None => format!("panic thread={} location={} message={}", thread, location, message),
+    }
+}
+
+// Notify the supervisor, if there is one, of a panic inside a machine.
+fn notify_supervisor(message: &str, location: &str) {
+    if let Some(context) = current_machine() {
+        if let Some(supervisor) = SUPERVISOR.lock().unwrap_or_else(|err| err.into_inner()).as_ref() {
+            let notification = MachinePanic {
+                id: context.id,
+                instruction_set: context.instruction_set,
+                instruction: context.instruction,
+                location: location.to_string(),
+                message: message.to_string(),
+            };
+            if supervisor.try_send(notification).is_err() {
+                log::warn!("panic supervisor full or closed, machine={}", context.id);
+            }
+        }
+    }
+}
+
+fn write_crash_report(dir: &Path, report: &str) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = dir.join(format!("crash-{}-{}.txt", secs, Uuid::new_v4()));
+    if let Err(err) = fs::write(&path, report) {
+        log::error!("failed to write crash report path={} error={}", path.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_machine_context() {
+        let dir = std::env::temp_dir().join(format!("crash-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        install_panic_hook(Some(dir.clone()));
+        // installing again doesn't add another hook, which would write another report
+        install_panic_hook(None);
+        let context = MachineContext::new::<u8>(Uuid::new_v4()).with_instruction("Red");
+        panic::catch_unwind(|| context.enter_with(|| panic!("boom"))).ok();
+
+        // other tests may panic, while the crash dir is set, only this test's report counts
+        let reports: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .filter(|report| report.contains(&context.id.to_string()))
+            .collect();
+        set_crash_dir(None);
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(1, reports.len());
+        assert!(reports[0].contains(&format!("machine={} instruction_set=u8 instruction=Red", context.id)));
+        assert!(reports[0].ends_with("message=boom"));
+    }
+
+    #[derive(Debug, Clone)]
+    struct Boom;
+    impl MachineImpl for Boom {
+        type Adapter = Self;
+        type InstructionSet = Self;
+        fn variant_name(&self) -> &'static str { "Boom" }
+    }
+    impl MachineBuilder for Boom {
+        type InstructionSet = Self;
+    }
+
+    struct Fragile;
+    impl Machine<Boom> for Fragile {
+        fn receive(&self, _cmd: Boom, _sender: &mut MachineSender) { panic!("fragile") }
+    }
+
+    #[test]
+    fn notify_supervisor_of_panic() {
+        install_panic_hook(None);
+        let (supervisor, notifications) = smol::channel::unbounded();
+        set_panic_supervisor(Some(supervisor));
+        let (_fragile, sender, adapter) = Boom::unbounded(Fragile);
+        smol::block_on(async {
+            sender.send(Boom).await.ok();
+            // other tests may panic inside their machines
+            loop {
+                let notification = notifications.recv().await.unwrap();
+                if notification.instruction_set.ends_with("Boom") {
+                    assert_eq!(adapter.get_id(), notification.id);
+                    assert_eq!("Boom", notification.instruction);
+                    assert_eq!("fragile", notification.message);
+                    break;
+                }
+            }
+        });
+        set_panic_supervisor(None);
+    }
+}
@@ -0,0 +1,87 @@
+use super::*;
+use std::sync::{Mutex, Weak};
+
+// The receive in progress on a thread, if any, and when it began.
+type Slot = AtomicCell<Option<(MachineContext, Instant)>>;
+
+/// Whether the receives in progress are watched.
+#[allow(non_upper_case_globals)]
+static watch_receives: AtomicCell<bool> = AtomicCell::new(false);
+
+// The slot of each thread which has received an instruction while watched.
+static SLOTS: Lazy<Mutex<Vec<Weak<Slot>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+thread_local! {
+    static SLOT: Arc<Slot> = {
+        let slot = Arc::new(Slot::new(None));
+        let mut slots = SLOTS.lock().unwrap_or_else(|err| err.into_inner());
+        slots.retain(|slot| slot.strong_count() > 0);
+        slots.push(Arc::downgrade(&slot));
+        slot
+    };
+}
+
+/// Set whether the receives in progress are watched, returning the previous setting, the default
+/// is not to. While watched, get_receives_in_progress() tells of each machine receiving an
+/// instruction, such as for a watchdog to find those which have stalled.
+pub fn set_watch_receives(watch: bool) -> bool { watch_receives.swap(watch) }
+
+/// Get whether the receives in progress are watched.
+pub fn get_watch_receives() -> bool { watch_receives.load() }
+
+/// Get the machines receiving an instruction, and for how long they have been, longest first.
+/// It is empty unless the receives are watched, see set_watch_receives().
+pub fn get_receives_in_progress() -> Vec<(MachineContext, Duration)> {
+    let mut receives: Vec<(MachineContext, Duration)> = SLOTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .filter_map(|slot| slot.upgrade()?.load())
+        .map(|(context, began)| (context, began.elapsed()))
+        .collect();
+    receives.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+    receives
+}
+
+// Clears the slot of the thread once the receive has returned, or panicked.
+struct Watched;
+
+impl Drop for Watched {
+    fn drop(&mut self) { SLOT.with(|slot| slot.store(None)); }
+}
+
+// Run a machine's receive, with its context, which is in progress until it returns, if watched.
+pub(crate) fn watch<R>(context: MachineContext, f: impl FnOnce() -> R) -> R {
+    if !get_watch_receives() {
+        return f();
+    }
+    SLOT.with(|slot| slot.store(Some((context, Instant::now()))));
+    let _watched = Watched;
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_receive_in_progress() {
+        let context = MachineContext::new::<u16>(Uuid::new_v4()).with_instruction("Stuck");
+        let previous = set_watch_receives(true);
+        let (entered, began) = smol::channel::bounded::<()>(1);
+        let (release, released) = smol::channel::bounded::<()>(1);
+        let receiving = thread::spawn(move || {
+            watch(context, || {
+                entered.try_send(()).ok();
+                smol::block_on(released.recv()).ok();
+            })
+        });
+        smol::block_on(began.recv()).ok();
+        let in_progress = || get_receives_in_progress().into_iter().any(|(c, _)| c == context);
+        assert!(in_progress());
+        release.try_send(()).ok();
+        receiving.join().unwrap();
+        assert!(!in_progress());
+        set_watch_receives(previous);
+    }
+}
@@ -0,0 +1,197 @@
+use super::*;
+use std::{future::Future, pin::Pin, sync::Mutex};
+
+/// A boxed future, as spawned, or slept upon, by a Runtime.
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// The Runtime runs the tasks of machines, and provides the timers they wait upon. By default, it
+/// is the smol runtime, the executors of the framework. Another runtime, such as tokio, can be set
+/// via set_runtime(), so that machines can be embedded into an application already running it,
+/// rather than running two runtimes.
+pub trait Runtime: fmt::Debug + Send + Sync {
+    /// Spawn a task, which runs until it completes.
+    fn spawn(&self, future: BoxFuture);
+
+    /// Spawn the task of a machine, the executor being the one selected for it, with the priority
+    /// of the machine. By default, the executor and priority are ignored, and the task is spawned.
+    fn spawn_machine(&self, _executor: &Arc<InstrumentedExecutor>, _priority: Priority, future: BoxFuture) { self.spawn(future) }
+
+    /// Return a future, which completes once the duration has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture;
+
+    /// Get the capacity of the channel of a machine being created, given the capacity requested,
+    /// None being unbounded. The channel itself is a smol::channel, whatever the runtime, as it
+    /// isn't bound to one, so a machine on tokio and one on smol can send to each other. A runtime
+    /// can override the capacity, such as to bound every queue of the application embedding it.
+    /// By default, the capacity requested is kept.
+    fn channel_capacity(&self, capacity: Option<usize>) -> Option<usize> { capacity }
+}
+
+/// Create the channel of a machine, with the capacity requested, None being unbounded, as
+/// overridden by the runtime.
+pub fn machine_channel<T>(capacity: Option<usize>) -> (smol::channel::Sender<T>, smol::channel::Receiver<T>) {
+    match get_runtime().channel_capacity(capacity) {
+        Some(capacity) => smol::channel::bounded(capacity),
+        None => smol::channel::unbounded(),
+    }
+}
+
+/// The SmolRuntime runs tasks on the executors of the framework, and is the default Runtime.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SmolRuntime;
+
+impl Runtime for SmolRuntime {
+    fn spawn(&self, future: BoxFuture) { get_executor().spawn(future).detach() }
+
+    fn spawn_machine(&self, executor: &Arc<InstrumentedExecutor>, priority: Priority, future: BoxFuture) {
+        executor.spawn_with_priority(priority, future).detach()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+}
+
+/// The TokioRuntime runs tasks on a tokio runtime, via its handle. It requires the tokio feature.
+///
+/// Examples:
+///
+/// ```rust,ignore
+/// #[tokio::main]
+/// async fn main() {
+///     server_core::set_runtime(std::sync::Arc::new(server_core::TokioRuntime::current()));
+///     // machines created from here on run on tokio
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct TokioRuntime {
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioRuntime {
+    /// Create a runtime, which spawns tasks via the handle.
+    pub fn new(handle: tokio::runtime::Handle) -> Self { Self { handle } }
+
+    /// Create a runtime, which spawns tasks via the handle of the tokio runtime currently running.
+    /// It panics if called outside of a tokio runtime.
+    pub fn current() -> Self { Self::new(tokio::runtime::Handle::current()) }
+}
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, future: BoxFuture) { drop(self.handle.spawn(future)) }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        // the timer is created on the runtime, as it may be awaited outside of it
+        let sleep = self.handle.spawn(async move { tokio::time::sleep(duration).await });
+        Box::pin(async move {
+            sleep.await.ok();
+        })
+    }
+}
+
+// The runtime which machines are spawned upon.
+static RUNTIME: Lazy<Mutex<Arc<dyn Runtime>>> = Lazy::new(|| Mutex::new(Arc::new(SmolRuntime)));
+
+/// Set the runtime, returning the previous one. Machines already created continue running on the
+/// runtime they were spawned upon.
+pub fn set_runtime(runtime: Arc<dyn Runtime>) -> Arc<dyn Runtime> {
+    std::mem::replace(&mut *RUNTIME.lock().unwrap_or_else(|err| err.into_inner()), runtime)
+}
+
+/// Get the runtime, which machines are spawned upon.
+pub fn get_runtime() -> Arc<dyn Runtime> { RUNTIME.lock().unwrap_or_else(|err| err.into_inner()).clone() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Ping(smol::channel::Sender<String>);
+    impl MachineImpl for Ping {
+        type Adapter = Ping;
+        type InstructionSet = Ping;
+    }
+    impl MachineBuilder for Ping {
+        type InstructionSet = Ping;
+    }
+
+    struct Pong;
+    impl Machine<Ping> for Pong {
+        fn receive(&self, cmd: Ping, _sender: &mut MachineSender) {
+            cmd.0.try_send(thread::current().name().unwrap_or_default().to_string()).ok();
+        }
+    }
+
+    // A runtime which counts the tasks spawned, running them on the smol runtime.
+    #[derive(Debug, Default)]
+    struct CountingRuntime(AtomicUsize);
+    impl Runtime for CountingRuntime {
+        fn spawn(&self, future: BoxFuture) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            SmolRuntime.spawn(future)
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture { SmolRuntime.sleep(duration) }
+    }
+
+    #[test]
+    fn run_on_runtime() {
+        let counting = Arc::new(CountingRuntime::default());
+        let previous = set_runtime(counting.clone());
+        let (_pong, sender, _adapter) = Ping::unbounded(Pong);
+        set_runtime(previous);
+        assert!(counting.0.load(Ordering::SeqCst) >= 1);
+        let (reply, replies) = smol::channel::unbounded();
+        smol::block_on(async {
+            sender.send(Ping(reply)).await.ok();
+            assert!(replies.recv().await.unwrap().starts_with("executor-"));
+        });
+        let start = Instant::now();
+        smol::block_on(get_runtime().sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    // A runtime which bounds every channel.
+    #[derive(Debug)]
+    struct BoundedRuntime;
+    impl Runtime for BoundedRuntime {
+        fn spawn(&self, future: BoxFuture) { SmolRuntime.spawn(future) }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture { SmolRuntime.sleep(duration) }
+
+        fn channel_capacity(&self, capacity: Option<usize>) -> Option<usize> { Some(capacity.unwrap_or(8).min(8)) }
+    }
+
+    #[test]
+    fn channel_capacity() {
+        assert_eq!(Some(4), machine_channel::<u8>(Some(4)).0.capacity());
+        assert_eq!(None, machine_channel::<u8>(None).0.capacity());
+        // the global runtime isn't set, as machines created by other tests would be bounded
+        assert_eq!(None, SmolRuntime.channel_capacity(None));
+        assert_eq!(Some(8), BoundedRuntime.channel_capacity(None));
+        assert_eq!(Some(8), BoundedRuntime.channel_capacity(Some(100)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn run_on_tokio() {
+        // the runtime isn't set, as machines created by other tests, while it was, would be spawned
+        // upon it, and stopped as it shuts down
+        let tokio = tokio::runtime::Builder::new_multi_thread().enable_time().build().unwrap();
+        let runtime = TokioRuntime::new(tokio.handle().clone());
+        let (reply, replies) = smol::channel::unbounded();
+        runtime.spawn(Box::pin(async move {
+            reply.send(thread::current().name().unwrap_or_default().to_string()).await.ok();
+        }));
+        assert!(smol::block_on(replies.recv()).unwrap().starts_with("tokio"));
+        let start = Instant::now();
+        smol::block_on(runtime.sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        tokio.shutdown_timeout(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,90 @@
+use bumpalo::Bump;
+use std::cell::RefCell;
+
+// The bytes an arena may keep, once reset, any more are returned to the allocator.
+const SCRATCH_RETAIN: usize = 1 << 20;
+
+thread_local! {
+    static SCRATCH: RefCell<Bump> = RefCell::new(Bump::new());
+}
+
+/// Run f with the scratch arena of the current thread, for temporary buffers, such as those of a
+/// codec, or a proxy, which don't outlive the receive of an instruction. Allocating from the arena
+/// is a pointer bump, and nothing is freed until the adapter resets it, once receive returns.
+/// Nothing allocated can escape f, and f mustn't hold anything across an await.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::with_scratch;
+///
+/// let sum = with_scratch(|scratch| {
+///     let mut buffer = bumpalo::collections::Vec::with_capacity_in(1024, scratch);
+///     buffer.extend((0 .. 1024).map(|i| (i % 7) as u32));
+///     buffer.iter().sum::<u32>()
+/// });
+/// assert_eq!(3067, sum);
+/// ```
+pub fn with_scratch<R>(f: impl FnOnce(&Bump) -> R) -> R { SCRATCH.with(|scratch| f(&scratch.borrow())) }
+
+/// Get the number of bytes held by the scratch arena of the current thread, the size of the
+/// chunks it has allocated since it was last reset.
+pub fn get_scratch_allocated() -> usize { SCRATCH.with(|scratch| scratch.borrow().allocated_bytes()) }
+
+// Reset the scratch arena of the current thread, releasing everything allocated from it. An arena
+// which has grown beyond SCRATCH_RETAIN, for an unusually large instruction, is replaced.
+pub(crate) fn reset_scratch() {
+    SCRATCH.with(|scratch| {
+        if let Ok(mut scratch) = scratch.try_borrow_mut() {
+            if scratch.allocated_bytes() > SCRATCH_RETAIN {
+                *scratch = Bump::new();
+            } else {
+                scratch.reset();
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[derive(Debug, Clone)]
+    struct Encode(usize, smol::channel::Sender<(usize, usize)>);
+    impl MachineImpl for Encode {
+        type Adapter = Self;
+        type InstructionSet = Self;
+    }
+    impl MachineBuilder for Encode {
+        type InstructionSet = Self;
+    }
+
+    struct Encoder;
+    impl Machine<Encode> for Encoder {
+        fn receive(&self, cmd: Encode, _sender: &mut MachineSender) {
+            let Encode(len, reply) = cmd;
+            let before = get_scratch_allocated();
+            let encoded = with_scratch(|scratch| scratch.alloc_slice_fill_copy(len, 1u8).len());
+            reply.try_send((before, encoded)).ok();
+        }
+    }
+
+    #[test]
+    fn reset_after_receive() {
+        let (_encoder, sender, _adapter) = Encode::unbounded(Encoder);
+        let (reply, replies) = smol::channel::unbounded();
+        smol::block_on(async {
+            for len in &[4096, 4096, 2 * SCRATCH_RETAIN, 16] {
+                sender.send(Encode(*len, reply.clone())).await.ok();
+                let (before, encoded) = replies.recv().await.unwrap();
+                assert_eq!(*len, encoded);
+                // the arena keeps its chunk, but nothing allocated by a previous receive
+                assert!(before <= SCRATCH_RETAIN);
+            }
+        });
+        with_scratch(|scratch| scratch.alloc_slice_fill_copy(2 * SCRATCH_RETAIN, 0u8).len());
+        reset_scratch();
+        assert!(get_scratch_allocated() <= SCRATCH_RETAIN);
+    }
+}
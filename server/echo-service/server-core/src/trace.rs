@@ -0,0 +1,231 @@
+use super::*;
+use std::{cell::Cell, collections::VecDeque, sync::Mutex};
+
+#[allow(non_upper_case_globals)]
+// The number of events kept in the trace buffer, 0 if tracing is off.
+static trace_capacity: AtomicCell<usize> = AtomicCell::new(0);
+
+// The trace buffer, the oldest events are dropped once it is full.
+static TRACE: Lazy<Mutex<VecDeque<TraceEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+thread_local! {
+    static CURRENT_CORRELATION: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+fn lock_trace() -> std::sync::MutexGuard<'static, VecDeque<TraceEvent>> { TRACE.lock().unwrap_or_else(|err| err.into_inner()) }
+
+/// A TraceEvent records an instruction sent, as part of a traced run, identified by its
+/// correlation id. The participants are the instruction sets, each standing for the machine
+/// receiving it, the sender being external if it wasn't sent by a machine.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// The correlation id of the run.
+    pub correlation: u64,
+    /// The instruction set of the sending machine, or external.
+    pub from: String,
+    /// The instruction set of the receiving machine.
+    pub to: String,
+    /// The variant of the instruction.
+    pub instruction: String,
+}
+
+/// Set the number of events kept in the trace buffer, returning the previous value. If 0, the
+/// default, tracing is off.
+pub fn set_trace_capacity(capacity: usize) -> usize {
+    let previous = trace_capacity.swap(capacity);
+    let mut trace = lock_trace();
+    while trace.len() > capacity {
+        trace.pop_front();
+    }
+    previous
+}
+/// Get the number of events kept in the trace buffer.
+pub fn get_trace_capacity() -> usize { trace_capacity.load() }
+
+/// Set the correlation id of the run which the instruction being received is part of, returning
+/// the previous one. The instructions sent via the MachineSender, for the rest of the receive, are
+/// traced with it. A machine sets it from the correlation id carried by the instruction, and
+/// passes it along in the instructions it sends. The adapter clears it after each receive.
+pub fn set_correlation(correlation: Option<u64>) -> Option<u64> { CURRENT_CORRELATION.with(|current| current.replace(correlation)) }
+/// Get the correlation id of the instruction being received, if any.
+pub fn get_correlation() -> Option<u64> { CURRENT_CORRELATION.with(|current| current.get()) }
+
+// Get the last segment of a type name, such as NetCmd for components::NetCmd.
+fn short_name(type_name: &str) -> &str { type_name.rsplit("::").next().unwrap_or(type_name) }
+
+// Trace an instruction sent by the current machine, if tracing, and the receive is correlated.
+pub(crate) fn record_send<T: MachineImpl>(cmd: &T) {
+    if get_trace_capacity() == 0 {
+        return;
+    }
+    if let Some(correlation) = get_correlation() {
+        let from = current_machine().map_or("external", |context| short_name(context.instruction_set));
+        record_trace(TraceEvent {
+            correlation,
+            from: from.to_string(),
+            to: short_name(std::any::type_name::<T>()).to_string(),
+            instruction: cmd.variant_name().to_string(),
+        });
+    }
+}
+
+/// Add an event to the trace buffer, if tracing, such as for an instruction sent from outside of
+/// a machine, which the MachineSender doesn't see.
+pub fn record_trace(event: TraceEvent) {
+    let capacity = get_trace_capacity();
+    if capacity == 0 {
+        return;
+    }
+    let mut trace = lock_trace();
+    if trace.len() >= capacity {
+        trace.pop_front();
+    }
+    trace.push_back(event);
+}
+
+/// Get the events in the trace buffer, oldest first, of the run with the correlation id, or of
+/// every run if None.
+pub fn get_trace(correlation: Option<u64>) -> Vec<TraceEvent> {
+    lock_trace()
+        .iter()
+        .filter(|event| correlation.is_none_or(|correlation| event.correlation == correlation))
+        .cloned()
+        .collect()
+}
+
+/// Empty the trace buffer.
+pub fn clear_trace() { lock_trace().clear() }
+
+/// The SequenceDiagram of the instruction flow between machines in a traced run, which can be
+/// exported as Mermaid, or PlantUML, for reviewing the topology of a service.
+///
+/// Examples:
+///
+/// ```rust
+/// use server_core::{SequenceDiagram, TraceEvent};
+///
+/// let event = |from: &str, to: &str, instruction: &str| TraceEvent {
+///     correlation: 1,
+///     from: from.to_string(),
+///     to: to.to_string(),
+///     instruction: instruction.to_string(),
+/// };
+/// let diagram = SequenceDiagram::new(&[event("ListenerCmd", "NetCmd", "BindConn"), event("ConnCmd", "NetCmd", "SendBytes")]);
+/// assert!(diagram.to_mermaid().contains("ListenerCmd->>NetCmd: BindConn"));
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SequenceDiagram {
+    participants: Vec<String>,
+    messages: Vec<(String, String, String)>,
+}
+
+impl SequenceDiagram {
+    /// Create the diagram of the events, in the order they occurred. The participants are ordered
+    /// by their first appearance.
+    pub fn new(events: &[TraceEvent]) -> Self {
+        let mut diagram = Self::default();
+        for event in events {
+            for participant in &[&event.from, &event.to] {
+                if !diagram.participants.contains(participant) {
+                    diagram.participants.push(participant.to_string());
+                }
+            }
+            diagram
+                .messages
+                .push((event.from.clone(), event.to.clone(), event.instruction.clone()));
+        }
+        diagram
+    }
+
+    /// Export the diagram as a Mermaid sequenceDiagram.
+    pub fn to_mermaid(&self) -> String {
+        let mut text = "sequenceDiagram\n".to_string();
+        for participant in &self.participants {
+            text.push_str(&format!("    participant {}\n", participant));
+        }
+        for (from, to, instruction) in &self.messages {
+            text.push_str(&format!("    {}->>{}: {}\n", from, to, instruction));
+        }
+        text
+    }
+
+    /// Export the diagram as PlantUML.
+    pub fn to_plantuml(&self) -> String {
+        let mut text = "@startuml\n".to_string();
+        for participant in &self.participants {
+            text.push_str(&format!("participant {}\n", participant));
+        }
+        for (from, to, instruction) in &self.messages {
+            text.push_str(&format!("{} -> {} : {}\n", from, to, instruction));
+        }
+        text.push_str("@enduml\n");
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    enum Request {
+        Start(u64, smol::channel::Sender<Reply>),
+    }
+    impl MachineImpl for Request {
+        type Adapter = Self;
+        type InstructionSet = Self;
+        fn variant_name(&self) -> &'static str { "Start" }
+    }
+    impl MachineBuilder for Request {
+        type InstructionSet = Self;
+    }
+
+    #[derive(Debug, Clone)]
+    enum Reply {
+        Done(u64),
+    }
+    impl MachineImpl for Reply {
+        type Adapter = Self;
+        type InstructionSet = Self;
+        fn variant_name(&self) -> &'static str { "Done" }
+    }
+
+    struct Worker;
+    impl Machine<Request> for Worker {
+        fn receive(&self, cmd: Request, sender: &mut MachineSender) {
+            let Request::Start(correlation, reply) = cmd;
+            set_correlation(Some(correlation));
+            sender.send(reply, Reply::Done(correlation));
+        }
+    }
+
+    #[test]
+    fn trace_run() {
+        set_trace_capacity(100);
+        let (_worker, worker, _adapter) = Request::unbounded(Worker);
+        let (reply, replies) = smol::channel::unbounded();
+        smol::block_on(async {
+            worker.send(Request::Start(7, reply.clone())).await.ok();
+            worker.send(Request::Start(8, reply)).await.ok();
+            for _ in 0 .. 2 {
+                assert!(matches!(replies.recv().await, Ok(Reply::Done(7)) | Ok(Reply::Done(8))));
+            }
+        });
+        assert_eq!(None, get_correlation());
+        let events = get_trace(Some(7));
+        assert_eq!(1, events.len());
+        assert_eq!("Request", events[0].from);
+        assert_eq!("Reply", events[0].to);
+        assert_eq!("Done", events[0].instruction);
+
+        let diagram = SequenceDiagram::new(&events);
+        assert_eq!(
+            "sequenceDiagram\n    participant Request\n    participant Reply\n    Request->>Reply: Done\n",
+            diagram.to_mermaid()
+        );
+        assert_eq!(
+            "@startuml\nparticipant Request\nparticipant Reply\nRequest -> Reply : Done\n@enduml\n",
+            diagram.to_plantuml()
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use super::*;
+use std::sync::RwLock;
+
+// The executors of the pool, which an idle executor thread steals tasks from.
+static EXECUTORS: Lazy<RwLock<Arc<Vec<Arc<InstrumentedExecutor>>>>> = Lazy::new(|| RwLock::new(Arc::new(Vec::new())));
+
+#[allow(non_upper_case_globals)]
+// If true, idle executor threads steal tasks from the other executors.
+static work_stealing: AtomicCell<bool> = AtomicCell::new(true);
+
+// The interval at which a parked executor thread wakes to check for tasks to steal.
+pub(crate) const STEAL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Set whether an executor thread, which has run out of tasks, steals tasks from the other
+/// executors in the pool, returning the previous value. Machines are placed on executors as
+/// they are created, and stealing keeps a hot executor from saturating its thread while the others
+/// idle. Work stealing is on by default.
+pub fn set_work_stealing(enabled: bool) -> bool { work_stealing.swap(enabled) }
+/// Get whether executor threads steal tasks from the other executors in the pool.
+pub fn get_work_stealing() -> bool { work_stealing.load() }
+
+// Set the pool of executors to steal from, whenever it is resized.
+pub(crate) fn set_executors(executors: Arc<Vec<Arc<InstrumentedExecutor>>>) {
+    *EXECUTORS.write().unwrap_or_else(|err| err.into_inner()) = executors;
+}
+
+// Steal tasks for an executor's thread, which has run out of tasks, from the executors of its
+// pool, the global pool if None.
+pub(crate) fn steal_from_pool(executor: &smol::Executor<'static>, pool: Option<&[Arc<InstrumentedExecutor>]>, batch: u32) -> bool {
+    if !get_work_stealing() {
+        return false;
+    }
+    match pool {
+        Some(executors) => steal(executor, executors, batch),
+        None => steal(executor, &EXECUTORS.read().unwrap_or_else(|err| err.into_inner()).clone(), batch),
+    }
+}
+
+// Steal up to a batch of tasks from the other executors, starting with the one after the thief,
+// so that idle threads spread their stealing. A stolen task is run by the thief's thread, but
+// remains a task of its executor. Returns true if any were stolen.
+fn steal(thief: &smol::Executor<'static>, executors: &[Arc<InstrumentedExecutor>], batch: u32) -> bool {
+    let start = executors
+        .iter()
+        .position(|e| std::ptr::eq::<smol::Executor>(&***e, thief))
+        .map_or(0, |idx| idx + 1);
+    let mut stolen = 0;
+    for idx in (0 .. executors.len()).map(|n| (start + n) % executors.len()) {
+        let victim: &smol::Executor = &executors[idx];
+        if std::ptr::eq(victim, thief) {
+            continue;
+        }
+        while stolen < batch && victim.try_tick() {
+            stolen += 1;
+        }
+        if stolen >= batch {
+            break;
+        }
+    }
+    stolen > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steal_from_busy_executor() {
+        let busy = Arc::new(InstrumentedExecutor::new());
+        let idle = Arc::new(InstrumentedExecutor::new());
+        let executors = vec![busy.clone(), idle.clone()];
+        let (sender, receiver) = smol::channel::unbounded();
+        for n in 0 .. 10 {
+            let sender = sender.clone();
+            busy.spawn(async move { sender.try_send(n).ok() }).detach();
+        }
+        // the idle executor has nothing to steal from itself
+        assert!(!steal(&busy, &executors[1 ..], 4));
+        assert!(steal(&idle, &executors, 4));
+        assert_eq!(4, receiver.len());
+        assert!(steal(&idle, &executors, 100));
+        assert_eq!(10, receiver.len());
+        assert!(!steal(&idle, &executors, 100));
+        assert_eq!(0, busy.get_pending_count());
+    }
+}
@@ -0,0 +1,44 @@
+//! The server-prelude re-exports the items a service commonly needs, from each of the layers, so
+//! that a service crate depends upon, and imports from, a single crate.
+//!
+//! The MachineImpl derive refers to `server_core` and `::smol`, both are re-exported, however smol
+//! must still be a dependency of a crate which derives an instruction set.
+//!
+//! Examples:
+//!
+//! ```rust
+//! use server_prelude::*;
+//!
+//! #[derive(Debug, Clone, MachineImpl)]
+//! pub enum Ping {
+//!     Ping(usize),
+//! }
+//!
+//! struct Pong;
+//! impl Machine<Ping> for Pong {
+//!     fn receive(&self, _cmd: Ping, _sender: &mut MachineSender) {}
+//! }
+//!
+//! let (_pong, sender) = machine::create(Pong);
+//! sender.try_send(Ping::Ping(1)).ok();
+//! ```
+pub use components::{
+    AsyncServiceStateTransition, Compression, ConnCmd, ConnData, ConnEnricher, ConnSender, Controller, ControllerMachine, ListenerCmd,
+    ListenerSender, NetCmd, NetConnId, NetCore, NetReceiver, NetSender, Probe, ServerService, ServiceError, ServiceFactory, ServiceManager,
+    ServiceResult, ServiceState, ServiceStateTransition, Snapshot, SwitchPolicy, TarpitPolicy, TarpitStats,
+};
+pub use config_foundation::{ConfigBuilder, ConfigLayer, ConfigMerger, ConfigMetaData, Environment, Log, MergedConfig, ServerSettings};
+pub use machine_foundation::{
+    get_executor, get_instruction_sets, get_machines, list_background_tasks, machine, spawn_blocking, BackgroundHandle, BackgroundTask,
+    BackgroundTaskGroup, ExecutorGroup, Machine, MachineBuilder, MachineGroup, MachineImpl, MachineInfo, MachineSender, Port, PortError,
+    Ports, SharedMachine, Shutdown, ShutdownStage, Watchdog, DEFAULT_WEIGHT,
+};
+pub use machine_impl::MachineImpl;
+pub use server_core::{install_panic_hook, stop_executors, stop_executors_and_wait};
+
+// The crates themselves, for the less common items, and for the MachineImpl derive.
+pub use components;
+pub use config_foundation;
+pub use machine_foundation;
+pub use server_core;
+pub use smol;
@@ -1,5 +1,9 @@
 use super::*;
-use rand::distributions::{Distribution, Uniform};
+use rand::{
+    distributions::{Distribution, Uniform},
+    rngs::StdRng,
+    SeedableRng,
+};
 
 /// ChaosMonkey will setup a network of machines in which a message received by any machine
 /// can be forwarded to any other machine, including itself. To break the cyclic nature of this,
@@ -19,6 +23,9 @@ use rand::distributions::{Distribution, Uniform};
 /// The message count represents concurrent number of messages flowing through the machines,
 /// while the inflection value represents the lifetime of the message. Varing the machine count
 /// varies the number of messages a machine may receive.
+///
+/// The randomness is seeded, by default from entropy. The seed is printed when a run fails, so
+/// that the run can be replayed by setting it.
 #[derive(Debug, SmartDefault)]
 pub struct ChaosMonkeyDriver {
     #[default = 3000]
@@ -36,6 +43,9 @@ pub struct ChaosMonkeyDriver {
     #[default(Duration::from_secs(10))]
     pub duration: Duration,
 
+    #[default(rand::random())]
+    pub seed: u64,
+
     #[default(Vec::with_capacity(3010))]
     pub senders: Vec<TestMessageSender>,
 
@@ -57,7 +67,7 @@ impl TestDriver for ChaosMonkeyDriver {
     // run a single iteration
     fn run(&self) {
         let range = Uniform::from(0 .. self.senders.len());
-        let mut rng = rand::rngs::OsRng::default();
+        let mut rng = StdRng::seed_from_u64(self.seed);
         let senders = self.senders.clone();
         let message_count = self.message_count;
         let inflection_value = self.inflection_value;
@@ -80,7 +90,7 @@ impl TestDriver for ChaosMonkeyDriver {
         if let Some(receiver) = self.receiver.as_ref() {
             println!("waiting for completion");
             if wait_for_notification(receiver, self.message_count, self.duration).is_err() {
-                panic!("chaos_monkey: completion notification failed");
+                panic!("chaos_monkey: completion notification failed, seed={}", self.seed);
             }
             println!("done");
         }
@@ -89,38 +99,31 @@ impl TestDriver for ChaosMonkeyDriver {
 
 impl ChaosMonkeyDriver {
     async fn async_setup(&mut self) {
-        let mut forwarders: Vec<Arc<Forwarder>> = Vec::new();
-        smol::block_on(async {
-            // we're going to create N machines, each having N senders, plus a notifier.
-            for idx in 1 ..= self.machine_count {
-                let (f, s) = if self.bound_queue {
-                    machine::create(Forwarder::new(idx))
-                } else {
-                    machine::create_unbounded(Forwarder::new(idx))
-                };
-                self.senders.push(s);
-                forwarders.push(f);
-            }
-            let (f, notifier) = if self.bound_queue {
-                machine::create(Forwarder::new(self.machine_count + 1))
-            } else {
-                machine::create_unbounded(Forwarder::new(self.machine_count + 1))
-            };
-            forwarders.push(f);
-            log::debug!("chaos_monkey: monkeys created");
-            // form a complete map by sending all the monkey's senders to each monkey
-            for s1 in &self.senders {
-                let cloned_senders = self.senders.clone();
-                s1.send(TestMessage::AddSenders(cloned_senders)).await.ok();
-                // chaos monkey ignores the count
-                s1.send(TestMessage::Notify(notifier.clone(), 0)).await.ok();
-            }
-            let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
-            notifier.send(TestMessage::Notify(sender, self.message_count)).await.ok();
-            self.receiver = Some(receiver);
-            log::info!("chaos_monkey: setup complete");
-            self.forwarders = forwarders;
-        })
+        // we're going to create N machines, each having N senders, plus a notifier.
+        let mut builder = TopologyBuilder::default();
+        if !self.bound_queue {
+            builder = builder.with_unbounded();
+        }
+        let monkeys: Vec<NodeId> = (1 ..= self.machine_count)
+            .map(|idx| builder.add_node(Forwarder::new(idx).with_seed(self.seed.wrapping_add(idx as u64))))
+            .collect();
+        let notifier = builder.add_node(Forwarder::new(self.machine_count + 1));
+        // form a complete map by sending all the monkey's senders to each monkey
+        builder.add_complete(&monkeys);
+        let topology = builder.build(|senders| vec![TestMessage::AddSenders(senders)]).await;
+        log::debug!("chaos_monkey: monkeys created");
+        let notifier = topology.get_sender(notifier).clone();
+        for monkey in &monkeys {
+            // chaos monkey ignores the count
+            let sender = topology.get_sender(*monkey);
+            sender.send(TestMessage::Notify(notifier.clone(), 0)).await.ok();
+            self.senders.push(sender.clone());
+        }
+        let (sender, receiver) = smol::channel::unbounded::<TestMessage>();
+        notifier.send(TestMessage::Notify(sender, self.message_count)).await.ok();
+        self.receiver = Some(receiver);
+        self.forwarders = topology.into_parts().0;
+        log::info!("chaos_monkey: setup complete, seed={}", self.seed);
     }
 }
 
@@ -55,12 +55,14 @@ impl TestDriver for DaisyChainDriver {
     fn run(&self) {
         let first_sender = self.first_sender.clone();
         let message_count = self.message_count;
+        // each run is a source of its own sequence, so runs may overlap
+        let source = self.iteration.fetch_add(1, Ordering::SeqCst);
         get_executor()
             .clone()
             .spawn(async move {
                 if let Some(sender) = first_sender.as_ref() {
                     for msg_id in 0 .. message_count {
-                        sender.send(TestMessage::TestData(msg_id)).await.ok();
+                        sender.send(TestMessage::TestData(source, msg_id)).await.ok();
                     }
                     log::info!("completed sending {} messages", message_count);
                 }
@@ -78,30 +80,27 @@ impl TestDriver for DaisyChainDriver {
 
 impl DaisyChainDriver {
     async fn async_setup(&mut self) {
-        let (f, s) = if self.bound_queue {
-            machine::create(Forwarder::new(1))
-        } else {
-            machine::create_unbounded(Forwarder::new(1))
-        };
-        self.forwarders.push(f);
-        self.first_sender = Some(s.clone());
-        let mut last_sender = s.clone();
-        self.senders.push(s);
-        for idx in 2 ..= self.machine_count {
-            let (f, s) = if self.bound_queue {
-                machine::create(Forwarder::new(idx))
-            } else {
-                machine::create_unbounded(Forwarder::new(idx))
-            };
-            self.forwarders.push(f);
-            last_sender.send(TestMessage::AddSender(s.clone())).await.ok();
-            last_sender
-                .send(TestMessage::ForwardingMultiplier(self.forwarding_multiplier))
-                .await
-                .ok();
-            last_sender = s.clone();
-            self.senders.push(s);
+        let mut builder = TopologyBuilder::default();
+        if !self.bound_queue {
+            builder = builder.with_unbounded();
         }
+        let nodes: Vec<NodeId> = (1 ..= self.machine_count)
+            .map(|idx| builder.add_node(Forwarder::new(idx)))
+            .collect();
+        builder.add_chain(&nodes);
+        let forwarding_multiplier = self.forwarding_multiplier;
+        let topology = builder
+            .build(|senders| {
+                let mut cmds: Vec<TestMessage> = senders.into_iter().map(TestMessage::AddSender).collect();
+                cmds.push(TestMessage::ForwardingMultiplier(forwarding_multiplier));
+                cmds
+            })
+            .await;
+        self.first_sender = Some(topology.get_sender(nodes[0]).clone());
+        let last_sender = topology.get_sender(nodes[nodes.len() - 1]).clone();
+        let (forwarders, senders) = topology.into_parts();
+        self.forwarders = forwarders;
+        self.senders = senders;
         self.exepected_message_count = self.message_count * (self.forwarding_multiplier.pow((self.machine_count - 1) as u32));
         if self.forwarding_multiplier > 1 {
             log::info!("daisy_chain: expecting {} messages", self.exepected_message_count);
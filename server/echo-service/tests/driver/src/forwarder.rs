@@ -2,7 +2,8 @@ use super::*;
 
 use parking_lot::Mutex;
 use rand::distributions::{Distribution, Uniform};
-use rand::prelude::*;
+use rand::{prelude::*, rngs::StdRng};
+use std::collections::HashMap;
 
 /// The Forwarder is the Swiss Army Knife of machine models. It implements the TestMessage instruction set.
 /// This allows it to be an accumulator of messages (fanin), a distributor of messages (fanout), a forwarder,
@@ -27,8 +28,6 @@ struct ForwarderMutable {
     senders: Vec<TestMessageSender>,
     /// received_count is the count of messages received by this forwarder.
     received_count: usize,
-    /// send_count is the count of messages sent by this forwarder.
-    send_count: usize,
     /// notify_count is compared against received_count for means of notifcation.
     notify_count: usize,
     /// notify_sender is sent a TestData message with the data being the number of messages received.
@@ -39,27 +38,42 @@ struct ForwarderMutable {
     // Chaos monkey random
     #[default(Uniform::from(0..1))]
     range: Uniform<usize>,
-    // for TestData, this is the next in sequence
+    // Chaos monkey random number generator, seeded so that a run can be replayed
+    #[default(StdRng::from_entropy())]
+    rng: StdRng,
+    // for TestData, the sequence of each source
+    sources: HashMap<usize, Sequence>,
+}
+
+/// This is the sequence of TestData from a single source, such as a run of a driver. Tracking each
+/// source apart allows the runs of a driver to overlap.
+#[derive(Debug, Default)]
+struct Sequence {
+    /// next_seq is the next sequence number expected from the source.
     next_seq: usize,
+    /// received_count is the count of messages received from the source.
+    received_count: usize,
+    /// send_count is the count of messages sent for the source, which is the sequence number sent.
+    send_count: usize,
 }
 impl ForwarderMutable {
     /// get an index suitable for obtaining a random sender from the senders vector
-    fn get_monkey_fwd(&mut self) -> usize {
-        let mut rng = thread_rng();
-        self.range.sample(&mut rng)
-    }
+    fn get_monkey_fwd(&mut self) -> usize { self.range.sample(&mut self.rng) }
     fn drop_all_senders(&mut self) {
         self.senders.clear();
         self.notify_sender = None;
     }
 
-    /// if msg is TestData, validate the sequence or reset if 0
+    /// if msg is TestData, validate the sequence of its source or reset if 0
     fn validate_sequence(&mut self, msg: TestMessage) -> Result<TestMessage, TestMessage> {
-        match msg {
-            TestMessage::TestData(seq) if seq == self.next_seq => self.next_seq += 1,
-            TestMessage::TestData(seq) if seq == 0 => self.next_seq = 1,
-            TestMessage::TestData(_) => return Err(msg),
-            _ => (),
+        if let TestMessage::TestData(source, seq) = msg {
+            let sequence = self.sources.entry(source).or_default();
+            match seq {
+                seq if seq == sequence.next_seq => sequence.next_seq += 1,
+                0 => sequence.next_seq = 1,
+                _ => return Err(msg),
+            }
+            sequence.received_count += 1;
         }
         // bump received count
         self.received_count += 1;
@@ -97,16 +111,15 @@ impl ForwarderMutable {
             },
             TestMessage::ChaosMonkey { .. } => {
                 if let Some(notifier) = self.notify_sender.as_ref() {
-                    sender.send(notifier.clone(), TestMessage::TestData(0));
+                    sender.send(notifier.clone(), TestMessage::TestData(0, 0));
                 }
             },
-            TestMessage::TestData(_) => {
+            TestMessage::TestData(source, _) => {
+                let sequence = self.sources.entry(source).or_default();
                 for s in &self.senders {
                     for _ in 0 .. self.forwarding_multiplier {
-                        // println!("fwd={} sending=TestData({}) to={}", self.id, self.send_count, sender.get_id());
-                        sender.send(s.clone(), TestMessage::TestData(self.send_count));
-                        // println!("fwd={} sent=TestData({}) to={}", self.id, self.send_count, sender.get_id());
-                        self.send_count += 1;
+                        sender.send(s.clone(), TestMessage::TestData(source, sequence.send_count));
+                        sequence.send_count += 1;
                     }
                 }
             },
@@ -122,12 +135,16 @@ impl ForwarderMutable {
         }
     }
 
-    /// handle sending out a notification and resetting counters when notificaiton is sent
-    fn handle_notification(&mut self, sender: &mut MachineSender) {
-        if self.received_count == self.notify_count {
-            // log::trace!("received {} out of {}", count, mutable.notify_count);
+    /// handle sending out a notification, when the count received from a source, or in total for other
+    /// messages, reaches the notify count
+    fn handle_notification(&mut self, source: Option<usize>, sender: &mut MachineSender) {
+        let (source, received_count) = match source.and_then(|source| self.sources.get(&source).map(|s| (source, s))) {
+            Some((source, sequence)) => (source, sequence.received_count),
+            None => (0, self.received_count),
+        };
+        if received_count == self.notify_count {
             if let Some(notifier) = &self.notify_sender {
-                sender.send(notifier.clone(), TestMessage::TestData(self.received_count));
+                sender.send(notifier.clone(), TestMessage::TestData(source, received_count));
             }
         }
     }
@@ -136,7 +153,7 @@ impl ForwarderMutable {
     fn get_and_clear_received_count(&mut self) -> usize {
         let received_count = self.received_count;
         self.received_count = 0;
-        self.send_count = 0;
+        self.sources.clear();
         received_count
     }
 }
@@ -145,6 +162,13 @@ impl Forwarder {
     /// Create a new Forwarder.
     pub fn new(id: usize) -> Self { Self { id, ..Default::default() } }
 
+    /// Seed the random selection of the sender a chaos monkey message is forwarded to. Without a
+    /// seed, the selection isn't reproducible.
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.data.lock().rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
     /// Get the id of this Forwarder.
     pub const fn get_id(&self) -> usize { self.id }
 
@@ -161,8 +185,12 @@ impl Machine<TestMessage> for Forwarder {
                 Ok(_) => (),
                 Err(msg) => match data.validate_sequence(msg) {
                     Ok(msg) => {
+                        let source = match msg {
+                            TestMessage::TestData(source, _) => Some(source),
+                            _ => None,
+                        };
                         data.handle_action(msg, self.id, sender);
-                        data.handle_notification(sender);
+                        data.handle_notification(source, sender);
                     },
                     Err(msg) => panic!("sequence error fwd {}, msg {:#?}", self.id, msg),
                 },
@@ -185,4 +213,39 @@ mod tests {
         std::thread::sleep(std::time::Duration::from_millis(20));
         assert_eq!(1, machine.data.lock().get_and_clear_received_count());
     }
+
+    #[test]
+    fn seeded_forwarding() {
+        let forwards = |seed| {
+            let forwarder = Forwarder::new(1).with_seed(seed);
+            let mut data = forwarder.data.lock();
+            data.range = Uniform::from(0 .. 1000);
+            (0 .. 10).map(|_| data.get_monkey_fwd()).collect::<Vec<_>>()
+        };
+        assert_eq!(forwards(7), forwards(7));
+        assert_ne!(forwards(7), forwards(8));
+    }
+
+    #[test]
+    fn interleaved_sources() {
+        let (_forwarder, sender) = machine::create_unbounded(Forwarder::new(1));
+        let (notifier, notifications) = smol::channel::unbounded();
+        sender.try_send(TestMessage::Notify(notifier, 3)).ok();
+        for seq in 0 .. 3 {
+            sender.try_send(TestMessage::TestData(1, seq)).ok();
+            sender.try_send(TestMessage::TestData(2, seq)).ok();
+        }
+        // each source notifies once the notify count has been received from it
+        let mut notified: Vec<(usize, usize)> = smol::block_on(async {
+            let mut notified = Vec::new();
+            for _ in 0 .. 2 {
+                if let Ok(TestMessage::TestData(source, count)) = notifications.recv().await {
+                    notified.push((source, count));
+                }
+            }
+            notified
+        });
+        notified.sort_unstable();
+        assert_eq!(vec![(1, 3), (2, 3)], notified);
+    }
 }
@@ -1,11 +1,14 @@
 use smart_default::*;
 
 #[allow(unused_imports)]
-use machine_foundation::{get_executor, machine, set_default_channel_max, Machine, MachineSender};
+use machine_foundation::{get_executor, machine, set_default_channel_max, Machine, MachineSender, NodeId, TopologyBuilder};
 #[allow(unused_imports)]
 use std::{
     io,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{self, Duration, Instant},
 };
 
@@ -22,6 +25,9 @@ pub use daisy_chain::DaisyChainDriver;
 mod chaos_monkey;
 pub use chaos_monkey::ChaosMonkeyDriver;
 
+mod net_echo;
+pub use net_echo::{NetEchoDriver, NetEchoResult};
+
 /// The TestDriver trait is implemented by tests and benchmarks for testing various throughput scenrios.
 pub trait TestDriver {
     /// Setup the test, initilizing and configuring machines.
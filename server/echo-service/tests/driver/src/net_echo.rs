@@ -0,0 +1,192 @@
+use super::*;
+use components::{ConnCmd, ConnSender, ListenerCmd, ListenerSender, NetCmd, NetCore, NetSender};
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// NetEchoDriver measures the network path, from NetCore, to a machine, and back to NetCore, on
+/// loopback. Each run connects a number of clients, one after another, each completing an echo,
+/// which measures accepts/sec, including the binding of the connection to its machine. Then the
+/// clients, together, send messages which are echoed, which measures echoed bytes/sec. It is the
+/// benchmark for changes to the NetCore controller, such as its locking or buffer handling.
+#[derive(Debug, SmartDefault)]
+pub struct NetEchoDriver {
+    #[default = 47400]
+    pub port: u16,
+
+    #[default = 100]
+    pub connection_count: usize,
+
+    #[default = 100]
+    pub message_count: usize,
+
+    #[default = 1024]
+    pub message_size: usize,
+
+    #[default(Duration::from_secs(10))]
+    pub duration: Duration,
+
+    pub listener: Option<ListenerSender>,
+    pub results: parking_lot::Mutex<Vec<NetEchoResult>>,
+}
+
+/// NetEchoResult is the result of a single run of the NetEchoDriver.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct NetEchoResult {
+    /// The number of connections accepted, and bound, per second.
+    pub accepts_per_sec: f64,
+    /// The number of bytes echoed per second.
+    pub echoed_bytes_per_sec: f64,
+}
+
+impl TestDriver for NetEchoDriver {
+    // setup the echo machine and bind the listener
+    fn setup(&mut self) {
+        NetCore::start();
+        let net_sender = NetCore::get_sender();
+        let echo = Echo {
+            net_sender: net_sender.clone(),
+            conn_sender: parking_lot::Mutex::new(None),
+        };
+        let (echo, listener) = machine::create_unbounded::<ListenerCmd, _>(echo);
+        *echo.conn_sender.lock() = Some(machine::extend_unbounded::<ConnCmd, _>(&echo));
+        let address = self.get_address();
+        smol::block_on(async {
+            net_sender
+                .send(NetCmd::BindTcpListener(address.clone(), listener.clone()))
+                .await
+                .ok();
+            // wait for the listener to be bound
+            for _ in 0 .. 100 {
+                if TcpStream::connect(address.as_str()).await.is_ok() {
+                    break;
+                }
+                smol::Timer::after(Duration::from_millis(10)).await;
+            }
+        });
+        self.listener = Some(listener);
+        log::info!("net_echo: setup complete");
+    }
+
+    // teardown the machines
+    fn teardown(mut _net_echo: Self) {
+        log::debug!("net_echo: tear-down started");
+        log::debug!("net_echo: tear-down complete");
+    }
+
+    // run a single iteration
+    fn run(&self) {
+        let address = self.get_address();
+        let (connection_count, message_count) = (self.connection_count, self.message_count);
+        let message = vec![7u8; self.message_size];
+        let executor = smol::LocalExecutor::new();
+        let run = executor.run(async {
+            let start = Instant::now();
+            let mut clients = Vec::with_capacity(connection_count);
+            for _ in 0 .. connection_count {
+                let mut client = TcpStream::connect(address.as_str()).await?;
+                echo(&mut client, &[0u8]).await?;
+                clients.push(client);
+            }
+            let accepts_elapsed = start.elapsed();
+
+            let start = Instant::now();
+            let tasks: Vec<smol::Task<io::Result<()>>> = clients
+                .into_iter()
+                .map(|mut client| {
+                    let message = message.clone();
+                    executor.spawn(async move {
+                        for _ in 0 .. message_count {
+                            echo(&mut client, &message).await?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await?;
+            }
+            let echoed_elapsed = start.elapsed();
+            Ok::<_, io::Error>(NetEchoResult {
+                accepts_per_sec: connection_count as f64 / accepts_elapsed.as_secs_f64(),
+                echoed_bytes_per_sec: (connection_count * message_count * message.len()) as f64 / echoed_elapsed.as_secs_f64(),
+            })
+        });
+        let timeout = async {
+            smol::Timer::after(self.duration).await;
+            Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+        };
+        match smol::block_on(smol::future::or(run, timeout)) {
+            Ok(result) => {
+                log::info!(
+                    "net_echo: accepts/sec={:.0} echoed bytes/sec={:.0}",
+                    result.accepts_per_sec,
+                    result.echoed_bytes_per_sec
+                );
+                self.results.lock().push(result);
+            },
+            Err(err) => panic!("net_echo: run failed, error={}", err),
+        }
+    }
+}
+
+impl NetEchoDriver {
+    fn get_address(&self) -> String { format!("127.0.0.1:{}", self.port) }
+}
+
+// Send the message, and wait for it to be echoed.
+async fn echo(client: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    client.write_all(message).await?;
+    let mut buf = vec![0u8; message.len()];
+    client.read_exact(&mut buf).await
+}
+
+// The Echo machine binds each connection accepted to itself, and echoes the bytes it receives.
+struct Echo {
+    net_sender: NetSender,
+    conn_sender: parking_lot::Mutex<Option<ConnSender>>,
+}
+
+impl Machine<ListenerCmd> for Echo {
+    fn receive(&self, cmd: ListenerCmd, sender: &mut MachineSender) {
+        if let ListenerCmd::NewConn(conn_id, ..) = cmd {
+            if let Some(conn_sender) = self.conn_sender.lock().clone() {
+                sender.send(self.net_sender.clone(), NetCmd::BindConn(conn_id, conn_sender));
+            }
+        }
+    }
+}
+
+impl Machine<ConnCmd> for Echo {
+    fn receive(&self, cmd: ConnCmd, sender: &mut MachineSender) {
+        match cmd {
+            ConnCmd::RecvBytes(conn_id, bytes) => sender.send(self.net_sender.clone(), NetCmd::SendBytes(conn_id, bytes)),
+            ConnCmd::CloseConn(conn_id) => sender.send(self.net_sender.clone(), NetCmd::CloseConn(conn_id)),
+            ConnCmd::SendReady(..) | ConnCmd::Rejected(..) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_net_echo() {
+        let mut config = NetEchoDriver {
+            port: 47401,
+            connection_count: 10,
+            message_count: 10,
+            message_size: 64,
+            ..Default::default()
+        };
+        config.setup();
+        config.run();
+        config.run();
+        let results = config.results.lock().clone();
+        assert_eq!(2, results.len());
+        assert!(results.iter().all(|r| r.accepts_per_sec > 0.0 && r.echoed_bytes_per_sec > 0.0));
+        NetEchoDriver::teardown(config);
+    }
+}